@@ -0,0 +1,31 @@
+//! Embeds the git commit and build time reported as `BuildInfo`
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Docker builds have no .git, so the commit can be passed in instead
+    println!("cargo:rerun-if-env-changed=FOUNDRY_GIT_SHA");
+    println!("cargo:rerun-if-changed=src");
+    for path in ["../../.git/HEAD", "../../.git/refs/heads", "../../.git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    let git_sha = std::env::var("FOUNDRY_GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.trim().is_empty())
+        .or_else(git_head)
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    println!("cargo:rustc-env=FOUNDRY_GIT_SHA={}", git_sha.trim());
+    println!("cargo:rustc-env=FOUNDRY_BUILD_TIMESTAMP={}", built_at);
+}
+
+fn git_head() -> Option<String> {
+    let output = Command::new("git").args(["rev-parse", "HEAD"]).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
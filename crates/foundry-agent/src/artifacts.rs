@@ -0,0 +1,101 @@
+//! Collecting and uploading `[artifacts]` files after a successful build
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use foundry_core::config::ArtifactsConfig;
+use foundry_core::ClaimedJob;
+
+use crate::server::ServerClient;
+
+/// Regular files under `dir` whose `/`-separated relative path matches the config,
+/// sorted by name. Symlinks are skipped so a build can't point an artifact at a
+/// file outside its workspace.
+pub fn collect(dir: &Path, config: &ArtifactsConfig) -> Vec<(String, PathBuf)> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if entry.file_name() != ".git" {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() {
+                let Ok(relative) = path.strip_prefix(dir) else {
+                    continue;
+                };
+                let name = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if config.matches(&name) {
+                    found.push((name, path));
+                }
+            }
+        }
+    }
+
+    found.sort();
+    found
+}
+
+/// Upload every artifact the build produced. Missing or failed uploads are logged
+/// to the job but don't fail a build that already succeeded.
+pub async fn upload(client: &ServerClient, job: &ClaimedJob, dir: &Path, config: &ArtifactsConfig) -> Result<()> {
+    if !config.is_enabled() {
+        return Ok(());
+    }
+
+    let files = collect(dir, config);
+    if files.is_empty() {
+        client
+            .log(job, &format!("⚠️  No files match [artifacts] paths {:?}", config.paths))
+            .await?;
+        return Ok(());
+    }
+
+    for (name, path) in files {
+        match client.upload_artifact(job, &name, &path).await {
+            Ok(()) => client.log(job, &format!("📦 Uploaded artifact {}", name)).await?,
+            Err(e) => client.log(job, &format!("⚠️  Failed to upload artifact {}: {}", name, e)).await?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect_matching_files() {
+        let dir = std::env::temp_dir().join(format!("foundry-artifacts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("target/release")).unwrap();
+        std::fs::create_dir_all(dir.join("dist/assets")).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join("target/release/app"), "bin").unwrap();
+        std::fs::write(dir.join("target/release/app.d"), "deps").unwrap();
+        std::fs::write(dir.join("dist/assets/main.js"), "js").unwrap();
+        std::fs::write(dir.join(".git/main.js"), "not an artifact").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/etc/hostname", dir.join("dist/leak.js")).unwrap();
+
+        let config = ArtifactsConfig {
+            paths: vec!["target/release/app".to_string(), "**/*.js".to_string()],
+        };
+        let names: Vec<String> = collect(&dir, &config).into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(names, vec!["dist/assets/main.js", "target/release/app"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
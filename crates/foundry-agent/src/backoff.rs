@@ -0,0 +1,56 @@
+//! Spacing of claims: the poll interval while jobs keep coming, doubling on each empty
+//! claim or error up to a cap, with jitter so agents restarted together drift apart
+
+use std::time::Duration;
+
+use rand::Rng;
+
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    /// Empty claims and errors since the last job
+    misses: u32,
+}
+
+impl Backoff {
+    /// `max` below `base` is raised to it
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max: max.max(base), misses: 0 }
+    }
+
+    /// How long to wait after an empty claim or an error. Each miss doubles the wait up
+    /// to the cap; the delay is drawn from the upper half of it.
+    pub fn next_delay(&mut self) -> Duration {
+        let step = self.base.saturating_mul(1 << self.misses.min(16)).min(self.max);
+        self.misses = self.misses.saturating_add(1);
+        step.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// A job was claimed; poll at the base interval again
+    pub fn reset(&mut self) {
+        self.misses = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_to_cap_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_secs(5), Duration::from_secs(30));
+        for step in [5, 10, 20, 30, 30] {
+            let delay = backoff.next_delay();
+            let step = Duration::from_secs(step);
+            assert!(delay >= step / 2 && delay <= step, "{:?} outside {:?}", delay, step);
+        }
+
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_secs(5));
+
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(1));
+        for _ in 0..40 {
+            assert!(backoff.next_delay() <= Duration::from_secs(10));
+        }
+    }
+}
@@ -1,18 +1,59 @@
 use anyhow::{Context, Result};
+use foundry_core::config::{validate_agent_label, validate_cpu_limit, validate_memory_limit};
 use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct Config {
     pub agent_id: String,
+    /// Capabilities such as `gpu`; jobs whose `build.runs_on` needs others go elsewhere
+    pub labels: Vec<String>,
     pub server_url: String,
+    /// Dashboard URL for job links on GitHub when the server doesn't send one
+    pub public_url: Option<String>,
+    /// Sent as a bearer token on every request to foundryd
+    pub agent_token: Option<String>,
     pub workspace_dir: String,
+    /// Wait between claims while jobs are coming in
     pub poll_interval_secs: u64,
+    /// Longest wait between claims that the backoff on empty claims and errors reaches
+    pub max_poll_interval_secs: u64,
+    /// Jobs this agent runs at once
+    pub max_concurrent_jobs: usize,
+    /// On shutdown, running jobs get this long to finish before they're handed back to
+    /// the queue; unset waits for them
+    pub shutdown_timeout_secs: Option<u64>,
     pub default_command: String,
+    pub default_timeout_secs: u64,
+    /// Limit on cloning and checking out a repo, separate from the build timeout
+    pub clone_timeout_secs: u64,
+    /// Commits of history fresh clones fetch, 0 for all; `build.clone_depth` can ask for more
+    pub clone_depth: u32,
+    /// Check out submodules unless `build.submodules` says otherwise
+    pub clone_submodules: bool,
+    /// Directory of bare repo mirrors that clones are made from; unset clones fresh each time
+    pub mirror_dir: Option<String>,
+    /// Root of the per-repo `build.cache` directories, kept across jobs
+    pub cache_dir: String,
+    pub default_cpus: Option<f64>,
+    pub default_memory: Option<String>,
+    /// Largest `build.tmpfs` mount a repo may request
+    pub max_tmpfs_size: String,
     pub github_app_id: Option<String>,
     pub github_installation_id: Option<String>,
     pub github_private_key: Option<String>,
     pub self_repo: Option<String>,
     pub self_deploy_script: Option<String>,
+    /// `docker login` credentials for `build.registry` pushes
+    pub registry_user: Option<String>,
+    pub registry_password: Option<String>,
+    /// Credentials for pulling private images, one per registry host
+    pub registry_auth: Vec<RegistryAuth>,
+    /// Deploy key for cloning over SSH instead of HTTPS
+    pub ssh_key_path: Option<String>,
+    /// ssh `StrictHostKeyChecking`: `yes`, `accept-new` or `no`
+    pub ssh_host_key_checking: String,
+    /// known_hosts file to check hosts against instead of ssh's default
+    pub ssh_known_hosts: Option<String>,
 }
 
 impl Config {
@@ -25,35 +66,307 @@ impl Config {
             Err(_) => std::env::var("GITHUB_APP_PRIVATE_KEY").ok(),
         };
 
+        let default_cpus = match std::env::var("FOUNDRY_DEFAULT_CPUS") {
+            Ok(v) => {
+                let cpus: f64 = v
+                    .parse()
+                    .with_context(|| format!("FOUNDRY_DEFAULT_CPUS is not a number: {}", v))?;
+                validate_cpu_limit(cpus).context("FOUNDRY_DEFAULT_CPUS")?;
+                Some(cpus)
+            }
+            Err(_) => None,
+        };
+
+        let default_memory = match std::env::var("FOUNDRY_DEFAULT_MEMORY") {
+            Ok(v) => {
+                validate_memory_limit(&v).context("FOUNDRY_DEFAULT_MEMORY")?;
+                Some(v)
+            }
+            Err(_) => None,
+        };
+
+        let workspace_dir = std::env::var("FOUNDRY_WORKSPACE_DIR").unwrap_or_else(|_| "/tmp/foundry".to_string());
+
+        let max_tmpfs_size = std::env::var("FOUNDRY_MAX_TMPFS_SIZE").unwrap_or_else(|_| "1g".to_string());
+        validate_memory_limit(&max_tmpfs_size).context("FOUNDRY_MAX_TMPFS_SIZE")?;
+
+        let ssh_key_path = std::env::var("FOUNDRY_SSH_KEY_PATH").ok().filter(|p| !p.is_empty());
+        if let Some(path) = &ssh_key_path {
+            if !std::path::Path::new(path).is_file() {
+                anyhow::bail!("FOUNDRY_SSH_KEY_PATH {} is not a file", path);
+            }
+        }
+        let ssh_host_key_checking =
+            std::env::var("FOUNDRY_SSH_HOST_KEY_CHECKING").unwrap_or_else(|_| "accept-new".to_string());
+        if !matches!(ssh_host_key_checking.as_str(), "yes" | "accept-new" | "no") {
+            anyhow::bail!(
+                "FOUNDRY_SSH_HOST_KEY_CHECKING must be yes, accept-new or no, got {}",
+                ssh_host_key_checking
+            );
+        }
+
+        let registry_auth = match std::env::var("FOUNDRY_REGISTRY_AUTH") {
+            Ok(v) => parse_registry_auth(&v).context("FOUNDRY_REGISTRY_AUTH")?,
+            Err(_) => Vec::new(),
+        };
+
+        let labels = parse_labels(&std::env::var("FOUNDRY_AGENT_LABELS").unwrap_or_default())
+            .context("FOUNDRY_AGENT_LABELS")?;
+
         Ok(Self {
             agent_id: std::env::var("FOUNDRY_AGENT_ID")
                 .unwrap_or_else(|_| format!("agent-{}", &Uuid::new_v4().to_string()[..8])),
 
+            labels,
+
             server_url: std::env::var("FOUNDRY_SERVER_URL")
                 .unwrap_or_else(|_| "http://localhost:8080".to_string()),
 
-            workspace_dir: std::env::var("FOUNDRY_WORKSPACE_DIR")
-                .unwrap_or_else(|_| "/tmp/foundry".to_string()),
+            public_url: std::env::var("FOUNDRY_PUBLIC_URL").ok().filter(|u| !u.is_empty()),
+
+            agent_token: std::env::var("FOUNDRY_AGENT_TOKEN").ok().filter(|t| !t.is_empty()),
+
+            cache_dir: std::env::var("FOUNDRY_CACHE_DIR")
+                .ok()
+                .filter(|d| !d.is_empty())
+                .unwrap_or_else(|| format!("{}/cache", workspace_dir)),
+
+            workspace_dir,
 
             poll_interval_secs: std::env::var("FOUNDRY_POLL_INTERVAL")
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(5),
 
+            max_poll_interval_secs: std::env::var("FOUNDRY_MAX_POLL_INTERVAL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+
+            max_concurrent_jobs: std::env::var("FOUNDRY_MAX_CONCURRENT_JOBS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&n| n > 0)
+                .unwrap_or(1),
+
+            shutdown_timeout_secs: std::env::var("FOUNDRY_SHUTDOWN_TIMEOUT")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+
             default_command: std::env::var("FOUNDRY_DEFAULT_COMMAND")
                 .unwrap_or_else(|_| "echo 'No command configured'".to_string()),
 
+            default_timeout_secs: std::env::var("FOUNDRY_DEFAULT_TIMEOUT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1800),
+
+            clone_timeout_secs: std::env::var("FOUNDRY_CLONE_TIMEOUT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600),
+
+            clone_depth: std::env::var("FOUNDRY_CLONE_DEPTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(50),
+
+            clone_submodules: std::env::var("FOUNDRY_CLONE_SUBMODULES")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(false),
+
+            mirror_dir: std::env::var("FOUNDRY_MIRROR_DIR").ok().filter(|d| !d.is_empty()),
+
+            default_cpus,
+            default_memory,
+            max_tmpfs_size,
+
             github_app_id: std::env::var("GITHUB_APP_ID").ok(),
             github_installation_id: std::env::var("GITHUB_INSTALLATION_ID").ok(),
             github_private_key,
             self_repo: std::env::var("FOUNDRY_SELF_REPO").ok(),
             self_deploy_script: std::env::var("FOUNDRY_SELF_DEPLOY_SCRIPT").ok(),
+            registry_user: std::env::var("FOUNDRY_REGISTRY_USER").ok().filter(|u| !u.is_empty()),
+            registry_password: std::env::var("FOUNDRY_REGISTRY_PASSWORD").ok().filter(|p| !p.is_empty()),
+            registry_auth,
+            ssh_key_path,
+            ssh_host_key_checking,
+            ssh_known_hosts: std::env::var("FOUNDRY_SSH_KNOWN_HOSTS").ok().filter(|p| !p.is_empty()),
         })
     }
 
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self {
+            agent_id: "test-agent".to_string(),
+            labels: Vec::new(),
+            server_url: "http://127.0.0.1:1".to_string(),
+            public_url: None,
+            agent_token: None,
+            workspace_dir: std::env::temp_dir().join("foundry-agent-tests").display().to_string(),
+            poll_interval_secs: 5,
+            max_poll_interval_secs: 30,
+            max_concurrent_jobs: 1,
+            shutdown_timeout_secs: None,
+            default_command: "true".to_string(),
+            default_timeout_secs: 60,
+            clone_timeout_secs: 60,
+            clone_depth: 50,
+            clone_submodules: false,
+            mirror_dir: None,
+            cache_dir: std::env::temp_dir().join("foundry-agent-tests-cache").display().to_string(),
+            default_cpus: None,
+            default_memory: None,
+            max_tmpfs_size: "1g".to_string(),
+            github_app_id: None,
+            github_installation_id: None,
+            github_private_key: None,
+            self_repo: None,
+            self_deploy_script: None,
+            registry_user: None,
+            registry_password: None,
+            registry_auth: Vec::new(),
+            ssh_key_path: None,
+            ssh_host_key_checking: "accept-new".to_string(),
+            ssh_known_hosts: None,
+        }
+    }
+
     pub fn has_github_app(&self) -> bool {
         self.github_app_id.is_some()
             && self.github_installation_id.is_some()
             && self.github_private_key.is_some()
     }
+
+    /// Pull credentials for a registry host, as returned by `docker::image_registry`
+    pub fn registry_auth_for(&self, registry: &str) -> Option<&RegistryAuth> {
+        self.registry_auth.iter().find(|auth| auth.registry == registry)
+    }
+
+    /// `GIT_SSH_COMMAND` that clones with the deploy key, if one is configured.
+    /// Git runs it through a shell, so paths are quoted.
+    pub fn ssh_command(&self) -> Option<String> {
+        let key = self.ssh_key_path.as_deref()?;
+        let mut command = format!(
+            "ssh -i {} -o IdentitiesOnly=yes -o BatchMode=yes -o StrictHostKeyChecking={}",
+            shell_quote(key),
+            self.ssh_host_key_checking
+        );
+        if let Some(known_hosts) = &self.ssh_known_hosts {
+            command.push_str(&format!(" -o UserKnownHostsFile={}", shell_quote(known_hosts)));
+        }
+        Some(command)
+    }
+}
+
+/// `docker login` credentials for one registry from `FOUNDRY_REGISTRY_AUTH`
+#[derive(Clone, PartialEq)]
+pub struct RegistryAuth {
+    /// Host as it appears in image references, `docker.io` for Docker Hub
+    pub registry: String,
+    pub user: String,
+    pub token: String,
+}
+
+impl std::fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryAuth")
+            .field("registry", &self.registry)
+            .field("user", &self.user)
+            .field("token", &"[REDACTED]")
+            .finish()
+    }
+}
+
+/// Parse newline-delimited `registry=user:token` entries, skipping blank lines
+/// Comma-separated labels, e.g. `gpu, linux:cuda-12`
+fn parse_labels(value: &str) -> Result<Vec<String>> {
+    let mut labels = Vec::new();
+    for label in value.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+        validate_agent_label(label)?;
+        if !labels.iter().any(|l| l == label) {
+            labels.push(label.to_string());
+        }
+    }
+    Ok(labels)
+}
+
+fn parse_registry_auth(value: &str) -> Result<Vec<RegistryAuth>> {
+    value
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            let (registry, credentials) = line
+                .split_once('=')
+                .with_context(|| format!("entry {} is not registry=user:token", i + 1))?;
+            let (user, token) = credentials
+                .split_once(':')
+                .with_context(|| format!("entry {} for {} is not registry=user:token", i + 1, registry))?;
+            let registry = registry.trim().trim_start_matches("https://").trim_end_matches('/');
+            if registry.is_empty() || user.is_empty() || token.is_empty() {
+                anyhow::bail!("entry {} has an empty registry, user or token", i + 1);
+            }
+            let registry = match registry {
+                "index.docker.io" | "registry-1.docker.io" => "docker.io",
+                other => other,
+            };
+            Ok(RegistryAuth { registry: registry.to_string(), user: user.to_string(), token: token.to_string() })
+        })
+        .collect()
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_command() {
+        let mut config = Config::for_tests();
+        assert_eq!(config.ssh_command(), None);
+
+        config.ssh_key_path = Some("/keys/deploy key".to_string());
+        assert_eq!(
+            config.ssh_command().unwrap(),
+            "ssh -i '/keys/deploy key' -o IdentitiesOnly=yes -o BatchMode=yes -o StrictHostKeyChecking=accept-new"
+        );
+
+        config.ssh_host_key_checking = "yes".to_string();
+        config.ssh_known_hosts = Some("/keys/it's_known_hosts".to_string());
+        assert_eq!(
+            config.ssh_command().unwrap(),
+            "ssh -i '/keys/deploy key' -o IdentitiesOnly=yes -o BatchMode=yes -o StrictHostKeyChecking=yes \
+             -o UserKnownHostsFile='/keys/it'\\''s_known_hosts'"
+        );
+    }
+
+    #[test]
+    fn test_parse_labels() {
+        assert_eq!(parse_labels(" gpu, linux:cuda-12,,gpu ").unwrap(), ["gpu", "linux:cuda-12"]);
+        assert!(parse_labels("").unwrap().is_empty());
+        assert!(parse_labels("gpu,big disk").is_err());
+    }
+
+    #[test]
+    fn test_parse_registry_auth() {
+        let auth = parse_registry_auth("ghcr.io=acme:ghp_abc\n\n  https://index.docker.io/=bot:dckr:pat  \n").unwrap();
+        assert_eq!(
+            auth,
+            [
+                RegistryAuth { registry: "ghcr.io".into(), user: "acme".into(), token: "ghp_abc".into() },
+                RegistryAuth { registry: "docker.io".into(), user: "bot".into(), token: "dckr:pat".into() },
+            ]
+        );
+        assert!(!format!("{:?}", auth).contains("ghp_abc"));
+
+        assert!(parse_registry_auth("ghcr.io").is_err());
+        assert!(parse_registry_auth("ghcr.io=acme").is_err());
+        assert!(parse_registry_auth("=acme:token").is_err());
+        assert!(parse_registry_auth("").unwrap().is_empty());
+    }
 }
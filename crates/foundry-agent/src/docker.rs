@@ -1,19 +1,77 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::time::Instant;
 
 use anyhow::{Context, Result};
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
 
-use foundry_core::{ClaimedJob, FoundryConfig};
-use foundry_core::cloudflare::CloudflareClient;
+use foundry_core::config::{cache_args, matrix_entries, parse_env_file, validate_port_mapping, BuildConfig, Healthcheck, RegistryConfig, tmpfs_args, validate_cpu_limit, validate_memory_limit, Entrypoint, StderrMode, DeployCloudflareConfig};
+use foundry_core::{
+    docker_arch, platform_arch, AppliedLimit, ClaimedJob, DeploymentRequest, FoundryConfig, JobLimits, JobStatus,
+    LimitSource, LogLevel, MonorepoConfig,
+};
+use foundry_core::cloudflare::{preview_hostname, CloudflareClient};
 
+use crate::artifacts;
 use crate::config::Config;
 use crate::github_app::GitHubApp;
+use crate::provenance::Provenance;
 use crate::server::ServerClient;
 
+/// A build or pipeline whose container exited unsuccessfully
+#[derive(Debug)]
+pub struct BuildFailed {
+    pub reason: &'static str,
+    /// `None` if the container was killed by a signal
+    pub exit_code: Option<i32>,
+}
+
+impl std::fmt::Display for BuildFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.exit_code {
+            Some(code) => write!(f, "{} (exit code {})", self.reason, code),
+            None => write!(f, "{}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for BuildFailed {}
+
+/// A clone or build that ran past its time limit and was killed
+#[derive(Debug)]
+pub struct TimedOut {
+    /// `"clone"` or `"build"`, so a hung clone is reported separately from a hung build
+    pub phase: &'static str,
+    pub secs: u64,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} exceeded timeout of {} seconds", self.phase, self.secs)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A job whose `build.runs_on` asks for labels this agent doesn't have; it goes back to
+/// the queue for an agent that does
+#[derive(Debug)]
+pub struct MissingLabels {
+    pub agent_id: String,
+    pub missing: Vec<String>,
+}
+
+impl std::fmt::Display for MissingLabels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Agent {} lacks labels {} from build.runs_on", self.agent_id, self.missing.join(", "))
+    }
+}
+
+impl std::error::Error for MissingLabels {}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct JobMetrics {
     pub clone_duration_ms: u64,
@@ -38,19 +96,28 @@ fn is_self_deploy(job: &ClaimedJob, config: &Config) -> bool {
     }
 }
 
+/// Directory holding the job's foundry.toml: the repo root, or the monorepo service it builds
+fn config_dir(job: &ClaimedJob, repo_dir: &Path) -> PathBuf {
+    match &job.service_path {
+        Some(service) => repo_dir.join(service),
+        None => repo_dir.to_path_buf(),
+    }
+}
+
 pub async fn run_job(
     client: &ServerClient,
     job: &ClaimedJob,
     config: &Config,
     github_app: Option<&GitHubApp>,
-) -> Result<()> {
+) -> Result<Option<Provenance>> {
     let job_start = Instant::now();
+    let started_at = chrono::Utc::now();
     
     if is_self_deploy(job, config) {
-        return run_self_deploy(client, job, config, github_app).await;
+        return run_self_deploy(client, job, config, github_app).await.map(|_| None);
     }
 
-    let workspace = PathBuf::from(&config.workspace_dir).join(format!("job-{}", job.id));
+    let workspace = workspace_path(&config.workspace_dir, job.id, &job.claim_token);
 
     if workspace.exists() {
         debug!("Cleaning up existing workspace: {:?}", workspace);
@@ -63,15 +130,28 @@ pub async fn run_job(
         .await
         .context("Failed to create workspace directory")?;
 
-    let repo_dir = workspace.join("repo");
+    let mut repo_dir = workspace.join("repo");
 
-    let clone_url = if let Some(app) = github_app {
+    // A deploy key clones over SSH; otherwise HTTPS, with an app token if there is one
+    let ssh_command = config.ssh_command();
+    let ssh_url = job.ssh_url.as_deref().filter(|_| ssh_command.is_some());
+    if ssh_command.is_some() && ssh_url.is_none() {
+        client.log(job, "No SSH URL known for this repo, cloning over HTTPS").await?;
+    }
+    let clone_url = if let Some(url) = ssh_url {
+        url.to_string()
+    } else if let Some(app) = github_app {
         client.log(job, "Fetching GitHub App installation token").await?;
         let token = app.get_installation_token().await?;
         app.authenticated_clone_url(&job.clone_url, &token)
     } else {
         job.clone_url.clone()
     };
+    let remote = Remote {
+        url: &clone_url,
+        safe_url: ssh_url.unwrap_or(&job.clone_url),
+        ssh_command: ssh_url.and(ssh_command.as_deref()),
+    };
 
     // For scheduled jobs, git_sha starts with "RESOLVE:" - we clone by branch and resolve later
     let (clone_ref, is_scheduled) = if job.git_sha.starts_with("RESOLVE:") {
@@ -87,50 +167,238 @@ pub async fn run_job(
             job,
             &format!(
                 "Cloning {} @ {}",
-                job.clone_url,
+                remote.safe_url,
                 if is_scheduled { &job.git_ref } else { &clone_ref[..8.min(clone_ref.len())] }
             ),
         )
         .await?;
 
-    clone_repo(&clone_url, &job.clone_url, &clone_ref, &repo_dir, is_scheduled).await?;
+    // A checkout kept by `build.persistent_workspace` is updated in place instead of
+    // cloned again. Its lock is held until the job ends, so two builds never share it.
+    let persistent = (job.service_path.is_none() && job.matrix.is_empty())
+        .then(|| persistent_path(&config.workspace_dir, &job.repo_owner, &job.repo_name));
+    let mut _persistent_lock = None;
+    let mut source = None;
+    if let Some(path) = persistent.as_deref().filter(|path| path.join(".git").exists()) {
+        match try_lock_checkout(path).await? {
+            Some(lock) => {
+                let update = update_checkout(&remote, &clone_ref, path, is_scheduled, config.clone_depth);
+                match tokio::time::timeout(std::time::Duration::from_secs(config.clone_timeout_secs), update).await {
+                    Ok(Ok(())) => {
+                        _persistent_lock = Some(lock);
+                        repo_dir = path.to_path_buf();
+                        source = Some(CloneSource::Persistent);
+                    }
+                    result => {
+                        let e = match result {
+                            Ok(Err(e)) => e,
+                            _ => TimedOut { phase: "clone", secs: config.clone_timeout_secs }.into(),
+                        };
+                        client.log(job, &format!("⚠️  Failed to update the kept workspace, cloning fresh: {}", e)).await?;
+                        if let Err(e) = tokio::fs::remove_dir_all(path).await {
+                            warn!("Failed to remove kept workspace {}: {}", path.display(), e);
+                        }
+                    }
+                }
+            }
+            None => client.log(job, "Kept workspace is in use by another build, cloning fresh").await?,
+        }
+    }
+
+    let mirror = config
+        .mirror_dir
+        .as_deref()
+        .map(|dir| mirror_path(dir, &job.repo_owner, &job.repo_name));
+    let source = match source {
+        Some(source) => source,
+        None => match clone_repo(
+            &remote,
+            &clone_ref,
+            &repo_dir,
+            is_scheduled,
+            mirror.as_deref(),
+            config.clone_timeout_secs,
+            config.clone_depth,
+        )
+        .await
+        {
+            Ok(source) => source,
+            Err(e) => {
+                if e.is::<TimedOut>() {
+                    client.log(job, &format!("⏰ {}, killed git", e)).await?;
+                }
+                return Err(e);
+            }
+        },
+    };
     let clone_duration_ms = clone_start.elapsed().as_millis() as u64;
 
-    client.log(job, &format!("Clone complete ({} ms)", clone_duration_ms)).await?;
+    let via = match source {
+        CloneSource::Fresh => "",
+        CloneSource::MirrorCreated => ", mirror created",
+        CloneSource::MirrorReused => ", from mirror",
+        CloneSource::Persistent => ", kept workspace updated",
+    };
+    client.log(job, &format!("Clone complete ({} ms{})", clone_duration_ms, via)).await?;
+
+    // A broken foundry.toml fails the build rather than running it as if there were none
+    let mut foundry_config = match FoundryConfig::load(&config_dir(job, &repo_dir)) {
+        Ok(fc) => fc,
+        Err(e) => {
+            if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
+                debug!("Failed to cleanup workspace: {}", e);
+            }
+            // Logged with the job's failure
+            return Err(match &job.service_path {
+                Some(service) => anyhow::anyhow!("{}: {}", service, e),
+                None => e,
+            });
+        }
+    };
+    if let Some(ref mut fc) = foundry_config {
+        fc.apply_matrix(&job.matrix);
+    }
+
+    if let Some(path) = &persistent {
+        let keep = foundry_config.as_ref().is_some_and(|fc| fc.build.persistent_workspace);
+        if keep && source != CloneSource::Persistent {
+            if let Some(lock) = try_lock_checkout(path).await? {
+                keep_checkout(&repo_dir, path, &remote).await?;
+                _persistent_lock = Some(lock);
+                repo_dir = path.clone();
+                client.log(job, "📌 Keeping this checkout for the repo's next builds").await?;
+            }
+        } else if !keep && source == CloneSource::Persistent {
+            // Build in the job's own workspace, so the checkout is removed along with it
+            let own = workspace.join("repo");
+            tokio::fs::rename(path, &own).await.context("Failed to move the kept workspace")?;
+            repo_dir = own;
+            _persistent_lock = None;
+            client.log(job, "build.persistent_workspace is off, discarding the kept workspace").await?;
+        }
+    }
 
-    let foundry_config = FoundryConfig::load(&repo_dir);
+    let checkout = CheckoutOptions::new(foundry_config.as_ref(), config);
+    let finished = tokio::time::timeout(
+        std::time::Duration::from_secs(config.clone_timeout_secs),
+        finish_checkout(client, job, &remote, &repo_dir, &clone_ref, config.clone_depth, checkout),
+    )
+    .await;
+    match finished {
+        Ok(result) => result?,
+        Err(_) => {
+            let e = TimedOut { phase: "clone", secs: config.clone_timeout_secs };
+            client.log(job, &format!("⏰ {}, killed git", e)).await?;
+            return Err(e.into());
+        }
+    }
 
     if let Some(ref fc) = foundry_config {
-        client.log(job, "Found foundry.toml").await?;
-        
-        // Sync schedule configuration from foundry.toml to the server
-        if let Err(e) = client.sync_schedule(job, fc.schedule.as_ref()).await {
-            client.log(job, &format!("⚠️  Failed to sync schedule: {}", e)).await?;
-        } else if fc.schedule.is_some() {
-            let sched = fc.schedule.as_ref().unwrap();
-            client.log(job, &format!("📅 Schedule synced: {}", sched.cron)).await?;
+        match &job.service_path {
+            Some(service) => client.log(job, &format!("Found {}/foundry.toml", service)).await?,
+            None => client.log(job, "Found foundry.toml").await?,
+        }
+
+        // Synced for every job, since a monorepo service can ask for other labels than
+        // the repo root. The server then keeps the job from agents without them.
+        let missing = fc.build.missing_labels(&config.labels);
+        match client.sync_runs_on(job, &fc.build.runs_on).await {
+            Err(e) if missing.is_empty() => client.log(job, &format!("⚠️  Failed to sync runs_on: {}", e)).await?,
+            // Handing the job back unsynced would only get it claimed here again
+            Err(e) => return Err(e.context("build.runs_on needs labels this agent lacks")),
+            Ok(()) if missing.is_empty() => {
+                if !fc.build.runs_on.is_empty() {
+                    client.log(job, &format!("🏷️  Runs on agents labelled {}", fc.build.runs_on.join(", "))).await?;
+                }
+            }
+            Ok(()) => {
+                if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
+                    debug!("Failed to cleanup workspace: {}", e);
+                }
+                return Err(MissingLabels {
+                    agent_id: config.agent_id.clone(),
+                    missing: missing.into_iter().map(String::from).collect(),
+                }
+                .into());
+            }
         }
         
-        // Sync trigger configuration
-        if let Err(e) = client.sync_triggers(job, &fc.triggers).await {
-            client.log(job, &format!("⚠️  Failed to sync triggers: {}", e)).await?;
-        } else {
-            client.log(job, &format!("🎯 Triggers synced: branches={:?}", fc.triggers.branches)).await?;
+        // Schedules and triggers belong to the repo root config, not individual services
+        // or matrix combinations
+        if job.service_path.is_none() && job.matrix.is_empty() {
+            // Sync schedule configuration from foundry.toml to the server
+            if let Err(e) = client.sync_schedule(job, fc.schedule.as_ref()).await {
+                client.log(job, &format!("⚠️  Failed to sync schedule: {}", e)).await?;
+            } else if let Some(sched) = &fc.schedule {
+                client.log(job, &format!("📅 Schedule synced: {}", sched.cron)).await?;
+            }
+            
+            // Sync trigger configuration
+            if let Err(e) = client.sync_triggers(job, &fc.triggers).await {
+                client.log(job, &format!("⚠️  Failed to sync triggers: {}", e)).await?;
+            } else {
+                client.log(job, &format!("🎯 Triggers synced: branches={:?}", fc.triggers.branches)).await?;
+            }
+
+            if let Err(e) = client.sync_notify(job, &fc.notify).await {
+                client.log(job, &format!("⚠️  Failed to sync notifications: {}", e)).await?;
+            } else if fc.notify != Default::default() {
+                client.log(job, "🔔 Notification webhooks synced").await?;
+            }
+
+            if let Err(e) = client.sync_concurrency(job, &fc.concurrency).await {
+                client.log(job, &format!("⚠️  Failed to sync concurrency group: {}", e)).await?;
+            } else if let Some(group) = &fc.concurrency.group {
+                client.log(job, &format!("🚦 Concurrency group synced: {}", group)).await?;
+            }
+
+            // This build already reports under the context it was claimed with
+            if let Err(e) = client.sync_check_context(job, fc.github.context.as_deref()).await {
+                client.log(job, &format!("⚠️  Failed to sync check context: {}", e)).await?;
+            } else if fc.github.context != job.check_context {
+                let context = fc.github.context.as_deref().unwrap_or("foundry");
+                client.log(job, &format!("✔️  Later builds report to GitHub as {}", context)).await?;
+            }
+
+            // The server filters on the last synced config; this push may have changed it
+            if !fc.triggers.should_build_paths(&job.changed_files) {
+                client.log(job, "No changed files match [triggers] paths, skipping build").await?;
+                if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
+                    debug!("Failed to cleanup workspace: {}", e);
+                }
+                return Ok(None);
+            }
+
+            if let Some(monorepo) = &fc.monorepo {
+                let result = run_fan_out(client, job, &repo_dir, monorepo).await;
+                if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
+                    debug!("Failed to cleanup workspace: {}", e);
+                }
+                return result.map(|_| None);
+            }
+        }
+
+        if job.matrix.is_empty() && !fc.matrix.is_empty() {
+            let result = run_matrix(client, job, fc).await;
+            if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
+                debug!("Failed to cleanup workspace: {}", e);
+            }
+            return result.map(|_| None);
         }
         
         if fc.deploy.is_enabled() {
-            return run_deploy(client, job, &repo_dir, config, fc).await;
+            return run_deploy(client, job, &repo_dir, config, fc).await.map(|_| None);
         }
         
         if fc.has_stages() {
-            return run_stages(client, job, &repo_dir, config, fc, clone_duration_ms).await;
+            return run_stages(client, job, &repo_dir, config, fc, clone_duration_ms, started_at).await.map(Some);
         }
     }
 
     let build_start = Instant::now();
     let (image, command) = if let Some(ref fc) = foundry_config {
         let img = if fc.build.dockerfile.is_some() {
-            build_image(client, job, &repo_dir, fc).await?
+            build_image(client, job, &repo_dir, config, fc).await?
         } else {
             fc.build.image.clone()
         };
@@ -146,11 +414,53 @@ pub async fn run_job(
         .await?;
 
     let env_vars = foundry_config.as_ref().map(|fc| &fc.env);
-    let timeout_secs = foundry_config.as_ref().map(|fc| fc.build.timeout).unwrap_or(1800);
+    let secrets = match foundry_config {
+        Some(ref fc) => build_secrets(client, job, fc).await?,
+        None => Vec::new(),
+    };
+    let mut resources = build_resources(client, job, foundry_config.as_ref(), config).await?;
+    if let Some(ref fc) = foundry_config {
+        resources.extend(build_cache_args(job, fc, config).await?);
+    } else if let Some(platform) = &job.platform {
+        if let Some(warning) = platform_warning(platform, docker_arch(std::env::consts::ARCH)) {
+            client.log_raw(job.id, &job.claim_token, &warning, LogLevel::Warn).await?;
+        }
+    }
+    let timeout_secs = foundry_config
+        .as_ref()
+        .and_then(|fc| fc.build.timeout)
+        .unwrap_or(config.default_timeout_secs);
     
     client.log(job, &format!("Timeout: {} seconds", timeout_secs)).await?;
-    
-    let success = run_container(client, job, &repo_dir, &image, &command, env_vars, timeout_secs).await?;
+
+    let success_when = foundry_config.as_ref().map(|fc| fc.build.success_when.clone()).unwrap_or_default();
+    let output_pattern = success_when.output_regex()?;
+    let shell = foundry_config.as_ref().map(|fc| fc.build.shell_argv()).unwrap_or_else(|| BuildConfig::default().shell_argv());
+
+    let exit = run_container(
+        client,
+        job,
+        &repo_dir,
+        config,
+        ContainerRun {
+            image: &image,
+            command: &command,
+            env_vars,
+            timeout_secs,
+            resources: &resources,
+            log_prefix: None,
+            entrypoint: foundry_config.as_ref().and_then(|fc| fc.build.entrypoint.as_ref()),
+            shell: &shell,
+            stderr: foundry_config.as_ref().map(|fc| fc.build.stderr).unwrap_or_default(),
+            secrets: &secrets,
+            output_pattern: output_pattern.as_ref(),
+        },
+    )
+    .await?;
+    let failure = success_when.failure(exit.status.code(), exit.output_matched, &config_dir(job, &repo_dir));
+    if let (Some(code), None) = (exit.status.code().filter(|&code| code != 0), failure) {
+        client.log(job, &format!("Exit code {} passes build.success_when", code)).await?;
+    }
     
     let total_duration_ms = job_start.elapsed().as_millis() as u64;
     let metrics = JobMetrics {
@@ -162,50 +472,256 @@ pub async fn run_job(
     
     client.report_metrics(job, &metrics).await.ok();
 
+    let provenance = if failure.is_none() {
+        if let Some(ref fc) = foundry_config {
+            artifacts::upload(client, job, &config_dir(job, &repo_dir), &fc.artifacts).await?;
+        }
+        Some(record_provenance(client, job, config, &repo_dir, &workspace, &image, started_at).await)
+    } else {
+        None
+    };
+
     if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
         debug!("Failed to cleanup workspace: {}", e);
     }
 
-    if success {
-        Ok(())
+    if let Some(provenance) = provenance {
+        Ok(Some(provenance))
     } else {
-        anyhow::bail!("Container exited with non-zero status")
+        Err(BuildFailed {
+            reason: failure.unwrap_or("Container exited with non-zero status"),
+            exit_code: exit.status.code(),
+        }
+        .into())
+    }
+}
+
+/// Queue a child job for every monorepo service touched by this push
+async fn run_fan_out(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    repo_dir: &Path,
+    monorepo: &MonorepoConfig,
+) -> Result<()> {
+    let services = discover_services(repo_dir, monorepo);
+    if services.is_empty() {
+        client.log(job, "⚠️  Monorepo config found but no service directories contain a foundry.toml").await?;
+        return Ok(());
+    }
+
+    let affected = monorepo.affected_services(&services, &job.changed_files);
+    if affected.is_empty() {
+        client.log(job, "📦 No services affected by this change, nothing to build").await?;
+        return Ok(());
+    }
+
+    client
+        .log(job, &format!("📦 Building {} of {} services: {}", affected.len(), services.len(), affected.join(", ")))
+        .await?;
+
+    client.fan_out(job, &affected).await
+}
+
+/// Queue a child job for every combination of the `[matrix]` axes
+async fn run_matrix(client: &ServerClient, job: &ClaimedJob, fc: &FoundryConfig) -> Result<()> {
+    let entries = matrix_entries(&fc.matrix)?;
+    client
+        .log(
+            job,
+            &format!(
+                "🧮 Building {} matrix combinations: {}",
+                entries.len(),
+                entries.iter().map(|e| e.key.as_str()).collect::<Vec<_>>().join(" | ")
+            ),
+        )
+        .await?;
+
+    client.fan_out_matrix(job, entries).await
+}
+
+/// Find service directories (relative to the repo root) that match the monorepo
+/// globs and contain their own foundry.toml. Symlinks are skipped so an untrusted
+/// checkout can't point the walk outside the repo or into a loop.
+fn discover_services(repo_dir: &Path, monorepo: &MonorepoConfig) -> Vec<String> {
+    let mut services = Vec::new();
+    let mut pending = vec![repo_dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let path = entry.path();
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') || name == "node_modules" || name == "target" {
+                continue;
+            }
+            if let Ok(rel) = path.strip_prefix(repo_dir) {
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                if monorepo.is_service_dir(&rel) && path.join("foundry.toml").exists() {
+                    services.push(rel);
+                }
+            }
+            pending.push(path);
+        }
+    }
+
+    services.sort();
+    services
+}
+
+/// Resolve once the server reports the job as cancelled
+pub async fn wait_for_cancel(client: &ServerClient, job: &ClaimedJob, poll_interval_secs: u64) {
+    let interval = std::time::Duration::from_secs(poll_interval_secs.max(1));
+    loop {
+        tokio::time::sleep(interval).await;
+        match client.job_status(job.id).await {
+            Ok(foundry_core::JobStatus::Cancelled) => return,
+            Ok(_) => {}
+            Err(e) => debug!("Failed to check status of job {}: {}", job.id, e),
+        }
+    }
+}
+
+/// Heartbeat `job` every `interval` until the server says it is no longer ours, so a
+/// job whose agent dies gets requeued. Network errors are retried on the next beat.
+pub async fn keep_alive(client: &ServerClient, job: &ClaimedJob, interval: std::time::Duration) {
+    loop {
+        match client.heartbeat(job).await {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => debug!("Failed to heartbeat job {}: {}", job.id, e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Check that the Docker daemon answers, returning why it doesn't
+pub async fn check_docker() -> std::result::Result<(), String> {
+    let info = Command::new("docker")
+        .args(["info", "--format", "{{.ServerVersion}}"])
+        .kill_on_drop(true)
+        .output();
+
+    match tokio::time::timeout(std::time::Duration::from_secs(10), info).await {
+        Ok(Ok(output)) if output.status.success() => Ok(()),
+        Ok(Ok(output)) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(stderr.trim().lines().last().unwrap_or("docker info failed").to_string())
+        }
+        Ok(Err(e)) => Err(format!("Failed to run docker: {}", e)),
+        Err(_) => Err("docker info did not answer within 10 seconds".to_string()),
+    }
+}
+
+/// Kill every container started for a job and remove its workspace
+pub async fn abort_job(job: &ClaimedJob, config: &Config) {
+    let output = Command::new("docker")
+        .args(["ps", "-q", "--filter", &format!("label=foundry.job={}", job.id)])
+        .output()
+        .await;
+
+    match output {
+        Ok(output) => {
+            let ids = String::from_utf8_lossy(&output.stdout);
+            for id in ids.split_whitespace() {
+                info!("Killing container {} for job {}", id, job.id);
+                let _ = Command::new("docker").args(["kill", id]).output().await;
+            }
+        }
+        Err(e) => debug!("Failed to list containers for job {}: {}", job.id, e),
+    }
+
+    let workspace = workspace_path(&config.workspace_dir, job.id, &job.claim_token);
+    if let Err(e) = tokio::fs::remove_dir_all(&workspace).await {
+        debug!("Failed to cleanup workspace: {}", e);
+    }
+}
+
+/// Workspace for one claim of a job. The claim token is part of the name so a job
+/// reclaimed by another agent on a shared volume doesn't reuse the same directory.
+fn workspace_path(workspace_dir: &str, job_id: i64, claim_token: &Uuid) -> PathBuf {
+    PathBuf::from(workspace_dir).join(format!(
+        "job-{}-{}",
+        job_id,
+        &claim_token.simple().to_string()[..8]
+    ))
+}
+
+/// Job id from a workspace directory name (`job-{id}-{token}`, or the older `job-{id}`)
+fn workspace_job_id(name: &str) -> Option<i64> {
+    let rest = name.strip_prefix("job-")?;
+    rest.split('-').next()?.parse().ok()
+}
+
+/// Remove workspaces left behind by a crashed agent. Only jobs the server reports as
+/// finished are removed, since other agents may share the workspace volume.
+pub async fn cleanup_orphaned_workspaces(client: &ServerClient, config: &Config) {
+    let mut entries = match tokio::fs::read_dir(&config.workspace_dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Failed to read workspace dir: {}", e);
+            return;
+        }
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(job_id) = workspace_job_id(&name) else {
+            continue;
+        };
+
+        match client.job_status(job_id).await {
+            Ok(JobStatus::Queued | JobStatus::Running) => {}
+            Ok(_) => {
+                info!("Removing orphaned workspace {}", name);
+                if let Err(e) = tokio::fs::remove_dir_all(entry.path()).await {
+                    debug!("Failed to remove orphaned workspace {}: {}", name, e);
+                }
+            }
+            Err(e) => debug!("Keeping workspace {}: {}", name, e),
+        }
     }
 }
 
 async fn run_stages(
     client: &ServerClient,
     job: &ClaimedJob,
-    repo_dir: &PathBuf,
+    repo_dir: &Path,
     config: &Config,
     fc: &FoundryConfig,
     clone_duration_ms: u64,
-) -> Result<()> {
+    started_at: chrono::DateTime<chrono::Utc>,
+) -> Result<Provenance> {
     let job_start = Instant::now();
+    let mut resources = build_resources(client, job, Some(fc), config).await?;
+    resources.extend(build_cache_args(job, fc, config).await?);
+    let secrets = build_secrets(client, job, fc).await?;
     let mut stage_metrics: Vec<StageMetrics> = vec![];
     let mut any_failed = false;
+    let mut failed_exit_code = None;
     
     let image = if fc.build.dockerfile.is_some() {
-        build_image(client, job, repo_dir, fc).await?
+        build_image(client, job, repo_dir, config, fc).await?
     } else {
         fc.build.image.clone()
     };
     
     client.log(job, &format!("📋 Running {} stages", fc.stages.len())).await?;
+
+    let shell = fc.build.shell_argv();
+
+    let is_pr = job.git_ref.starts_with("refs/pull/");
     
     for (i, stage) in fc.stages.iter().enumerate() {
         let stage_image = stage.image.as_ref().unwrap_or(&image);
         let stage_start = Instant::now();
         
-        let should_run = match &stage.condition {
-            Some(foundry_core::config::StageCondition::Always) => true,
-            Some(foundry_core::config::StageCondition::OnFailure) => any_failed,
-            Some(foundry_core::config::StageCondition::OnSuccess) | None => !any_failed,
-            Some(foundry_core::config::StageCondition::OnPr) => job.git_ref.starts_with("refs/pull/"),
-            Some(foundry_core::config::StageCondition::OnPush) => !job.git_ref.starts_with("refs/pull/"),
-        };
-        
-        if !should_run {
+        if !stage.should_run(is_pr, any_failed) {
             client.log(job, &format!("⏭️  Stage {}: {} (skipped)", i + 1, stage.name)).await?;
             stage_metrics.push(StageMetrics {
                 name: stage.name.clone(),
@@ -225,16 +741,29 @@ async fn run_stages(
             client,
             job,
             repo_dir,
-            stage_image,
-            &stage.command,
-            Some(&stage_env),
-            stage.timeout,
-        ).await;
+            config,
+            ContainerRun {
+                image: stage_image,
+                command: &stage.command,
+                env_vars: Some(&stage_env),
+                timeout_secs: stage.timeout,
+                resources: &resources,
+                log_prefix: Some(&stage.name),
+                // The override is for the build image; stages with their own image keep theirs
+                entrypoint: if stage.image.is_none() { fc.build.entrypoint.as_ref() } else { None },
+                shell: &shell,
+                stderr: fc.build.stderr,
+                secrets: &secrets,
+                output_pattern: None,
+            },
+        )
+        .await
+        .map(|exit| exit.status);
         
         let duration_ms = stage_start.elapsed().as_millis() as u64;
         
         match result {
-            Ok(true) => {
+            Ok(status) if status.success() => {
                 client.log(job, &format!("✅ Stage {} complete ({} ms)", stage.name, duration_ms)).await?;
                 stage_metrics.push(StageMetrics {
                     name: stage.name.clone(),
@@ -243,20 +772,23 @@ async fn run_stages(
                     exit_code: Some(0),
                 });
             }
-            Ok(false) | Err(_) => {
+            _ => {
+                if let Err(e) = &result {
+                    client.log(job, &format!("[{}] ERROR: {}", stage.name, e)).await?;
+                }
                 client.log(job, &format!("❌ Stage {} failed ({} ms)", stage.name, duration_ms)).await?;
+                let exit_code = result.as_ref().ok().and_then(|s| s.code());
                 stage_metrics.push(StageMetrics {
                     name: stage.name.clone(),
                     status: "failed".to_string(),
                     duration_ms,
-                    exit_code: Some(1),
+                    exit_code,
                 });
                 
-                if !stage.allow_failure {
+                // Later stages still get a chance to run if they are `always`/`on_failure`
+                if !stage.allow_failure && !any_failed {
                     any_failed = true;
-                    if stage.condition.is_none() || stage.condition == Some(foundry_core::config::StageCondition::OnSuccess) {
-                        break;
-                    }
+                    failed_exit_code = exit_code;
                 }
             }
         }
@@ -273,10 +805,36 @@ async fn run_stages(
     client.report_metrics(job, &metrics).await.ok();
     
     if any_failed {
-        anyhow::bail!("Pipeline failed")
+        return Err(BuildFailed {
+            reason: "Pipeline failed",
+            exit_code: failed_exit_code,
+        }
+        .into());
     }
-    
-    Ok(())
+
+    artifacts::upload(client, job, &config_dir(job, repo_dir), &fc.artifacts).await?;
+
+    let workspace = workspace_path(&config.workspace_dir, job.id, &job.claim_token);
+    Ok(record_provenance(client, job, config, repo_dir, &workspace, &image, started_at).await)
+}
+
+/// Collect provenance for a successful build and upload it as `provenance.json`.
+/// A failed upload is logged but doesn't fail the build.
+async fn record_provenance(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    config: &Config,
+    repo_dir: &Path,
+    workspace: &Path,
+    image: &str,
+    started_at: chrono::DateTime<chrono::Utc>,
+) -> Provenance {
+    let provenance = Provenance::collect(&config.agent_id, job, repo_dir, image, started_at).await;
+    if let Err(e) = provenance.publish(client, job, workspace).await {
+        warn!("Failed to upload provenance for job {}: {}", job.id, e);
+        let _ = client.log(job, &format!("⚠️  Failed to upload provenance: {}", e)).await;
+    }
+    provenance
 }
 
 async fn run_self_deploy(
@@ -323,22 +881,22 @@ async fn run_self_deploy(
 
     let job_id = job.id;
     let client_clone = client.clone();
-    let claim_token = job.claim_token.clone();
+    let claim_token = job.claim_token;
 
     let stdout_handle = tokio::spawn(async move {
-        let mut reader = BufReader::new(stdout).lines();
-        while let Ok(Some(line)) = reader.next_line().await {
-            let _ = client_clone.log_raw(job_id, &claim_token, &line).await;
+        let mut reader = OutputLines::new(stdout);
+        while let Some(line) = reader.next_line().await {
+            let _ = client_clone.log_raw(job_id, &claim_token, &line, LogLevel::Info).await;
         }
     });
 
     let client_clone2 = client.clone();
-    let claim_token2 = job.claim_token.clone();
+    let claim_token2 = job.claim_token;
 
     let stderr_handle = tokio::spawn(async move {
-        let mut reader = BufReader::new(stderr).lines();
-        while let Ok(Some(line)) = reader.next_line().await {
-            let _ = client_clone2.log_raw(job_id, &claim_token2, &format!("STDERR: {}", line)).await;
+        let mut reader = OutputLines::new(stderr);
+        while let Some(line) = reader.next_line().await {
+            let _ = client_clone2.log_raw(job_id, &claim_token2, &format!("STDERR: {}", line), LogLevel::Error).await;
         }
     });
 
@@ -356,102 +914,778 @@ async fn run_self_deploy(
     }
 }
 
-async fn clone_repo(url: &str, safe_url: &str, sha_or_branch: &str, dest: &PathBuf, clone_by_branch: bool) -> Result<()> {
-    let mut args = vec!["clone", "--depth", "50"];
-    
-    // If cloning by branch (scheduled jobs), specify the branch explicitly
-    if clone_by_branch {
-        args.push("-b");
-        args.push(sha_or_branch);
-    }
-    
-    args.push(url);
-    
-    let output = Command::new("git")
-        .args(&args)
-        .arg(dest)
-        .env("GIT_TERMINAL_PROMPT", "0")
-        .output()
-        .await
-        .context("Failed to run git clone")?;
+/// Where a job's checkout came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CloneSource {
+    Fresh,
+    MirrorCreated,
+    MirrorReused,
+    /// Updated in place from a `build.persistent_workspace` checkout
+    Persistent,
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let sanitized = sanitize_git_error(&stderr, url, safe_url);
-        anyhow::bail!("git clone failed: {}", sanitized);
+/// Where git fetches a repo from
+struct Remote<'a> {
+    /// May carry an access token, so it is never logged
+    url: &'a str,
+    /// `url` without credentials, for errors and the checkout's `origin`
+    safe_url: &'a str,
+    /// `GIT_SSH_COMMAND` when cloning over SSH with a deploy key
+    ssh_command: Option<&'a str>,
+}
+
+impl Remote<'_> {
+    /// Environment for git commands that talk to the remote
+    fn env(&self) -> Option<(&'static str, &str)> {
+        self.ssh_command.map(|command| ("GIT_SSH_COMMAND", command))
     }
+}
 
-    // For scheduled jobs, we're already on the right branch after clone
-    // For regular jobs, checkout the specific SHA
-    if !clone_by_branch {
-        let output = Command::new("git")
-            .args(["checkout", sha_or_branch])
-            .current_dir(dest)
-            .output()
+/// Clone and check out the job's commit, through `mirror` when one is given. The
+/// clone and checkout share one `timeout_secs` budget; git is killed if it runs past it.
+/// `depth` applies to fresh clones; 0 fetches all history. Mirror clones always have it all.
+async fn clone_repo(
+    remote: &Remote<'_>,
+    sha_or_branch: &str,
+    dest: &Path,
+    clone_by_branch: bool,
+    mirror: Option<&Path>,
+    timeout_secs: u64,
+    depth: u32,
+) -> Result<CloneSource> {
+    let clone = async {
+        if let Some(mirror) = mirror {
+            match clone_from_mirror(mirror, remote, sha_or_branch, dest, clone_by_branch).await {
+                Ok(source) => return Ok(source),
+                Err(e) => {
+                    // Most likely a corrupt mirror; start it over on the next build
+                    warn!("Clone via mirror {} failed, falling back to a fresh clone: {}", mirror.display(), e);
+                    let _ = tokio::fs::remove_dir_all(mirror).await;
+                }
+            }
+        }
+        clone_and_checkout(remote, sha_or_branch, dest, clone_by_branch, depth)
             .await
-            .context("Failed to run git checkout")?;
+            .map(|_| CloneSource::Fresh)
+    };
+    // Dropping the future on timeout drops the git child, which kill_on_drop terminates
+    match tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), clone).await {
+        Ok(result) => result,
+        Err(_) => Err(TimedOut { phase: "clone", secs: timeout_secs }.into()),
+    }
+}
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            anyhow::bail!("git checkout failed: {}", stderr);
-        }
+/// Repo owner or name as a single path component. GitHub names are already safe;
+/// this just keeps `..` from escaping the directory it is joined onto.
+fn path_component(s: &str) -> String {
+    let name: String = s
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with('.') {
+        format!("_{}", name)
+    } else {
+        name
     }
+}
 
-    Ok(())
+/// Bare mirror of a repo under the agent's mirror cache
+fn mirror_path(mirror_dir: &str, owner: &str, name: &str) -> PathBuf {
+    PathBuf::from(mirror_dir)
+        .join(path_component(owner))
+        .join(format!("{}.git", path_component(name)))
 }
 
-fn sanitize_git_error(stderr: &str, secret_url: &str, safe_url: &str) -> String {
-    stderr.replace(secret_url, safe_url)
+/// Checkout kept between a repo's builds with `build.persistent_workspace`. It sits
+/// beside the job workspaces, whose cleanup only touches `job-*` directories.
+fn persistent_path(workspace_dir: &str, owner: &str, name: &str) -> PathBuf {
+    PathBuf::from(workspace_dir)
+        .join("persistent")
+        .join(path_component(owner))
+        .join(path_component(name))
 }
 
-async fn build_image(
-    client: &ServerClient,
-    job: &ClaimedJob,
-    repo_dir: &PathBuf,
-    fc: &FoundryConfig,
-) -> Result<String> {
-    let dockerfile = fc.build.dockerfile.as_deref().unwrap_or("Dockerfile");
-    let context = fc.build.context.as_deref().unwrap_or(".");
-    let image_tag = format!("foundry-{}-{}:latest", job.repo_name, job.id);
+/// Take a kept checkout's lock without waiting; `None` if another build holds it
+async fn try_lock_checkout(checkout: &Path) -> Result<Option<std::fs::File>> {
+    use fs2::FileExt;
 
-    client.log(job, &format!("Building image from {}", dockerfile)).await?;
+    let lock_path = PathBuf::from(format!("{}.lock", checkout.display()));
+    if let Some(parent) = lock_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(file)),
+            Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to lock {}", lock_path.display())),
+        }
+    })
+    .await?
+}
 
-    let context_path = repo_dir.join(context);
+/// Bring a kept checkout to `rev`: fetch it, force the working tree to it and remove
+/// untracked files. Ignored files, such as build output, are left for the build to reuse.
+async fn update_checkout(remote: &Remote<'_>, rev: &str, dir: &Path, by_branch: bool, depth: u32) -> Result<()> {
+    let depth_flag = format!("--depth={}", depth);
+    let mut fetch = vec!["fetch", "--quiet"];
+    // Keep a shallow checkout shallow; one that was deepened fetches everything
+    if depth > 0 && dir.join(".git").join("shallow").exists() {
+        fetch.push(&depth_flag);
+    }
+    fetch.extend([remote.url, rev]);
+    retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || git(dir, &fetch, remote)).await?;
 
-    let output = Command::new("docker")
-        .args([
-            "build",
-            "-t", &image_tag,
-            "-f", &repo_dir.join(dockerfile).to_string_lossy(),
-            &context_path.to_string_lossy(),
-        ])
-        .current_dir(repo_dir)
-        .output()
-        .await
-        .context("Failed to run docker build")?;
+    let target = if by_branch { "FETCH_HEAD" } else { rev };
+    git(dir, &["checkout", "--force", "--quiet", "--detach", target], remote).await?;
+    git(dir, &["clean", "-ffd", "--quiet"], remote).await
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        client.log(job, &format!("Build failed: {}", stderr)).await?;
+/// Move a fresh checkout to `path` to keep it, replacing whatever was there. The
+/// origin URL may carry a short-lived token, so it is reset to the plain one.
+async fn keep_checkout(checkout: &Path, path: &Path, remote: &Remote<'_>) -> Result<()> {
+    if path.exists() {
+        tokio::fs::remove_dir_all(path).await.context("Failed to remove the old kept workspace")?;
+    }
+    tokio::fs::rename(checkout, path).await.context("Failed to keep the workspace")?;
+    git(path, &["remote", "set-url", "origin", remote.safe_url], remote).await
+}
+
+/// Directory holding a repo's `build.cache` entries. Caches are per repo so one
+/// project's builds can never read or poison another's.
+fn repo_cache_dir(cache_dir: &str, owner: &str, name: &str) -> PathBuf {
+    PathBuf::from(cache_dir).join(path_component(owner)).join(path_component(name))
+}
+
+/// `-v` flags for the repo's `build.cache` entries, creating the host directories
+async fn build_cache_args(job: &ClaimedJob, fc: &FoundryConfig, config: &Config) -> Result<Vec<String>> {
+    let repo_dir = repo_cache_dir(&config.cache_dir, &job.repo_owner, &job.repo_name);
+    let args = cache_args(&fc.build.cache, &repo_dir)?;
+    for key in fc.build.cache.keys() {
+        tokio::fs::create_dir_all(repo_dir.join(key))
+            .await
+            .with_context(|| format!("Failed to create cache directory for '{}'", key))?;
+    }
+    Ok(args)
+}
+
+/// The `[secrets]` the repo asked for, as (name, value). Names the server didn't
+/// send are reported in the log and left unset.
+async fn build_secrets(client: &ServerClient, job: &ClaimedJob, fc: &FoundryConfig) -> Result<Vec<(String, String)>> {
+    if fc.secrets.names.is_empty() {
+        return Ok(Vec::new());
+    }
+    if job.git_ref.starts_with("refs/pull/") {
+        client.log(job, "⚠️ Secrets are not passed to pull request builds").await?;
+        return Ok(Vec::new());
+    }
+
+    let mut secrets = Vec::new();
+    for name in &fc.secrets.names {
+        match job.secrets.0.get(name) {
+            Some(value) => secrets.push((name.clone(), value.clone())),
+            None => client.log(job, &format!("⚠️ Secret {} is not set for this repo", name)).await?,
+        }
+    }
+    Ok(secrets)
+}
+
+/// Bring the repo's mirror up to date and clone the job from it. Local clones
+/// hardlink objects, so only new commits cross the network.
+///
+/// The mirror is held under an exclusive file lock for the whole update and clone,
+/// so agents sharing the cache never fetch into a mirror another job is cloning from.
+async fn clone_from_mirror(
+    mirror: &Path,
+    remote: &Remote<'_>,
+    sha_or_branch: &str,
+    dest: &Path,
+    clone_by_branch: bool,
+) -> Result<CloneSource> {
+    let _lock = lock_mirror(mirror).await?;
+
+    let source = if mirror.join("HEAD").exists() {
+        CloneSource::MirrorReused
+    } else {
+        tokio::fs::create_dir_all(mirror).await?;
+        git(mirror, &["init", "--bare", "--quiet"], remote).await?;
+        CloneSource::MirrorCreated
+    };
+
+    // Fetch by URL rather than storing a remote, since the URL carries a short-lived token
+    let fetch = ["fetch", "--prune", "--quiet", remote.url, "+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+    retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || git(mirror, &fetch, remote)).await?;
+
+    // Pull request heads (and force-pushed commits) aren't on any branch
+    if !clone_by_branch && git(mirror, &["cat-file", "-e", &format!("{}^{{commit}}", sha_or_branch)], remote).await.is_err() {
+        let refspec = format!("+{}:refs/foundry/{}", sha_or_branch, sha_or_branch);
+        let fetch = ["fetch", "--quiet", remote.url, &refspec];
+        retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || git(mirror, &fetch, remote)).await?;
+    }
+
+    if dest.exists() {
+        tokio::fs::remove_dir_all(dest).await?;
+    }
+    let mirror_str = mirror.to_string_lossy();
+    let dest_str = dest.to_string_lossy();
+    let mut args = vec!["clone", "--quiet"];
+    if clone_by_branch {
+        args.extend(["-b", sha_or_branch]);
+    } else {
+        args.push("--no-checkout");
+    }
+    args.extend([mirror_str.as_ref(), dest_str.as_ref()]);
+    git(Path::new("."), &args, remote).await?;
+
+    if !clone_by_branch {
+        git(dest, &["checkout", "--quiet", sha_or_branch], remote).await?;
+    }
+    // Builds that inspect their remote should see the repo, not the mirror
+    git(dest, &["remote", "set-url", "origin", remote.safe_url], remote).await?;
+
+    Ok(source)
+}
+
+/// Run a git command in `dir`, failing with its (credential-scrubbed) stderr
+async fn git(dir: &Path, args: &[&str], remote: &Remote<'_>) -> Result<()> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .envs(remote.env())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run git {}", args[0]))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(GitFailed {
+            step: match args[0] {
+                "fetch" => "fetch",
+                "clone" => "clone",
+                "checkout" => "checkout",
+                "submodule" => "submodule update",
+                _ => "command",
+            },
+            stderr: sanitize_git_error(&stderr, remote.url, remote.safe_url),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// History and submodules a job needs, from `foundry.toml` or the agent's defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CheckoutOptions {
+    /// 0 for all history
+    depth: u32,
+    submodules: bool,
+}
+
+impl CheckoutOptions {
+    fn new(fc: Option<&FoundryConfig>, config: &Config) -> Self {
+        Self {
+            depth: fc.and_then(|fc| fc.build.clone_depth).unwrap_or(config.clone_depth),
+            submodules: fc.and_then(|fc| fc.build.submodules).unwrap_or(config.clone_submodules),
+        }
+    }
+
+    /// The `git fetch` flag that extends a clone made `cloned_depth` deep, if it's too shallow
+    fn deepen_flag(&self, cloned_depth: u32) -> Option<String> {
+        match (cloned_depth, self.depth) {
+            (0, _) => None,
+            (_, 0) => Some("--unshallow".to_string()),
+            (cloned, wanted) if wanted > cloned => Some(format!("--depth={}", wanted)),
+            _ => None,
+        }
+    }
+}
+
+/// Bring a fresh checkout up to what `foundry.toml` asks for, which isn't known until
+/// after the clone: more history than the agent fetched, and submodules
+async fn finish_checkout(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    remote: &Remote<'_>,
+    repo_dir: &Path,
+    rev: &str,
+    cloned_depth: u32,
+    options: CheckoutOptions,
+) -> Result<()> {
+    // Clones from a mirror are never shallow
+    let shallow = repo_dir.join(".git").join("shallow").exists();
+    if let Some(flag) = options.deepen_flag(cloned_depth).filter(|_| shallow) {
+        match options.depth {
+            0 => client.log(job, "Fetching full history").await?,
+            depth => client.log(job, &format!("Fetching {} commits of history", depth)).await?,
+        }
+        let fetch = ["fetch", "--quiet", &flag, remote.url, rev];
+        retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || git(repo_dir, &fetch, remote)).await?;
+    }
+
+    if options.submodules && repo_dir.join(".gitmodules").exists() {
+        client.log(job, "Checking out submodules").await?;
+        let update = ["submodule", "update", "--init", "--recursive", "--quiet"];
+        retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || git(repo_dir, &update, remote)).await?;
+    }
+    Ok(())
+}
+
+/// Take the mirror's lock file, waiting for any other holder
+async fn lock_mirror(mirror: &Path) -> Result<std::fs::File> {
+    use fs2::FileExt;
+
+    let lock_path = mirror.with_extension("lock");
+    if let Some(parent) = lock_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+        file.lock_exclusive()
+            .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+        Ok(file)
+    })
+    .await?
+}
+
+async fn clone_and_checkout(
+    remote: &Remote<'_>,
+    sha_or_branch: &str,
+    dest: &Path,
+    clone_by_branch: bool,
+    depth: u32,
+) -> Result<()> {
+    let depth = depth.to_string();
+    let mut args = vec!["clone"];
+    if depth != "0" {
+        args.extend(["--depth", &depth]);
+    }
+    
+    // If cloning by branch (scheduled jobs), specify the branch explicitly
+    if clone_by_branch {
+        args.push("-b");
+        args.push(sha_or_branch);
+    }
+    
+    args.push(remote.url);
+    
+    retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || async {
+        // A failed attempt can leave a partial clone that git refuses to clone over
+        if dest.exists() {
+            tokio::fs::remove_dir_all(dest).await.ok();
+        }
+
+        let output = Command::new("git")
+            .args(&args)
+            .arg(dest)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .envs(remote.env())
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run git clone")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(GitFailed {
+                step: "clone",
+                stderr: sanitize_git_error(&stderr, remote.url, remote.safe_url),
+            }
+            .into());
+        }
+        Ok(())
+    })
+    .await?;
+
+    // For scheduled jobs, we're already on the right branch after clone
+    // For regular jobs, checkout the specific SHA
+    if !clone_by_branch {
+        retry_git(GIT_ATTEMPTS, GIT_RETRY_DELAY, || async {
+            let output = Command::new("git")
+                .args(["checkout", sha_or_branch])
+                .current_dir(dest)
+                .kill_on_drop(true)
+                .output()
+                .await
+                .context("Failed to run git checkout")?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(GitFailed {
+                    step: "checkout",
+                    stderr: stderr.into_owned(),
+                }
+                .into());
+            }
+            Ok(())
+        })
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Attempts at each git step before a transient failure fails the job
+const GIT_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry; doubled for each one after
+const GIT_RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// A git command that exited unsuccessfully
+#[derive(Debug)]
+struct GitFailed {
+    step: &'static str,
+    /// git's stderr, with credentials already scrubbed
+    stderr: String,
+}
+
+impl std::fmt::Display for GitFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "git {} failed: {}", self.step, self.stderr)
+    }
+}
+
+impl std::error::Error for GitFailed {}
+
+impl GitFailed {
+    /// Network and server hiccups are worth retrying. Anything else (bad credentials,
+    /// missing repo, unknown revision) fails the same way every time.
+    fn is_transient(&self) -> bool {
+        const FATAL: &[&str] = &[
+            "Authentication failed",
+            "could not read Username",
+            "Permission denied",
+            "Repository not found",
+            "returned error: 401",
+            "returned error: 403",
+            "returned error: 404",
+        ];
+        const TRANSIENT: &[&str] = &[
+            "Could not resolve host",
+            "Temporary failure in name resolution",
+            "Failed to connect",
+            "Connection timed out",
+            "Connection reset",
+            "Connection refused",
+            "Operation timed out",
+            "remote end hung up unexpectedly",
+            "early EOF",
+            "RPC failed",
+            "The requested URL returned error: 5",
+            "unable to access",
+        ];
+
+        !FATAL.iter().any(|m| self.stderr.contains(m)) && TRANSIENT.iter().any(|m| self.stderr.contains(m))
+    }
+}
+
+/// Run a git step, retrying with exponential backoff while it fails transiently
+async fn retry_git<F, Fut>(attempts: u32, delay: std::time::Duration, mut step: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut attempt = 1;
+    loop {
+        match step().await {
+            Err(e)
+                if attempt < attempts
+                    && e.downcast_ref::<GitFailed>().is_some_and(GitFailed::is_transient) =>
+            {
+                warn!("{}; retrying ({}/{})", e.to_string().trim_end(), attempt + 1, attempts);
+                tokio::time::sleep(delay * 2u32.pow(attempt - 1)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn sanitize_git_error(stderr: &str, secret_url: &str, safe_url: &str) -> String {
+    stderr.replace(secret_url, safe_url)
+}
+
+async fn build_image(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    repo_dir: &Path,
+    config: &Config,
+    fc: &FoundryConfig,
+) -> Result<String> {
+    let dockerfile = fc.build.dockerfile.as_deref().unwrap_or("Dockerfile");
+    let context = fc.build.context.as_deref().unwrap_or(".");
+    let image_tag = format!("foundry-{}-{}:latest", job.repo_name, job.id);
+
+    client.log(job, &format!("Building image from {}", dockerfile)).await?;
+
+    let repo_dir = config_dir(job, repo_dir);
+    let context_path = repo_dir.join(context);
+
+    // A missing Dockerfile is reported by docker build itself
+    let base_images = tokio::fs::read_to_string(repo_dir.join(dockerfile))
+        .await
+        .map(|content| dockerfile_base_images(&content))
+        .unwrap_or_default();
+    let base_images: Vec<&str> = base_images.iter().map(String::as_str).collect();
+    let docker_config = registry_login(client, job, config, &base_images).await?;
+
+    let mut cmd = Command::new("docker");
+    if let Some(dir) = &docker_config {
+        cmd.env("DOCKER_CONFIG", dir);
+    }
+    let output = cmd
+        .args(["build", "-t", &image_tag])
+        .args(foundry_labels(job, ContainerKind::Build))
+        .args([
+            "-f", &repo_dir.join(dockerfile).to_string_lossy(),
+            &context_path.to_string_lossy(),
+        ])
+        .current_dir(&repo_dir)
+        .output()
+        .await
+        .context("Failed to run docker build")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        client.log(job, &format!("Build failed: {}", stderr)).await?;
         anyhow::bail!("Docker build failed");
     }
 
     client.log(job, "Image built successfully").await?;
+
+    if let Some(registry) = &fc.build.registry {
+        push_image(client, job, config, registry, &image_tag).await?;
+    }
+
     Ok(image_tag)
 }
 
+/// Push a built image to `build.registry` under its configured tag and the short sha.
+/// Credentials go into a per-job docker config that is removed afterwards.
+async fn push_image(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    config: &Config,
+    registry: &RegistryConfig,
+    image_tag: &str,
+) -> Result<()> {
+    let refs = registry.image_refs(&job.git_sha)?;
+    let docker_config = workspace_path(&config.workspace_dir, job.id, &job.claim_token).join("docker-config");
+    tokio::fs::create_dir_all(&docker_config)
+        .await
+        .context("Failed to create docker config directory")?;
+
+    let result = async {
+        let host = registry.host();
+        if let Some(password) = &config.registry_password {
+            let Some(user) = config.registry_user.as_deref() else {
+                anyhow::bail!("FOUNDRY_REGISTRY_PASSWORD is set without FOUNDRY_REGISTRY_USER");
+            };
+            docker_login(client, job, &docker_config, host, user, password).await?;
+        } else if let Some(auth) = config.registry_auth_for(if host.is_empty() { "docker.io" } else { host }) {
+            docker_login(client, job, &docker_config, host, &auth.user, &auth.token).await?;
+        }
+
+        for image_ref in &refs {
+            client.log(job, &format!("📤 Pushing {}", image_ref)).await?;
+
+            let status = Command::new("docker")
+                .args(["tag", image_tag, image_ref])
+                .status()
+                .await
+                .context("Failed to run docker tag")?;
+            if !status.success() {
+                anyhow::bail!("docker tag {} failed", image_ref);
+            }
+
+            let mut push = Command::new("docker");
+            push.env("DOCKER_CONFIG", &docker_config).args(["push", image_ref]);
+            if !run_streamed(client, job, push, None).await?.success() {
+                anyhow::bail!("docker push {} failed", image_ref);
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = tokio::fs::remove_dir_all(&docker_config).await {
+        debug!("Failed to remove docker config: {}", e);
+    }
+
+    match &result {
+        Ok(()) => client.log(job, &format!("✅ Pushed {}", refs.join(", "))).await?,
+        Err(e) => client.log(job, &format!("❌ Image push failed: {}", e)).await?,
+    }
+    result
+}
+
+/// `docker login` into `docker_config`, with an empty or `docker.io` host meaning Docker
+/// Hub. The password is redacted from the job log before docker can echo it.
+async fn docker_login(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    docker_config: &Path,
+    host: &str,
+    user: &str,
+    password: &str,
+) -> Result<()> {
+    client.add_redaction(job.id, password);
+    let host = if host == "docker.io" { "" } else { host };
+    let name = if host.is_empty() { "Docker Hub" } else { host };
+    client.log(job, &format!("🔑 Logging in to {}", name)).await?;
+
+    let mut login = Command::new("docker");
+    login.env("DOCKER_CONFIG", docker_config)
+        .args(["login", "-u", user, "--password-stdin"]);
+    if !host.is_empty() {
+        login.arg(host);
+    }
+    if !run_streamed(client, job, login, Some(password)).await?.success() {
+        anyhow::bail!("docker login to {} failed", name);
+    }
+    Ok(())
+}
+
+/// Log in to the registries of `images` that `FOUNDRY_REGISTRY_AUTH` has credentials
+/// for, returning the docker config directory to pull them with. `None` when no
+/// image needs credentials, so public pulls keep the agent's own docker config.
+/// The directory sits beside the repo checkout, outside anything mounted into builds.
+async fn registry_login(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    config: &Config,
+    images: &[&str],
+) -> Result<Option<PathBuf>> {
+    let mut registries: Vec<&str> = images.iter().map(|image| image_registry(image)).collect();
+    registries.sort_unstable();
+    registries.dedup();
+    let logins: Vec<_> = registries.into_iter().filter_map(|r| config.registry_auth_for(r)).collect();
+    if logins.is_empty() {
+        return Ok(None);
+    }
+
+    let docker_config = workspace_path(&config.workspace_dir, job.id, &job.claim_token).join("docker-auth");
+    tokio::fs::create_dir_all(&docker_config)
+        .await
+        .context("Failed to create docker config directory")?;
+    for auth in logins {
+        docker_login(client, job, &docker_config, &auth.registry, &auth.user, &auth.token).await?;
+    }
+    Ok(Some(docker_config))
+}
+
+/// Registry host an image is pulled from. Like docker, the first path component is
+/// only a host if it has a `.` or `:` or is `localhost`; anything else is Docker Hub.
+fn image_registry(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains(['.', ':']) || first == "localhost" => first,
+        _ => "docker.io",
+    }
+}
+
+/// Images a Dockerfile's `FROM` lines pull, leaving out `scratch` and earlier stages
+fn dockerfile_base_images(dockerfile: &str) -> Vec<String> {
+    let mut stages = Vec::new();
+    let mut images = Vec::new();
+    for line in dockerfile.lines() {
+        let mut words = line.split_whitespace().filter(|word| !word.starts_with("--"));
+        if !words.next().is_some_and(|word| word.eq_ignore_ascii_case("FROM")) {
+            continue;
+        }
+        let Some(image) = words.next() else { continue };
+        if image != "scratch" && !stages.iter().any(|stage: &String| stage.eq_ignore_ascii_case(image)) {
+            images.push(image.to_string());
+        }
+        if words.next().is_some_and(|word| word.eq_ignore_ascii_case("AS")) {
+            stages.extend(words.next().map(str::to_string));
+        }
+    }
+    images
+}
+
+/// Run `cmd`, streaming its output to the job log, optionally writing `stdin` first
+async fn run_streamed(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    mut cmd: Command,
+    stdin: Option<&str>,
+) -> Result<std::process::ExitStatus> {
+    cmd.stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = cmd.spawn().context("Failed to start docker")?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        use tokio::io::AsyncWriteExt;
+        pipe.write_all(input.as_bytes()).await.context("Failed to write to docker stdin")?;
+        // Dropping the pipe closes stdin
+    }
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+
+    let (job_id, claim_token) = (job.id, job.claim_token);
+    let out_client = client.clone();
+    let stdout_handle = tokio::spawn(async move {
+        let mut reader = OutputLines::new(stdout);
+        while let Some(line) = reader.next_line().await {
+            let _ = out_client.log_raw(job_id, &claim_token, &line, LogLevel::Info).await;
+        }
+    });
+
+    let err_client = client.clone();
+    let stderr_handle = tokio::spawn(async move {
+        let mut reader = OutputLines::new(stderr);
+        while let Some(line) = reader.next_line().await {
+            let _ = err_client.log_raw(job_id, &claim_token, &format!("STDERR: {}", line), LogLevel::Error).await;
+        }
+    });
+
+    let status = child.wait().await.context("Failed to wait for docker")?;
+    let _ = stdout_handle.await;
+    let _ = stderr_handle.await;
+    Ok(status)
+}
+
 async fn run_deploy(
     client: &ServerClient,
     job: &ClaimedJob,
-    repo_dir: &PathBuf,
-    _config: &Config,
+    repo_dir: &Path,
+    config: &Config,
     fc: &FoundryConfig,
 ) -> Result<()> {
+    // `build.user` is for build containers, not the deployed app
+    let limits = JobLimits { user: None, ..job_limits(job, Some(fc), config) };
+    let resources = resource_args(&limits, Some(fc), config)?;
     let app_name = fc.deploy.name.as_deref().unwrap_or(&job.repo_name);
+    let preview = preview_of(job, fc);
+    let app_name: &str = &match &preview {
+        Some((pr, _)) => format!("{}-pr-{}", app_name, pr),
+        None => app_name.to_string(),
+    };
 
     client.log(job, &format!("🚀 Deploying {}", app_name)).await?;
 
+    let repo_dir = &config_dir(job, repo_dir);
+
     if let Some(compose_file) = &fc.deploy.compose_file {
+        if preview.is_some() {
+            anyhow::bail!("Pull request previews need a docker run deploy, not compose_file");
+        }
+        if let Some(Healthcheck::Command(_)) = fc.deploy.healthcheck() {
+            anyhow::bail!("Compose deploys only support URL healthchecks; give the service a healthcheck in the compose file instead");
+        }
         client.log(job, &format!("Using compose file: {}", compose_file)).await?;
 
         let compose_path = repo_dir.join(compose_file);
@@ -464,7 +1698,7 @@ async fn run_deploy(
             app_name.to_string(),
         ];
 
-        // Add env file if specified (absolute path on host)
+        // Compose reads it for variable substitution; a path outside the repo is allowed here
         if let Some(env_file) = &fc.deploy.env_file {
             client.log(job, &format!("Using env file: {}", env_file)).await?;
             args.push("--env-file".to_string());
@@ -508,80 +1742,71 @@ async fn run_deploy(
             client.log(job, &format!("Deploy failed: {}", stderr)).await?;
             anyhow::bail!("Docker compose failed");
         }
+
+        let timeout_secs = fc.deploy.healthcheck_timeout_secs();
+        if let Err(e) = wait_compose_healthy(client, job, app_name, timeout_secs).await {
+            client.log(job, &format!("❌ {} never became healthy: {}", app_name, e)).await?;
+            anyhow::bail!("Healthcheck failed: {}", e);
+        }
+        if let Some(check) = fc.deploy.healthcheck() {
+            if let Err(e) = wait_healthy(client, job, &check, None, timeout_secs).await {
+                client.log(job, &format!("❌ {} never became healthy: {}", app_name, e)).await?;
+                anyhow::bail!("Healthcheck failed: {}", e);
+            }
+        }
     } else {
         let image_tag = if fc.build.dockerfile.is_some() {
-            build_image(client, job, repo_dir, fc).await?
+            build_image(client, job, repo_dir, config, fc).await?
         } else {
             fc.build.image.clone()
         };
 
         let container_name = format!("foundry-{}", app_name);
+        let previous_name = format!("{}-previous", container_name);
+
+        let file_env = match &fc.deploy.env_file {
+            Some(env_file) => {
+                let vars = read_env_file(repo_dir, env_file)?;
+                client.log(job, &format!("Loaded {} variables from {}", vars.len(), env_file)).await?;
+                vars
+            }
+            None => Vec::new(),
+        };
+        let args = deploy_args(job, fc, &container_name, resources, &file_env)?;
+        if let Some(network) = &fc.deploy.network {
+            ensure_network(client, job, network).await?;
+        }
+
+        let command: Vec<String> = fc
+            .build
+            .command
+            .as_deref()
+            .map(|cmd| cmd.split_whitespace().map(String::from).collect())
+            .unwrap_or_default();
 
+        // Keep the running version aside until the new one is up and healthy
         client.log(job, &format!("Stopping existing container: {}", container_name)).await?;
         let _ = Command::new("docker")
-            .args(["stop", &container_name])
+            .args(["rm", "-f", &previous_name])
             .output()
             .await;
         let _ = Command::new("docker")
-            .args(["rm", &container_name])
+            .args(["stop", &container_name])
             .output()
             .await;
+        let kept_previous = Command::new("docker")
+            .args(["rename", &container_name, &previous_name])
+            .output()
+            .await
+            .is_ok_and(|output| output.status.success());
 
-        let mut args = vec![
-            "run".to_string(),
-            "-d".to_string(),
-            "--name".to_string(),
-            container_name.clone(),
-            "--restart".to_string(),
-            "unless-stopped".to_string(),
-        ];
-
-        if let Some(port) = fc.deploy.port {
-            args.push("-p".to_string());
-            args.push(format!("{}:{}", port, port));
-        }
-
-        // Add volume mounts (validated)
-        if let Some(volumes) = &fc.deploy.volumes {
-            for vol in volumes {
-                // Validate volume spec: block host paths that could compromise the host
-                let host_part = vol.split(':').next().unwrap_or("");
-                let blocked = [
-                    "/var/run/docker.sock",
-                    "/etc",
-                    "/root",
-                    "/home",
-                    "/proc",
-                    "/sys",
-                    "/dev",
-                    "/boot",
-                    "/var/run",
-                ];
-                let is_blocked = blocked.iter().any(|b| host_part == *b || host_part.starts_with(&format!("{}/", b)));
-                if is_blocked {
-                    tracing::warn!("Blocked dangerous volume mount: {}", vol);
-                    return Err(anyhow::anyhow!("Volume mount not allowed: {}", host_part));
-                }
-                args.push("-v".to_string());
-                args.push(vol.clone());
-            }
-        }
-
-        for (key, value) in &fc.env {
-            args.push("-e".to_string());
-            args.push(format!("{}={}", key, value));
-        }
-
-        args.push(image_tag);
-
-        if let Some(cmd) = &fc.build.command {
-            args.extend(cmd.split_whitespace().map(String::from));
-        }
-
-        client.log(job, &format!("Starting container: {}", container_name)).await?;
+        client.log(job, &format!("Starting container: {}", container_name)).await?;
 
         let output = Command::new("docker")
+            .arg("run")
             .args(&args)
+            .arg(&image_tag)
+            .args(&command)
             .current_dir(repo_dir)
             .output()
             .await
@@ -590,17 +1815,68 @@ async fn run_deploy(
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
             client.log(job, &format!("Failed to start: {}", stderr)).await?;
+            restore_previous(client, job, &container_name, &previous_name, kept_previous).await?;
             anyhow::bail!("Failed to start container");
         }
+
+        let healthcheck = match fc.deploy.healthcheck() {
+            // The URL points at the app, not at the preview's port
+            Some(Healthcheck::Url(_)) if preview.is_some() => {
+                client.log(job, "Skipping the URL healthcheck for the preview").await?;
+                None
+            }
+            check => check,
+        };
+        if let Some(check) = healthcheck {
+            if let Err(e) = wait_healthy(client, job, &check, Some(&container_name), fc.deploy.healthcheck_timeout_secs()).await {
+                client.log(job, &format!("❌ {} never became healthy: {}", app_name, e)).await?;
+                client.log(job, &format!("Removing unhealthy container: {}", container_name)).await?;
+                let _ = Command::new("docker")
+                    .args(["rm", "-f", &container_name])
+                    .output()
+                    .await;
+                restore_previous(client, job, &container_name, &previous_name, kept_previous).await?;
+                anyhow::bail!("Healthcheck failed: {}", e);
+            }
+        }
+
+        if kept_previous {
+            let _ = Command::new("docker")
+                .args(["rm", &previous_name])
+                .output()
+                .await;
+        }
+
+        let deployment = DeploymentRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            app_name: app_name.to_string(),
+            image: image_tag,
+            args,
+            command,
+        };
+        // The app is already running; losing its history only costs the rollback
+        if let Err(e) = client.record_deployment(&deployment).await {
+            client.log(job, &format!("⚠️  Failed to record deployment, rollback won't be available: {}", e)).await?;
+        }
     }
 
-    let domains = fc.deploy.all_domains();
+    let (domains, port) = match &preview {
+        Some((_, hostname)) => {
+            let container_name = format!("foundry-{}", app_name);
+            let port = published_port(&container_name, fc.deploy.port.unwrap_or(8080)).await?;
+            if let Err(e) = client.record_preview(job, app_name, hostname).await {
+                client.log(job, &format!("⚠️  Failed to record preview, it won't be removed when the PR closes: {}", e)).await?;
+            }
+            (vec![hostname.as_str()], port)
+        }
+        None => (fc.deploy.all_domains(), fc.deploy.port.unwrap_or(8080)),
+    };
     if !domains.is_empty() {
-        let port = fc.deploy.port.unwrap_or(8080);
         client.log(job, &format!("🌐 Configuring {} domain route(s) -> port {}", domains.len(), port)).await?;
         
         for domain in domains {
-            match setup_domain_route(domain, port).await {
+            match setup_domain_route(domain, port, &fc.deploy.cloudflare).await {
                 Ok(()) => {
                     client.log(job, &format!("✅ Domain configured: https://{}", domain)).await?;
                 }
@@ -616,20 +1892,361 @@ async fn run_deploy(
     Ok(())
 }
 
-async fn setup_domain_route(domain: &str, port: u16) -> anyhow::Result<()> {
+/// Put the container a deploy set aside back in place after the new one failed
+async fn restore_previous(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    container_name: &str,
+    previous_name: &str,
+    kept_previous: bool,
+) -> Result<()> {
+    if !kept_previous {
+        client.log(job, "No previous container to restore").await?;
+        return Ok(());
+    }
+
+    let renamed = Command::new("docker")
+        .args(["rename", previous_name, container_name])
+        .output()
+        .await
+        .is_ok_and(|output| output.status.success());
+    let started = renamed
+        && Command::new("docker")
+            .args(["start", container_name])
+            .output()
+            .await
+            .is_ok_and(|output| output.status.success());
+
+    if started {
+        client.log(job, &format!("↩️  Restored the previous {}", container_name)).await?;
+    } else {
+        client
+            .log(job, &format!("⚠️  Failed to restore the previous container, it is still named {}", previous_name))
+            .await?;
+    }
+    Ok(())
+}
+
+const HEALTHCHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Probe `check` until it passes or `timeout_secs` runs out, returning the last failure.
+/// Commands run inside `container`, which compose deploys don't have.
+async fn wait_healthy(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    check: &Healthcheck<'_>,
+    container: Option<&str>,
+    timeout_secs: u64,
+) -> Result<()> {
+    let label = match (check, container) {
+        (Healthcheck::Url(url), _) => url.to_string(),
+        (Healthcheck::Command(command), Some(container)) => format!("`{}` in {}", command, container),
+        (Healthcheck::Command(_), None) => anyhow::bail!("command healthchecks need a container"),
+    };
+    client.log(job, &format!("🩺 Waiting up to {}s for {} to pass", timeout_secs, label)).await?;
+
+    let http = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+    let deadline = Instant::now() + std::time::Duration::from_secs(timeout_secs);
+    loop {
+        match probe(&http, check, container).await {
+            Ok(()) => {
+                client.log(job, "✅ Healthcheck passed").await?;
+                return Ok(());
+            }
+            Err(e) if Instant::now() + HEALTHCHECK_INTERVAL > deadline => {
+                anyhow::bail!("{} still failing after {}s: {}", label, timeout_secs, e)
+            }
+            Err(_) => tokio::time::sleep(HEALTHCHECK_INTERVAL).await,
+        }
+    }
+}
+
+/// One healthcheck attempt
+async fn probe(http: &reqwest::Client, check: &Healthcheck<'_>, container: Option<&str>) -> std::result::Result<(), String> {
+    match check {
+        Healthcheck::Url(url) => match http.get(*url).send().await {
+            Ok(resp) if resp.status().is_success() => Ok(()),
+            Ok(resp) => Err(format!("HTTP {}", resp.status())),
+            Err(e) => Err(e.to_string()),
+        },
+        Healthcheck::Command(command) => {
+            let container = container.ok_or("command healthchecks need a container")?;
+            let output = Command::new("docker")
+                .args(["exec", container, "sh", "-c", command])
+                .output()
+                .await
+                .map_err(|e| e.to_string())?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "exited with {}: {}",
+                    output.status.code().map(|c| c.to_string()).unwrap_or_else(|| "a signal".to_string()),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+            }
+        }
+    }
+}
+
+/// Wait for every service in the compose project that defines a healthcheck to report
+/// healthy. Services without one are taken as they are.
+async fn wait_compose_healthy(client: &ServerClient, job: &ClaimedJob, project: &str, timeout_secs: u64) -> Result<()> {
+    let deadline = Instant::now() + std::time::Duration::from_secs(timeout_secs);
+    let mut announced = false;
+    loop {
+        let output = Command::new("docker")
+            .args(["compose", "-p", project, "ps", "--all", "--format", "json"])
+            .output()
+            .await
+            .context("Failed to run docker compose ps")?;
+        if !output.status.success() {
+            anyhow::bail!("docker compose ps failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        match compose_health(&parse_compose_services(&String::from_utf8_lossy(&output.stdout))) {
+            ComposeHealth::Healthy => {
+                if announced {
+                    client.log(job, "✅ All compose healthchecks passed").await?;
+                }
+                return Ok(());
+            }
+            ComposeHealth::Unhealthy(services) => anyhow::bail!("unhealthy services: {}", services.join(", ")),
+            ComposeHealth::Starting(services) => {
+                if Instant::now() + HEALTHCHECK_INTERVAL > deadline {
+                    anyhow::bail!("services still starting after {}s: {}", timeout_secs, services.join(", "));
+                }
+                if !announced {
+                    client
+                        .log(job, &format!("🩺 Waiting up to {}s for compose healthchecks: {}", timeout_secs, services.join(", ")))
+                        .await?;
+                    announced = true;
+                }
+                tokio::time::sleep(HEALTHCHECK_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ComposeService {
+    #[serde(rename = "Service", default)]
+    service: String,
+    /// `starting`, `healthy`, `unhealthy`, or empty without a healthcheck
+    #[serde(rename = "Health", default)]
+    health: String,
+}
+
+/// Containers from `docker compose ps --format json`, which is a JSON array on older
+/// Compose versions and one object per line on newer ones
+fn parse_compose_services(stdout: &str) -> Vec<ComposeService> {
+    let trimmed = stdout.trim();
+    if trimmed.starts_with('[') {
+        return serde_json::from_str(trimmed).unwrap_or_default();
+    }
+    trimmed
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum ComposeHealth {
+    Healthy,
+    Starting(Vec<String>),
+    Unhealthy(Vec<String>),
+}
+
+fn compose_health(services: &[ComposeService]) -> ComposeHealth {
+    let with_health = |health: &str| -> Vec<String> {
+        services
+            .iter()
+            .filter(|s| s.health == health)
+            .map(|s| s.service.clone())
+            .collect()
+    };
+
+    let unhealthy = with_health("unhealthy");
+    if !unhealthy.is_empty() {
+        return ComposeHealth::Unhealthy(unhealthy);
+    }
+    let starting = with_health("starting");
+    if !starting.is_empty() {
+        return ComposeHealth::Starting(starting);
+    }
+    ComposeHealth::Healthy
+}
+
+/// `docker run` flags for a deployed app's container, without the image and command
+/// Create the user-defined network a deploy joins unless it already exists
+async fn ensure_network(client: &ServerClient, job: &ClaimedJob, network: &str) -> Result<()> {
+    let exists = Command::new("docker")
+        .args(["network", "inspect", network])
+        .output()
+        .await
+        .context("Failed to run docker network inspect")?
+        .status
+        .success();
+    if exists {
+        return Ok(());
+    }
+
+    client.log(job, &format!("Creating network: {}", network)).await?;
+    let output = Command::new("docker")
+        .args(["network", "create", network])
+        .output()
+        .await
+        .context("Failed to run docker network create")?;
+    // Another deploy may have created it in the meantime
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.contains("already exists") {
+            anyhow::bail!("Failed to create network {}: {}", network, stderr.trim());
+        }
+    }
+    Ok(())
+}
+
+/// Variables of `deploy.env_file`, which must be a file inside the repo
+fn read_env_file(repo_dir: &Path, env_file: &str) -> Result<Vec<(String, String)>> {
+    let path = repo_dir.join(env_file);
+    let inside_repo = match (path.canonicalize(), repo_dir.canonicalize()) {
+        (Ok(path), Ok(repo)) => path.starts_with(repo),
+        _ => anyhow::bail!("deploy.env_file {} not found in the repo", env_file),
+    };
+    if !inside_repo {
+        anyhow::bail!("deploy.env_file {} must be inside the repo", env_file);
+    }
+    let content = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", env_file))?;
+    parse_env_file(&content).with_context(|| format!("Invalid deploy.env_file {}", env_file))
+}
+
+fn deploy_args(
+    job: &ClaimedJob,
+    fc: &FoundryConfig,
+    container_name: &str,
+    resources: Vec<String>,
+    file_env: &[(String, String)],
+) -> Result<Vec<String>> {
+    let mut args = vec![
+        "-d".to_string(),
+        "--name".to_string(),
+        container_name.to_string(),
+        "--restart".to_string(),
+        "unless-stopped".to_string(),
+    ];
+    // The repo label lets the dashboard find deployments that aren't compose projects
+    args.extend(foundry_labels(job, ContainerKind::Deploy));
+
+    args.extend(resources);
+
+    // A preview runs beside the app, so it leaves the app's host ports and network
+    // aliases alone and is reached on a local port Docker picks
+    let preview = preview_of(job, fc).is_some();
+    if let Some(port) = fc.deploy.port {
+        args.push("-p".to_string());
+        args.push(if preview { format!("127.0.0.1::{}", port) } else { format!("{}:{}", port, port) });
+    }
+    for mapping in &fc.deploy.ports {
+        validate_port_mapping(mapping)?;
+        if !preview {
+            args.push("-p".to_string());
+            args.push(mapping.clone());
+        }
+    }
+
+    let aliases = if preview { &[] } else { fc.deploy.network_aliases.as_deref().unwrap_or_default() };
+    match &fc.deploy.network {
+        Some(network) => {
+            args.push("--network".to_string());
+            args.push(network.clone());
+            for alias in aliases {
+                args.push("--network-alias".to_string());
+                args.push(alias.clone());
+            }
+        }
+        None if !aliases.is_empty() => anyhow::bail!("deploy.network_aliases needs deploy.network"),
+        None => {}
+    }
+
+    // Add volume mounts (validated)
+    if let Some(volumes) = &fc.deploy.volumes {
+        for vol in volumes {
+            // Validate volume spec: block host paths that could compromise the host
+            let host_part = vol.split(':').next().unwrap_or("");
+            let blocked = [
+                "/var/run/docker.sock",
+                "/etc",
+                "/root",
+                "/home",
+                "/proc",
+                "/sys",
+                "/dev",
+                "/boot",
+                "/var/run",
+            ];
+            let is_blocked = blocked.iter().any(|b| host_part == *b || host_part.starts_with(&format!("{}/", b)));
+            if is_blocked {
+                tracing::warn!("Blocked dangerous volume mount: {}", vol);
+                return Err(anyhow::anyhow!("Volume mount not allowed: {}", host_part));
+            }
+            args.push("-v".to_string());
+            args.push(vol.clone());
+        }
+    }
+
+    // `[env]` wins over the env file
+    let file_env = file_env.iter().filter(|(key, _)| !fc.env.contains_key(key)).map(|(key, value)| (key, value));
+    for (key, value) in file_env.chain(&fc.env) {
+        args.push("-e".to_string());
+        args.push(format!("{}={}", key, value));
+    }
+
+    Ok(args)
+}
+
+/// The PR number and hostname of a pull request build of an app with
+/// `deploy.preview_domain`, which deploys as its own `<name>-pr-<number>` copy
+fn preview_of(job: &ClaimedJob, fc: &FoundryConfig) -> Option<(u64, String)> {
+    let domain = fc.deploy.preview_domain.as_deref()?;
+    let pr = crate::pr_number(&job.git_ref)?;
+    Some((pr, preview_hostname(domain, pr)))
+}
+
+/// Host port Docker published a container's `port` on
+async fn published_port(container_name: &str, port: u16) -> Result<u16> {
+    let output = Command::new("docker")
+        .args(["port", container_name, &format!("{}/tcp", port)])
+        .output()
+        .await
+        .context("Failed to run docker port")?;
+    if !output.status.success() {
+        anyhow::bail!("docker port failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    parse_published_port(&String::from_utf8_lossy(&output.stdout))
+        .with_context(|| format!("Port {} of {} is not published", port, container_name))
+}
+
+/// First port in `docker port` output, e.g. `127.0.0.1:49153`
+fn parse_published_port(output: &str) -> Option<u16> {
+    output.lines().find_map(|line| line.trim().rsplit_once(':')?.1.parse().ok())
+}
+
+async fn setup_domain_route(domain: &str, port: u16, cloudflare: &DeployCloudflareConfig) -> anyhow::Result<()> {
     if let Some(cf_client) = CloudflareClient::from_env()? {
+        let service = cloudflare.service(port);
         if let Some(existing_service) = cf_client.get_route(domain).await? {
-            let new_service = format!("http://127.0.0.1:{}", port);
-            if existing_service != new_service {
+            if existing_service != service {
                 tracing::info!(
                     "Domain {} is currently routed to {}, updating to {}",
-                    domain, existing_service, new_service
+                    domain, existing_service, service
                 );
             }
         }
 
-        let service = format!("http://127.0.0.1:{}", port);
-        cf_client.add_route(domain, &service).await?;
+        cf_client.add_route(domain, &service, cloudflare.origin_request()).await?;
         tracing::info!("Domain route configured: {} -> {}", domain, service);
     } else {
         tracing::warn!(
@@ -640,38 +2257,266 @@ async fn setup_domain_route(domain: &str, port: u16) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn run_container(
+/// `--cpus`/`--memory`/`--tmpfs` flags from `[build]`, falling back to the agent defaults
+/// Limits for the job's containers: `foundry.toml` wins over the agent's defaults, and
+/// the platform of a server-picked default image only applies without a `foundry.toml`
+fn job_limits(job: &ClaimedJob, fc: Option<&FoundryConfig>, config: &Config) -> JobLimits {
+    fn applied<T>(source: LimitSource) -> impl FnOnce(T) -> AppliedLimit<T> {
+        move |value| AppliedLimit { value, source }
+    }
+
+    JobLimits {
+        cpus: fc
+            .and_then(|fc| fc.build.cpus)
+            .map(applied(LimitSource::Config))
+            .or(config.default_cpus.map(applied(LimitSource::Agent))),
+        memory: fc
+            .and_then(|fc| fc.build.memory.clone())
+            .map(applied(LimitSource::Config))
+            .or(config.default_memory.clone().map(applied(LimitSource::Agent))),
+        platform: match fc {
+            Some(_) => None,
+            None => job.platform.clone().map(applied(LimitSource::Server)),
+        },
+        user: fc.and_then(|fc| fc.build.user.clone()).map(applied(LimitSource::Config)),
+    }
+}
+
+fn resource_args(limits: &JobLimits, fc: Option<&FoundryConfig>, config: &Config) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    if let Some(cpus) = &limits.cpus {
+        validate_cpu_limit(cpus.value)?;
+        args.push("--cpus".to_string());
+        args.push(cpus.value.to_string());
+    }
+    if let Some(memory) = &limits.memory {
+        validate_memory_limit(&memory.value)?;
+        args.push("--memory".to_string());
+        args.push(memory.value.clone());
+    }
+    if let Some(platform) = &limits.platform {
+        args.push("--platform".to_string());
+        args.push(platform.value.clone());
+    }
+    if let Some(user) = &limits.user {
+        args.push("--user".to_string());
+        args.push(user.value.clone());
+    }
+    if let Some(fc) = fc {
+        args.extend(tmpfs_args(&fc.build.tmpfs, &config.max_tmpfs_size)?);
+    }
+    Ok(args)
+}
+
+/// `docker run` flags for the job's build containers, recording the limits on the job
+/// so the dashboard shows what a build actually ran with
+async fn build_resources(
     client: &ServerClient,
     job: &ClaimedJob,
-    repo_dir: &PathBuf,
-    image: &str,
-    command: &str,
-    env_vars: Option<&std::collections::HashMap<String, String>>,
+    fc: Option<&FoundryConfig>,
+    config: &Config,
+) -> Result<Vec<String>> {
+    let limits = job_limits(job, fc, config);
+    let args = resource_args(&limits, fc, config)?;
+    if let Err(e) = client.report_limits(job, &limits).await {
+        debug!("Failed to report limits for job {}: {}", job.id, e);
+    }
+    Ok(args)
+}
+
+/// A single command to run in a build container
+struct ContainerRun<'a> {
+    image: &'a str,
+    command: &'a str,
+    env_vars: Option<&'a std::collections::HashMap<String, String>>,
     timeout_secs: u64,
-) -> Result<bool> {
+    /// Extra `docker run` flags such as resource limits and cache mounts
+    resources: &'a [String],
+    /// Prepended to every output line, e.g. the stage name
+    log_prefix: Option<&'a str>,
+    /// Replaces the image's `ENTRYPOINT`; the shell and command become its arguments
+    entrypoint: Option<&'a Entrypoint>,
+    /// Runs `command`, which is passed as its last argument
+    shell: &'a [String],
+    stderr: StderrMode,
+    /// Passed through the docker CLI's environment so values never appear in its arguments
+    secrets: &'a [(String, String)],
+    /// Watched for in stdout and stderr, for `build.success_when.output`
+    output_pattern: Option<&'a regex::Regex>,
+}
+
+/// How a container run ended
+struct ContainerExit {
+    status: std::process::ExitStatus,
+    /// Whether any output line matched `ContainerRun::output_pattern`
+    output_matched: bool,
+}
+
+/// Script passed to the build shell. Merging redirects stderr inside the container so the
+/// two streams arrive over one pipe in the order they were written.
+fn shell_command(command: &str, stderr: StderrMode) -> String {
+    match stderr {
+        StderrMode::Tag => command.to_string(),
+        StderrMode::Merge => format!("exec 2>&1\n{}", command),
+    }
+}
+
+/// Warning for a default image picked for another architecture than the agent's,
+/// which only runs if the host can emulate it
+fn platform_warning(platform: &str, agent_arch: &str) -> Option<String> {
+    (platform_arch(platform) != agent_arch).then(|| {
+        format!(
+            "⚠️  No default image is configured for {}, running the {} image under emulation; \
+             builds fail with 'exec format error' if this host can't emulate it",
+            agent_arch, platform
+        )
+    })
+}
+
+/// Whether a line of `docker run` stderr says the container's command couldn't be
+/// started, e.g. `exec: "bash": executable file not found in $PATH`
+fn is_missing_executable(line: &str) -> bool {
+    line.contains("executable file not found")
+}
+
+/// Job log line for one line of container output
+fn log_line(prefix: &str, line: &str, from_stderr: bool) -> String {
+    if from_stderr {
+        format!("{}STDERR: {}", prefix, line)
+    } else {
+        format!("{}{}", prefix, line)
+    }
+}
+
+/// `docker run` arguments for a build container
+fn container_args(job: &ClaimedJob, repo_dir: &Path, container_name: &str, run: &ContainerRun<'_>) -> Vec<String> {
+    let (entrypoint_flags, entrypoint_args) = run.entrypoint.map(Entrypoint::docker_args).unwrap_or_default();
+
     let mut args = vec![
         "run".to_string(),
         "--rm".to_string(),
+        "--name".to_string(),
+        container_name.to_string(),
+    ];
+    args.extend(foundry_labels(job, ContainerKind::Build));
+    args.extend([
         "-v".to_string(),
         format!("{}:/work", repo_dir.display()),
         "-w".to_string(),
-        "/work".to_string(),
-    ];
+        match &job.service_path {
+            Some(service) => format!("/work/{}", service),
+            None => "/work".to_string(),
+        },
+    ]);
 
-    if let Some(env) = env_vars {
+    args.extend(run.resources.iter().cloned());
+    args.extend(entrypoint_flags);
+
+    if let Some(env) = run.env_vars {
         for (key, value) in env {
             args.push("-e".to_string());
             args.push(format!("{}={}", key, value));
         }
     }
+    for (name, _) in run.secrets {
+        args.push("-e".to_string());
+        args.push(name.clone());
+    }
+
+    args.push(run.image.to_string());
+    args.extend(entrypoint_args);
+    args.extend(run.shell.iter().cloned());
+    args.push(shell_command(run.command, run.stderr));
+    args
+}
+
+/// What a container Foundry started is for, recorded in its `foundry.kind` label
+#[derive(Debug, Clone, Copy)]
+enum ContainerKind {
+    Build,
+    Deploy,
+}
 
-    args.push(image.to_string());
-    args.push("bash".to_string());
-    args.push("-lc".to_string());
-    args.push(command.to_string());
+/// `--label` flags for every container and image Foundry creates, so its own can be
+/// told apart from other workloads on a shared host
+fn foundry_labels(job: &ClaimedJob, kind: ContainerKind) -> Vec<String> {
+    let kind = match kind {
+        ContainerKind::Build => "build",
+        ContainerKind::Deploy => "deploy",
+    };
+    [
+        "foundry.managed=true".to_string(),
+        format!("foundry.repo={}/{}", job.repo_owner, job.repo_name),
+        format!("foundry.job={}", job.id),
+        format!("foundry.kind={}", kind),
+    ]
+    .into_iter()
+    .flat_map(|label| ["--label".to_string(), label])
+    .collect()
+}
 
-    let mut child = Command::new("docker")
+/// Longest line passed on from a child's output; longer ones are split
+const MAX_OUTPUT_LINE: usize = 64 * 1024;
+
+/// A child's output, line by line as it is produced. Invalid UTF-8, e.g. binary noise,
+/// is replaced rather than ending the stream, which would leave the child blocked on
+/// a full pipe and its log silent.
+struct OutputLines<R> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> OutputLines<R> {
+    fn new(output: R) -> Self {
+        Self { reader: BufReader::new(output), line: Vec::new() }
+    }
+
+    /// The next line without its `\n` or `\r\n`; `None` once the output ends
+    async fn next_line(&mut self) -> Option<String> {
+        self.line.clear();
+        while self.line.len() < MAX_OUTPUT_LINE {
+            let available = match self.reader.fill_buf().await {
+                Ok(available) if !available.is_empty() => available,
+                _ if self.line.is_empty() => return None,
+                _ => break,
+            };
+            let room = MAX_OUTPUT_LINE - self.line.len();
+            if let Some(end) = available.iter().take(room).position(|&b| b == b'\n') {
+                self.line.extend_from_slice(&available[..end]);
+                self.reader.consume(end + 1);
+                break;
+            }
+            let taken = available.len().min(room);
+            self.line.extend_from_slice(&available[..taken]);
+            self.reader.consume(taken);
+        }
+        if self.line.last() == Some(&b'\r') {
+            self.line.pop();
+        }
+        Some(String::from_utf8_lossy(&self.line).into_owned())
+    }
+}
+
+async fn run_container(
+    client: &ServerClient,
+    job: &ClaimedJob,
+    repo_dir: &Path,
+    config: &Config,
+    run: ContainerRun<'_>,
+) -> Result<ContainerExit> {
+    // Name the container so it can be killed directly if the build times out
+    let container_name = format!("foundry-job-{}-{}", job.id, &Uuid::new_v4().simple().to_string()[..8]);
+    let args = container_args(job, repo_dir, &container_name, &run);
+    let docker_config = registry_login(client, job, config, &[run.image]).await?;
+    let ContainerRun { timeout_secs, log_prefix, shell, secrets, output_pattern, .. } = run;
+
+    let mut cmd = Command::new("docker");
+    if let Some(dir) = &docker_config {
+        cmd.env("DOCKER_CONFIG", dir);
+    }
+    let mut child = cmd
         .args(&args)
+        .envs(secrets.iter().map(|(name, value)| (name, value)))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -680,23 +2525,40 @@ async fn run_container(
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
 
-    let stdout_handle = tokio::spawn(async move {
-        let mut lines = Vec::new();
-        let mut reader = BufReader::new(stdout).lines();
-        while let Ok(Some(line)) = reader.next_line().await {
-            lines.push(line);
-        }
-        lines
-    });
+    let prefix = log_prefix.map(|p| format!("[{}] ", p)).unwrap_or_default();
+
+    // Stream output to the server as it is produced
+    let stdout_handle = {
+        let client = client.clone();
+        let prefix = prefix.clone();
+        let pattern = output_pattern.cloned();
+        let (job_id, claim_token) = (job.id, job.claim_token);
+        tokio::spawn(async move {
+            let mut matched = false;
+            let mut reader = OutputLines::new(stdout);
+            while let Some(line) = reader.next_line().await {
+                matched |= pattern.as_ref().is_some_and(|p| p.is_match(&line));
+                let _ = client.log_raw(job_id, &claim_token, &log_line(&prefix, &line, false), LogLevel::Info).await;
+            }
+            matched
+        })
+    };
 
-    let stderr_handle = tokio::spawn(async move {
-        let mut lines = Vec::new();
-        let mut reader = BufReader::new(stderr).lines();
-        while let Ok(Some(line)) = reader.next_line().await {
-            lines.push(format!("STDERR: {}", line));
-        }
-        lines
-    });
+    let stderr_handle = {
+        let client = client.clone();
+        let pattern = output_pattern.cloned();
+        let (job_id, claim_token) = (job.id, job.claim_token);
+        tokio::spawn(async move {
+            let (mut matched, mut missing_executable) = (false, false);
+            let mut reader = OutputLines::new(stderr);
+            while let Some(line) = reader.next_line().await {
+                matched |= pattern.as_ref().is_some_and(|p| p.is_match(&line));
+                missing_executable |= is_missing_executable(&line);
+                let _ = client.log_raw(job_id, &claim_token, &log_line(&prefix, &line, true), LogLevel::Error).await;
+            }
+            (matched, missing_executable)
+        })
+    };
 
     let timeout_duration = std::time::Duration::from_secs(timeout_secs);
     let wait_result = tokio::time::timeout(timeout_duration, child.wait()).await;
@@ -707,42 +2569,666 @@ async fn run_container(
             return Err(anyhow::anyhow!("Failed to wait for container: {}", e));
         }
         Err(_) => {
-            client.log(job, &format!("⏰ Build timed out after {} seconds", timeout_secs)).await?;
-            
+            client
+                .log(job, &format!("⏰ Build exceeded {} seconds, killing container", timeout_secs))
+                .await?;
+
+            let kill = Command::new("docker")
+                .args(["kill", &container_name])
+                .output()
+                .await;
+            if let Err(e) = kill {
+                tracing::warn!("Failed to kill container {}: {}", container_name, e);
+            }
+
             if let Err(e) = child.kill().await {
                 tracing::warn!("Failed to kill timed out process: {}", e);
             }
-            
-            let container_list = Command::new("docker")
-                .args(["ps", "-q", "--filter", &format!("label=foundry.job_id={}", job.id)])
+
+            stdout_handle.abort();
+            stderr_handle.abort();
+
+            return Err(TimedOut { phase: "build", secs: timeout_secs }.into());
+        }
+    };
+
+    let stdout_matched = stdout_handle.await.unwrap_or(false);
+    let (stderr_matched, missing_executable) = stderr_handle.await.unwrap_or_default();
+
+    if missing_executable && status.code() == Some(127) {
+        let hint = format!(
+            "💡 {} isn't in the image. For images without bash, such as alpine or distroless, \
+             set `shell = \"sh -c\"` (or another shell the image has) under [build] in foundry.toml",
+            shell.first().map(String::as_str).unwrap_or("The shell")
+        );
+        client.log_raw(job.id, &job.claim_token, &hint, LogLevel::Warn).await?;
+    }
+
+    Ok(ContainerExit { status, output_matched: stdout_matched || stderr_matched })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reclaimed_job_gets_distinct_workspace() {
+        let first = workspace_path("/tmp/foundry", 42, &Uuid::new_v4());
+        let second = workspace_path("/tmp/foundry", 42, &Uuid::new_v4());
+        assert_ne!(first, second);
+        assert_eq!(workspace_job_id(&first.file_name().unwrap().to_string_lossy()), Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_output_lines_survive_binary_noise() {
+        let mut output = b"ok\n\xff\xfebinary\r\n".to_vec();
+        output.extend(vec![b'x'; MAX_OUTPUT_LINE + 10]);
+        output.extend_from_slice(b"\nlast");
+        let mut lines = OutputLines::new(output.as_slice());
+
+        assert_eq!(lines.next_line().await.as_deref(), Some("ok"));
+        assert_eq!(lines.next_line().await.as_deref(), Some("\u{fffd}\u{fffd}binary"));
+        assert_eq!(lines.next_line().await.map(|l| l.len()), Some(MAX_OUTPUT_LINE));
+        assert_eq!(lines.next_line().await.as_deref(), Some("xxxxxxxxxx"));
+        assert_eq!(lines.next_line().await.as_deref(), Some("last"));
+        assert_eq!(lines.next_line().await, None);
+    }
+
+    #[test]
+    fn test_workspace_job_id() {
+        assert_eq!(workspace_job_id("job-7"), Some(7));
+        assert_eq!(workspace_job_id("job-7-1a2b3c4d"), Some(7));
+        assert_eq!(workspace_job_id("cache"), None);
+    }
+
+    #[tokio::test]
+    async fn test_hung_clone_times_out() {
+        // Accepts connections but never answers, like a stalled git server
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/acme/app.git", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+
+        let dest = std::env::temp_dir().join(format!("foundry-clone-test-{}", Uuid::new_v4()));
+        let start = Instant::now();
+        let remote = Remote { url: &url, safe_url: &url, ssh_command: None };
+        let err = clone_repo(&remote, "main", &dest, true, None, 1, 50).await.unwrap_err();
+
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+        assert_eq!(err.to_string(), "clone exceeded timeout of 1 seconds");
+        server.abort();
+        let _ = std::fs::remove_dir_all(&dest);
+    }
+
+    #[test]
+    fn test_clone_and_build_timeouts_are_distinct() {
+        let clone = TimedOut { phase: "clone", secs: 600 }.to_string();
+        let build = TimedOut { phase: "build", secs: 600 }.to_string();
+        assert_eq!(build, "build exceeded timeout of 600 seconds");
+        assert_ne!(clone, build);
+    }
+
+    fn git_failure(stderr: &str) -> anyhow::Error {
+        GitFailed { step: "clone", stderr: stderr.to_string() }.into()
+    }
+
+    #[tokio::test]
+    async fn test_git_retries_transient_failure() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let result = retry_git(3, std::time::Duration::from_millis(1), || async {
+            if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                Err(git_failure("fatal: unable to access 'https://github.com/acme/app.git/': Could not resolve host: github.com"))
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_git_does_not_retry_auth_failure() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let result = retry_git(3, std::time::Duration::from_millis(1), || async {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(git_failure(
+                "remote: Invalid username or password.\nfatal: Authentication failed for 'https://github.com/acme/app.git/'",
+            ))
+        })
+        .await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "git clone failed: remote: Invalid username or password.\nfatal: Authentication failed for 'https://github.com/acme/app.git/'"
+        );
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_git_error_classification() {
+        let transient = |stderr: &str| GitFailed { step: "clone", stderr: stderr.to_string() }.is_transient();
+        assert!(transient("error: RPC failed; curl 56 GnuTLS recv error\nfatal: early EOF"));
+        assert!(transient("fatal: unable to access 'https://github.com/acme/app.git/': The requested URL returned error: 502"));
+        assert!(!transient("fatal: unable to access 'https://github.com/acme/app.git/': The requested URL returned error: 403"));
+        assert!(!transient("error: pathspec 'deadbeef' did not match any file(s) known to git"));
+        assert!(!transient("fatal: Remote branch nope not found in upstream origin"));
+    }
+
+    /// A throwaway upstream repo with one commit on `main`; returns its path and HEAD sha
+    fn upstream_repo(root: &Path) -> (String, String) {
+        let upstream = root.join("upstream");
+        std::fs::create_dir_all(&upstream).unwrap();
+        let run = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .args(["-c", "user.name=Foundry", "-c", "user.email=ci@example.com"])
+                .args(args)
+                .current_dir(&upstream)
                 .output()
-                .await;
-            
-            if let Ok(output) = container_list {
-                let container_ids = String::from_utf8_lossy(&output.stdout);
-                for container_id in container_ids.lines() {
-                    let _ = Command::new("docker")
-                        .args(["kill", container_id.trim()])
-                        .output()
-                        .await;
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        run(&["init", "--quiet", "-b", "main"]);
+        std::fs::write(upstream.join("README.md"), "hello").unwrap();
+        run(&["add", "."]);
+        run(&["commit", "--quiet", "-m", "initial"]);
+        (upstream.display().to_string(), run(&["rev-parse", "HEAD"]))
+    }
+
+    #[test]
+    fn test_checkout_options() {
+        let mut config = Config::for_tests();
+        config.clone_submodules = true;
+        assert_eq!(CheckoutOptions::new(None, &config), CheckoutOptions { depth: 50, submodules: true });
+
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "build": { "clone_depth": 0, "submodules": false }
+        }))
+        .unwrap();
+        let options = CheckoutOptions::new(Some(&fc), &config);
+        assert_eq!(options, CheckoutOptions { depth: 0, submodules: false });
+
+        // Full history for `git describe` from the agent's default shallow clone
+        assert_eq!(options.deepen_flag(50).as_deref(), Some("--unshallow"));
+        assert_eq!(options.deepen_flag(0), None);
+        let deeper = CheckoutOptions { depth: 200, submodules: false };
+        assert_eq!(deeper.deepen_flag(50).as_deref(), Some("--depth=200"));
+        // A clone that's already deep enough is left alone
+        assert_eq!(CheckoutOptions { depth: 10, submodules: false }.deepen_flag(50), None);
+    }
+
+    #[tokio::test]
+    async fn test_clone_reuses_mirror() {
+        let root = std::env::temp_dir().join(format!("foundry-mirror-test-{}", Uuid::new_v4()));
+        let (url, sha) = upstream_repo(&root);
+        let mirror = mirror_path(&root.join("mirrors").display().to_string(), "acme", "app");
+        let safe_url = "https://github.com/acme/app.git";
+
+        let remote = Remote { url: &url, safe_url, ssh_command: None };
+        let cold = clone_repo(&remote, &sha, &root.join("job-1"), false, Some(&mirror), 30, 50).await.unwrap();
+        let warm = clone_repo(&remote, &sha, &root.join("job-2"), false, Some(&mirror), 30, 50).await.unwrap();
+        let fresh = clone_repo(&remote, &sha, &root.join("job-3"), false, None, 30, 50).await.unwrap();
+
+        assert_eq!(cold, CloneSource::MirrorCreated);
+        assert_eq!(warm, CloneSource::MirrorReused);
+        assert_eq!(fresh, CloneSource::Fresh);
+        assert!(root.join("job-2/README.md").exists());
+        let origin = std::process::Command::new("git")
+            .args(["remote", "get-url", "origin"])
+            .current_dir(root.join("job-2"))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&origin.stdout).trim(), safe_url);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kept_checkout_updated_in_place() {
+        let root = std::env::temp_dir().join(format!("foundry-persistent-test-{}", Uuid::new_v4()));
+        let (url, sha) = upstream_repo(&root);
+        let kept = persistent_path(&root.join("workspaces").display().to_string(), "acme", "app");
+        let safe_url = "https://github.com/acme/app.git";
+        let remote = Remote { url: &url, safe_url, ssh_command: None };
+
+        clone_repo(&remote, &sha, &root.join("job-1"), false, None, 30, 50).await.unwrap();
+        let lock = try_lock_checkout(&kept).await.unwrap().expect("lock is free");
+        keep_checkout(&root.join("job-1"), &kept, &remote).await.unwrap();
+        assert!(try_lock_checkout(&kept).await.unwrap().is_none());
+
+        // Build output is ignored and survives; other leftovers don't
+        std::fs::write(kept.join(".git/info/exclude"), "target/\n").unwrap();
+        std::fs::create_dir_all(kept.join("target")).unwrap();
+        std::fs::write(kept.join("target/app"), "binary").unwrap();
+        std::fs::write(kept.join("scratch.txt"), "tmp").unwrap();
+
+        let upstream = root.join("upstream");
+        std::fs::write(upstream.join("README.md"), "updated").unwrap();
+        let commit = std::process::Command::new("git")
+            .args(["-c", "user.name=Foundry", "-c", "user.email=ci@example.com", "commit", "--quiet", "-am", "update"])
+            .current_dir(&upstream)
+            .status()
+            .unwrap();
+        assert!(commit.success());
+
+        update_checkout(&remote, "main", &kept, true, 50).await.unwrap();
+        assert_eq!(std::fs::read_to_string(kept.join("README.md")).unwrap(), "updated");
+        assert!(kept.join("target/app").exists());
+        assert!(!kept.join("scratch.txt").exists());
+
+        drop(lock);
+        assert!(try_lock_checkout(&kept).await.unwrap().is_some());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_mirror_falls_back_to_fresh_clone() {
+        let root = std::env::temp_dir().join(format!("foundry-mirror-test-{}", Uuid::new_v4()));
+        let (url, sha) = upstream_repo(&root);
+        let mirror = mirror_path(&root.join("mirrors").display().to_string(), "acme", "app");
+        std::fs::create_dir_all(&mirror).unwrap();
+        std::fs::write(mirror.join("HEAD"), "garbage").unwrap();
+
+        let remote = Remote { url: &url, safe_url: &url, ssh_command: None };
+        let source = clone_repo(&remote, &sha, &root.join("job"), false, Some(&mirror), 30, 50).await.unwrap();
+
+        assert_eq!(source, CloneSource::Fresh);
+        assert!(root.join("job/README.md").exists());
+        assert!(!mirror.exists());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_mirror_path_stays_in_cache() {
+        assert_eq!(mirror_path("/cache", "acme", "app"), PathBuf::from("/cache/acme/app.git"));
+        assert_eq!(mirror_path("/cache", "..", "../x"), PathBuf::from("/cache/_../_.._x.git"));
+    }
+
+    #[test]
+    fn test_build_caches_are_per_repo() {
+        assert_eq!(repo_cache_dir("/cache", "acme", "app"), PathBuf::from("/cache/acme/app"));
+        assert_ne!(repo_cache_dir("/cache", "acme", "app"), repo_cache_dir("/cache", "acme", "api"));
+        assert_eq!(repo_cache_dir("/cache", "..", ".."), PathBuf::from("/cache/_../_.."));
+    }
+
+    #[tokio::test]
+    async fn test_merged_stderr_is_untagged_and_ordered() {
+        let script = shell_command(
+            "echo compiling; echo 'warning: unused' >&2; echo linking; echo 'progress 100%' >&2",
+            StderrMode::Merge,
+        );
+        let output = Command::new("bash").args(["-c", &script]).output().await.unwrap();
+        assert!(output.status.success());
+        assert!(output.stderr.is_empty());
+
+        let lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| log_line("[build] ", line, false))
+            .collect();
+        assert_eq!(
+            lines,
+            vec!["[build] compiling", "[build] warning: unused", "[build] linking", "[build] progress 100%"]
+        );
+    }
+
+    #[test]
+    fn test_tagged_stderr() {
+        assert_eq!(shell_command("make", StderrMode::Tag), "make");
+        assert_eq!(log_line("", "warning: unused", true), "STDERR: warning: unused");
+        assert_eq!(log_line("[test] ", "ok", false), "[test] ok");
+    }
+
+    #[test]
+    fn test_job_limits_precedence() {
+        let config = Config {
+            default_cpus: Some(2.0),
+            default_memory: Some("4g".to_string()),
+            ..Config::for_tests()
+        };
+        let mut job = labelled_job();
+        job.platform = Some("linux/amd64".to_string());
+
+        // No foundry.toml: agent defaults, and the platform the server picked the image for
+        let limits = job_limits(&job, None, &config);
+        assert_eq!(limits.cpus, Some(AppliedLimit { value: 2.0, source: LimitSource::Agent }));
+        assert_eq!(limits.platform.as_ref().unwrap().source, LimitSource::Server);
+        assert_eq!(
+            resource_args(&limits, None, &config).unwrap(),
+            ["--cpus", "2", "--memory", "4g", "--platform", "linux/amd64"]
+        );
+
+        // foundry.toml overrides what it sets and leaves the rest to the agent
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "build": {"memory": "8g", "user": "1000:1000"}
+        }))
+        .unwrap();
+        let limits = job_limits(&job, Some(&fc), &config);
+        assert_eq!(
+            serde_json::to_value(&limits).unwrap(),
+            serde_json::json!({
+                "cpus": {"value": 2.0, "source": "agent"},
+                "memory": {"value": "8g", "source": "config"},
+                "user": {"value": "1000:1000", "source": "config"},
+            })
+        );
+        assert_eq!(
+            resource_args(&limits, Some(&fc), &config).unwrap(),
+            ["--cpus", "2", "--memory", "8g", "--user", "1000:1000"]
+        );
+    }
+
+    #[test]
+    fn test_platform_warning() {
+        assert_eq!(platform_warning("linux/arm64", "arm64"), None);
+        let warning = platform_warning("linux/amd64", "arm64").unwrap();
+        assert!(warning.contains("for arm64, running the linux/amd64 image"), "{}", warning);
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_stops_when_job_is_reclaimed() {
+        // Accepts two heartbeats, then answers like the server after a reclaim
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::for_tests();
+        config.server_url = format!("http://{}", listener.local_addr().unwrap());
+        let beats = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = {
+            let beats = beats.clone();
+            tokio::spawn(async move {
+                use tokio::io::{AsyncReadExt, AsyncWriteExt};
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let mut buf = [0u8; 4096];
+                    let n = stream.read(&mut buf).await.unwrap();
+                    assert!(String::from_utf8_lossy(&buf[..n]).starts_with("POST /agent/heartbeat "));
+                    let (status, body) = if beats.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 {
+                        ("200 OK", r#"{"ok":true}"#)
+                    } else {
+                        ("403 Forbidden", r#"{"ok":false,"error":"Invalid job or token"}"#)
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
                 }
+            })
+        };
+
+        let client = ServerClient::new(&config).unwrap();
+        let job: ClaimedJob = serde_json::from_value(serde_json::json!({
+            "id": 7,
+            "repo_id": 1,
+            "repo_owner": "acme",
+            "repo_name": "app",
+            "clone_url": "https://github.com/acme/app.git",
+            "git_sha": "abc123",
+            "git_ref": "refs/heads/main",
+            "image": "ubuntu:latest",
+            "claim_token": Uuid::new_v4(),
+        }))
+        .unwrap();
+
+        let alive = keep_alive(&client, &job, std::time::Duration::from_millis(10));
+        tokio::time::timeout(std::time::Duration::from_secs(10), alive).await.unwrap();
+        assert_eq!(beats.load(std::sync::atomic::Ordering::SeqCst), 3);
+        server.abort();
+    }
+
+    fn labelled_job() -> ClaimedJob {
+        serde_json::from_value(serde_json::json!({
+            "id": 7,
+            "repo_id": 1,
+            "repo_owner": "acme",
+            "repo_name": "app",
+            "clone_url": "https://github.com/acme/app.git",
+            "git_sha": "abc123",
+            "git_ref": "refs/heads/main",
+            "image": "ubuntu:latest",
+            "claim_token": Uuid::new_v4(),
+        }))
+        .unwrap()
+    }
+
+    fn labels(args: &[String]) -> Vec<&str> {
+        args.windows(2)
+            .filter(|pair| pair[0] == "--label")
+            .map(|pair| pair[1].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_build_and_deploy_containers_are_labelled() {
+        let job = labelled_job();
+        let run = ContainerRun {
+            image: "rust:1.80",
+            command: "cargo test",
+            env_vars: None,
+            timeout_secs: 60,
+            resources: &[],
+            log_prefix: None,
+            entrypoint: None,
+            shell: &["sh".to_string(), "-c".to_string()],
+            stderr: StderrMode::Tag,
+            secrets: &[],
+            output_pattern: None,
+        };
+        let build = container_args(&job, Path::new("/tmp/work"), "foundry-job-7-abc", &run);
+        assert_eq!(
+            labels(&build),
+            vec!["foundry.managed=true", "foundry.repo=acme/app", "foundry.job=7", "foundry.kind=build"]
+        );
+        // Labels are flags, so they must come before the image
+        let image_at = build.iter().position(|a| a == "rust:1.80").unwrap();
+        assert!(build.iter().rposition(|a| a == "--label").unwrap() < image_at);
+        assert_eq!(build[image_at + 1..], ["sh", "-c", "cargo test"]);
+
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": { "name": "api", "port": 3000 }
+        }))
+        .unwrap();
+        let deploy = deploy_args(&job, &fc, "foundry-api", Vec::new(), &[]).unwrap();
+        assert_eq!(
+            labels(&deploy),
+            vec!["foundry.managed=true", "foundry.repo=acme/app", "foundry.job=7", "foundry.kind=deploy"]
+        );
+        assert!(!deploy.iter().any(|a| a == "--network"));
+    }
+
+    #[test]
+    fn test_deploy_network() {
+        let job = labelled_job();
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": { "name": "api", "network": "backend", "network_aliases": ["api", "api.internal"] }
+        }))
+        .unwrap();
+        let args = deploy_args(&job, &fc, "foundry-api", Vec::new(), &[]).unwrap();
+        let joined = args.join(" ");
+        assert!(joined.contains("--network backend --network-alias api --network-alias api.internal"), "{}", joined);
+
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": { "name": "api", "network_aliases": ["api"] }
+        }))
+        .unwrap();
+        assert!(deploy_args(&job, &fc, "foundry-api", Vec::new(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_deploy_ports() {
+        let job = labelled_job();
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": { "name": "api", "port": 3000, "ports": ["8443:443", "127.0.0.1:9090:9090"] }
+        }))
+        .unwrap();
+        let args = deploy_args(&job, &fc, "foundry-api", Vec::new(), &[]).unwrap();
+        let ports: Vec<_> = args.windows(2).filter(|w| w[0] == "-p").map(|w| w[1].as_str()).collect();
+        assert_eq!(ports, ["3000:3000", "8443:443", "127.0.0.1:9090:9090"]);
+
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": { "name": "api", "ports": ["8443"] }
+        }))
+        .unwrap();
+        assert!(deploy_args(&job, &fc, "foundry-api", Vec::new(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_preview_deploy() {
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": {
+                "name": "api", "port": 3000, "ports": ["8443:443"], "preview_domain": "example.com",
+                "network": "backend", "network_aliases": ["api"]
             }
-            
-            return Err(anyhow::anyhow!("Build timed out after {} seconds", timeout_secs));
-        }
-    };
+        }))
+        .unwrap();
+        let mut job = labelled_job();
+        assert_eq!(preview_of(&job, &fc), None);
+
+        job.git_ref = "refs/pull/12/merge".to_string();
+        assert_eq!(preview_of(&job, &fc), Some((12, "pr-12.example.com".to_string())));
+        let args = deploy_args(&job, &fc, "foundry-api-pr-12", Vec::new(), &[]).unwrap();
+        let ports: Vec<_> = args.windows(2).filter(|w| w[0] == "-p").map(|w| w[1].as_str()).collect();
+        assert_eq!(ports, ["127.0.0.1::3000"]);
+        assert!(args.windows(2).any(|w| w == ["--network", "backend"]));
+        assert!(!args.iter().any(|a| a == "--network-alias"));
+
+        assert_eq!(parse_published_port("127.0.0.1:49153\n"), Some(49153));
+        assert_eq!(parse_published_port("[::]:49154\n"), Some(49154));
+        assert_eq!(parse_published_port(""), None);
+    }
 
-    if let Ok(stdout_lines) = stdout_handle.await {
-        for line in stdout_lines {
-            let _ = client.log(job, &line).await;
+    #[test]
+    fn test_deploy_env_file() {
+        let root = std::env::temp_dir().join(format!("foundry-env-file-test-{}", Uuid::new_v4()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::write(repo.join(".env.production"), "API_URL=https://api.example.com\nNODE_ENV=staging\n").unwrap();
+        std::fs::write(root.join("host.env"), "SECRET=x\n").unwrap();
+        let file_env = read_env_file(&repo, ".env.production").unwrap();
+
+        let fc: FoundryConfig = serde_json::from_value(serde_json::json!({
+            "deploy": { "name": "api", "env_file": ".env.production" },
+            "env": { "NODE_ENV": "production" }
+        }))
+        .unwrap();
+        let args = deploy_args(&labelled_job(), &fc, "foundry-api", Vec::new(), &file_env).unwrap();
+        let env: Vec<_> = args.windows(2).filter(|w| w[0] == "-e").map(|w| w[1].as_str()).collect();
+        assert_eq!(env, ["API_URL=https://api.example.com", "NODE_ENV=production"]);
+
+        assert!(read_env_file(&repo, "missing.env").is_err());
+        for outside in ["../host.env", &root.join("host.env").display().to_string()] {
+            let err = read_env_file(&repo, outside).unwrap_err();
+            assert!(err.to_string().contains("inside the repo"), "{}", err);
         }
+        std::fs::remove_dir_all(&root).unwrap();
     }
 
-    if let Ok(stderr_lines) = stderr_handle.await {
-        for line in stderr_lines {
-            let _ = client.log(job, &line).await;
-        }
+    #[test]
+    fn test_compose_health() {
+        let ndjson = concat!(
+            r#"{"Service":"web","State":"running","Health":"healthy"}"#, "\n",
+            r#"{"Service":"worker","State":"running","Health":""}"#, "\n",
+            r#"{"Service":"db","State":"running","Health":"starting"}"#, "\n",
+        );
+        let services = parse_compose_services(ndjson);
+        assert_eq!(services.len(), 3);
+        assert_eq!(compose_health(&services), ComposeHealth::Starting(vec!["db".to_string()]));
+
+        let array = r#"[{"Service":"web","Health":"unhealthy"},{"Service":"db","Health":"starting"}]"#;
+        assert_eq!(
+            compose_health(&parse_compose_services(array)),
+            ComposeHealth::Unhealthy(vec!["web".to_string()])
+        );
+
+        // Services without a healthcheck don't hold the deploy up
+        let array = r#"[{"Service":"web","Health":"healthy"},{"Service":"migrate","State":"exited","Health":""}]"#;
+        assert_eq!(compose_health(&parse_compose_services(array)), ComposeHealth::Healthy);
+        assert_eq!(compose_health(&parse_compose_services("")), ComposeHealth::Healthy);
+    }
+
+    #[tokio::test]
+    async fn test_url_healthcheck_probe() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                let status = if String::from_utf8_lossy(&buf[..n]).starts_with("GET /health ") {
+                    "200 OK"
+                } else {
+                    "503 Service Unavailable"
+                };
+                let reply = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let http = reqwest::Client::new();
+        let healthy = format!("http://{}/health", addr);
+        assert_eq!(probe(&http, &Healthcheck::Url(&healthy), None).await, Ok(()));
+
+        let failing = format!("http://{}/ready", addr);
+        let err = probe(&http, &Healthcheck::Url(&failing), None).await.unwrap_err();
+        assert!(err.contains("503"), "{}", err);
+
+        assert!(probe(&http, &Healthcheck::Command("true"), None).await.is_err());
+        server.abort();
+    }
+
+    #[test]
+    fn test_image_registry() {
+        assert_eq!(image_registry("node:20"), "docker.io");
+        assert_eq!(image_registry("acme/app:1.0"), "docker.io");
+        assert_eq!(image_registry("ghcr.io/acme/app"), "ghcr.io");
+        assert_eq!(image_registry("localhost:5000/app"), "localhost:5000");
+        assert_eq!(image_registry("localhost/app"), "localhost");
+    }
+
+    #[test]
+    fn test_dockerfile_base_images() {
+        let dockerfile = "\
+FROM --platform=$BUILDPLATFORM ghcr.io/acme/rust:1.80 AS build
+RUN cargo build --release
+
+from build as test
+RUN cargo test
+
+FROM scratch
+FROM registry.example.com/base:latest
+COPY --from=build /app /app
+";
+        assert_eq!(dockerfile_base_images(dockerfile), ["ghcr.io/acme/rust:1.80", "registry.example.com/base:latest"]);
     }
 
-    Ok(status.success())
+    #[test]
+    fn test_is_missing_executable() {
+        assert!(is_missing_executable(
+            "docker: Error response from daemon: failed to create task for container: exec: \"bash\": executable file not found in $PATH: unknown."
+        ));
+        assert!(!is_missing_executable("bash: line 1: npm: command not found"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_discover_services_skips_symlinks() {
+        let root = std::env::temp_dir().join(format!("foundry-services-test-{}", Uuid::new_v4()));
+        let repo = root.join("repo");
+        std::fs::create_dir_all(repo.join("services/api")).unwrap();
+        std::fs::write(repo.join("services/api/foundry.toml"), "").unwrap();
+        std::fs::create_dir_all(root.join("outside")).unwrap();
+        std::fs::write(root.join("outside/foundry.toml"), "").unwrap();
+        std::os::unix::fs::symlink(root.join("outside"), repo.join("services/linked")).unwrap();
+        std::os::unix::fs::symlink(repo.join("services"), repo.join("services/api/loop")).unwrap();
+
+        let monorepo = MonorepoConfig { services: vec!["services/*".to_string()], shared: vec![] };
+        assert_eq!(discover_services(&repo, &monorepo), vec!["services/api"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
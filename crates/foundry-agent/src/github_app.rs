@@ -3,6 +3,7 @@ use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
+use foundry_core::github::CHECK_RUN_CANCEL_ACTION;
 
 pub struct GitHubApp {
     app_id: String,
@@ -52,13 +53,6 @@ struct CreateStatusRequest<'a> {
     context: &'a str,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CheckStatus {
-    Queued,
-    InProgress,
-    Completed,
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CheckConclusion {
     Success,
@@ -86,24 +80,38 @@ struct CheckRunOutput<'a> {
     text: Option<&'a str>,
 }
 
+/// Button shown on the check run; GitHub sends a `requested_action` event when clicked
+#[derive(Serialize)]
+struct CheckRunAction<'a> {
+    label: &'a str,
+    description: &'a str,
+    identifier: &'a str,
+}
+
 #[derive(Serialize)]
 struct CreateCheckRunRequest<'a> {
     name: &'a str,
     head_sha: &'a str,
     status: &'a str,
     #[serde(skip_serializing_if = "Option::is_none")]
+    details_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     conclusion: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     output: Option<CheckRunOutput<'a>>,
+    actions: Vec<CheckRunAction<'a>>,
 }
 
 #[derive(Serialize)]
 struct UpdateCheckRunRequest<'a> {
     status: &'a str,
     #[serde(skip_serializing_if = "Option::is_none")]
+    details_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     conclusion: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     output: Option<CheckRunOutput<'a>>,
+    actions: Vec<CheckRunAction<'a>>,
 }
 
 #[derive(Deserialize)]
@@ -111,6 +119,21 @@ pub struct CheckRun {
     pub id: i64,
 }
 
+#[derive(Serialize)]
+struct CommentRequest<'a> {
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct IssueComment {
+    id: i64,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Comments listed per page when looking for Foundry's own
+const COMMENTS_PER_PAGE: usize = 100;
+
 impl GitHubApp {
     pub fn new(app_id: String, installation_id: String, private_key_pem: &str) -> Result<Self> {
         let private_key = EncodingKey::from_rsa_pem(private_key_pem.as_bytes())
@@ -169,13 +192,14 @@ impl GitHubApp {
         clone_url.replace("https://", &format!("https://x-access-token:{}@", token))
     }
 
+    /// Set the commit's status under `context` to a state and its description
     pub async fn create_commit_status(
         &self,
         owner: &str,
         repo: &str,
         sha: &str,
-        status: CommitStatus,
-        description: Option<&str>,
+        context: &str,
+        (status, description): (CommitStatus, &str),
         target_url: Option<&str>,
     ) -> Result<()> {
         let token = self.get_installation_token().await?;
@@ -188,8 +212,8 @@ impl GitHubApp {
         let body = CreateStatusRequest {
             state: status.as_str(),
             target_url,
-            description,
-            context: "foundry",
+            description: Some(description),
+            context,
         };
 
         let resp = self
@@ -219,6 +243,7 @@ impl GitHubApp {
         repo: &str,
         sha: &str,
         name: &str,
+        details_url: Option<&str>,
     ) -> Result<i64> {
         let token = self.get_installation_token().await?;
 
@@ -231,12 +256,18 @@ impl GitHubApp {
             name,
             head_sha: sha,
             status: "in_progress",
+            details_url,
             conclusion: None,
             output: Some(CheckRunOutput {
                 title: "Build in progress",
                 summary: "Foundry is building your project...",
                 text: None,
             }),
+            actions: vec![CheckRunAction {
+                label: "Cancel",
+                description: "Cancel this build",
+                identifier: CHECK_RUN_CANCEL_ACTION,
+            }],
         };
 
         let resp = self
@@ -261,6 +292,58 @@ impl GitHubApp {
         Ok(check_run.id)
     }
 
+    /// Put an existing check run back in progress, e.g. when its job is retried
+    pub async fn restart_check_run(
+        &self,
+        owner: &str,
+        repo: &str,
+        check_run_id: i64,
+        details_url: Option<&str>,
+    ) -> Result<()> {
+        let token = self.get_installation_token().await?;
+
+        let url = format!(
+            "https://api.github.com/repos/{}/{}/check-runs/{}",
+            owner, repo, check_run_id
+        );
+
+        let body = UpdateCheckRunRequest {
+            status: "in_progress",
+            details_url,
+            conclusion: None,
+            output: Some(CheckRunOutput {
+                title: "Build in progress",
+                summary: "Foundry is building your project...",
+                text: None,
+            }),
+            actions: vec![CheckRunAction {
+                label: "Cancel",
+                description: "Cancel this build",
+                identifier: CHECK_RUN_CANCEL_ACTION,
+            }],
+        };
+
+        let resp = self
+            .client
+            .patch(&url)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "foundry-agent")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .json(&body)
+            .send()
+            .await
+            .context("Failed to update check run")?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("GitHub API error {}: {}", status, body);
+        }
+
+        Ok(())
+    }
+
     pub async fn complete_check_run(
         &self,
         owner: &str,
@@ -294,12 +377,15 @@ impl GitHubApp {
 
         let body = UpdateCheckRunRequest {
             status: "completed",
+            details_url: None,
             conclusion: Some(conclusion.as_str()),
             output: Some(CheckRunOutput {
                 title,
                 summary,
                 text: truncated_logs,
             }),
+            // Nothing left to cancel
+            actions: Vec::new(),
         };
 
         let resp = self
@@ -322,4 +408,81 @@ impl GitHubApp {
 
         Ok(())
     }
+
+    /// Post `body` on a pull request, or edit the comment from an earlier build that
+    /// contains `marker` so each PR keeps a single up-to-date comment
+    pub async fn create_or_update_pr_comment(
+        &self,
+        owner: &str,
+        repo: &str,
+        pr_number: u64,
+        marker: &str,
+        body: &str,
+    ) -> Result<()> {
+        let token = self.get_installation_token().await?;
+
+        let mut existing = None;
+        for page in 1.. {
+            let url = format!(
+                "https://api.github.com/repos/{}/{}/issues/{}/comments?per_page={}&page={}",
+                owner, repo, pr_number, COMMENTS_PER_PAGE, page
+            );
+
+            let resp = self
+                .client
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Accept", "application/vnd.github+json")
+                .header("User-Agent", "foundry-agent")
+                .header("X-GitHub-Api-Version", "2022-11-28")
+                .send()
+                .await
+                .context("Failed to list PR comments")?;
+
+            if !resp.status().is_success() {
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                anyhow::bail!("GitHub API error {}: {}", status, body);
+            }
+
+            let comments: Vec<IssueComment> = resp.json().await.context("Failed to parse PR comments")?;
+            let last_page = comments.len() < COMMENTS_PER_PAGE;
+            existing = comments
+                .into_iter()
+                .find(|c| c.body.as_deref().is_some_and(|b| b.contains(marker)))
+                .map(|c| c.id);
+            if existing.is_some() || last_page {
+                break;
+            }
+        }
+
+        let request = match existing {
+            Some(id) => self.client.patch(format!(
+                "https://api.github.com/repos/{}/{}/issues/comments/{}",
+                owner, repo, id
+            )),
+            None => self.client.post(format!(
+                "https://api.github.com/repos/{}/{}/issues/{}/comments",
+                owner, repo, pr_number
+            )),
+        };
+
+        let resp = request
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "foundry-agent")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .json(&CommentRequest { body })
+            .send()
+            .await
+            .context("Failed to post PR comment")?;
+
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let body = resp.text().await.unwrap_or_default();
+            anyhow::bail!("GitHub API error {}: {}", status, body);
+        }
+
+        Ok(())
+    }
 }
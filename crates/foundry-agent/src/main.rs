@@ -1,31 +1,51 @@
+mod artifacts;
+mod backoff;
 mod config;
 mod docker;
 mod github_app;
+mod provenance;
 mod server;
 mod watchdog;
 
+use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use tokio::sync::{watch, Semaphore};
+use foundry_core::{ClaimedJob, JobStatus, LogLevel};
 use tracing::{error, info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt};
 
+use crate::backoff::Backoff;
 use crate::config::Config;
-use crate::github_app::{CheckConclusion, GitHubApp};
+use crate::github_app::{CheckConclusion, CommitStatus, GitHubApp};
 use crate::server::ServerClient;
 
+/// How often a running job is heartbeated; keep well under the server's FOUNDRY_HEARTBEAT_TIMEOUT
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let once = parse_args(std::env::args().skip(1))?;
+
+    // With --once stdout carries the job's log, so the agent's own goes to stderr
+    let log_format = foundry_core::LogFormat::from_env()?;
+    let writer = || match once {
+        Some(_) => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "foundry_agent=debug".into()),
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+                if once.is_some() { "foundry_agent=info" } else { "foundry_agent=debug" }.into()
+            }),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with((log_format == foundry_core::LogFormat::Text).then(|| tracing_subscriber::fmt::layer().with_writer(writer())))
+        .with((log_format == foundry_core::LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json().with_writer(writer())))
         .init();
 
     let config = Config::from_env()?;
-    info!("Starting foundry-agent: {}", config.agent_id);
+    info!("Starting foundry-agent {}: {}", foundry_core::build_info!(), config.agent_id);
     info!("Server URL: {}", config.server_url);
 
     let github_app = if config.has_github_app() {
@@ -40,106 +60,790 @@ async fn main() -> Result<()> {
         None
     };
 
-    let client = ServerClient::new(&config);
+    let client = ServerClient::new(&config)?;
+
+    if let Some(target) = once {
+        let code = run_once(&target, client, &config, github_app.as_ref()).await?;
+        std::process::exit(code);
+    }
 
     // Start the foundryd watchdog
     watchdog::start_foundryd_watchdog();
 
+    docker::cleanup_orphaned_workspaces(&client, &config).await;
+
+    let max_jobs = config.max_concurrent_jobs;
+    info!("Running up to {} job(s) at a time", max_jobs);
+
+    let slots = Arc::new(Semaphore::new(max_jobs));
+    let client = Arc::new(client);
+    let config = Arc::new(config);
+    let github_app = github_app.map(Arc::new);
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    // Set once running jobs should give up and go back to the queue
+    let (requeue_tx, requeue_rx) = watch::channel(false);
+    let mut docker_up = true;
+    let mut backoff = Backoff::new(
+        Duration::from_secs(config.poll_interval_secs),
+        Duration::from_secs(config.max_poll_interval_secs),
+    );
+
     loop {
-        match client.claim_job().await {
+        // Only poll for work while a slot is free
+        let permit = tokio::select! {
+            _ = &mut shutdown => break,
+            permit = slots.clone().acquire_owned() => permit?,
+        };
+
+        let docker = docker::check_docker().await;
+        match &docker {
+            Err(e) if docker_up => error!("Docker is unavailable, not claiming jobs until it is reachable: {}", e),
+            Ok(()) if !docker_up => info!("Docker is reachable again, claiming jobs"),
+            _ => {}
+        }
+        docker_up = docker.is_ok();
+
+        let delay = match claim_next(&client, docker).await {
             Ok(Some(job)) => {
-                info!(
-                    "Claimed job {} for {}/{} @ {}",
-                    job.id,
-                    job.repo_owner,
-                    job.repo_name,
-                    &job.git_sha[..8.min(job.git_sha.len())]
-                );
+                backoff.reset();
+                let (client, config, github_app) = (client.clone(), config.clone(), github_app.clone());
+                let requeue = requeue_rx.clone();
+                tokio::spawn(async move {
+                    process_job(job, &client, &config, github_app.as_deref(), requeue).await;
+                    drop(permit);
+                });
+                continue;
+            }
+            Ok(None) => backoff.next_delay(),
+            Err(e) => {
+                let delay = backoff.next_delay();
+                warn!("Failed to claim job, retrying in {:.1}s: {}", delay.as_secs_f64(), e);
+                delay
+            }
+        };
+        drop(permit);
 
-                let check_run_id = if let Some(ref app) = github_app {
-                    info!("Creating GitHub check run for {}/{}", job.repo_owner, job.repo_name);
-                    match app
-                        .create_check_run(
-                            &job.repo_owner,
-                            &job.repo_name,
-                            &job.git_sha,
-                            "Foundry CI",
-                        )
-                        .await
-                    {
-                        Ok(id) => {
-                            info!("Created check run with ID {}", id);
-                            Some(id)
-                        }
-                        Err(e) => {
-                            warn!("Failed to create check run: {}", e);
-                            None
-                        }
-                    }
-                } else {
-                    None
-                };
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
 
-                let (success, error_msg) =
-                    match docker::run_job(&client, &job, &config, github_app.as_ref()).await {
-                        Ok(()) => {
-                            info!("Job {} completed successfully", job.id);
-                            (true, None)
-                        }
-                        Err(e) => {
-                            error!("Job {} failed: {}", job.id, e);
-                            let _ = client.log(&job, &format!("ERROR: {}", e)).await;
-                            (false, Some(e.to_string()))
-                        }
-                    };
+    let running = max_jobs - slots.available_permits();
+    if running > 0 {
+        info!("Shutting down, waiting for {} running job(s) to finish", running);
+    }
+    let drained = slots.acquire_many(max_jobs as u32);
+    match config.shutdown_timeout_secs {
+        Some(secs) => {
+            tokio::pin!(drained);
+            if tokio::time::timeout(Duration::from_secs(secs), &mut drained).await.is_err() {
+                warn!("Jobs still running after {}s, handing them back to the queue", secs);
+                let _ = requeue_tx.send(true);
+                let _ = drained.await;
+            }
+        }
+        None => {
+            let _ = drained.await;
+        }
+    }
+    info!("Shutdown complete");
+    Ok(())
+}
 
-                if let Some(ref app) = github_app {
-                    if let Some(check_id) = check_run_id {
-                        let logs = match client.get_logs(&job).await {
-                            Ok(logs) => Some(logs),
-                            Err(e) => {
-                                warn!("Failed to fetch logs: {}", e);
-                                None
-                            }
-                        };
-
-                        let (conclusion, summary) = if success {
-                            (CheckConclusion::Success, "Build completed successfully! ✅".to_string())
-                        } else {
-                            let summary = format!(
-                                "Build failed ❌\n\n{}",
-                                error_msg.unwrap_or_default()
-                            );
-                            (CheckConclusion::Failure, summary)
-                        };
-
-                        if let Err(e) = app
-                            .complete_check_run(
-                                &job.repo_owner,
-                                &job.repo_name,
-                                check_id,
-                                conclusion,
-                                &summary,
-                                logs.as_deref(),
-                            )
-                            .await
-                        {
-                            warn!("Failed to complete check run: {}", e);
-                        }
-                    }
+/// `--once <job>`, the only argument the agent takes
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<String>> {
+    const USAGE: &str = "Usage: foundry-agent [--once <job-id | job.json | - | JSON>]";
+    match (args.next().as_deref(), args.next(), args.next()) {
+        (None, _, _) => Ok(None),
+        (Some("--once"), Some(job), None) => Ok(Some(job)),
+        (Some("-h" | "--help"), None, None) => {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+/// Run one job in the foreground, printing its log to stdout, and return the build's
+/// exit status. A job id is claimed from the server and reported as usual; a job given
+/// as JSON runs without a server.
+async fn run_once(target: &str, client: ServerClient, config: &Config, github_app: Option<&GitHubApp>) -> Result<i32> {
+    if let Err(e) = docker::check_docker().await {
+        anyhow::bail!("Docker is unavailable: {}", e);
+    }
+
+    let (job, client) = match target.parse::<i64>() {
+        Ok(id) => {
+            let client = client.with_echo();
+            let job = client
+                .claim_job_id(id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Job {} isn't queued, or needs labels or an agent this one doesn't match", id))?;
+            (job, client)
+        }
+        Err(_) => {
+            let json = match target {
+                "-" => std::io::read_to_string(std::io::stdin())?,
+                json if json.trim_start().starts_with('{') => json.to_string(),
+                path => std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?,
+            };
+            (local_job(&json)?, client.offline())
+        }
+    };
+
+    // Ctrl-C hands a claimed job back to the queue and removes its containers
+    let (requeue_tx, requeue_rx) = watch::channel(false);
+    let run = process_job(job, &client, config, github_app, requeue_rx);
+    tokio::pin!(run);
+    tokio::select! {
+        code = &mut run => return Ok(code),
+        _ = shutdown_signal() => {}
+    }
+    let _ = requeue_tx.send(true);
+    run.await;
+    Ok(130)
+}
+
+/// A job to run without a server. Fields only the server assigns may be left out.
+fn local_job(json: &str) -> Result<ClaimedJob> {
+    let mut job: serde_json::Value = serde_json::from_str(json).context("Job is not valid JSON")?;
+    let Some(fields) = job.as_object_mut() else {
+        anyhow::bail!("Job must be a JSON object");
+    };
+    for (field, default) in [
+        ("id", serde_json::json!(0)),
+        ("repo_id", serde_json::json!(0)),
+        ("git_ref", serde_json::json!("refs/heads/main")),
+        ("image", serde_json::json!("ubuntu:24.04")),
+        ("claim_token", serde_json::json!(uuid::Uuid::nil())),
+    ] {
+        fields.entry(field).or_insert(default);
+    }
+    serde_json::from_value(job).context("Invalid job")
+}
+
+/// Claim a job if Docker is reachable. Otherwise only check in, so the dashboard shows
+/// why this agent is idle, and hand back any job a server that predates
+/// `docker_error` gives out anyway.
+async fn claim_next(client: &ServerClient, docker: Result<(), String>) -> Result<Option<ClaimedJob>> {
+    let docker_error = match docker {
+        Ok(()) => return client.claim_job(None).await,
+        Err(e) => e,
+    };
+
+    if let Some(job) = client.claim_job(Some(docker_error.clone())).await? {
+        client.forget_secrets(job.id);
+        client
+            .release(&job, &format!("Agent can't reach Docker ({})", docker_error))
+            .await?;
+    }
+    Ok(None)
+}
+
+/// Resolve on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Run a claimed job and report its outcome to GitHub and the server. Returns the
+/// exit status for `--once`: 0 on success, else the build's exit code or 1.
+async fn process_job(
+    job: ClaimedJob,
+    client: &ServerClient,
+    config: &Config,
+    github_app: Option<&GitHubApp>,
+    mut requeue: watch::Receiver<bool>,
+) -> i32 {
+    info!(
+        "Claimed job {} for {}/{} @ {}",
+        job.id,
+        job.repo_owner,
+        job.repo_name,
+        &job.git_sha[..8.min(job.git_sha.len())]
+    );
+
+    let started = std::time::Instant::now();
+    let details_url = job_details_url(&job, config.public_url.as_deref());
+    // A job run without a server isn't reported on its commit
+    let reporter = github_app.filter(|_| !client.is_offline());
+    let report = match reporter {
+        Some(app) => start_report(app, client, &job, details_url.as_deref()).await,
+        None => GitHubReport::None,
+    };
+
+    // Race the build against the server-side cancel flag, heartbeating as it runs
+    let outcome = tokio::select! {
+        result = docker::run_job(client, &job, config, github_app) => Some(result),
+        _ = docker::wait_for_cancel(client, &job, config.poll_interval_secs) => None,
+        _ = docker::keep_alive(client, &job, HEARTBEAT_INTERVAL) => {
+            if !matches!(client.job_status(job.id).await, Ok(JobStatus::Cancelled)) {
+                // The server requeued the job after missed heartbeats; whoever runs it
+                // next reports to GitHub and the server
+                warn!("Job {} is no longer ours, abandoning it", job.id);
+                docker::abort_job(&job, config).await;
+                client.forget_secrets(job.id);
+                return 1;
+            }
+            None
+        }
+        true = async { requeue.wait_for(|requeue| *requeue).await.is_ok() } => {
+            // Another agent reruns the build from scratch and reports it
+            let reason = format!("Agent {} shut down before the job finished", config.agent_id);
+            if let Err(e) = client.release(&job, &reason).await {
+                error!("Failed to requeue job {}: {}", job.id, e);
+            }
+            docker::abort_job(&job, config).await;
+            client.forget_secrets(job.id);
+            return 1;
+        }
+    };
+
+    if let Some(Err(e)) = &outcome {
+        if let Some(missing) = e.downcast_ref::<docker::MissingLabels>() {
+            info!("Job {} needs other labels, requeuing it: {}", job.id, missing);
+            if let Err(e) = client.release(&job, &missing.to_string()).await {
+                error!("Failed to requeue job {}: {}", job.id, e);
+            }
+            docker::abort_job(&job, config).await;
+            client.forget_secrets(job.id);
+            return 1;
+        }
+    }
+
+    let timed_out = matches!(&outcome, Some(Err(e)) if e.is::<docker::TimedOut>());
+
+    // A job that broke because Docker went away says nothing about the commit; requeue
+    // it for an agent that can run it
+    if let Some(Err(e)) = &outcome {
+        if !timed_out && !e.is::<docker::BuildFailed>() {
+            if let Err(docker_error) = docker::check_docker().await {
+                warn!("Job {} failed while Docker is unavailable, requeuing it: {}", job.id, docker_error);
+                let reason = format!("Agent {} lost its connection to Docker ({})", config.agent_id, docker_error);
+                if let Err(e) = client.release(&job, &reason).await {
+                    error!("Failed to requeue job {}: {}", job.id, e);
                 }
+                docker::abort_job(&job, config).await;
+                client.forget_secrets(job.id);
+                return 1;
+            }
+        }
+    }
+    let mut provenance = None;
+    let (success, cancelled, error_msg, exit_code) = match outcome {
+        Some(Ok(recorded)) => {
+            info!("Job {} completed successfully", job.id);
+            provenance = recorded;
+            (true, false, None, Some(0))
+        }
+        Some(Err(e)) => {
+            let message = client.redact(job.id, &e.to_string());
+            error!("Job {} failed: {}", job.id, message);
+            let _ = client.log_raw(job.id, &job.claim_token, &format!("ERROR: {}", message), LogLevel::Error).await;
+            let exit_code = e.downcast_ref::<docker::BuildFailed>().and_then(|f| f.exit_code);
+            (false, false, Some(message), exit_code)
+        }
+        None => {
+            info!("Job {} was cancelled", job.id);
+            docker::abort_job(&job, config).await;
+            (false, true, None, None)
+        }
+    };
 
-                if let Err(e) = client.finish(&job, success).await {
-                    error!("Failed to report job completion: {}", e);
+    match (reporter, report) {
+        (Some(app), GitHubReport::CheckRun(check_id)) => {
+            let logs = match client.get_logs(&job).await {
+                Ok(logs) => Some(logs),
+                Err(e) => {
+                    warn!("Failed to fetch logs: {}", e);
+                    None
                 }
+            };
+
+            let (conclusion, summary) = if success {
+                let mut summary = "Build completed successfully! ✅".to_string();
+                if let Some(provenance) = &provenance {
+                    summary.push_str("\n\n");
+                    summary.push_str(&provenance.markdown());
+                }
+                (CheckConclusion::Success, summary)
+            } else if cancelled {
+                (CheckConclusion::Cancelled, "Build cancelled 🛑".to_string())
+            } else if timed_out {
+                let summary = format!("Build timed out ⏰\n\n{}", error_msg.unwrap_or_default());
+                (CheckConclusion::TimedOut, summary)
+            } else {
+                let summary = format!(
+                    "Build failed ❌\n\n{}",
+                    error_msg.unwrap_or_default()
+                );
+                (CheckConclusion::Failure, summary)
+            };
+
+            if let Err(e) = app
+                .complete_check_run(
+                    &job.repo_owner,
+                    &job.repo_name,
+                    check_id,
+                    conclusion,
+                    &summary,
+                    logs.as_deref(),
+                )
+                .await
+            {
+                warn!("Failed to complete check run: {}", e);
             }
-            Ok(None) => {
-                tokio::time::sleep(Duration::from_secs(config.poll_interval_secs)).await;
+        }
+        (Some(app), GitHubReport::Status) => {
+            if let Err(e) = app
+                .create_commit_status(
+                    &job.repo_owner,
+                    &job.repo_name,
+                    &job.git_sha,
+                    status_context(&job),
+                    commit_status(success, cancelled, timed_out),
+                    details_url.as_deref(),
+                )
+                .await
+            {
+                warn!("Failed to update commit status: {}", e);
             }
-            Err(e) => {
-                warn!("Failed to claim job: {}", e);
-                tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        _ => {}
+    }
+
+    // Matrix combinations would overwrite each other's comment, so only their parent posts
+    if let (Some(app), Some(pr)) = (reporter, pr_number(&job.git_ref)) {
+        if job.matrix.is_empty() {
+            let (marker, body) = pr_comment(
+                &job,
+                success,
+                cancelled,
+                timed_out,
+                started.elapsed(),
+                details_url.as_deref(),
+            );
+            if let Err(e) = app
+                .create_or_update_pr_comment(&job.repo_owner, &job.repo_name, pr, &marker, &body)
+                .await
+            {
+                warn!("Failed to comment on PR #{}: {}", pr, e);
             }
         }
     }
+
+    // A cancelled job was already finished server-side
+    if !cancelled {
+        if let Err(e) = client.finish(&job, success, exit_code).await {
+            error!("Failed to report job completion: {}", e);
+        }
+    }
+    client.forget_secrets(job.id);
+
+    match exit_code {
+        _ if success => 0,
+        Some(code) if code != 0 => code,
+        _ => 1,
+    }
+}
+
+/// How a job's result is shown on its commit
+enum GitHubReport {
+    CheckRun(i64),
+    /// Plain commit status, for installations that can't create check runs
+    Status,
+    None,
+}
+
+/// Dashboard link for the job: the server's, or one built from `FOUNDRY_PUBLIC_URL`
+fn job_details_url(job: &ClaimedJob, public_url: Option<&str>) -> Option<String> {
+    job.details_url.clone().or_else(|| {
+        public_url.map(|url| format!("{}/j/{}", url.trim_end_matches('/'), job.public_id))
+    })
+}
+
+/// Commit status context: the repo's `[github] context`, or `foundry`
+fn status_context(job: &ClaimedJob) -> &str {
+    job.check_context.as_deref().unwrap_or("foundry")
+}
+
+/// Check run name: the repo's `[github] context`, or `Foundry CI`
+fn check_run_name(job: &ClaimedJob) -> &str {
+    job.check_context.as_deref().unwrap_or("Foundry CI")
+}
+
+/// Final commit status and description for a job's outcome
+fn commit_status(success: bool, cancelled: bool, timed_out: bool) -> (CommitStatus, &'static str) {
+    if success {
+        (CommitStatus::Success, "Build succeeded")
+    } else if cancelled {
+        (CommitStatus::Error, "Build cancelled")
+    } else if timed_out {
+        (CommitStatus::Failure, "Build timed out")
+    } else {
+        (CommitStatus::Failure, "Build failed")
+    }
+}
+
+/// Pull request number of a `refs/pull/<n>/head` job
+fn pr_number(git_ref: &str) -> Option<u64> {
+    git_ref.strip_prefix("refs/pull/")?.split('/').next()?.parse().ok()
+}
+
+/// Hidden marker and body of the job's PR comment. Each monorepo service keeps its
+/// own comment; later builds of the PR edit it.
+fn pr_comment(
+    job: &ClaimedJob,
+    success: bool,
+    cancelled: bool,
+    timed_out: bool,
+    duration: Duration,
+    details_url: Option<&str>,
+) -> (String, String) {
+    let marker = match &job.service_path {
+        Some(service) => format!("<!-- foundry-ci:{} -->", service),
+        None => "<!-- foundry-ci -->".to_string(),
+    };
+
+    let icon = if success {
+        "✅"
+    } else if cancelled {
+        "🛑"
+    } else if timed_out {
+        "⏰"
+    } else {
+        "❌"
+    };
+    let (_, description) = commit_status(success, cancelled, timed_out);
+    let secs = duration.as_secs();
+    let took = if secs >= 60 { format!("{}m {}s", secs / 60, secs % 60) } else { format!("{}s", secs) };
+
+    let mut body = format!("{}\n{} **{}**", marker, icon, description);
+    if let Some(service) = &job.service_path {
+        body.push_str(&format!(" for `{}`", service));
+    }
+    body.push_str(&format!(" at {} in {}", &job.git_sha[..7.min(job.git_sha.len())], took));
+    if let Some(url) = details_url {
+        body.push_str(&format!("\n\n[View build on Foundry]({})", url));
+    }
+    (marker, body)
+}
+
+/// Mark the commit as building: with a check run, or a pending commit status if
+/// the check run can't be created
+async fn start_report(
+    app: &GitHubApp,
+    client: &ServerClient,
+    job: &ClaimedJob,
+    details_url: Option<&str>,
+) -> GitHubReport {
+    if let Some(id) = start_check_run(app, client, job, details_url).await {
+        return GitHubReport::CheckRun(id);
+    }
+
+    match app
+        .create_commit_status(
+            &job.repo_owner,
+            &job.repo_name,
+            &job.git_sha,
+            status_context(job),
+            (CommitStatus::Pending, "Build in progress"),
+            details_url,
+        )
+        .await
+    {
+        Ok(()) => GitHubReport::Status,
+        Err(e) => {
+            warn!("Failed to create commit status: {}", e);
+            GitHubReport::None
+        }
+    }
+}
+
+/// Get the job's check run in progress. A check run stored by an earlier attempt is
+/// reused so retries don't pile up duplicate runs on the commit.
+async fn start_check_run(
+    app: &GitHubApp,
+    client: &ServerClient,
+    job: &ClaimedJob,
+    details_url: Option<&str>,
+) -> Option<i64> {
+    if let Some(id) = job.check_run_id {
+        match app
+            .restart_check_run(&job.repo_owner, &job.repo_name, id, details_url)
+            .await
+        {
+            Ok(()) => {
+                info!("Reusing check run {} for job {}", id, job.id);
+                return Some(id);
+            }
+            Err(e) => warn!("Failed to reuse check run {}, creating a new one: {}", id, e),
+        }
+    }
+
+    info!("Creating GitHub check run for {}/{}", job.repo_owner, job.repo_name);
+    match app
+        .create_check_run(
+            &job.repo_owner,
+            &job.repo_name,
+            &job.git_sha,
+            check_run_name(job),
+            details_url,
+        )
+        .await
+    {
+        Ok(id) => {
+            info!("Created check run with ID {}", id);
+            // Lets the check run's Cancel button find this job
+            if let Err(e) = client.set_check_run(job, id).await {
+                warn!("Failed to store check run for job {}: {}", job.id, e);
+            }
+            Some(id)
+        }
+        Err(e) => {
+            warn!("Failed to create check run: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(details_url: Option<&str>) -> ClaimedJob {
+        serde_json::from_value(serde_json::json!({
+            "id": 7,
+            "public_id": "abcd2345",
+            "repo_id": 1,
+            "repo_owner": "acme",
+            "repo_name": "app",
+            "clone_url": "https://github.com/acme/app.git",
+            "git_sha": "abc123",
+            "git_ref": "refs/heads/main",
+            "image": "ubuntu:latest",
+            "claim_token": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "details_url": details_url,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pr_comment() {
+        let mut pr_job = job(None);
+        pr_job.git_ref = "refs/pull/42/head".to_string();
+        assert_eq!(pr_number(&pr_job.git_ref), Some(42));
+        assert_eq!(pr_number("refs/heads/main"), None);
+
+        let (marker, body) = pr_comment(
+            &pr_job,
+            false,
+            false,
+            false,
+            Duration::from_secs(135),
+            Some("https://ci.example.com/j/abcd2345"),
+        );
+        assert_eq!(marker, "<!-- foundry-ci -->");
+        assert_eq!(
+            body,
+            "<!-- foundry-ci -->\n❌ **Build failed** at abc123 in 2m 15s\n\n[View build on Foundry](https://ci.example.com/j/abcd2345)"
+        );
+
+        // Services comment separately, so one passing doesn't hide another failing
+        pr_job.service_path = Some("services/api".to_string());
+        let (marker, body) = pr_comment(&pr_job, true, false, false, Duration::from_secs(9), None);
+        assert_eq!(marker, "<!-- foundry-ci:services/api -->");
+        assert!(body.ends_with("✅ **Build succeeded** for `services/api` at abc123 in 9s"), "{}", body);
+    }
+
+    #[test]
+    fn test_job_details_url() {
+        assert_eq!(
+            job_details_url(&job(None), Some("https://ci.example.com/")).as_deref(),
+            Some("https://ci.example.com/j/abcd2345")
+        );
+        // The server's link wins, since it knows its own URL best
+        assert_eq!(
+            job_details_url(&job(Some("https://server.example.com/j/abcd2345")), Some("https://ci.example.com")).as_deref(),
+            Some("https://server.example.com/j/abcd2345")
+        );
+        assert_eq!(job_details_url(&job(None), None), None);
+    }
+
+    #[test]
+    fn test_check_context() {
+        // Unset keeps the names branch protection rules already require
+        let mut job = job(None);
+        assert_eq!((status_context(&job), check_run_name(&job)), ("foundry", "Foundry CI"));
+        job.check_context = Some("foundry/test".to_string());
+        assert_eq!((status_context(&job), check_run_name(&job)), ("foundry/test", "foundry/test"));
+    }
+
+    #[test]
+    fn test_commit_status_for_outcome() {
+        assert_eq!(commit_status(true, false, false).0, CommitStatus::Success);
+        assert_eq!(commit_status(false, true, false).0, CommitStatus::Error);
+        assert_eq!(commit_status(false, false, true), (CommitStatus::Failure, "Build timed out"));
+        assert_eq!(commit_status(false, false, false), (CommitStatus::Failure, "Build failed"));
+    }
+
+    /// Answers claims with `claim` and every other request with `{"ok":true}`, recording
+    /// each request's path and JSON body
+    async fn fake_server(
+        claim: serde_json::Value,
+    ) -> (Config, Arc<std::sync::Mutex<Vec<(String, serde_json::Value)>>>, tokio::task::JoinHandle<()>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::for_tests();
+        config.server_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let server = {
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    let (head_len, body_len) = loop {
+                        let n = stream.read(&mut chunk).await.unwrap();
+                        buf.extend_from_slice(&chunk[..n]);
+                        let text = String::from_utf8_lossy(&buf).to_string();
+                        if let Some(end) = text.find("\r\n\r\n") {
+                            let length = text[..end]
+                                .lines()
+                                .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length: ").map(|v| v.trim().parse().unwrap()))
+                                .unwrap_or(0);
+                            break (end + 4, length);
+                        }
+                    };
+                    while buf.len() < head_len + body_len {
+                        let n = stream.read(&mut chunk).await.unwrap();
+                        buf.extend_from_slice(&chunk[..n]);
+                    }
+
+                    let path = String::from_utf8_lossy(&buf).split_whitespace().nth(1).unwrap().to_string();
+                    let body = serde_json::from_slice(&buf[head_len..]).unwrap_or_default();
+                    let response = if path == "/agent/claim" { claim.to_string() } else { r#"{"ok":true}"#.to_string() };
+                    requests.lock().unwrap().push((path, body));
+
+                    let reply = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        response.len(),
+                        response
+                    );
+                    stream.write_all(reply.as_bytes()).await.unwrap();
+                }
+            })
+        };
+
+        (config, requests, server)
+    }
+
+    #[tokio::test]
+    async fn test_no_claim_while_docker_is_unavailable() {
+        let claimed = serde_json::json!({"status": "claimed", "job": job(None)});
+        let (config, requests, server) = fake_server(claimed).await;
+        let client = ServerClient::new(&config).unwrap();
+
+        // Checks in with the reason, and hands back the job an older server gave out anyway
+        let result = claim_next(&client, Err("Cannot connect to the Docker daemon".to_string())).await.unwrap();
+        assert!(result.is_none());
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert_eq!(requests[0].0, "/agent/claim");
+            assert_eq!(requests[0].1["docker_error"], "Cannot connect to the Docker daemon");
+            assert_eq!(requests[1].0, "/agent/release");
+            assert_eq!(requests[1].1["job_id"], 7);
+        }
+
+        // Claims normally once Docker answers
+        let result = claim_next(&client, Ok(())).await.unwrap();
+        assert_eq!(result.map(|job| job.id), Some(7));
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 3);
+            assert!(requests[2].1["docker_error"].is_null());
+        }
+        server.abort();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |list: &[&str]| parse_args(list.iter().map(|a| a.to_string()));
+        assert_eq!(args(&[]).unwrap(), None);
+        assert_eq!(args(&["--once", "42"]).unwrap().as_deref(), Some("42"));
+        assert_eq!(args(&["--once", "job.json"]).unwrap().as_deref(), Some("job.json"));
+        assert!(args(&["--once"]).is_err());
+        assert!(args(&["--once", "1", "2"]).is_err());
+        assert!(args(&["--run"]).is_err());
+    }
+
+    #[test]
+    fn test_local_job() {
+        let job = local_job(
+            r#"{"repo_owner": "acme", "repo_name": "app", "clone_url": "https://github.com/acme/app.git", "git_sha": "abc123"}"#,
+        )
+        .unwrap();
+        assert_eq!(job.id, 0);
+        assert_eq!(job.git_ref, "refs/heads/main");
+        assert_eq!(job.image, "ubuntu:24.04");
+
+        let job = local_job(
+            r#"{"repo_owner": "acme", "repo_name": "app", "clone_url": "https://github.com/acme/app.git", "git_sha": "abc123", "image": "rust:1"}"#,
+        )
+        .unwrap();
+        assert_eq!(job.image, "rust:1");
+
+        assert!(local_job(r#"{"repo_owner": "acme"}"#).is_err());
+        assert!(local_job("[]").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_once_client() {
+        let claimed = serde_json::json!({"status": "claimed", "job": job(None)});
+        let (config, requests, server) = fake_server(claimed).await;
+
+        // A job given by id is claimed by id
+        let client = ServerClient::new(&config).unwrap().with_echo();
+        let job = client.claim_job_id(7).await.unwrap().unwrap();
+        assert_eq!(requests.lock().unwrap()[0].1["job_id"], 7);
+
+        // A job given as JSON never reaches the server
+        let client = ServerClient::new(&config).unwrap().offline();
+        client.log(&job, "building").await.unwrap();
+        assert!(client.heartbeat(&job).await.unwrap());
+        client.finish(&job, true, Some(0)).await.unwrap();
+        assert!(client.fan_out(&job, &["api"]).await.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        server.abort();
+    }
 }
@@ -0,0 +1,176 @@
+//! Build provenance: what was built, from which commit, in which image, by which agent
+
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use foundry_core::ClaimedJob;
+use serde::Serialize;
+use tokio::process::Command;
+
+use crate::server::ServerClient;
+
+/// Artifact name the provenance record is uploaded under
+pub const PROVENANCE_ARTIFACT: &str = "provenance.json";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Provenance {
+    /// Agent that ran the build
+    pub builder: String,
+    pub job_id: i64,
+    pub repository: String,
+    pub git_ref: String,
+    /// Commit actually checked out, which for scheduled builds is only known after cloning
+    pub git_sha: String,
+    pub image: String,
+    /// Registry digest of the image, or its local image id when it was never pushed
+    pub image_digest: Option<String>,
+    pub started_at: String,
+    pub finished_at: String,
+}
+
+impl Provenance {
+    /// Record provenance for a build that just finished in `repo_dir` using `image`
+    pub async fn collect(
+        agent_id: &str,
+        job: &ClaimedJob,
+        repo_dir: &Path,
+        image: &str,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        let git_sha = command_output("git", &["rev-parse", "HEAD"], Some(repo_dir))
+            .await
+            .unwrap_or_else(|| job.git_sha.clone());
+
+        Self {
+            builder: agent_id.to_string(),
+            job_id: job.id,
+            repository: job.clone_url.clone(),
+            git_ref: job.git_ref.clone(),
+            git_sha,
+            image: image.to_string(),
+            image_digest: image_digest(image).await,
+            started_at: started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            finished_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        }
+    }
+
+    /// Markdown block for the check run summary
+    pub fn markdown(&self) -> String {
+        format!(
+            "### Provenance\n\n\
+             | | |\n\
+             |---|---|\n\
+             | Builder | `{}` |\n\
+             | Job | {} |\n\
+             | Repository | {} |\n\
+             | Ref | `{}` |\n\
+             | Commit | `{}` |\n\
+             | Image | `{}` |\n\
+             | Image digest | `{}` |\n\
+             | Started | {} |\n\
+             | Finished | {} |\n",
+            self.builder,
+            self.job_id,
+            self.repository,
+            self.git_ref,
+            self.git_sha,
+            self.image,
+            self.image_digest.as_deref().unwrap_or("unknown"),
+            self.started_at,
+            self.finished_at,
+        )
+    }
+
+    /// Write the record into `dir` and upload it as the job's `provenance.json` artifact
+    pub async fn publish(&self, client: &ServerClient, job: &ClaimedJob, dir: &Path) -> Result<()> {
+        let path = dir.join(PROVENANCE_ARTIFACT);
+        tokio::fs::write(&path, serde_json::to_vec_pretty(self)?).await?;
+        client.upload_artifact(job, PROVENANCE_ARTIFACT, &path).await
+    }
+}
+
+async fn image_digest(image: &str) -> Option<String> {
+    let inspect = |format: &'static str| {
+        let args = ["image", "inspect", "--format", format, image];
+        async move { command_output("docker", &args, None).await }
+    };
+    match inspect("{{join .RepoDigests \",\"}}").await {
+        Some(digests) if !digests.is_empty() => digests.split(',').next().map(str::to_string),
+        _ => inspect("{{.Id}}").await,
+    }
+}
+
+/// Trimmed stdout of a successful command, or `None` if it fails
+async fn command_output(program: &str, args: &[&str], dir: Option<&Path>) -> Option<String> {
+    let mut command = Command::new(program);
+    command.args(args);
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let output = command.output().await.ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_provenance_records_build() {
+        let repo = std::env::temp_dir().join(format!("foundry-provenance-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&repo).unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .args(["-c", "user.name=Foundry", "-c", "user.email=ci@example.com"])
+                .args(args)
+                .current_dir(&repo)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        git(&["init", "--quiet"]);
+        git(&["commit", "--quiet", "--allow-empty", "-m", "initial"]);
+        let head = git(&["rev-parse", "HEAD"]);
+
+        // Scheduled jobs only learn their commit after cloning
+        let job: ClaimedJob = serde_json::from_value(serde_json::json!({
+            "id": 42,
+            "repo_id": 1,
+            "repo_owner": "acme",
+            "repo_name": "app",
+            "clone_url": "https://github.com/acme/app.git",
+            "git_sha": "RESOLVE:main",
+            "git_ref": "refs/heads/main",
+            "image": "ubuntu:latest",
+            "claim_token": "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        }))
+        .unwrap();
+        let started = Utc::now();
+
+        let provenance = Provenance::collect("agent-1", &job, &repo, "foundry-test-missing:latest", started).await;
+
+        assert_eq!(provenance.builder, "agent-1");
+        assert_eq!(provenance.job_id, 42);
+        assert_eq!(provenance.repository, "https://github.com/acme/app.git");
+        assert_eq!(provenance.git_ref, "refs/heads/main");
+        assert_eq!(provenance.git_sha, head);
+        assert_eq!(provenance.image, "foundry-test-missing:latest");
+        assert_eq!(provenance.image_digest, None);
+        assert_eq!(provenance.started_at, started.to_rfc3339_opts(SecondsFormat::Secs, true));
+        assert!(provenance.finished_at >= provenance.started_at);
+
+        let json = serde_json::to_value(&provenance).unwrap();
+        for field in [
+            "builder", "job_id", "repository", "git_ref", "git_sha", "image", "image_digest", "started_at", "finished_at",
+        ] {
+            assert!(json.get(field).is_some(), "missing {}", field);
+        }
+        assert!(provenance.markdown().contains(&format!("| Commit | `{}` |", head)));
+        std::fs::remove_dir_all(&repo).unwrap();
+    }
+}
@@ -1,10 +1,15 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
 use anyhow::{Context, Result};
 use reqwest::Client;
 use tracing::debug;
 
 use foundry_core::{
-    ApiResponse, ClaimRequest, ClaimResponse, ClaimedJob, FinishRequest, LogRequest,
-    SyncScheduleRequest, SyncTriggersRequest,
+    config::MatrixEntry, docker_arch, ApiResponse, CheckRunRequest, ClaimRequest, ClaimResponse, ClaimedJob,
+    DeploymentRequest, FanOutRequest, FinishRequest, HeartbeatRequest, JobLimits, JobStatus, JobStatusResponse,
+    LimitsRequest, LogLevel, LogRequest, MatrixRequest, PreviewRequest, ReleaseRequest, SyncCheckContextRequest, SyncConcurrencyRequest, SyncNotifyRequest, SyncRunsOnRequest, SyncScheduleRequest, SyncTriggersRequest,
+    PROTOCOL_HEADER, PROTOCOL_VERSION,
 };
 
 use crate::config::Config;
@@ -14,21 +19,73 @@ pub struct ServerClient {
     client: Client,
     server_url: String,
     agent_id: String,
+    labels: Vec<String>,
+    /// Secret values to mask in each running job's log, longest first
+    redactions: Arc<RwLock<HashMap<i64, Vec<String>>>>,
+    /// Also print job log lines to stdout, for `--once`
+    echo: bool,
+    /// No server behind this client: a `--once` job read from a file. Reports are
+    /// dropped and the job is never cancelled.
+    offline: bool,
 }
 
 impl ServerClient {
-    pub fn new(config: &Config) -> Self {
-        Self {
-            client: Client::new(),
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(PROTOCOL_HEADER, reqwest::header::HeaderValue::from(PROTOCOL_VERSION));
+        if let Some(token) = &config.agent_token {
+            let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+                .context("FOUNDRY_AGENT_TOKEN contains invalid characters")?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+
+        Ok(Self {
+            client: Client::builder().default_headers(headers).build()?,
             server_url: config.server_url.clone(),
             agent_id: config.agent_id.clone(),
-        }
+            labels: config.labels.clone(),
+            redactions: Arc::default(),
+            echo: false,
+            offline: false,
+        })
+    }
+
+    /// Print job log lines to stdout as well as sending them
+    pub fn with_echo(self) -> Self {
+        Self { echo: true, ..self }
     }
 
-    pub async fn claim_job(&self) -> Result<Option<ClaimedJob>> {
+    /// Print job log lines to stdout instead of talking to the server
+    pub fn offline(self) -> Self {
+        Self { echo: true, offline: true, ..self }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Ask for a job. With `docker_error` set this only checks in, reporting why the
+    /// agent can't run builds.
+    pub async fn claim_job(&self, docker_error: Option<String>) -> Result<Option<ClaimedJob>> {
+        self.claim(docker_error, None).await
+    }
+
+    /// Claim job `job_id`, if it's queued and this agent can run it
+    pub async fn claim_job_id(&self, job_id: i64) -> Result<Option<ClaimedJob>> {
+        self.claim(None, Some(job_id)).await
+    }
+
+    async fn claim(&self, docker_error: Option<String>, job_id: Option<i64>) -> Result<Option<ClaimedJob>> {
         let url = format!("{}/agent/claim", self.server_url);
         let req = ClaimRequest {
             agent_id: self.agent_id.clone(),
+            agent_version: Some(foundry_core::build_info!().to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+            docker_error,
+            arch: Some(docker_arch(std::env::consts::ARCH).to_string()),
+            labels: self.labels.clone(),
+            job_id,
         };
 
         let response = self
@@ -54,46 +111,68 @@ impl ServerClient {
         })?;
 
         match resp {
-            ClaimResponse::Claimed { job } => Ok(Some(job)),
+            ClaimResponse::Claimed { job } => {
+                let values = redacted_values(job.secrets.0.values());
+                if !values.is_empty() {
+                    self.redactions.write().unwrap().insert(job.id, values);
+                }
+                Ok(Some(*job))
+            }
             ClaimResponse::Empty => Ok(None),
+            ClaimResponse::Rejected { error } => anyhow::bail!("Server refused to hand out work: {}", error),
         }
     }
 
+    /// Log an agent status message
     pub async fn log(&self, job: &ClaimedJob, line: &str) -> Result<()> {
-        let url = format!("{}/agent/log", self.server_url);
-        let req = LogRequest {
-            job_id: job.id,
-            claim_token: job.claim_token,
-            line: line.to_string(),
-        };
-
-        debug!("[job {}] {}", job.id, line);
+        self.log_raw(job.id, &job.claim_token, line, LogLevel::Info).await
+    }
 
-        let resp: ApiResponse = self
-            .client
-            .post(&url)
-            .json(&req)
-            .send()
-            .await?
-            .json()
-            .await?;
+    /// Mask the job's secret values in `text`
+    pub fn redact(&self, job_id: i64, text: &str) -> String {
+        match self.redactions.read().unwrap().get(&job_id) {
+            Some(values) => redact(text, values),
+            None => text.to_string(),
+        }
+    }
 
-        if !resp.ok {
-            anyhow::bail!("Server rejected log: {:?}", resp.error);
+    /// Also mask `value` in the job's log from now on
+    pub fn add_redaction(&self, job_id: i64, value: &str) {
+        let mut redactions = self.redactions.write().unwrap();
+        let values = redactions.entry(job_id).or_default();
+        for value in redacted_values(std::iter::once(&value.to_string())) {
+            if !values.contains(&value) {
+                values.push(value);
+            }
         }
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+    }
 
-        Ok(())
+    /// Stop tracking a finished job's secrets
+    pub fn forget_secrets(&self, job_id: i64) {
+        self.redactions.write().unwrap().remove(&job_id);
     }
 
-    pub async fn log_raw(&self, job_id: i64, claim_token: &uuid::Uuid, line: &str) -> Result<()> {
+    pub async fn log_raw(&self, job_id: i64, claim_token: &uuid::Uuid, line: &str, level: LogLevel) -> Result<()> {
         let url = format!("{}/agent/log", self.server_url);
+        let line = self.redact(job_id, line);
+        if self.echo {
+            match level {
+                LogLevel::Info => println!("{}", line),
+                LogLevel::Warn | LogLevel::Error => eprintln!("{}", line),
+            }
+        }
+        if self.offline {
+            return Ok(());
+        }
         let req = LogRequest {
             job_id,
             claim_token: *claim_token,
-            line: line.to_string(),
+            line,
+            level,
         };
 
-        debug!("[job {}] {}", job_id, line);
+        debug!("[job {}] {}", job_id, req.line);
 
         let resp: ApiResponse = self
             .client
@@ -111,12 +190,16 @@ impl ServerClient {
         Ok(())
     }
 
-    pub async fn finish(&self, job: &ClaimedJob, success: bool) -> Result<()> {
+    pub async fn finish(&self, job: &ClaimedJob, success: bool, exit_code: Option<i32>) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
         let url = format!("{}/agent/finish", self.server_url);
         let req = FinishRequest {
             job_id: job.id,
             claim_token: job.claim_token,
             success,
+            exit_code,
         };
 
         let resp: ApiResponse = self
@@ -136,6 +219,9 @@ impl ServerClient {
     }
 
     pub async fn report_metrics(&self, job: &ClaimedJob, metrics: &crate::docker::JobMetrics) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
         let url = format!("{}/agent/metrics", self.server_url);
         
         #[derive(serde::Serialize)]
@@ -165,7 +251,59 @@ impl ServerClient {
         Ok(())
     }
 
+    /// Record the limits the job's containers run with
+    pub async fn report_limits(&self, job: &ClaimedJob, limits: &JobLimits) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/limits", self.server_url);
+        let req = LimitsRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            limits: limits.clone(),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Server rejected limits: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn job_status(&self, job_id: i64) -> Result<JobStatus> {
+        if self.offline {
+            return Ok(JobStatus::Running);
+        }
+        let url = format!("{}/agent/job/{}/status", self.server_url, job_id);
+
+        let resp = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to fetch job status")?;
+
+        if !resp.status().is_success() {
+            anyhow::bail!("Server returned error: {}", resp.status());
+        }
+
+        let resp: JobStatusResponse = resp.json().await.context("Failed to parse job status")?;
+        Ok(resp.status)
+    }
+
     pub async fn get_logs(&self, job: &ClaimedJob) -> Result<String> {
+        if self.offline {
+            return Ok(String::new());
+        }
         let url = format!("{}/agent/logs/{}", self.server_url, job.id);
 
         let resp = self
@@ -188,6 +326,9 @@ impl ServerClient {
         job: &ClaimedJob,
         schedule: Option<&foundry_core::ScheduleConfig>,
     ) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
         let url = format!("{}/agent/schedule", self.server_url);
         
         let req = SyncScheduleRequest {
@@ -215,11 +356,232 @@ impl ServerClient {
         Ok(())
     }
 
+    /// Hand a job back to the queue without finishing it
+    pub async fn release(&self, job: &ClaimedJob, reason: &str) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/release", self.server_url);
+
+        let req = ReleaseRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            reason: reason.to_string(),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to release job: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn fan_out(&self, job: &ClaimedJob, services: &[&str]) -> Result<()> {
+        if self.offline {
+            anyhow::bail!("Monorepo fan-out needs a server; set service_path in the job to build one service");
+        }
+        let url = format!("{}/agent/fanout", self.server_url);
+
+        let req = FanOutRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            services: services.iter().map(|s| s.to_string()).collect(),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to fan out job: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn fan_out_matrix(&self, job: &ClaimedJob, entries: Vec<MatrixEntry>) -> Result<()> {
+        if self.offline {
+            anyhow::bail!("Matrix builds need a server; set matrix in the job to build one combination");
+        }
+        let url = format!("{}/agent/matrix", self.server_url);
+
+        let req = MatrixRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            entries,
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to fan out matrix: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn record_deployment(&self, deployment: &DeploymentRequest) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/deployment", self.server_url);
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(deployment)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to record deployment: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn record_preview(&self, job: &ClaimedJob, app_name: &str, hostname: &str) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/preview", self.server_url);
+        let req = PreviewRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            app_name: app_name.to_string(),
+            hostname: hostname.to_string(),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to record preview: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn set_check_run(&self, job: &ClaimedJob, check_run_id: i64) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/check_run", self.server_url);
+        let req = CheckRunRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            check_run_id,
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to store check run: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    /// Tell the server this agent is still working on `job`. Returns false once the
+    /// server no longer considers the job ours (reclaimed, cancelled or finished).
+    pub async fn heartbeat(&self, job: &ClaimedJob) -> Result<bool> {
+        if self.offline {
+            return Ok(true);
+        }
+        let url = format!("{}/agent/heartbeat", self.server_url);
+        let req = HeartbeatRequest {
+            job_id: job.id,
+            claim_token: job.claim_token,
+            agent_version: Some(foundry_core::build_info!().to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+        };
+
+        let resp = self.client.post(&url).json(&req).send().await?;
+        if resp.status() == reqwest::StatusCode::FORBIDDEN {
+            return Ok(false);
+        }
+
+        let resp: ApiResponse = resp.json().await?;
+        if !resp.ok {
+            anyhow::bail!("Server rejected heartbeat: {:?}", resp.error);
+        }
+
+        Ok(true)
+    }
+
+    /// Upload a file as a build artifact named `name`
+    pub async fn upload_artifact(&self, job: &ClaimedJob, name: &str, path: &std::path::Path) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/artifact", self.server_url);
+
+        // The server checks the claim before reading the file, so these go first
+        let form = reqwest::multipart::Form::new()
+            .text("job_id", job.id.to_string())
+            .text("claim_token", job.claim_token.to_string())
+            .part("file", reqwest::multipart::Part::file(path).await?.file_name(name.to_string()));
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .multipart(form)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to upload artifact: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
     pub async fn sync_triggers(
         &self,
         job: &ClaimedJob,
         triggers: &foundry_core::config::TriggersConfig,
     ) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
         let url = format!("{}/agent/triggers", self.server_url);
         
         let req = SyncTriggersRequest {
@@ -228,6 +590,10 @@ impl ServerClient {
             branches: triggers.branches.clone(),
             pull_requests: triggers.pull_requests,
             pr_target_branches: triggers.pr_target_branches.clone(),
+            paths: triggers.paths.clone(),
+            paths_ignore: triggers.paths_ignore.clone(),
+            tags: triggers.tags.clone(),
+            releases: triggers.releases,
         };
 
         let resp: ApiResponse = self
@@ -245,4 +611,187 @@ impl ServerClient {
 
         Ok(())
     }
+
+    pub async fn sync_notify(
+        &self,
+        job: &ClaimedJob,
+        notify: &foundry_core::config::NotifyConfig,
+    ) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/notify", self.server_url);
+
+        let req = SyncNotifyRequest {
+            repo_id: job.repo_id,
+            claim_token: job.claim_token,
+            slack_webhook: notify.slack_webhook.clone(),
+            discord_webhook: notify.discord_webhook.clone(),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to sync notifications: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn sync_concurrency(
+        &self,
+        job: &ClaimedJob,
+        concurrency: &foundry_core::config::ConcurrencyConfig,
+    ) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/concurrency", self.server_url);
+
+        let req = SyncConcurrencyRequest {
+            repo_id: job.repo_id,
+            claim_token: job.claim_token,
+            group: concurrency.group.clone(),
+            cancel_in_progress: concurrency.cancel_in_progress,
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to sync concurrency group: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn sync_check_context(&self, job: &ClaimedJob, context: Option<&str>) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/check-context", self.server_url);
+
+        let req = SyncCheckContextRequest {
+            repo_id: job.repo_id,
+            claim_token: job.claim_token,
+            context: context.map(String::from),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to sync check context: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+
+    pub async fn sync_runs_on(&self, job: &ClaimedJob, runs_on: &[String]) -> Result<()> {
+        if self.offline {
+            return Ok(());
+        }
+        let url = format!("{}/agent/runs-on", self.server_url);
+
+        let req = SyncRunsOnRequest {
+            repo_id: job.repo_id,
+            claim_token: job.claim_token,
+            runs_on: runs_on.to_vec(),
+        };
+
+        let resp: ApiResponse = self
+            .client
+            .post(&url)
+            .json(&req)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.ok {
+            anyhow::bail!("Failed to sync runs_on: {:?}", resp.error);
+        }
+
+        Ok(())
+    }
+}
+
+/// Strings to mask for a job's secrets: each value, plus each line of multi-line
+/// values since output is logged line by line. Lines too short to identify a
+/// secret (a lone `{` in a JSON key file) are left alone.
+fn redacted_values<'a>(secrets: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    for secret in secrets {
+        values.push(secret.clone());
+        if secret.contains('\n') {
+            values.extend(secret.lines().map(str::trim).filter(|l| l.len() >= 4).map(String::from));
+        }
+    }
+    values.retain(|v| !v.is_empty());
+    // Longest first, so a value containing another is masked whole
+    values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+    values.dedup();
+    values
+}
+
+fn redact(text: &str, values: &[String]) -> String {
+    values.iter().fold(text.to_string(), |text, value| text.replace(value.as_str(), "***"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secret_values() {
+        let secrets = ["npm_abc123".to_string(), "abc".to_string(), String::new()];
+        let values = redacted_values(secrets.iter());
+        assert_eq!(values, vec!["npm_abc123", "abc"]);
+        assert_eq!(
+            redact("//registry/:_authToken=npm_abc123 and abc", &values),
+            "//registry/:_authToken=*** and ***"
+        );
+        assert_eq!(redact("nothing to hide", &values), "nothing to hide");
+    }
+
+    #[test]
+    fn test_redact_multiline_secret_by_line() {
+        let key = "{\n  \"private_key\": \"-----BEGIN KEY-----\"\n}".to_string();
+        let values = redacted_values(std::iter::once(&key));
+        assert_eq!(redact("echo \"private_key\": \"-----BEGIN KEY-----\"", &values), "echo ***");
+        assert_eq!(redact("{ braces }", &values), "{ braces }");
+    }
+
+    #[test]
+    fn test_add_redaction_masks_later_lines() {
+        let client = ServerClient::new(&Config::for_tests()).unwrap();
+        assert_eq!(client.redact(7, "password hunter2"), "password hunter2");
+
+        client.add_redaction(7, "hunter2");
+        client.add_redaction(7, "hunter2");
+        assert_eq!(client.redact(7, "password hunter2"), "password ***");
+        assert_eq!(client.redact(8, "password hunter2"), "password hunter2");
+
+        client.forget_secrets(7);
+        assert_eq!(client.redact(7, "password hunter2"), "password hunter2");
+    }
 }
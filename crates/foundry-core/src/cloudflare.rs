@@ -42,10 +42,14 @@ pub struct IngressRule {
     pub origin_request: Option<OriginRequest>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
 pub struct OriginRequest {
     #[serde(rename = "httpHostHeader", skip_serializing_if = "Option::is_none")]
     pub http_host_header: Option<String>,
+    #[serde(rename = "noTLSVerify", skip_serializing_if = "Option::is_none")]
+    pub no_tls_verify: Option<bool>,
+    #[serde(rename = "originServerName", skip_serializing_if = "Option::is_none")]
+    pub origin_server_name: Option<String>,
 }
 
 impl CloudflareClient {
@@ -157,10 +161,32 @@ impl CloudflareClient {
         Ok(())
     }
 
+    /// The zone's domain, e.g. `example.com`
+    pub async fn zone_name(&self) -> Result<String> {
+        let url = format!("https://api.cloudflare.com/client/v4/zones/{}", self.zone_id);
+
+        let resp: ApiResponse<Zone> = self
+            .client
+            .get(&url)
+            .bearer_auth(&self.api_token)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        if !resp.success {
+            let msg = resp.errors.first().map(|e| e.message.clone()).unwrap_or_default();
+            return Err(anyhow!("Failed to get zone: {}", msg));
+        }
+
+        resp.result.map(|zone| zone.name).ok_or_else(|| anyhow!("No zone in response"))
+    }
+
     pub async fn ensure_dns_record(&self, hostname: &str, tunnel_id: &str) -> Result<()> {
         let cname_target = format!("{}.cfargotunnel.com", tunnel_id);
         
-        let record_name = hostname.split('.').next().unwrap_or(hostname);
+        let zone = self.zone_name().await?;
+        let record_name = record_name(hostname, &zone);
 
         let url = format!(
             "https://api.cloudflare.com/client/v4/zones/{}/dns_records?type=CNAME&name={}",
@@ -265,7 +291,9 @@ impl CloudflareClient {
             .map(|rule| rule.service.clone()))
     }
 
-    pub async fn add_route(&self, hostname: &str, service: &str) -> Result<()> {
+    /// Route `hostname` to `service`. `origin_request` replaces the rule's origin
+    /// settings; `None` keeps whatever an existing rule has.
+    pub async fn add_route(&self, hostname: &str, service: &str, origin_request: Option<OriginRequest>) -> Result<()> {
         let tunnel = self
             .get_tunnel()
             .await?
@@ -280,20 +308,24 @@ impl CloudflareClient {
         });
 
         if let Some(idx) = existing_idx {
-            let old_service = &config.ingress[idx].service;
-            if old_service == service {
+            let rule = &mut config.ingress[idx];
+            let same_origin = origin_request.is_none() || rule.origin_request == origin_request;
+            if rule.service == service && same_origin {
                 tracing::info!("Route already exists and matches: {} -> {}", hostname, service);
                 return Ok(());
             }
-            tracing::info!("Updating route: {} -> {} (was: {})", hostname, service, old_service);
-            config.ingress[idx].service = service.to_string();
+            tracing::info!("Updating route: {} -> {} (was: {})", hostname, service, rule.service);
+            rule.service = service.to_string();
+            if origin_request.is_some() {
+                rule.origin_request = origin_request;
+            }
         } else {
             let catch_all_idx = config.ingress.iter().position(|rule| rule.hostname.is_none());
             
             let new_rule = IngressRule {
                 hostname: Some(hostname.to_string()),
                 service: service.to_string(),
-                origin_request: None,
+                origin_request,
             };
 
             if let Some(idx) = catch_all_idx {
@@ -386,6 +418,35 @@ impl CloudflareClient {
     }
 }
 
+/// Where a pull request's preview of an app is served: `pr-<number>.<preview_domain>`
+pub fn preview_hostname(preview_domain: &str, pr_number: u64) -> String {
+    format!("pr-{}.{}", pr_number, preview_domain.trim_matches('.'))
+}
+
+/// DNS record name for `hostname` within `zone`: the part before the zone, `@` for the
+/// zone apex, or the full name for a host outside the zone
+fn record_name(hostname: &str, zone: &str) -> String {
+    let hostname = hostname.trim_end_matches('.');
+    let zone = zone.trim_end_matches('.');
+    if hostname.eq_ignore_ascii_case(zone) {
+        return "@".to_string();
+    }
+    match hostname.len().checked_sub(zone.len() + 1) {
+        Some(split) if hostname.is_char_boundary(split)
+            && hostname[split..].starts_with('.')
+            && hostname[split + 1..].eq_ignore_ascii_case(zone) =>
+        {
+            hostname[..split].to_string()
+        }
+        _ => hostname.to_string(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Zone {
+    name: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct ConfigWrapper {
     config: TunnelConfig,
@@ -396,3 +457,23 @@ struct DnsRecord {
     id: String,
     content: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_name() {
+        assert_eq!(record_name("app.example.com", "example.com"), "app");
+        assert_eq!(record_name("pr-123.app.example.com", "example.com"), "pr-123.app");
+        assert_eq!(record_name("Example.com.", "example.com"), "@");
+        assert_eq!(record_name("app.example.org", "example.com"), "app.example.org");
+        assert_eq!(record_name("appexample.com", "example.com"), "appexample.com");
+    }
+
+    #[test]
+    fn test_preview_hostname() {
+        assert_eq!(preview_hostname("example.com", 123), "pr-123.example.com");
+        assert_eq!(preview_hostname("preview.example.com.", 7), "pr-7.preview.example.com");
+    }
+}
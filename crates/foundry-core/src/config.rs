@@ -1,4 +1,7 @@
+use globset::{GlobBuilder, GlobSetBuilder};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 
 #[derive(Debug, Clone, Deserialize, Serialize, Default)]
@@ -15,6 +18,20 @@ pub struct FoundryConfig {
     pub stages: Vec<StageConfig>,
     #[serde(default)]
     pub env: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    pub monorepo: Option<MonorepoConfig>,
+    #[serde(default)]
+    pub artifacts: ArtifactsConfig,
+    #[serde(default)]
+    pub secrets: SecretsConfig,
+    #[serde(default)]
+    pub matrix: MatrixConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    #[serde(default)]
+    pub github: GithubConfig,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -39,22 +56,31 @@ fn default_stage_timeout() -> u64 {
     600
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+impl StageConfig {
+    /// Whether this stage runs given the trigger and whether an earlier stage failed.
+    /// After a failure only `always` and `on_failure` stages still run.
+    pub fn should_run(&self, is_pr: bool, previous_failed: bool) -> bool {
+        match self.condition.clone().unwrap_or_default() {
+            StageCondition::Always => true,
+            StageCondition::OnSuccess => !previous_failed,
+            StageCondition::OnFailure => previous_failed,
+            StageCondition::OnPr => is_pr && !previous_failed,
+            StageCondition::OnPush => !is_pr && !previous_failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum StageCondition {
     Always,
+    #[default]
     OnSuccess,
     OnFailure,
     OnPr,
     OnPush,
 }
 
-impl Default for StageCondition {
-    fn default() -> Self {
-        StageCondition::OnSuccess
-    }
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ScheduleConfig {
     pub cron: String,
@@ -78,12 +104,236 @@ pub struct BuildConfig {
     pub command: Option<String>,
     #[serde(default)]
     pub args: Vec<String>,
-    #[serde(default = "default_timeout")]
-    pub timeout: u64,
+    /// Build timeout in seconds; falls back to the agent's default when unset.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// CPU limit passed to `docker run --cpus`, e.g. `1.5`
+    #[serde(default)]
+    pub cpus: Option<f64>,
+    /// Memory limit passed to `docker run --memory`, e.g. `512m` or `2g`
+    #[serde(default)]
+    pub memory: Option<String>,
+    /// User build containers run as, passed to `docker run --user`, e.g. `1000:1000`
+    #[serde(default)]
+    pub user: Option<String>,
+    /// Overrides the image's `ENTRYPOINT`; `""` clears it
+    #[serde(default)]
+    pub entrypoint: Option<Entrypoint>,
+    /// Shell the command is run with, e.g. `sh -c` for images without bash; `bash -lc` when unset
+    #[serde(default)]
+    pub shell: Option<Shell>,
+    /// In-memory mounts passed to `docker run --tmpfs`, e.g. `/tmp` or `/tmp:size=512m`
+    #[serde(default)]
+    pub tmpfs: Vec<String>,
+    /// How the container's stderr shows up in the job log
+    #[serde(default)]
+    pub stderr: StderrMode,
+    /// Directories kept between builds, keyed by name, e.g. `cargo = "/usr/local/cargo/registry"`
+    #[serde(default)]
+    pub cache: std::collections::HashMap<String, String>,
+    /// Where the image built from `dockerfile` is pushed
+    #[serde(default)]
+    pub registry: Option<RegistryConfig>,
+    /// What counts as a passing build, instead of just exit code 0
+    #[serde(default)]
+    pub success_when: SuccessCriteria,
+    /// Commits of history to fetch, 0 for all of it; the agent's `FOUNDRY_CLONE_DEPTH` when unset
+    #[serde(default)]
+    pub clone_depth: Option<u32>,
+    /// Check out submodules recursively; the agent's `FOUNDRY_CLONE_SUBMODULES` when unset
+    #[serde(default)]
+    pub submodules: Option<bool>,
+    /// Keep the checkout between the repo's builds on an agent and update it in place,
+    /// so ignored build output carries over
+    #[serde(default)]
+    pub persistent_workspace: bool,
+    /// Labels an agent needs in `FOUNDRY_AGENT_LABELS` to run the job, e.g. `["gpu"]`
+    #[serde(default)]
+    pub runs_on: Vec<String>,
 }
 
-fn default_timeout() -> u64 {
-    1800
+/// `[build.success_when]`: conditions checked after the build container exits.
+/// Every condition that is set must hold; with none set, exit code 0 passes.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct SuccessCriteria {
+    /// Exit codes that pass, e.g. `[0, 3]`; only 0 when empty
+    #[serde(default)]
+    pub exit_codes: Vec<i32>,
+    /// Regex that some line of output (stdout or stderr) must match, e.g. `'^test result: ok'`
+    #[serde(default)]
+    pub output: Option<String>,
+    /// File the build must leave behind, relative to its working directory
+    #[serde(default)]
+    pub artifact: Option<String>,
+}
+
+impl SuccessCriteria {
+    /// The compiled `output` pattern, matched against each line as it streams
+    pub fn output_regex(&self) -> anyhow::Result<Option<Regex>> {
+        self.output
+            .as_deref()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid build.success_when.output '{}': {}", pattern, e))
+            })
+            .transpose()
+    }
+
+    /// Why a build failed, or `None` if it passed. `exit_code` is `None` for a container
+    /// killed by a signal, and `output_matched` is whether any line matched `output`.
+    pub fn failure(&self, exit_code: Option<i32>, output_matched: bool, work_dir: &Path) -> Option<&'static str> {
+        let exit_ok = match exit_code {
+            Some(code) if self.exit_codes.is_empty() => code == 0,
+            Some(code) => self.exit_codes.contains(&code),
+            None => false,
+        };
+        if !exit_ok {
+            return Some(if self.exit_codes.is_empty() {
+                "Container exited with non-zero status"
+            } else {
+                "Container exit code is not in build.success_when.exit_codes"
+            });
+        }
+        if self.output.is_some() && !output_matched {
+            return Some("No output matched build.success_when.output");
+        }
+        if let Some(artifact) = &self.artifact {
+            if !work_dir.join(artifact).exists() {
+                return Some("Build did not create build.success_when.artifact");
+            }
+        }
+        None
+    }
+}
+
+/// Registry push of the built image, e.g. `url = "ghcr.io"`, `repository = "acme/app"`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RegistryConfig {
+    /// Registry host; Docker Hub when empty
+    #[serde(default)]
+    pub url: String,
+    pub repository: String,
+    #[serde(default = "default_registry_tag")]
+    pub tag: String,
+}
+
+fn default_registry_tag() -> String {
+    "latest".to_string()
+}
+
+impl RegistryConfig {
+    /// Registry host for `docker login`, without any URL scheme
+    pub fn host(&self) -> &str {
+        let url = self.url.trim_end_matches('/');
+        url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")).unwrap_or(url)
+    }
+
+    /// Image references to push: the configured tag and the short commit sha
+    pub fn image_refs(&self, git_sha: &str) -> anyhow::Result<Vec<String>> {
+        let valid_repository = !self.repository.is_empty()
+            && self.repository.split('/').all(|part| {
+                !part.is_empty()
+                    && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-'))
+            });
+        if !valid_repository {
+            anyhow::bail!(
+                "Invalid registry repository '{}': use lowercase path components like 'acme/app'",
+                self.repository
+            );
+        }
+
+        let image = match self.host() {
+            "" => self.repository.clone(),
+            host => format!("{}/{}", host, self.repository),
+        };
+        let short_sha = &git_sha[..7.min(git_sha.len())];
+        let mut refs = Vec::new();
+        for tag in [self.tag.as_str(), short_sha] {
+            let valid_tag = tag.len() <= 128
+                && !tag.starts_with(['.', '-'])
+                && !tag.is_empty()
+                && tag.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+            if !valid_tag {
+                anyhow::bail!("Invalid image tag '{}'", tag);
+            }
+            let image_ref = format!("{}:{}", image, tag);
+            if !refs.contains(&image_ref) {
+                refs.push(image_ref);
+            }
+        }
+        Ok(refs)
+    }
+}
+
+/// Handling of container stderr in job logs
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StderrMode {
+    /// Log stderr separately, each line prefixed with `STDERR:`
+    #[default]
+    Tag,
+    /// Redirect stderr into stdout inside the container, so lines are untagged and
+    /// keep the order they were written in. Suits tools that print progress or
+    /// warnings to stderr.
+    Merge,
+}
+
+/// Container entrypoint override, written as a string or an argv array
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Entrypoint {
+    Command(String),
+    Argv(Vec<String>),
+}
+
+impl Entrypoint {
+    /// Split into the `docker run` flags and the arguments that go right after the image.
+    /// `--entrypoint` only takes an executable, so the rest of an argv entrypoint is
+    /// passed ahead of the command instead.
+    pub fn docker_args(&self) -> (Vec<String>, Vec<String>) {
+        let argv: Vec<String> = match self {
+            Entrypoint::Command(cmd) => vec![cmd.clone()],
+            Entrypoint::Argv(argv) => argv.clone(),
+        };
+        match argv.split_first() {
+            Some((exe, rest)) => (vec!["--entrypoint".to_string(), exe.clone()], rest.to_vec()),
+            // An empty array clears the entrypoint just like ""
+            None => (vec!["--entrypoint".to_string(), String::new()], Vec::new()),
+        }
+    }
+}
+
+/// Shell that runs the build command, written as a string split on whitespace or an
+/// argv array. The command is passed as its last argument.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Shell {
+    Command(String),
+    Argv(Vec<String>),
+}
+
+impl Shell {
+    pub fn argv(&self) -> Vec<String> {
+        match self {
+            Shell::Command(cmd) => cmd.split_whitespace().map(str::to_string).collect(),
+            Shell::Argv(argv) => argv.clone(),
+        }
+    }
+}
+
+impl BuildConfig {
+    /// The configured shell, or `bash -lc` so login profiles set up `PATH`
+    pub fn shell_argv(&self) -> Vec<String> {
+        match &self.shell {
+            Some(shell) => shell.argv(),
+            None => vec!["bash".to_string(), "-lc".to_string()],
+        }
+    }
+
+    /// Labels in `runs_on` that an agent with `labels` lacks
+    pub fn missing_labels<'a>(&'a self, labels: &[String]) -> Vec<&'a str> {
+        self.runs_on.iter().filter(|l| !labels.contains(l)).map(String::as_str).collect()
+    }
 }
 
 impl Default for BuildConfig {
@@ -94,7 +344,21 @@ impl Default for BuildConfig {
             context: None,
             command: None,
             args: Vec::new(),
-            timeout: default_timeout(),
+            timeout: None,
+            cpus: None,
+            memory: None,
+            user: None,
+            entrypoint: None,
+            shell: None,
+            tmpfs: Vec::new(),
+            stderr: StderrMode::default(),
+            cache: std::collections::HashMap::new(),
+            registry: None,
+            success_when: SuccessCriteria::default(),
+            clone_depth: None,
+            submodules: None,
+            persistent_workspace: false,
+            runs_on: Vec::new(),
         }
     }
 }
@@ -107,6 +371,18 @@ pub struct TriggersConfig {
     pub pull_requests: bool,
     #[serde(default)]
     pub pr_target_branches: Option<Vec<String>>,
+    /// Only build when a changed file matches one of these globs (empty = any file)
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Changed files matching these globs never trigger a build
+    #[serde(default)]
+    pub paths_ignore: Vec<String>,
+    /// Tag globs to build on push (empty = tag pushes never build)
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Build when a GitHub release is published
+    #[serde(default)]
+    pub releases: bool,
 }
 
 fn default_branches() -> Vec<String> {
@@ -123,13 +399,33 @@ impl Default for TriggersConfig {
             branches: default_branches(),
             pull_requests: default_true(),
             pr_target_branches: None,
+            paths: Vec::new(),
+            paths_ignore: Vec::new(),
+            tags: Vec::new(),
+            releases: false,
         }
     }
 }
 
 impl TriggersConfig {
     pub fn should_build_branch(&self, branch: &str) -> bool {
-        self.branches.iter().any(|b| b == branch)
+        matches_glob(&self.branches, branch)
+    }
+
+    pub fn should_build_tag(&self, tag: &str) -> bool {
+        matches_glob(&self.tags, tag)
+    }
+
+    /// Whether a change touching `files` passes the `paths`/`paths_ignore` filters.
+    /// An empty file list means the changes are unknown, so it always builds.
+    pub fn should_build_paths(&self, files: &[String]) -> bool {
+        if files.is_empty() {
+            return true;
+        }
+        files
+            .iter()
+            .filter(|f| !matches_glob(&self.paths_ignore, f))
+            .any(|f| self.paths.is_empty() || matches_glob(&self.paths, f))
     }
 
     pub fn should_build_pr(&self, target_branch: &str) -> bool {
@@ -152,16 +448,121 @@ pub struct DeployConfig {
     pub domain: Option<String>,
     #[serde(default)]
     pub domains: Option<Vec<String>>,
+    /// Published as `port:port`, and the port domain routes point at
     #[serde(default)]
     pub port: Option<u16>,
+    /// More ports to publish, as `host:container`, e.g. `8443:443`, optionally with a
+    /// host IP (`127.0.0.1:9090:9090`) or protocol (`5353:53/udp`)
+    #[serde(default)]
+    pub ports: Vec<String>,
     #[serde(default)]
     pub compose_file: Option<String>,
+    /// `http(s)://` URL that must answer 2xx, or a command that must exit 0 inside the
+    /// deployed container, before the deploy counts as successful
     #[serde(default)]
     pub healthcheck: Option<String>,
+    /// Seconds to wait for the app to become healthy
+    #[serde(default)]
+    pub healthcheck_timeout: Option<u64>,
     #[serde(default)]
     pub volumes: Option<Vec<String>>,
+    /// Dotenv file in the repo whose variables a `docker run` deploy gets, under `[env]`;
+    /// compose deploys pass it to `docker compose --env-file`
     #[serde(default)]
     pub env_file: Option<String>,
+    /// Docker network a `docker run` deploy joins instead of the default bridge,
+    /// created if missing
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Extra names other containers on `network` can reach the app by
+    #[serde(default)]
+    pub network_aliases: Option<Vec<String>>,
+    /// How the Cloudflare tunnel reaches the app behind its domains
+    #[serde(default)]
+    pub cloudflare: DeployCloudflareConfig,
+    /// Domain pull request previews go under: a PR build deploys its own copy of the
+    /// app at `pr-<number>.<preview_domain>`, removed when the PR closes
+    #[serde(default)]
+    pub preview_domain: Option<String>,
+}
+
+/// `[deploy.cloudflare]`: origin settings for the tunnel routes of a deploy's domains
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct DeployCloudflareConfig {
+    /// Whether the tunnel talks to the app over plain HTTP or TLS
+    #[serde(default)]
+    pub scheme: OriginScheme,
+    /// Host header sent to the app instead of the domain, for virtual-hosted apps
+    #[serde(default)]
+    pub http_host_header: Option<String>,
+    /// Accept the app's certificate without verifying it, e.g. a self-signed one
+    #[serde(default)]
+    pub no_tls_verify: bool,
+    /// Name the app's certificate is checked against instead of the domain
+    #[serde(default)]
+    pub origin_server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OriginScheme {
+    #[default]
+    Http,
+    Https,
+}
+
+impl DeployCloudflareConfig {
+    /// Tunnel service for an app published on `port`
+    pub fn service(&self, port: u16) -> String {
+        match self.scheme {
+            OriginScheme::Http => format!("http://127.0.0.1:{}", port),
+            OriginScheme::Https => format!("https://127.0.0.1:{}", port),
+        }
+    }
+
+    /// `originRequest` for the route, or `None` when nothing is set so the route
+    /// keeps its current settings
+    pub fn origin_request(&self) -> Option<crate::cloudflare::OriginRequest> {
+        let origin = crate::cloudflare::OriginRequest {
+            http_host_header: self.http_host_header.clone(),
+            no_tls_verify: self.no_tls_verify.then_some(true),
+            origin_server_name: self.origin_server_name.clone(),
+        };
+        (origin != Default::default()).then_some(origin)
+    }
+}
+
+pub const DEFAULT_HEALTHCHECK_TIMEOUT_SECS: u64 = 60;
+
+/// Check a `deploy.ports` entry: `[ip:]host:container[/tcp|/udp|/sctp]`
+pub fn validate_port_mapping(mapping: &str) -> anyhow::Result<()> {
+    let invalid = || anyhow::anyhow!("Invalid port mapping '{}': use host:container, e.g. 8080:80", mapping);
+    let (ports, protocol) = mapping.split_once('/').unwrap_or((mapping, "tcp"));
+    if !matches!(protocol, "tcp" | "udp" | "sctp") {
+        return Err(invalid());
+    }
+    let parts: Vec<&str> = ports.split(':').collect();
+    let (ip, host, container) = match parts[..] {
+        [host, container] => (None, host, container),
+        [ip, host, container] => (Some(ip), host, container),
+        _ => return Err(invalid()),
+    };
+    if ip.is_some_and(|ip| ip.parse::<std::net::Ipv4Addr>().is_err()) {
+        return Err(invalid());
+    }
+    for port in [host, container] {
+        if !port.parse::<u16>().is_ok_and(|p| p > 0) {
+            return Err(invalid());
+        }
+    }
+    Ok(())
+}
+
+/// How a deploy's `healthcheck` is probed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Healthcheck<'a> {
+    Url(&'a str),
+    Command(&'a str),
 }
 
 impl DeployConfig {
@@ -169,6 +570,19 @@ impl DeployConfig {
         self.name.is_some() || self.compose_file.is_some()
     }
 
+    pub fn healthcheck(&self) -> Option<Healthcheck<'_>> {
+        let check = self.healthcheck.as_deref().map(str::trim).filter(|c| !c.is_empty())?;
+        if check.starts_with("http://") || check.starts_with("https://") {
+            Some(Healthcheck::Url(check))
+        } else {
+            Some(Healthcheck::Command(check))
+        }
+    }
+
+    pub fn healthcheck_timeout_secs(&self) -> u64 {
+        self.healthcheck_timeout.unwrap_or(DEFAULT_HEALTHCHECK_TIMEOUT_SECS)
+    }
+
     pub fn all_domains(&self) -> Vec<&str> {
         let mut result = Vec::new();
         if let Some(d) = &self.domain {
@@ -183,19 +597,433 @@ impl DeployConfig {
     }
 }
 
+/// Files kept after a successful build for download from the dashboard
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct ArtifactsConfig {
+    /// Globs relative to the build's working directory, e.g. `target/release/app`
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+impl ArtifactsConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.paths.is_empty()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        matches_glob(&self.paths, path)
+    }
+}
+
+/// Repo secrets set on the server that builds receive as environment variables.
+/// Only names live in foundry.toml; values never touch git.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct SecretsConfig {
+    #[serde(default)]
+    pub names: Vec<String>,
+}
+
+/// Chat webhooks the server posts each finished build to
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq)]
+pub struct NotifyConfig {
+    /// Slack incoming webhook URL
+    #[serde(default)]
+    pub slack_webhook: Option<String>,
+    /// Discord channel webhook URL
+    #[serde(default)]
+    pub discord_webhook: Option<String>,
+}
+
+/// Jobs sharing a concurrency group run one at a time, across every repo
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq)]
+pub struct ConcurrencyConfig {
+    /// Group name, e.g. `deploy-prod`
+    #[serde(default)]
+    pub group: Option<String>,
+    /// A new job cancels the group's queued and running jobs instead of waiting for them
+    #[serde(default)]
+    pub cancel_in_progress: bool,
+}
+
+/// Concurrency group names are shared across repos, so keep them to simple identifiers
+pub fn validate_concurrency_group(group: &str) -> anyhow::Result<()> {
+    let valid = !group.is_empty()
+        && group.len() <= 100
+        && group.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | '/'));
+    if !valid {
+        anyhow::bail!(
+            "Invalid concurrency group '{}': use up to 100 letters, digits, '.', '_', '-' or '/'",
+            group
+        );
+    }
+    Ok(())
+}
+
+/// How builds show up on GitHub
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq)]
+pub struct GithubConfig {
+    /// Commit status context and check run name, e.g. `foundry/test`, so several
+    /// pipelines report separately and branch protection can require each one.
+    /// Defaults to `foundry` for statuses and `Foundry CI` for check runs.
+    #[serde(default)]
+    pub context: Option<String>,
+}
+
+/// GitHub shows the context on every commit and branch protection matches it exactly
+pub fn validate_check_context(context: &str) -> anyhow::Result<()> {
+    if context.trim().is_empty() || context.len() > 100 || context.chars().any(char::is_control) {
+        anyhow::bail!("Invalid check context '{}': use 1 to 100 printable characters", context);
+    }
+    Ok(())
+}
+
+/// Agent labels are matched exactly, so keep them to simple identifiers
+pub fn validate_agent_label(label: &str) -> anyhow::Result<()> {
+    let valid = !label.is_empty()
+        && label.len() <= 100
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-' | ':'));
+    if !valid {
+        anyhow::bail!("Invalid agent label '{}': use up to 100 letters, digits, '.', '_', '-' or ':'", label);
+    }
+    Ok(())
+}
+
+/// `[matrix]` axes, e.g. `rust = ["1.75", "1.76"]`; one job runs per combination
+pub type MatrixConfig = BTreeMap<String, Vec<MatrixValue>>;
+
+/// Most jobs one matrix may expand to
+pub const MAX_MATRIX_JOBS: usize = 64;
+
+/// A matrix axis value. Floats are rejected so `3.10` can't silently become `3.1`;
+/// quote versions instead.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum MatrixValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl std::fmt::Display for MatrixValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatrixValue::String(s) => f.write_str(s),
+            MatrixValue::Integer(i) => write!(f, "{}", i),
+            MatrixValue::Boolean(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// One combination of matrix values
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MatrixEntry {
+    /// `name=value` pairs in name order, e.g. `os=linux, rust=1.75`
+    pub key: String,
+    pub vars: BTreeMap<String, String>,
+}
+
+/// Every combination of the matrix axes, in a stable order
+pub fn matrix_entries(matrix: &MatrixConfig) -> anyhow::Result<Vec<MatrixEntry>> {
+    let mut combinations = vec![BTreeMap::new()];
+    for (name, values) in matrix {
+        if !is_valid_env_name(name) {
+            anyhow::bail!("Matrix name '{}' is not a valid environment variable name", name);
+        }
+        if values.is_empty() {
+            anyhow::bail!("Matrix '{}' has no values", name);
+        }
+        if combinations.len() * values.len() > MAX_MATRIX_JOBS {
+            anyhow::bail!("Matrix expands to more than {} jobs", MAX_MATRIX_JOBS);
+        }
+        combinations = combinations
+            .into_iter()
+            .flat_map(|vars| {
+                values.iter().map(move |value| {
+                    let mut vars = vars.clone();
+                    vars.insert(name.clone(), value.to_string());
+                    vars
+                })
+            })
+            .collect();
+    }
+
+    if matrix.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(combinations
+        .into_iter()
+        .map(|vars| MatrixEntry {
+            key: vars.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(", "),
+            vars,
+        })
+        .collect())
+}
+
+/// Replace `${matrix.NAME}` in `value` with the job's matrix values
+pub fn interpolate_matrix(value: &str, vars: &BTreeMap<String, String>) -> String {
+    vars.iter()
+        .fold(value.to_string(), |acc, (name, v)| acc.replace(&format!("${{matrix.{}}}", name), v))
+}
+
+/// Whether `name` can be used as an environment variable name
+pub fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Monorepo layout: each directory matching `services` holds its own
+/// foundry.toml and is built as a separate job.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct MonorepoConfig {
+    /// Globs for service directories, e.g. `services/*`
+    #[serde(default = "default_service_globs")]
+    pub services: Vec<String>,
+    /// Globs for code shared by all services; touching any of them rebuilds every service
+    #[serde(default)]
+    pub shared: Vec<String>,
+}
+
+fn default_service_globs() -> Vec<String> {
+    vec!["services/*".to_string()]
+}
+
+impl MonorepoConfig {
+    pub fn is_service_dir(&self, dir: &str) -> bool {
+        matches_glob(&self.services, dir)
+    }
+
+    /// Select the services affected by a change.
+    ///
+    /// A file under a service directory rebuilds that service, a file matching
+    /// `shared` rebuilds every service, and anything else is ignored. When the
+    /// changed files are unknown (scheduled, manual, PR builds) every service is built.
+    pub fn affected_services<'a>(&self, services: &'a [String], changed_files: &[String]) -> Vec<&'a str> {
+        if changed_files.is_empty() || changed_files.iter().any(|f| matches_glob(&self.shared, f)) {
+            return services.iter().map(String::as_str).collect();
+        }
+
+        services
+            .iter()
+            .filter(|dir| {
+                let prefix = format!("{}/", dir.trim_end_matches('/'));
+                changed_files.iter().any(|f| f.starts_with(&prefix))
+            })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+/// Check a docker `--memory` value such as `512m` or `2g` so bad config fails
+/// before docker is invoked.
+pub fn validate_memory_limit(value: &str) -> anyhow::Result<()> {
+    parse_memory_size(value).map(|_| ())
+}
+
+/// Parse a docker-style size such as `512m` or `2g` into bytes
+pub fn parse_memory_size(value: &str) -> anyhow::Result<u64> {
+    let digits_end = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits_end);
+    let multiplier: Option<u64> = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => Some(1),
+        "k" | "kb" => Some(1 << 10),
+        "m" | "mb" => Some(1 << 20),
+        "g" | "gb" => Some(1 << 30),
+        "t" | "tb" => Some(1 << 40),
+        _ => None,
+    };
+
+    match (number.parse::<u64>(), multiplier) {
+        (Ok(n), Some(m)) if n > 0 => n.checked_mul(m).ok_or_else(|| {
+            anyhow::anyhow!("Invalid memory limit '{}': value is too large", value)
+        }),
+        _ => anyhow::bail!(
+            "Invalid memory limit '{}': expected a positive integer with an optional b, k, m, g or t suffix (e.g. 512m, 2g)",
+            value
+        ),
+    }
+}
+
+/// `-v` flags mounting each `build.cache` entry from `{repo_cache_dir}/{key}`, in key order.
+/// Keys become directory names, so they are limited to letters, digits, `-`, `_` and `.`.
+pub fn cache_args(
+    cache: &std::collections::HashMap<String, String>,
+    repo_cache_dir: &Path,
+) -> anyhow::Result<Vec<String>> {
+    let mut entries: Vec<_> = cache.iter().collect();
+    entries.sort();
+
+    let mut args = Vec::new();
+    for (key, path) in entries {
+        let valid_key = !key.is_empty()
+            && !key.starts_with('.')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid_key {
+            anyhow::bail!("Invalid cache key '{}': use letters, digits, '-', '_' or '.'", key);
+        }
+        if !path.starts_with('/') {
+            anyhow::bail!("Invalid cache path '{}' for '{}': path must be absolute", path, key);
+        }
+
+        args.push("-v".to_string());
+        args.push(format!("{}:{}", repo_cache_dir.join(key).display(), path));
+    }
+    Ok(args)
+}
+
+/// Variables of a dotenv file, in file order: `KEY=VALUE` lines, optionally prefixed with
+/// `export`, with blank lines and `#` comments skipped. Values may be single-quoted
+/// (literal) or double-quoted (`\n`, `\"` and `\\` escapes); unquoted values end at ` #`.
+pub fn parse_env_file(content: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!("line {}: expected KEY=VALUE", i + 1);
+        };
+        let key = key.trim();
+        let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_key {
+            anyhow::bail!("line {}: invalid variable name '{}'", i + 1, key);
+        }
+
+        let value = value.trim();
+        let value = if let Some(rest) = value.strip_prefix('\'') {
+            let Some(end) = rest.find('\'') else {
+                anyhow::bail!("line {}: unterminated quote", i + 1);
+            };
+            rest[..end].to_string()
+        } else if let Some(rest) = value.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = rest.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some(c) => unescaped.push(c),
+                        None => anyhow::bail!("line {}: unterminated quote", i + 1),
+                    },
+                    Some(c) => unescaped.push(c),
+                    None => anyhow::bail!("line {}: unterminated quote", i + 1),
+                }
+            }
+            unescaped
+        } else {
+            value.split(" #").next().unwrap_or_default().trim_end().to_string()
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// `--tmpfs` flags for `build.tmpfs` entries of the form `/path[:options]`.
+/// Mounts without a `size=` option are capped at `max_size`; larger sizes are rejected.
+pub fn tmpfs_args(mounts: &[String], max_size: &str) -> anyhow::Result<Vec<String>> {
+    let max_bytes = parse_memory_size(max_size)?;
+    let mut args = Vec::new();
+
+    for mount in mounts {
+        let (path, options) = mount.split_once(':').unwrap_or((mount.as_str(), ""));
+        if !path.starts_with('/') {
+            anyhow::bail!("Invalid tmpfs mount '{}': path must be absolute", mount);
+        }
+
+        let mut options: Vec<String> = options
+            .split(',')
+            .filter(|o| !o.is_empty())
+            .map(str::to_string)
+            .collect();
+        match options.iter().find_map(|o| o.strip_prefix("size=")) {
+            Some(size) => {
+                if parse_memory_size(size)? > max_bytes {
+                    anyhow::bail!(
+                        "tmpfs mount '{}' exceeds the agent's maximum size of {}",
+                        mount,
+                        max_size
+                    );
+                }
+            }
+            None => options.push(format!("size={}", max_size)),
+        }
+
+        args.push("--tmpfs".to_string());
+        args.push(format!("{}:{}", path, options.join(",")));
+    }
+
+    Ok(args)
+}
+
+/// Check a docker `--cpus` value
+pub fn validate_cpu_limit(value: f64) -> anyhow::Result<()> {
+    if !(value.is_finite() && value > 0.0) {
+        anyhow::bail!("Invalid CPU limit '{}': expected a positive number (e.g. 0.5, 2)", value);
+    }
+    Ok(())
+}
+
+/// Match a path or ref against a list of globs. `*` does not cross `/`; use `**` for that.
+pub fn matches_glob(patterns: &[String], value: &str) -> bool {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        match GlobBuilder::new(pattern).literal_separator(true).build() {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => tracing::warn!("Ignoring invalid glob '{}': {}", pattern, e),
+        }
+    }
+    builder
+        .build()
+        .map(|set| set.is_match(value))
+        .unwrap_or(false)
+}
+
+/// First pattern in `patterns` that matches `value`, for logging which rule applied
+pub fn matching_glob<'a>(patterns: &'a [String], value: &str) -> Option<&'a String> {
+    patterns
+        .iter()
+        .find(|pattern| matches_glob(std::slice::from_ref(pattern), value))
+}
+
 fn default_image() -> String {
     "ubuntu:latest".to_string()
 }
 
 impl FoundryConfig {
-    pub fn load(repo_dir: &Path) -> Option<Self> {
+    /// Read `foundry.toml` from `repo_dir`: `None` if there isn't one, an error if it
+    /// can't be read or parsed
+    pub fn load(repo_dir: &Path) -> anyhow::Result<Option<Self>> {
         let config_path = repo_dir.join("foundry.toml");
         if !config_path.exists() {
-            return None;
+            return Ok(None);
         }
 
-        let content = std::fs::read_to_string(&config_path).ok()?;
-        toml::from_str(&content).ok()
+        let content = std::fs::read_to_string(&config_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read foundry.toml: {}", e))?;
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("Invalid foundry.toml: {}", e))
+    }
+
+    /// Set up a matrix job: its values become environment variables (overriding
+    /// `[env]`) and fill `${matrix.NAME}` in the build and stage images
+    pub fn apply_matrix(&mut self, vars: &BTreeMap<String, String>) {
+        if vars.is_empty() {
+            return;
+        }
+        self.env.extend(vars.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.build.image = interpolate_matrix(&self.build.image, vars);
+        for stage in &mut self.stages {
+            if let Some(image) = &stage.image {
+                stage.image = Some(interpolate_matrix(image, vars));
+            }
+        }
     }
 
     pub fn effective_command(&self, default: &str) -> String {
@@ -214,6 +1042,10 @@ impl FoundryConfig {
         !self.stages.is_empty()
     }
 
+    pub fn is_monorepo(&self) -> bool {
+        self.monorepo.is_some()
+    }
+
     pub fn has_dockerfile(&self) -> bool {
         self.build.dockerfile.is_some()
     }
@@ -221,16 +1053,827 @@ impl FoundryConfig {
     pub fn stages_for_trigger(&self, is_pr: bool, previous_failed: bool) -> Vec<&StageConfig> {
         self.stages
             .iter()
-            .filter(|s| {
-                match &s.condition {
-                    Some(StageCondition::Always) => true,
-                    Some(StageCondition::OnSuccess) => !previous_failed,
-                    Some(StageCondition::OnFailure) => previous_failed,
-                    Some(StageCondition::OnPr) => is_pr,
-                    Some(StageCondition::OnPush) => !is_pr,
-                    None => !previous_failed,
-                }
-            })
+            .filter(|s| s.should_run(is_pr, previous_failed))
             .collect()
     }
+
+    /// Problems that would fail or misdirect a build, beyond what parsing catches.
+    /// Agent-specific limits (e.g. `FOUNDRY_MAX_TMPFS_SIZE`) aren't known here.
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        let mut check = |field: &str, result: anyhow::Result<()>| {
+            if let Err(e) = result {
+                errors.push(ConfigError { field: field.to_string(), message: e.to_string(), line: None });
+            }
+        };
+
+        let build = &self.build;
+        if let Some(cpus) = build.cpus {
+            check("build.cpus", validate_cpu_limit(cpus));
+        }
+        if let Some(memory) = &build.memory {
+            check("build.memory", validate_memory_limit(memory));
+        }
+        check("build.cache", cache_args(&build.cache, Path::new("/")).map(|_| ()));
+        for mount in &build.tmpfs {
+            let (path, options) = mount.split_once(':').unwrap_or((mount.as_str(), ""));
+            let size = options.split(',').find_map(|o| o.strip_prefix("size="));
+            check(
+                "build.tmpfs",
+                if !path.starts_with('/') {
+                    Err(anyhow::anyhow!("Invalid tmpfs mount '{}': path must be absolute", mount))
+                } else {
+                    size.map(validate_memory_limit).unwrap_or(Ok(()))
+                },
+            );
+        }
+        check("build.success_when.output", build.success_when.output_regex().map(|_| ()));
+        if build.shell.as_ref().is_some_and(|shell| shell.argv().is_empty()) {
+            check("build.shell", Err(anyhow::anyhow!("build.shell can't be empty")));
+        }
+        for label in &build.runs_on {
+            check("build.runs_on", validate_agent_label(label));
+        }
+        if let Some(registry) = &build.registry {
+            check("build.registry", registry.image_refs("0000000").map(|_| ()));
+        }
+
+        let mut stage_names = std::collections::HashSet::new();
+        for stage in &self.stages {
+            if stage.name.trim().is_empty() {
+                check("stages", Err(anyhow::anyhow!("Every stage needs a name")));
+            } else if !stage_names.insert(stage.name.as_str()) {
+                check("stages", Err(anyhow::anyhow!("Stage '{}' is defined more than once", stage.name)));
+            }
+        }
+        for stage in &self.stages {
+            for dependency in &stage.depends_on {
+                if dependency == &stage.name {
+                    check("stages.depends_on", Err(anyhow::anyhow!("Stage '{}' depends on itself", stage.name)));
+                } else if !stage_names.contains(dependency.as_str()) {
+                    check(
+                        "stages.depends_on",
+                        Err(anyhow::anyhow!("Stage '{}' depends on unknown stage '{}'", stage.name, dependency)),
+                    );
+                }
+            }
+        }
+
+        let deploy = &self.deploy;
+        if deploy.port == Some(0) {
+            check("deploy.port", Err(anyhow::anyhow!("deploy.port must be between 1 and 65535")));
+        }
+        for mapping in &deploy.ports {
+            check("deploy.ports", validate_port_mapping(mapping));
+        }
+        if let Some(name) = &deploy.name {
+            let valid = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+            if !valid {
+                check("deploy.name", Err(anyhow::anyhow!("Invalid container name '{}'", name)));
+            }
+        }
+        for domain in deploy.all_domains() {
+            if !is_valid_domain(domain) {
+                check("deploy.domain", Err(anyhow::anyhow!("Invalid domain '{}'", domain)));
+            }
+        }
+        if let Some(domain) = &deploy.preview_domain {
+            if !is_valid_domain(domain) {
+                check("deploy.preview_domain", Err(anyhow::anyhow!("Invalid domain '{}'", domain)));
+            } else if deploy.port.is_none() || deploy.compose_file.is_some() {
+                check(
+                    "deploy.preview_domain",
+                    Err(anyhow::anyhow!("Previews need deploy.port and a docker run deploy, not compose_file")),
+                );
+            }
+        }
+        let cloudflare = &deploy.cloudflare;
+        if cloudflare.http_host_header.as_deref().is_some_and(|h| h.trim().is_empty() || h.contains(char::is_whitespace)) {
+            check("deploy.cloudflare.http_host_header", Err(anyhow::anyhow!("Invalid host header")));
+        }
+        if (cloudflare.no_tls_verify || cloudflare.origin_server_name.is_some()) && cloudflare.scheme != OriginScheme::Https {
+            check(
+                "deploy.cloudflare.scheme",
+                Err(anyhow::anyhow!("no_tls_verify and origin_server_name need scheme = \"https\"")),
+            );
+        }
+        if deploy.network_aliases.as_ref().is_some_and(|a| !a.is_empty()) && deploy.network.is_none() {
+            check("deploy.network_aliases", Err(anyhow::anyhow!("deploy.network_aliases needs deploy.network")));
+        }
+
+        check("matrix", matrix_entries(&self.matrix).map(|_| ()));
+        for name in &self.secrets.names {
+            if !is_valid_env_name(name) {
+                check(
+                    "secrets.names",
+                    Err(anyhow::anyhow!("Secret name '{}' is not a valid environment variable name", name)),
+                );
+            }
+        }
+        for (field, url) in [
+            ("notify.slack_webhook", &self.notify.slack_webhook),
+            ("notify.discord_webhook", &self.notify.discord_webhook),
+        ] {
+            if url.as_ref().is_some_and(|u| !u.starts_with("https://")) {
+                check(field, Err(anyhow::anyhow!("{} must be an https:// URL", field)));
+            }
+        }
+        match &self.concurrency.group {
+            Some(group) => check("concurrency.group", validate_concurrency_group(group)),
+            None if self.concurrency.cancel_in_progress => check(
+                "concurrency.cancel_in_progress",
+                Err(anyhow::anyhow!("concurrency.cancel_in_progress needs concurrency.group")),
+            ),
+            None => {}
+        }
+        if let Some(context) = &self.github.context {
+            check("github.context", validate_check_context(context));
+        }
+
+        errors
+    }
+}
+
+/// A problem found in a foundry.toml
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConfigError {
+    /// Dotted key the problem is in, e.g. `deploy.port`; empty for syntax errors
+    pub field: String,
+    pub message: String,
+    /// 1-based line, when the problem can be placed
+    pub line: Option<usize>,
+}
+
+/// Parse a foundry.toml, placing any syntax or type error on its line
+pub fn parse_config(content: &str) -> Result<FoundryConfig, ConfigError> {
+    toml::from_str(content).map_err(|e| {
+        let line = e.span().map(|span| content[..span.start].matches('\n').count() + 1);
+        ConfigError { field: String::new(), message: e.message().to_string(), line }
+    })
+}
+
+/// Whether `domain` is a hostname like `app.example.com`
+fn is_valid_domain(domain: &str) -> bool {
+    domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monorepo() -> MonorepoConfig {
+        MonorepoConfig {
+            services: vec!["services/*".to_string()],
+            shared: vec!["libs/**".to_string(), "Cargo.lock".to_string()],
+        }
+    }
+
+    fn services() -> Vec<String> {
+        vec!["services/api".to_string(), "services/web".to_string()]
+    }
+
+    #[test]
+    fn test_single_service_change() {
+        let changed = vec!["services/api/src/main.rs".to_string(), "README.md".to_string()];
+        let services = services();
+        assert_eq!(monorepo().affected_services(&services, &changed), vec!["services/api"]);
+    }
+
+    #[test]
+    fn test_shared_change_builds_all() {
+        let changed = vec!["libs/common/src/lib.rs".to_string()];
+        let services = services();
+        assert_eq!(
+            monorepo().affected_services(&services, &changed),
+            vec!["services/api", "services/web"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_changes_build_all() {
+        let services = services();
+        assert_eq!(monorepo().affected_services(&services, &[]).len(), 2);
+    }
+
+    #[test]
+    fn test_service_dir_glob() {
+        let config = monorepo();
+        assert!(config.is_service_dir("services/api"));
+        assert!(!config.is_service_dir("services/api/nested"));
+        assert!(!config.is_service_dir("tools/api"));
+    }
+
+    fn stage(name: &str, condition: Option<StageCondition>) -> StageConfig {
+        StageConfig {
+            name: name.to_string(),
+            image: None,
+            command: "true".to_string(),
+            timeout: 600,
+            allow_failure: false,
+            env: Default::default(),
+            depends_on: vec![],
+            condition,
+        }
+    }
+
+    #[test]
+    fn test_stage_conditions_after_failure() {
+        let build = stage("build", None);
+        let cleanup = stage("cleanup", Some(StageCondition::Always));
+        let notify = stage("notify", Some(StageCondition::OnFailure));
+        let pr_only = stage("preview", Some(StageCondition::OnPr));
+
+        assert!(build.should_run(false, false));
+        assert!(!build.should_run(false, true));
+        assert!(cleanup.should_run(false, true));
+        assert!(!notify.should_run(false, false));
+        assert!(notify.should_run(false, true));
+        assert!(pr_only.should_run(true, false));
+        assert!(!pr_only.should_run(false, false));
+        assert!(!pr_only.should_run(true, true));
+    }
+
+    #[test]
+    fn test_parse_stages() {
+        let config: FoundryConfig = toml::from_str(
+            r#"
+            [[stages]]
+            name = "test"
+            command = "cargo test"
+
+            [[stages]]
+            name = "report"
+            image = "alpine"
+            command = "echo done"
+            condition = "always"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.stages.len(), 2);
+        assert_eq!(config.stages[0].condition, None);
+        assert_eq!(config.stages[1].image.as_deref(), Some("alpine"));
+        assert_eq!(config.stages[1].condition, Some(StageCondition::Always));
+    }
+
+    #[test]
+    fn test_validate_memory_limit() {
+        for ok in ["512m", "2g", "2G", "1024", "256mb", "1gb"] {
+            assert!(validate_memory_limit(ok).is_ok(), "{} should be valid", ok);
+        }
+        for bad in ["", "m", "0", "-1g", "1.5g", "2 g", "lots", "12x"] {
+            assert!(validate_memory_limit(bad).is_err(), "{} should be invalid", bad);
+        }
+    }
+
+    #[test]
+    fn test_validate_cpu_limit() {
+        assert!(validate_cpu_limit(0.5).is_ok());
+        assert!(validate_cpu_limit(2.0).is_ok());
+        assert!(validate_cpu_limit(0.0).is_err());
+        assert!(validate_cpu_limit(-1.0).is_err());
+        assert!(validate_cpu_limit(f64::NAN).is_err());
+    }
+
+    fn files(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_paths_ignore_skips_docs_only_push() {
+        let triggers = TriggersConfig {
+            paths_ignore: vec!["docs/**".to_string(), "**/*.md".to_string()],
+            ..Default::default()
+        };
+        assert!(!triggers.should_build_paths(&files(&["docs/intro.md", "README.md"])));
+        assert!(triggers.should_build_paths(&files(&["README.md", "src/main.rs"])));
+    }
+
+    #[test]
+    fn test_paths_requires_a_match() {
+        let triggers = TriggersConfig {
+            paths: vec!["src/**".to_string()],
+            paths_ignore: vec!["src/**/*.snap".to_string()],
+            ..Default::default()
+        };
+        assert!(triggers.should_build_paths(&files(&["src/lib.rs"])));
+        assert!(!triggers.should_build_paths(&files(&["src/tests/out.snap"])));
+        assert!(!triggers.should_build_paths(&files(&["Makefile"])));
+        // No file list (e.g. schedules, PRs) always builds
+        assert!(triggers.should_build_paths(&[]));
+    }
+
+    fn entrypoint(toml: &str) -> Option<Entrypoint> {
+        let fc: FoundryConfig = toml::from_str(toml).unwrap();
+        fc.build.entrypoint
+    }
+
+    #[test]
+    fn test_shell() {
+        let shell = |toml: &str| toml::from_str::<FoundryConfig>(toml).unwrap().build.shell_argv();
+        assert_eq!(shell("[build]\nimage = \"node:20\""), ["bash", "-lc"]);
+        assert_eq!(shell("[build]\nshell = \"sh -c\""), ["sh", "-c"]);
+        assert_eq!(shell("[build]\nshell = [\"/busybox/sh\", \"-c\"]"), ["/busybox/sh", "-c"]);
+
+        let fc: FoundryConfig = toml::from_str("[build]\nshell = \" \"").unwrap();
+        assert_eq!(fc.validate()[0].field, "build.shell");
+    }
+
+    #[test]
+    fn test_runs_on() {
+        let fc: FoundryConfig = toml::from_str("[build]\nruns_on = [\"gpu\", \"linux:cuda-12\"]").unwrap();
+        assert!(fc.validate().is_empty());
+        let labels = |l: &[&str]| l.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(fc.build.missing_labels(&labels(&["gpu", "linux:cuda-12", "big"])), Vec::<&str>::new());
+        assert_eq!(fc.build.missing_labels(&labels(&["gpu"])), ["linux:cuda-12"]);
+        assert!(BuildConfig::default().missing_labels(&[]).is_empty());
+
+        let fc: FoundryConfig = toml::from_str("[build]\nruns_on = [\"gpu,big\"]").unwrap();
+        assert_eq!(fc.validate()[0].field, "build.runs_on");
+    }
+
+    #[test]
+    fn test_entrypoint_unset_keeps_image_default() {
+        assert_eq!(entrypoint("[build]\nimage = \"node:20\""), None);
+    }
+
+    #[test]
+    fn test_entrypoint_empty_string_clears() {
+        let (flags, leading) = entrypoint("[build]\nentrypoint = \"\"").unwrap().docker_args();
+        assert_eq!(flags, vec!["--entrypoint", ""]);
+        assert!(leading.is_empty());
+    }
+
+    #[test]
+    fn test_entrypoint_string() {
+        let (flags, leading) = entrypoint("[build]\nentrypoint = \"/usr/bin/env\"").unwrap().docker_args();
+        assert_eq!(flags, vec!["--entrypoint", "/usr/bin/env"]);
+        assert!(leading.is_empty());
+    }
+
+    #[test]
+    fn test_entrypoint_array_passes_rest_as_args() {
+        let (flags, leading) = entrypoint("[build]\nentrypoint = [\"tini\", \"--\"]").unwrap().docker_args();
+        assert_eq!(flags, vec!["--entrypoint", "tini"]);
+        assert_eq!(leading, vec!["--"]);
+    }
+
+    #[test]
+    fn test_cache_args() {
+        let fc: FoundryConfig = toml::from_str(
+            "[build.cache]\ncargo = \"/usr/local/cargo/registry\"\ntarget = \"/work/target\"",
+        )
+        .unwrap();
+        assert_eq!(
+            cache_args(&fc.build.cache, Path::new("/cache/acme/app")).unwrap(),
+            vec![
+                "-v",
+                "/cache/acme/app/cargo:/usr/local/cargo/registry",
+                "-v",
+                "/cache/acme/app/target:/work/target",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_args_rejects_bad_entries() {
+        let root = Path::new("/cache/acme/app");
+        let cache = |key: &str, path: &str| [(key.to_string(), path.to_string())].into_iter().collect();
+        assert!(cache_args(&cache("..", "/root/.npm"), root).is_err());
+        assert!(cache_args(&cache("npm/../../other", "/root/.npm"), root).is_err());
+        assert!(cache_args(&cache("npm", "~/.npm"), root).is_err());
+        assert!(cache_args(&cache("npm", "/root/.npm"), root).is_ok());
+    }
+
+    #[test]
+    fn test_registry_image_refs() {
+        let fc: FoundryConfig = toml::from_str(
+            "[build]\ndockerfile = \"Dockerfile\"\n[build.registry]\nurl = \"https://ghcr.io/\"\nrepository = \"acme/app\"\ntag = \"v1.2\"",
+        )
+        .unwrap();
+        let registry = fc.build.registry.unwrap();
+        assert_eq!(registry.host(), "ghcr.io");
+        assert_eq!(
+            registry.image_refs("1a2b3c4d5e6f").unwrap(),
+            vec!["ghcr.io/acme/app:v1.2", "ghcr.io/acme/app:1a2b3c4"]
+        );
+
+        // Docker Hub, default tag
+        let registry = RegistryConfig { url: String::new(), repository: "acme/app".into(), tag: default_registry_tag() };
+        assert_eq!(registry.image_refs("1a2b3c4d").unwrap(), vec!["acme/app:latest", "acme/app:1a2b3c4"]);
+        // Tagging with the sha itself doesn't push twice
+        let registry = RegistryConfig { tag: "1a2b3c4".into(), ..registry };
+        assert_eq!(registry.image_refs("1a2b3c4d").unwrap(), vec!["acme/app:1a2b3c4"]);
+    }
+
+    #[test]
+    fn test_registry_rejects_bad_references() {
+        let registry = |repository: &str, tag: &str| RegistryConfig {
+            url: "ghcr.io".into(),
+            repository: repository.into(),
+            tag: tag.into(),
+        };
+        assert!(registry("Acme/App", "latest").image_refs("1a2b3c4").is_err());
+        assert!(registry("acme//app", "latest").image_refs("1a2b3c4").is_err());
+        assert!(registry("acme/app", "-rc").image_refs("1a2b3c4").is_err());
+        assert!(registry("acme/app", "v1 && rm -rf /").image_refs("1a2b3c4").is_err());
+    }
+
+    #[test]
+    fn test_secrets_config() {
+        let fc: FoundryConfig = toml::from_str("[secrets]\nnames = [\"NPM_TOKEN\", \"AWS_SECRET_ACCESS_KEY\"]").unwrap();
+        assert_eq!(fc.secrets.names, vec!["NPM_TOKEN", "AWS_SECRET_ACCESS_KEY"]);
+        assert!(is_valid_env_name("_PRIVATE_KEY2"));
+        assert!(!is_valid_env_name("2FA_CODE"));
+        assert!(!is_valid_env_name("MY-TOKEN"));
+        assert!(!is_valid_env_name(""));
+    }
+
+    #[test]
+    fn test_deploy_healthcheck() {
+        let fc: FoundryConfig = toml::from_str("[deploy]\nname = \"api\"\nhealthcheck = \"http://localhost:3000/health\"").unwrap();
+        assert_eq!(fc.deploy.healthcheck(), Some(Healthcheck::Url("http://localhost:3000/health")));
+        assert_eq!(fc.deploy.healthcheck_timeout_secs(), DEFAULT_HEALTHCHECK_TIMEOUT_SECS);
+
+        let fc: FoundryConfig = toml::from_str(
+            "[deploy]\nname = \"api\"\nhealthcheck = \"curl -fs localhost:3000\"\nhealthcheck_timeout = 120",
+        )
+        .unwrap();
+        assert_eq!(fc.deploy.healthcheck(), Some(Healthcheck::Command("curl -fs localhost:3000")));
+        assert_eq!(fc.deploy.healthcheck_timeout_secs(), 120);
+
+        let fc: FoundryConfig = toml::from_str("[deploy]\nname = \"api\"\nhealthcheck = \" \"").unwrap();
+        assert_eq!(fc.deploy.healthcheck(), None);
+    }
+
+    #[test]
+    fn test_stderr_mode() {
+        let fc: FoundryConfig = toml::from_str("[build]\nimage = \"node:20\"").unwrap();
+        assert_eq!(fc.build.stderr, StderrMode::Tag);
+        let fc: FoundryConfig = toml::from_str("[build]\nstderr = \"merge\"").unwrap();
+        assert_eq!(fc.build.stderr, StderrMode::Merge);
+        assert!(toml::from_str::<FoundryConfig>("[build]\nstderr = \"drop\"").is_err());
+    }
+
+    #[test]
+    fn test_branch_globs() {
+        let triggers = TriggersConfig {
+            branches: vec!["main".to_string(), "release/*".to_string(), "develop".to_string()],
+            ..Default::default()
+        };
+        assert!(triggers.should_build_branch("main"));
+        assert!(triggers.should_build_branch("release/1.2"));
+        assert!(triggers.should_build_branch("develop"));
+        assert!(!triggers.should_build_branch("release/1.2/hotfix"));
+        assert!(!triggers.should_build_branch("feature/login"));
+        assert_eq!(
+            matching_glob(&triggers.branches, "release/1.2").map(String::as_str),
+            Some("release/*")
+        );
+        assert_eq!(matching_glob(&triggers.branches, "feature/login"), None);
+    }
+
+    #[test]
+    fn test_matrix_entries() {
+        let fc: FoundryConfig = toml::from_str(
+            "[build]\nimage = \"rust:${matrix.rust}\"\n[matrix]\nrust = [\"1.75\", \"1.76\"]\nfeatures = [\"default\", \"full\"]\nnode = [18]",
+        )
+        .unwrap();
+        let entries = matrix_entries(&fc.matrix).unwrap();
+        let keys: Vec<&str> = entries.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "features=default, node=18, rust=1.75",
+                "features=default, node=18, rust=1.76",
+                "features=full, node=18, rust=1.75",
+                "features=full, node=18, rust=1.76",
+            ]
+        );
+
+        let mut job_config = fc.clone();
+        job_config.apply_matrix(&entries[1].vars);
+        assert_eq!(job_config.build.image, "rust:1.76");
+        assert_eq!(job_config.env.get("node").map(String::as_str), Some("18"));
+
+        assert!(matrix_entries(&MatrixConfig::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_matrix_rejects_bad_axes() {
+        assert!(toml::from_str::<FoundryConfig>("[matrix]\npython = [3.10]").is_err());
+
+        let bad_name = MatrixConfig::from([("rust-version".to_string(), vec![MatrixValue::Integer(1)])]);
+        assert!(matrix_entries(&bad_name).is_err());
+
+        let empty = MatrixConfig::from([("rust".to_string(), vec![])]);
+        assert!(matrix_entries(&empty).is_err());
+
+        let values: Vec<MatrixValue> = (0..9).map(MatrixValue::Integer).collect();
+        let huge = MatrixConfig::from([("a".to_string(), values.clone()), ("b".to_string(), values)]);
+        assert!(matrix_entries(&huge).is_err());
+    }
+
+    #[test]
+    fn test_tag_globs() {
+        assert!(!TriggersConfig::default().should_build_tag("v1.0.0"));
+
+        let fc: FoundryConfig = toml::from_str("[triggers]\ntags = [\"v*\"]\nreleases = true").unwrap();
+        assert!(fc.triggers.should_build_tag("v1.0.0"));
+        assert!(!fc.triggers.should_build_tag("nightly"));
+        assert!(fc.triggers.releases);
+    }
+
+    #[test]
+    fn test_parse_env_file() {
+        let content = r#"
+# Production settings
+DATABASE_URL=postgres://db/app
+export NODE_ENV=production
+PORT = 3000 # inline comment
+GREETING="hello \"world\"\nbye"
+RAW='$HOME #not a comment'
+EMPTY=
+"#;
+        let vars = parse_env_file(content).unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("DATABASE_URL".to_string(), "postgres://db/app".to_string()),
+                ("NODE_ENV".to_string(), "production".to_string()),
+                ("PORT".to_string(), "3000".to_string()),
+                ("GREETING".to_string(), "hello \"world\"\nbye".to_string()),
+                ("RAW".to_string(), "$HOME #not a comment".to_string()),
+                ("EMPTY".to_string(), String::new()),
+            ]
+        );
+
+        assert!(parse_env_file("JUST_A_NAME").unwrap_err().to_string().contains("line 1"));
+        assert!(parse_env_file("\n1BAD=x").unwrap_err().to_string().contains("line 2"));
+        assert!(parse_env_file("OPEN=\"never closed").is_err());
+    }
+
+    #[test]
+    fn test_tmpfs_args() {
+        let mounts = vec!["/tmp".to_string(), "/cache:size=256m,mode=1777".to_string()];
+        assert_eq!(
+            tmpfs_args(&mounts, "1g").unwrap(),
+            vec!["--tmpfs", "/tmp:size=1g", "--tmpfs", "/cache:size=256m,mode=1777"]
+        );
+    }
+
+    #[test]
+    fn test_tmpfs_rejects_oversized_and_relative_mounts() {
+        assert!(tmpfs_args(&["/tmp:size=2g".to_string()], "1g").is_err());
+        assert!(tmpfs_args(&["/tmp:size=1024m".to_string()], "1g").is_ok());
+        assert!(tmpfs_args(&["tmp".to_string()], "1g").is_err());
+        assert!(tmpfs_args(&["/tmp:size=lots".to_string()], "1g").is_err());
+    }
+
+    #[test]
+    fn test_artifact_paths() {
+        let config: FoundryConfig = toml::from_str(
+            r#"
+            [artifacts]
+            paths = ["target/release/app", "dist/**/*.js"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.artifacts.matches("target/release/app"));
+        assert!(config.artifacts.matches("dist/assets/main.js"));
+        assert!(!config.artifacts.matches("target/release/app.d"));
+        assert!(!FoundryConfig::default().artifacts.is_enabled());
+    }
+
+    fn success_criteria(toml_str: &str) -> SuccessCriteria {
+        let config: FoundryConfig = toml::from_str(toml_str).unwrap();
+        config.build.success_when
+    }
+
+    #[test]
+    fn test_success_defaults_to_exit_code_zero() {
+        let criteria = SuccessCriteria::default();
+        let dir = Path::new("/nonexistent");
+        assert_eq!(criteria.failure(Some(0), false, dir), None);
+        assert_eq!(criteria.failure(Some(1), true, dir), Some("Container exited with non-zero status"));
+        assert!(criteria.failure(None, false, dir).is_some());
+        assert!(criteria.output_regex().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_success_allowed_exit_codes() {
+        let criteria = success_criteria(
+            r#"
+            [build.success_when]
+            exit_codes = [0, 3]
+            "#,
+        );
+        let dir = Path::new("/nonexistent");
+        assert_eq!(criteria.failure(Some(3), false, dir), None);
+        assert_eq!(
+            criteria.failure(Some(1), false, dir),
+            Some("Container exit code is not in build.success_when.exit_codes")
+        );
+        // Killed by a signal never passes
+        assert!(criteria.failure(None, false, dir).is_some());
+    }
+
+    #[test]
+    fn test_success_requires_output_match() {
+        let criteria = success_criteria(
+            r#"
+            [build.success_when]
+            output = '^test result: ok\.'
+            "#,
+        );
+        let regex = criteria.output_regex().unwrap().unwrap();
+        assert!(regex.is_match("test result: ok. 12 passed"));
+        assert!(!regex.is_match("test result: FAILED. 11 passed; 1 failed"));
+
+        let dir = Path::new("/nonexistent");
+        assert_eq!(criteria.failure(Some(0), false, dir), Some("No output matched build.success_when.output"));
+        assert_eq!(criteria.failure(Some(0), true, dir), None);
+
+        let invalid = SuccessCriteria { output: Some("(".to_string()), ..Default::default() };
+        assert!(invalid.output_regex().is_err());
+    }
+
+    #[test]
+    fn test_success_requires_artifact() {
+        let dir = std::env::temp_dir().join(format!("foundry-success-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("dist")).unwrap();
+        let criteria = success_criteria(
+            r#"
+            [build.success_when]
+            artifact = "dist/app"
+            "#,
+        );
+
+        assert_eq!(criteria.failure(Some(0), false, &dir), Some("Build did not create build.success_when.artifact"));
+        std::fs::write(dir.join("dist/app"), b"binary").unwrap();
+        assert_eq!(criteria.failure(Some(0), false, &dir), None);
+        // The artifact doesn't rescue a failing exit code
+        assert!(criteria.failure(Some(1), false, &dir).is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_distinguishes_missing_and_invalid() {
+        let dir = std::env::temp_dir().join(format!("foundry-config-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let _ = std::fs::remove_file(dir.join("foundry.toml"));
+        assert!(FoundryConfig::load(&dir).unwrap().is_none());
+
+        std::fs::write(dir.join("foundry.toml"), "[build]\ntimeout = \"soon\"\n").unwrap();
+        let err = FoundryConfig::load(&dir).unwrap_err().to_string();
+        assert!(err.starts_with("Invalid foundry.toml"), "{}", err);
+        assert!(err.contains("line 2"), "{}", err);
+
+        std::fs::write(dir.join("foundry.toml"), "[build]\ntimeout = 60\n").unwrap();
+        assert_eq!(FoundryConfig::load(&dir).unwrap().unwrap().build.timeout, Some(60));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let config = parse_config(
+            r#"
+            [build]
+            command = "cargo test"
+
+            [[stages]]
+            name = "test"
+            command = "cargo test"
+
+            [[stages]]
+            name = "lint"
+            command = "cargo clippy"
+            depends_on = ["test"]
+
+            [deploy]
+            name = "my-app"
+            domain = "app.example.com"
+            port = 3000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.validate(), []);
+
+        let error = parse_config("[build]\ncommand = \"x\"\n\n[deploy]\nport = 70000\n").unwrap_err();
+        assert_eq!(error.line, Some(5));
+
+        let errors = parse_config(
+            r#"
+            [build]
+            memory = "lots"
+
+            [[stages]]
+            name = "lint"
+            command = "cargo clippy"
+            depends_on = ["tests"]
+
+            [deploy]
+            name = "my-app"
+            domain = "https://app.example.com"
+            port = 0
+            "#,
+        )
+        .unwrap()
+        .validate();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["build.memory", "stages.depends_on", "deploy.port", "deploy.domain"]);
+        assert_eq!(errors[1].message, "Stage 'lint' depends on unknown stage 'tests'");
+    }
+
+    #[test]
+    fn test_port_mappings() {
+        let fc: FoundryConfig =
+            toml::from_str("[deploy]\nname = \"app\"\nport = 3000\nports = [\"8443:443\", \"127.0.0.1:9090:9090\", \"5353:53/udp\"]")
+                .unwrap();
+        assert_eq!(fc.deploy.ports.len(), 3);
+        assert!(fc.validate().is_empty());
+
+        for mapping in ["8080", "0:80", "8080:70000", "a:80", "localhost:80:80", "80:80/http", "1:2:3:4"] {
+            assert!(validate_port_mapping(mapping).is_err(), "{}", mapping);
+        }
+        let fc: FoundryConfig = toml::from_str("[deploy]\nname = \"app\"\nports = [\"80\"]").unwrap();
+        assert_eq!(fc.validate()[0].field, "deploy.ports");
+    }
+
+    #[test]
+    fn test_deploy_cloudflare() {
+        let fc: FoundryConfig = toml::from_str("[deploy]\nname = \"app\"\ndomain = \"app.example.com\"").unwrap();
+        assert_eq!(fc.deploy.cloudflare.service(8080), "http://127.0.0.1:8080");
+        assert!(fc.deploy.cloudflare.origin_request().is_none());
+
+        let fc: FoundryConfig = toml::from_str(
+            "[deploy]\nname = \"app\"\ndomain = \"app.example.com\"\n[deploy.cloudflare]\nscheme = \"https\"\nhttp_host_header = \"app.internal\"\nno_tls_verify = true",
+        )
+        .unwrap();
+        assert!(fc.validate().is_empty());
+        assert_eq!(fc.deploy.cloudflare.service(8443), "https://127.0.0.1:8443");
+        let origin = fc.deploy.cloudflare.origin_request().unwrap();
+        assert_eq!(
+            serde_json::to_value(origin).unwrap(),
+            serde_json::json!({"httpHostHeader": "app.internal", "noTLSVerify": true})
+        );
+
+        let fc: FoundryConfig = toml::from_str("[deploy]\nname = \"app\"\n[deploy.cloudflare]\nno_tls_verify = true").unwrap();
+        assert_eq!(fc.validate()[0].field, "deploy.cloudflare.scheme");
+        let fc: FoundryConfig = toml::from_str("[deploy]\nname = \"app\"\n[deploy.cloudflare]\nhttp_host_header = \"\"").unwrap();
+        assert_eq!(fc.validate()[0].field, "deploy.cloudflare.http_host_header");
+        assert!(toml::from_str::<FoundryConfig>("[deploy.cloudflare]\nscheme = \"ftp\"").is_err());
+    }
+
+    #[test]
+    fn test_preview_domain() {
+        let fc: FoundryConfig =
+            toml::from_str("[deploy]\nname = \"app\"\nport = 3000\npreview_domain = \"preview.example.com\"").unwrap();
+        assert!(fc.validate().is_empty());
+
+        for toml in [
+            "[deploy]\nname = \"app\"\nport = 3000\npreview_domain = \"example\"",
+            "[deploy]\nname = \"app\"\npreview_domain = \"example.com\"",
+            "[deploy]\ncompose_file = \"docker-compose.yml\"\nport = 3000\npreview_domain = \"example.com\"",
+        ] {
+            let fc: FoundryConfig = toml::from_str(toml).unwrap();
+            assert_eq!(fc.validate()[0].field, "deploy.preview_domain", "{}", toml);
+        }
+    }
+
+    #[test]
+    fn test_check_context() {
+        let fc: FoundryConfig = toml::from_str("[github]\ncontext = \"foundry/test\"").unwrap();
+        assert_eq!(fc.github.context.as_deref(), Some("foundry/test"));
+        assert!(fc.validate().is_empty());
+        assert_eq!(FoundryConfig::default().github.context, None);
+
+        let fc: FoundryConfig = toml::from_str("[github]\ncontext = \" \"").unwrap();
+        assert_eq!(fc.validate()[0].field, "github.context");
+        assert!(validate_check_context(&"x".repeat(101)).is_err());
+        assert!(validate_check_context("foundry/deploy (prod)").is_ok());
+    }
+
+    #[test]
+    fn test_concurrency_group() {
+        let fc: FoundryConfig = toml::from_str("[concurrency]\ngroup = \"deploy-prod\"\ncancel_in_progress = true").unwrap();
+        assert_eq!(fc.concurrency.group.as_deref(), Some("deploy-prod"));
+        assert!(fc.concurrency.cancel_in_progress);
+        assert!(fc.validate().is_empty());
+
+        let fc: FoundryConfig = toml::from_str("[concurrency]\ngroup = \"deploy prod\"").unwrap();
+        assert_eq!(fc.validate()[0].field, "concurrency.group");
+        let fc: FoundryConfig = toml::from_str("[concurrency]\ncancel_in_progress = true").unwrap();
+        assert_eq!(fc.validate()[0].field, "concurrency.cancel_in_progress");
+    }
 }
@@ -18,12 +18,15 @@ pub fn verify_github_signature(secret: &str, body: &[u8], header: &str) -> bool
     mac.verify_slice(&sig_bytes).is_ok()
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum TriggerType {
     Push,
     PullRequest,
     Manual,
+    Scheduled,
+    Tag,
+    Release,
 }
 
 impl std::fmt::Display for TriggerType {
@@ -32,6 +35,9 @@ impl std::fmt::Display for TriggerType {
             TriggerType::Push => write!(f, "push"),
             TriggerType::PullRequest => write!(f, "pull_request"),
             TriggerType::Manual => write!(f, "manual"),
+            TriggerType::Scheduled => write!(f, "scheduled"),
+            TriggerType::Tag => write!(f, "tag"),
+            TriggerType::Release => write!(f, "release"),
         }
     }
 }
@@ -43,6 +49,9 @@ impl std::str::FromStr for TriggerType {
             "push" => Ok(TriggerType::Push),
             "pull_request" => Ok(TriggerType::PullRequest),
             "manual" => Ok(TriggerType::Manual),
+            "scheduled" => Ok(TriggerType::Scheduled),
+            "tag" => Ok(TriggerType::Tag),
+            "release" => Ok(TriggerType::Release),
             _ => Err(()),
         }
     }
@@ -58,6 +67,38 @@ pub struct PullRequestEvent {
     pub installation: Option<Installation>,
 }
 
+/// Identifier of the "Cancel" button foundry adds to its check runs
+pub const CHECK_RUN_CANCEL_ACTION: &str = "cancel";
+
+/// `check_run` webhook event; only the fields needed to act on it
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct CheckRunEvent {
+    pub action: String,
+    pub check_run: CheckRunRef,
+    #[serde(default)]
+    pub requested_action: Option<RequestedAction>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct CheckRunRef {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct RequestedAction {
+    pub identifier: String,
+}
+
+impl CheckRunEvent {
+    /// The check run id if this event is someone pressing our "Cancel" button
+    pub fn cancel_requested(&self) -> Option<i64> {
+        let identifier = &self.requested_action.as_ref()?.identifier;
+        (self.action == "requested_action" && identifier == CHECK_RUN_CANCEL_ACTION)
+            .then_some(self.check_run.id)
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct PullRequest {
     pub id: i64,
@@ -99,6 +140,55 @@ impl PullRequestEvent {
     }
 }
 
+impl PushEvent {
+    /// Tag name if this push created or moved a tag
+    pub fn tag(&self) -> Option<&str> {
+        self.git_ref.strip_prefix("refs/tags/")
+    }
+}
+
+/// `release` webhook event
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct ReleaseEvent {
+    pub action: String,
+    pub release: Release,
+    pub repository: Repository,
+    pub sender: Option<Sender>,
+    pub installation: Option<Installation>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Release {
+    pub id: i64,
+    pub tag_name: String,
+    /// Branch or commit sha the tag was created from
+    pub target_commitish: String,
+    pub name: Option<String>,
+    pub html_url: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+}
+
+impl ReleaseEvent {
+    /// Builds run once, when the release is published (drafts publish later)
+    pub fn should_build(&self) -> bool {
+        self.action == "published" && !self.release.draft
+    }
+
+    /// The commit to build: `target_commitish` when it is a full sha, otherwise a
+    /// `RESOLVE:` placeholder for the release tag, which the agent clones by name
+    pub fn build_sha(&self) -> String {
+        let target = &self.release.target_commitish;
+        if target.len() == 40 && target.chars().all(|c| c.is_ascii_hexdigit()) {
+            target.clone()
+        } else {
+            format!("RESOLVE:{}", self.release.tag_name)
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
 pub struct PushEvent {
     #[serde(rename = "ref")]
@@ -242,4 +332,72 @@ mod tests {
             "sha256=abc123"
         ));
     }
+
+    #[test]
+    fn test_check_run_cancel_requested() {
+        let event: CheckRunEvent = serde_json::from_value(serde_json::json!({
+            "action": "requested_action",
+            "check_run": {"id": 4242, "name": "Foundry CI", "status": "in_progress"},
+            "requested_action": {"identifier": "cancel"},
+            "repository": {"name": "app"}
+        }))
+        .unwrap();
+        assert_eq!(event.cancel_requested(), Some(4242));
+    }
+
+    #[test]
+    fn test_check_run_other_actions_ignored() {
+        let rerequested: CheckRunEvent = serde_json::from_value(serde_json::json!({
+            "action": "rerequested",
+            "check_run": {"id": 4242, "name": "Foundry CI"}
+        }))
+        .unwrap();
+        assert_eq!(rerequested.cancel_requested(), None);
+
+        let other_button: CheckRunEvent = serde_json::from_value(serde_json::json!({
+            "action": "requested_action",
+            "check_run": {"id": 4242, "name": "Foundry CI"},
+            "requested_action": {"identifier": "fix"}
+        }))
+        .unwrap();
+        assert_eq!(other_button.cancel_requested(), None);
+    }
+
+    fn release_event(action: &str, target: &str) -> ReleaseEvent {
+        serde_json::from_value(serde_json::json!({
+            "action": action,
+            "release": {
+                "id": 1,
+                "tag_name": "v1.2.0",
+                "target_commitish": target,
+                "name": "v1.2.0",
+                "html_url": "https://github.com/acme/app/releases/tag/v1.2.0",
+                "draft": false,
+                "prerelease": false
+            },
+            "repository": {
+                "id": 7, "name": "app", "full_name": "acme/app", "private": false,
+                "owner": {"login": "acme", "id": 1},
+                "html_url": "https://github.com/acme/app", "description": null, "fork": false,
+                "url": "https://api.github.com/repos/acme/app",
+                "clone_url": "https://github.com/acme/app.git", "ssh_url": "git@github.com:acme/app.git",
+                "default_branch": "main", "language": "Rust"
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_release_builds_once_published() {
+        assert!(release_event("published", "main").should_build());
+        assert!(!release_event("created", "main").should_build());
+        assert!(!release_event("edited", "main").should_build());
+    }
+
+    #[test]
+    fn test_release_build_sha() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(release_event("published", sha).build_sha(), sha);
+        assert_eq!(release_event("published", "main").build_sha(), "RESOLVE:v1.2.0");
+    }
 }
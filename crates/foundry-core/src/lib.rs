@@ -3,6 +3,6 @@ pub mod github;
 pub mod types;
 pub mod cloudflare;
 
-pub use config::{FoundryConfig, StageConfig, StageCondition, ScheduleConfig};
+pub use config::{FoundryConfig, MonorepoConfig, StageConfig, StageCondition, ScheduleConfig};
 pub use github::{verify_github_signature, TriggerType};
 pub use types::*;
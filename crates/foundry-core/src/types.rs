@@ -1,6 +1,36 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Version of the `/agent/*` API contract. Agents send it in the `PROTOCOL_HEADER`
+/// of every request and in claims and heartbeats. Bump it when a change needs agents
+/// and the server to be upgraded together, and raise `MIN_PROTOCOL_VERSION` once the
+/// server stops accepting the old contract.
+///
+/// 1. First versioned contract
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest agent protocol the server still serves
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+pub const PROTOCOL_HEADER: &str = "x-foundry-protocol";
+
+/// Why an agent speaking protocol `version` can't work with this server, if it can't
+pub fn protocol_mismatch(version: u32) -> Option<String> {
+    if version < MIN_PROTOCOL_VERSION {
+        Some(format!(
+            "Agent protocol {} is too old: this server supports protocols {} to {}, upgrade the agent",
+            version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ))
+    } else if version > PROTOCOL_VERSION {
+        Some(format!(
+            "Agent protocol {} is too new: this server supports protocols {} to {}, upgrade foundryd",
+            version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ))
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum JobStatus {
@@ -8,6 +38,50 @@ pub enum JobStatus {
     Running,
     Success,
     Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "queued" => Some(JobStatus::Queued),
+            "running" => Some(JobStatus::Running),
+            "success" => Some(JobStatus::Success),
+            "failed" => Some(JobStatus::Failed),
+            "cancelled" => Some(JobStatus::Cancelled),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Success => "success",
+            JobStatus::Failed => "failed",
+            JobStatus::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Severity of a job log line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -17,24 +91,116 @@ pub struct ClaimedJob {
     pub repo_owner: String,
     pub repo_name: String,
     pub clone_url: String,
+    /// `git@github.com:owner/name.git`, cloned instead of `clone_url` by agents with a deploy key
+    #[serde(default)]
+    pub ssh_url: Option<String>,
     pub git_sha: String,
     pub git_ref: String,
     pub image: String,
     pub claim_token: Uuid,
+    /// Files touched by the triggering push, used to pick monorepo services
+    #[serde(default)]
+    pub changed_files: Vec<String>,
+    /// Monorepo service directory this job builds, relative to the repo root
+    #[serde(default)]
+    pub service_path: Option<String>,
+    /// Short unguessable id used in shareable job links
+    #[serde(default)]
+    pub public_id: String,
+    /// Dashboard link for the job, when the server knows its public URL
+    #[serde(default)]
+    pub details_url: Option<String>,
+    /// GitHub check run already created for this job (or the job it retries); the
+    /// agent updates it instead of creating a second one
+    #[serde(default)]
+    pub check_run_id: Option<i64>,
+    /// The repo's secrets, sent only to non-PR builds
+    #[serde(default)]
+    pub secrets: Secrets,
+    /// Values for one combination of a `[matrix]` build; empty for other jobs
+    #[serde(default)]
+    pub matrix: std::collections::BTreeMap<String, String>,
+    /// Platform `image` was picked for, e.g. `linux/amd64`, when the server chose it
+    /// from its per-arch default images
+    #[serde(default)]
+    pub platform: Option<String>,
+    /// The repo's `[github] context`, naming its commit status and check run
+    #[serde(default)]
+    pub check_context: Option<String>,
+}
+
+/// Secret values by name. `Debug` lists only the names so claimed jobs can be logged.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secrets(pub std::collections::HashMap<String, String>);
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClaimRequest {
     pub agent_id: String,
+    /// The agent's `BuildInfo`, e.g. `0.1.0 (1a2b3c4)`
+    #[serde(default)]
+    pub agent_version: Option<String>,
+    /// `PROTOCOL_VERSION` the agent was built with; absent from agents older than it
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+    /// Why the agent can't reach Docker. Such a claim only reports the agent as alive;
+    /// the server hands it no job.
+    #[serde(default)]
+    pub docker_error: Option<String>,
+    /// Docker architecture of the agent's host, e.g. `amd64` or `arm64`
+    #[serde(default)]
+    pub arch: Option<String>,
+    /// `FOUNDRY_AGENT_LABELS`; the agent only gets jobs whose `runs_on` they all cover
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Claim only this job, for `foundry-agent --once <id>`
+    #[serde(default)]
+    pub job_id: Option<i64>,
+}
+
+/// Docker's name for a Rust target architecture, e.g. `aarch64` is `arm64`
+pub fn docker_arch(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "powerpc64" => "ppc64le",
+        other => other,
+    }
+}
+
+/// Architecture part of a Docker platform, e.g. `arm64` for `linux/arm64/v8`
+pub fn platform_arch(platform: &str) -> &str {
+    platform.split('/').nth(1).unwrap_or(platform)
+}
+
+/// Hand a claimed job back to the queue without running it, e.g. because the agent
+/// lost its Docker daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub reason: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "status")]
 pub enum ClaimResponse {
     #[serde(rename = "claimed")]
-    Claimed { job: ClaimedJob },
+    Claimed { job: Box<ClaimedJob> },
     #[serde(rename = "empty")]
     Empty,
+    /// The server won't give this agent work, e.g. because it is incompatible
+    #[serde(rename = "rejected")]
+    Rejected { error: String },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +208,47 @@ pub struct LogRequest {
     pub job_id: i64,
     pub claim_token: Uuid,
     pub line: String,
+    /// Older agents don't send a level, so their lines are info
+    #[serde(default)]
+    pub level: LogLevel,
+}
+
+/// Where a limit a job ran with came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitSource {
+    /// The repo's `foundry.toml`
+    Config,
+    /// The agent's `FOUNDRY_DEFAULT_*` settings
+    Agent,
+    /// The server, e.g. the platform of its default image
+    Server,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppliedLimit<T> {
+    pub value: T,
+    pub source: LimitSource,
+}
+
+/// Settings a job's build containers actually ran with. Unset ones were left to Docker.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<AppliedLimit<f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<AppliedLimit<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<AppliedLimit<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<AppliedLimit<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub limits: JobLimits,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +256,144 @@ pub struct FinishRequest {
     pub job_id: i64,
     pub claim_token: Uuid,
     pub success: bool,
+    /// Exit code of the failing container, or 0 on success
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+}
+
+/// Record the GitHub check run created for a job, so check run events can find it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckRunRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub check_run_id: i64,
+}
+
+/// Sent periodically by the agent while it works on a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    /// The agent's `BuildInfo`, e.g. `0.1.0 (1a2b3c4)`
+    #[serde(default)]
+    pub agent_version: Option<String>,
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+}
+
+/// What a running binary was built from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_sha: String,
+    /// RFC 3339 time of the build
+    pub built_at: String,
+}
+
+impl BuildInfo {
+    /// `build_timestamp` is in Unix seconds, as set by the build script
+    pub fn new(version: &str, git_sha: &str, build_timestamp: &str) -> Self {
+        let built_at = build_timestamp
+            .parse()
+            .ok()
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+            .unwrap_or_else(|| "unknown".to_string());
+        Self {
+            version: version.to_string(),
+            git_sha: git_sha.to_string(),
+            built_at,
+        }
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.version, &self.git_sha[..7.min(self.git_sha.len())])
+    }
+}
+
+/// How foundryd and the agent write their own logs, from `FOUNDRY_LOG_FORMAT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for Loki, Elasticsearch and the like
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "text" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// Read `FOUNDRY_LOG_FORMAT`, defaulting to text when it's unset
+    pub fn from_env() -> anyhow::Result<Self> {
+        let value = std::env::var("FOUNDRY_LOG_FORMAT").unwrap_or_default();
+        Self::parse(&value)
+            .ok_or_else(|| anyhow::anyhow!("FOUNDRY_LOG_FORMAT must be text or json, got {:?}", value))
+    }
+}
+
+/// `BuildInfo` of the crate this is used in. Its build script must set
+/// `FOUNDRY_GIT_SHA` and `FOUNDRY_BUILD_TIMESTAMP`.
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::BuildInfo::new(
+            env!("CARGO_PKG_VERSION"),
+            env!("FOUNDRY_GIT_SHA"),
+            env!("FOUNDRY_BUILD_TIMESTAMP"),
+        )
+    };
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobStatusResponse {
+    pub status: JobStatus,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanOutRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub services: Vec<String>,
+}
+
+/// A container the agent started with `docker run`, kept so the dashboard can roll
+/// the app back to an earlier image with the same arguments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub app_name: String,
+    pub image: String,
+    /// `docker run` flags, between `run` and the image
+    pub args: Vec<String>,
+    /// Command passed after the image
+    pub command: Vec<String>,
+}
+
+/// A pull request preview the agent deployed, so the server can remove it when the
+/// PR closes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreviewRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub app_name: String,
+    pub hostname: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixRequest {
+    pub job_id: i64,
+    pub claim_token: Uuid,
+    pub entries: Vec<crate::config::MatrixEntry>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,6 +406,38 @@ pub struct SyncScheduleRequest {
     pub enabled: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncNotifyRequest {
+    pub repo_id: i64,
+    pub claim_token: Uuid,
+    pub slack_webhook: Option<String>,
+    pub discord_webhook: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncConcurrencyRequest {
+    pub repo_id: i64,
+    pub claim_token: Uuid,
+    pub group: Option<String>,
+    pub cancel_in_progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncCheckContextRequest {
+    pub repo_id: i64,
+    pub claim_token: Uuid,
+    pub context: Option<String>,
+}
+
+/// `[build] runs_on` of the job holding `claim_token`; a root job's is also kept for
+/// the repo's next jobs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRunsOnRequest {
+    pub repo_id: i64,
+    pub claim_token: Uuid,
+    pub runs_on: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncTriggersRequest {
     pub repo_id: i64,
@@ -68,6 +445,14 @@ pub struct SyncTriggersRequest {
     pub branches: Vec<String>,
     pub pull_requests: bool,
     pub pr_target_branches: Option<Vec<String>>,
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub paths_ignore: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub releases: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -92,3 +477,108 @@ impl ApiResponse {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claimed_job_json() -> serde_json::Value {
+        serde_json::json!({
+            "id": 7,
+            "repo_id": 1,
+            "repo_owner": "acme",
+            "repo_name": "app",
+            "clone_url": "https://github.com/acme/app.git",
+            "git_sha": "abc123",
+            "git_ref": "refs/heads/main",
+            "image": "ubuntu:latest",
+            "claim_token": "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        })
+    }
+
+    #[test]
+    fn test_claimed_job_carries_stored_check_run() {
+        let mut json = claimed_job_json();
+        json["check_run_id"] = serde_json::json!(987654);
+        let job: ClaimedJob = serde_json::from_value(json).unwrap();
+        assert_eq!(job.check_run_id, Some(987654));
+    }
+
+    #[test]
+    fn test_claimed_job_from_older_server() {
+        let job: ClaimedJob = serde_json::from_value(claimed_job_json()).unwrap();
+        assert_eq!(job.check_run_id, None);
+        assert!(job.changed_files.is_empty());
+    }
+
+    #[test]
+    fn test_protocol_mismatch() {
+        assert_eq!(protocol_mismatch(PROTOCOL_VERSION), None);
+        assert_eq!(protocol_mismatch(MIN_PROTOCOL_VERSION), None);
+
+        let too_new = protocol_mismatch(PROTOCOL_VERSION + 1).unwrap();
+        assert!(too_new.contains("too new"), "{}", too_new);
+        assert!(too_new.ends_with("upgrade foundryd"));
+
+        let too_old = protocol_mismatch(MIN_PROTOCOL_VERSION - 1).unwrap();
+        assert!(too_old.contains("too old"), "{}", too_old);
+        assert!(too_old.ends_with("upgrade the agent"));
+    }
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!(LogFormat::parse(""), Some(LogFormat::Text));
+        assert_eq!(LogFormat::parse("text"), Some(LogFormat::Text));
+        assert_eq!(LogFormat::parse(" JSON "), Some(LogFormat::Json));
+        assert_eq!(LogFormat::parse("logfmt"), None);
+    }
+
+    #[test]
+    fn test_build_info() {
+        let info = BuildInfo::new("0.1.0", "1a2b3c4d5e6f", "1700000000");
+        assert_eq!(info.built_at, "2023-11-14T22:13:20Z");
+        assert_eq!(info.to_string(), "0.1.0 (1a2b3c4)");
+
+        let info = BuildInfo::new("0.1.0", "unknown", "");
+        assert_eq!(info.built_at, "unknown");
+        assert_eq!(info.to_string(), "0.1.0 (unknown)");
+    }
+
+    #[test]
+    fn test_claimed_job_debug_hides_secret_values() {
+        let mut json = claimed_job_json();
+        json["secrets"] = serde_json::json!({"NPM_TOKEN": "npm_abcdef123456"});
+        let job: ClaimedJob = serde_json::from_value(json).unwrap();
+        assert_eq!(job.secrets.0["NPM_TOKEN"], "npm_abcdef123456");
+        let debug = format!("{:?}", job);
+        assert!(debug.contains("NPM_TOKEN"));
+        assert!(!debug.contains("npm_abcdef123456"));
+    }
+
+    #[test]
+    fn test_log_request_level_defaults_to_info() {
+        let json = serde_json::json!({
+            "job_id": 7,
+            "claim_token": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "line": "Cloning repo"
+        });
+        let req: LogRequest = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(req.level, LogLevel::Info);
+
+        let mut json = json;
+        json["level"] = serde_json::json!("error");
+        let req: LogRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(req.level, LogLevel::Error);
+        assert_eq!(serde_json::to_value(LogLevel::Warn).unwrap(), "warn");
+    }
+
+    #[test]
+    fn test_docker_arch() {
+        assert_eq!(docker_arch("x86_64"), "amd64");
+        assert_eq!(docker_arch("aarch64"), "arm64");
+        assert_eq!(docker_arch("s390x"), "s390x");
+        assert_eq!(platform_arch("linux/amd64"), "amd64");
+        assert_eq!(platform_arch("linux/arm64/v8"), "arm64");
+        assert_eq!(platform_arch("arm64"), "arm64");
+    }
+}
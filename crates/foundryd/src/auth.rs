@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Result};
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{FromRequestParts, Query, State},
+    http::{request::Parts, StatusCode},
     response::{IntoResponse, Json, Redirect, Response},
     routing::get,
     Router,
@@ -12,6 +12,7 @@ use rand::Rng;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::{sync::Arc, time::Duration};
+use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
 use crate::{config::AuthConfig, AppState};
@@ -24,12 +25,37 @@ pub struct AuthState {
     pub config: AuthConfig,
     pub oidc_config: OidcConfig,
     http_client: Client,
+    /// Signing keys from `jwks_uri`, refetched when a token names an unknown `kid`
+    jwks: Arc<RwLock<Vec<JwkKey>>>,
 }
 
 #[derive(Clone, Debug)]
 pub struct OidcConfig {
+    pub issuer: String,
     pub authorization_endpoint: String,
     pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JwkKey {
+    pub kid: Option<String>,
+    pub kty: String,
+    pub n: Option<String>,
+    pub e: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<JwkKey>,
+}
+
+/// Claims checked on tokens returned by the code exchange
+#[derive(Debug, Deserialize)]
+pub struct TokenClaims {
+    pub sub: String,
+    pub iss: String,
+    pub exp: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,7 +68,8 @@ pub struct SessionClaims {
 #[derive(Debug, Deserialize)]
 pub struct WorkOsAuthResponse {
     pub access_token: String,
-    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub id_token: Option<String>,
     pub user: WorkOsUser,
 }
 
@@ -60,11 +87,28 @@ pub struct AuthCallback {
     pub state: String,
 }
 
+/// What a signed-in user may do: viewers see jobs and logs, admins can also change things
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Admin,
+}
+
+/// A valid session, with the role its email has under the current config
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub email: String,
+    pub role: Role,
+}
+
 #[derive(Serialize)]
 pub struct AuthStatus {
     pub authenticated: bool,
     pub email: Option<String>,
     pub name: Option<String>,
+    /// `admin` when auth is disabled
+    pub role: Option<Role>,
 }
 
 impl AuthState {
@@ -74,8 +118,10 @@ impl AuthState {
             .build()?;
 
         let oidc_config = OidcConfig {
+            issuer: config.issuer_url.trim_end_matches('/').to_string(),
             authorization_endpoint: "https://api.workos.com/user_management/authorize".to_string(),
             token_endpoint: "https://api.workos.com/user_management/authenticate".to_string(),
+            jwks_uri: format!("https://api.workos.com/sso/jwks/{}", config.client_id),
         };
 
         info!("WorkOS auth initialised (client_id={})", config.client_id);
@@ -84,9 +130,60 @@ impl AuthState {
             config,
             oidc_config,
             http_client,
+            jwks: Arc::new(RwLock::new(Vec::new())),
         })
     }
 
+    /// Verify a token from the code exchange: RS256 signature against the JWKS, issuer,
+    /// expiry and, when `require_aud` is set or the token carries one, the audience.
+    /// WorkOS access tokens are issued by `{issuer}/user_management/{client_id}`.
+    pub async fn verify_token(&self, token: &str, require_aud: bool) -> Result<TokenClaims> {
+        use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+
+        let header = decode_header(token)?;
+        if header.alg != Algorithm::RS256 {
+            return Err(anyhow!("Unexpected token algorithm {:?}", header.alg));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[
+            self.oidc_config.issuer.clone(),
+            format!("{}/user_management/{}", self.oidc_config.issuer, self.config.client_id),
+        ]);
+        let mut required = vec!["exp", "iss", "sub"];
+        if require_aud {
+            required.push("aud");
+        }
+        validation.set_required_spec_claims(&required);
+
+        Ok(decode::<TokenClaims>(token, &key, &validation)?.claims)
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<jsonwebtoken::DecodingKey> {
+        if let Some(key) = find_jwk(&self.jwks.read().await, kid) {
+            return rsa_decoding_key(&key);
+        }
+
+        // Unknown key id: the provider may have rotated keys
+        let jwks: JwkSet = self
+            .http_client
+            .get(&self.oidc_config.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let key = find_jwk(&jwks.keys, kid);
+        *self.jwks.write().await = jwks.keys;
+
+        match key {
+            Some(key) => rsa_decoding_key(&key),
+            None => Err(anyhow!("No signing key found for kid {:?}", kid)),
+        }
+    }
+
     /// Validate a session token (our own HS256 JWT, not WorkOS's token).
     pub fn validate_session(&self, token: &str) -> Option<SessionClaims> {
         use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
@@ -113,6 +210,17 @@ impl AuthState {
         }
     }
 
+    /// Admins are the `FOUNDRY_ADMIN_EMAILS`; without any, everyone is a viewer.
+    /// Resolved per request rather than at sign-in, so removing someone from the list
+    /// takes effect without waiting for their session to expire.
+    pub fn role(&self, email: &str) -> Role {
+        if self.config.admin_emails.iter().any(|e| e == email) {
+            Role::Admin
+        } else {
+            Role::Viewer
+        }
+    }
+
     /// Create a signed session token for the given email (7-day expiry).
     pub fn create_session(&self, email: &str) -> Result<String> {
         use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
@@ -130,6 +238,25 @@ impl AuthState {
     }
 }
 
+/// The RSA key matching `kid`, or the only RSA key if the token has no `kid`
+fn find_jwk(keys: &[JwkKey], kid: Option<&str>) -> Option<JwkKey> {
+    let mut rsa_keys = keys.iter().filter(|k| k.kty == "RSA");
+    match kid {
+        Some(kid) => rsa_keys.find(|k| k.kid.as_deref() == Some(kid)).cloned(),
+        None => {
+            let first = rsa_keys.next()?;
+            rsa_keys.next().is_none().then(|| first.clone())
+        }
+    }
+}
+
+fn rsa_decoding_key(key: &JwkKey) -> Result<jsonwebtoken::DecodingKey> {
+    match (&key.n, &key.e) {
+        (Some(n), Some(e)) => Ok(jsonwebtoken::DecodingKey::from_rsa_components(n, e)?),
+        _ => Err(anyhow!("JWK {:?} is missing n/e", key.kid)),
+    }
+}
+
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/auth/login", get(login))
@@ -202,6 +329,23 @@ async fn callback(
         }
     };
 
+    // Don't trust the user object alone: it must match a token signed by the provider
+    let (token, require_aud) = match &workos_response.id_token {
+        Some(id_token) => (id_token.as_str(), true),
+        None => (workos_response.access_token.as_str(), false),
+    };
+    match auth.verify_token(token, require_aud).await {
+        Ok(claims) if claims.sub == workos_response.user.id => {}
+        Ok(claims) => {
+            warn!("Token subject {} does not match user {}", claims.sub, workos_response.user.id);
+            return (StatusCode::UNAUTHORIZED, "Authentication failed").into_response();
+        }
+        Err(e) => {
+            warn!("Token verification failed: {}", e);
+            return (StatusCode::UNAUTHORIZED, "Authentication failed").into_response();
+        }
+    }
+
     let email = workos_response.user.email.clone();
     let first_name = workos_response.user.first_name;
     let last_name = workos_response.user.last_name;
@@ -272,25 +416,25 @@ async fn status(State(state): State<Arc<AppState>>, jar: CookieJar) -> impl Into
                 authenticated: true,
                 email: None,
                 name: None,
+                role: Some(Role::Admin),
             })
         }
     };
 
-    // Validate session cookie
-    if let Some(session_cookie) = jar.get(SESSION_COOKIE_NAME) {
-        if let Some(claims) = auth.validate_session(session_cookie.value()) {
-            return Json(AuthStatus {
-                authenticated: true,
-                email: Some(claims.email),
-                name: None,
-            });
-        }
+    if let Some(session) = session(Some(auth), &jar) {
+        return Json(AuthStatus {
+            authenticated: true,
+            email: Some(session.email),
+            name: None,
+            role: Some(session.role),
+        });
     }
 
     Json(AuthStatus {
         authenticated: false,
         email: None,
         name: None,
+        role: None,
     })
 }
 
@@ -317,6 +461,45 @@ async fn exchange_code(auth: &AuthState, code: &str) -> Result<WorkOsAuthRespons
     Ok(response.json().await?)
 }
 
+/// The signed-in user, if auth is enabled and the request has a valid session
+pub fn session(auth: Option<&AuthState>, jar: &CookieJar) -> Option<Session> {
+    let auth = auth?;
+    let claims = auth.validate_session(jar.get(SESSION_COOKIE_NAME)?.value())?;
+    Some(Session {
+        role: auth.role(&claims.email),
+        email: claims.email,
+    })
+}
+
+/// Email of the signed-in user, if auth is enabled and the request has a valid session
+pub fn session_email(auth: Option<&AuthState>, jar: &CookieJar) -> Option<String> {
+    session(auth, jar).map(|session| session.email)
+}
+
+/// Whether the request may change anything: always without auth, otherwise only with
+/// an admin's session
+pub fn is_admin(auth: Option<&AuthState>, jar: &CookieJar) -> bool {
+    match auth {
+        None => true,
+        Some(_) => session(auth, jar).is_some_and(|session| session.role == Role::Admin),
+    }
+}
+
+/// Extractor for handlers that change anything; viewers get a 403
+pub struct RequireAdmin;
+
+impl FromRequestParts<Arc<AppState>> for RequireAdmin {
+    type Rejection = (StatusCode, Json<serde_json::Value>);
+
+    async fn from_request_parts(parts: &mut Parts, state: &Arc<AppState>) -> Result<Self, Self::Rejection> {
+        if is_admin(state.auth.as_ref(), &CookieJar::from_headers(&parts.headers)) {
+            Ok(Self)
+        } else {
+            Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"ok": false, "error": "Admin access required"}))))
+        }
+    }
+}
+
 // Middleware to check authentication
 pub async fn require_auth(
     State(state): State<Arc<AppState>>,
@@ -346,3 +529,112 @@ pub async fn require_auth(
     // For page requests, redirect to login
     Redirect::to("/auth/login").into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+
+    // Throwaway key used only to sign tokens in these tests
+    const TEST_KEY_PEM: &str = include_str!("../testdata/jwt_test_key.pem");
+    const TEST_KEY_N: &str = "2H1Tqq_lAXtTJgtr4kYuXThk3xaEA2DhGNr77BOGWV0VQYbcTCGanBaY1n_Qqb1nrDU1eiHMUkDsSehRuraZqeCV2Pj17c4uOj2nrJM6x48222Os0UNZIUlnEwUZ_iS7imMYU5VMm8BDIqAiBogTk2R0fxYYF_UIvRGpCgVomHQHb9N0VLhU_FkihoCf567TYGhFichJSDInfuAGBzDBV7NkC7lyuFPseormRLTncrbvXRTTXUEYfKqJwJ5Bj-0fmBjUms2llIFA0n3Xqeewj6YdAKc2GRnpAgt2X2rXjOmXCoN-s0TEmm-075i28x0uSBUn0yWSw3zTJ1TcyNg1Fw";
+
+    async fn auth_state() -> AuthState {
+        let auth = AuthState::new(AuthConfig {
+            issuer_url: "https://api.workos.com".to_string(),
+            client_id: "client_123".to_string(),
+            client_secret: "secret".to_string(),
+            cookie_secret: "cookie".to_string(),
+            redirect_url: "https://ci.example.com/auth/callback".to_string(),
+            allowed_emails: vec![],
+            admin_emails: vec![],
+        })
+        .await
+        .unwrap();
+        *auth.jwks.write().await = vec![JwkKey {
+            kid: Some("key-1".to_string()),
+            kty: "RSA".to_string(),
+            n: Some(TEST_KEY_N.to_string()),
+            e: Some("AQAB".to_string()),
+        }];
+        auth
+    }
+
+    fn sign(claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("key-1".to_string());
+        let key = EncodingKey::from_rsa_pem(TEST_KEY_PEM.as_bytes()).unwrap();
+        encode(&header, &claims, &key).unwrap()
+    }
+
+    fn claims(iss: &str, aud: &str, exp_offset: i64) -> serde_json::Value {
+        let now = chrono::Utc::now().timestamp();
+        serde_json::json!({"sub": "user_1", "iss": iss, "aud": aud, "exp": now + exp_offset, "iat": now})
+    }
+
+    #[tokio::test]
+    async fn test_session_role() {
+        let mut auth = auth_state().await;
+        let jar = CookieJar::new().add(Cookie::new(SESSION_COOKIE_NAME, auth.create_session("dev@example.com").unwrap()));
+        // Without an admin list nobody is an admin
+        assert_eq!(session(Some(&auth), &jar).unwrap().role, Role::Viewer);
+
+        auth.config.admin_emails = vec!["ops@example.com".to_string()];
+        let session = session(Some(&auth), &jar).unwrap();
+        assert_eq!((session.email.as_str(), session.role), ("dev@example.com", Role::Viewer));
+        assert!(!is_admin(Some(&auth), &jar));
+        assert!(!is_admin(Some(&auth), &CookieJar::new()));
+        assert_eq!(auth.role("ops@example.com"), Role::Admin);
+    }
+
+    #[tokio::test]
+    async fn test_valid_id_token() {
+        let auth = auth_state().await;
+        let token = sign(claims("https://api.workos.com", "client_123", 300));
+        let verified = auth.verify_token(&token, true).await.unwrap();
+        assert_eq!(verified.sub, "user_1");
+    }
+
+    #[tokio::test]
+    async fn test_access_token_issuer_without_aud() {
+        let auth = auth_state().await;
+        let now = chrono::Utc::now().timestamp();
+        let token = sign(serde_json::json!({
+            "sub": "user_1",
+            "iss": "https://api.workos.com/user_management/client_123",
+            "exp": now + 300,
+        }));
+        assert!(auth.verify_token(&token, false).await.is_ok());
+        // An id_token must carry an audience
+        assert!(auth.verify_token(&token, true).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_wrong_issuer_audience_or_expiry() {
+        let auth = auth_state().await;
+        for token in [
+            sign(claims("https://evil.example.com", "client_123", 300)),
+            sign(claims("https://api.workos.com", "someone_else", 300)),
+            sign(claims("https://api.workos.com", "client_123", -300)),
+        ] {
+            assert!(auth.verify_token(&token, true).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_forged_signature() {
+        let auth = auth_state().await;
+        let token = sign(claims("https://api.workos.com", "client_123", 300));
+        let (body, _) = token.rsplit_once('.').unwrap();
+        let forged = format!("{}.{}", body, URL_SAFE_NO_PAD.encode([0u8; 256]));
+        assert!(auth.verify_token(&forged, true).await.is_err());
+
+        let hs256 = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims("https://api.workos.com", "client_123", 300),
+            &EncodingKey::from_secret(TEST_KEY_N.as_bytes()),
+        )
+        .unwrap();
+        assert!(auth.verify_token(&hs256, true).await.is_err());
+    }
+}
@@ -16,7 +16,6 @@ pub struct CloudflareConfig {
 
 pub struct CloudflareTunnel {
     _process: Child,
-    pub tunnel_id: String,
     pub domain: String,
 }
 
@@ -39,7 +38,7 @@ impl CloudflareTunnel {
 
         info!("Adding route for {}...", config.domain);
         let service = format!("http://127.0.0.1:{}", config.local_port);
-        client.add_route(&config.domain, &service).await?;
+        client.add_route(&config.domain, &service, None).await?;
 
         info!("Getting tunnel token...");
         let token = client.get_tunnel_token(&tunnel.id).await?;
@@ -56,7 +55,6 @@ impl CloudflareTunnel {
 
         Ok(Self {
             _process: process,
-            tunnel_id: tunnel.id,
             domain: config.domain,
         })
     }
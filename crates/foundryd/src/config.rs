@@ -1,6 +1,11 @@
 use anyhow::{Context, Result};
+use foundry_core::config::parse_memory_size;
+use foundry_core::TriggerType;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 
+use crate::secrets::SecretKey;
+
 #[derive(Clone)]
 pub struct Config {
     pub bind_addr: String,
@@ -9,6 +14,160 @@ pub struct Config {
     pub github_webhook_secret: String,
     pub tunnel: Option<TunnelConfig>,
     pub auth: Option<AuthConfig>,
+    /// Externally reachable dashboard URL, used for links in checks and notifications
+    pub public_url: Option<String>,
+    /// Only resolve jobs by public id on the dashboard API, so numeric ids can't be enumerated
+    pub public_job_ids_only: bool,
+    /// Branch globs built on push for repos that haven't synced `[triggers] branches`.
+    /// Empty builds each repo's default branch.
+    pub build_branches: Vec<String>,
+    /// `owner/name` or `owner/*` entries whose repos build as soon as GitHub sends a
+    /// webhook; other new repos are added paused. Empty builds every repo.
+    pub allowed_repos: Vec<String>,
+    /// Bearer tokens accepted on `/agent/*`; empty leaves the agent API open
+    pub agent_tokens: Vec<String>,
+    /// Directory build artifacts are stored in, one file per artifact id
+    pub artifact_dir: String,
+    /// Largest artifact an agent may upload, in bytes
+    pub max_artifact_size: u64,
+    /// Bytes of log output kept per job; later lines are dropped
+    pub max_log_size: u64,
+    /// Webhook events not linked to a job are pruned after this many days
+    pub webhook_retention_days: u32,
+    /// Log lines of jobs that finished this many days ago are deleted; `None` keeps them
+    pub log_retention_days: Option<u32>,
+    /// Running jobs without an agent heartbeat for this long are requeued
+    pub heartbeat_timeout_secs: u64,
+    /// Jobs pinned to an agent that hasn't claimed them for this long are failed
+    pub pin_timeout_secs: u64,
+    /// How long in-flight requests get to finish after SIGTERM before the server exits
+    pub shutdown_timeout_secs: u64,
+    /// Claim priority given to new jobs by trigger type
+    pub trigger_priorities: TriggerPriorities,
+    /// Image for builds without a `foundry.toml`, by the claiming agent's architecture
+    pub default_images: DefaultImages,
+    /// Encrypts repo secrets at rest; secrets can't be set or used without it
+    pub secret_key: Option<SecretKey>,
+    /// Refuse jobs to agents built for a different `PROTOCOL_VERSION` instead of only warning
+    pub block_incompatible_agents: bool,
+    /// S3-compatible bucket finished jobs' logs are offloaded to
+    pub log_store: Option<LogStoreConfig>,
+}
+
+/// Operator policy for which jobs agents claim first. Each job gets the priority of
+/// its trigger type when it is enqueued; higher priorities are claimed first and
+/// equal ones in arrival order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggerPriorities(HashMap<TriggerType, i32>);
+
+impl Default for TriggerPriorities {
+    /// Someone is waiting on a manual build; scheduled builds can wait
+    fn default() -> Self {
+        Self(HashMap::from([
+            (TriggerType::Manual, 30),
+            (TriggerType::Push, 20),
+            (TriggerType::PullRequest, 20),
+            (TriggerType::Scheduled, 10),
+            (TriggerType::Tag, 20),
+            (TriggerType::Release, 20),
+        ]))
+    }
+}
+
+impl TriggerPriorities {
+    /// Parse `trigger=priority` pairs such as `pull_request=40,push=20`. Trigger types
+    /// left out keep their default.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut priorities = Self::default();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (trigger, priority) = entry
+                .split_once('=')
+                .with_context(|| format!("Expected trigger=priority, got '{}'", entry))?;
+            let trigger: TriggerType = trigger.trim().parse().map_err(|_| {
+                anyhow::anyhow!(
+                    "Unknown trigger type '{}': expected push, pull_request, manual, scheduled, tag or release",
+                    trigger.trim()
+                )
+            })?;
+            let priority = priority
+                .trim()
+                .parse()
+                .with_context(|| format!("Priority for {} is not an integer: '{}'", trigger, priority.trim()))?;
+            priorities.0.insert(trigger, priority);
+        }
+        Ok(priorities)
+    }
+
+    pub fn get(&self, trigger: TriggerType) -> i32 {
+        self.0.get(&trigger).copied().unwrap_or(0)
+    }
+
+    /// `{"push": 20, ...}`, for computing priorities inside SQL
+    pub fn to_json(&self) -> serde_json::Value {
+        self.0.iter().map(|(trigger, priority)| (trigger.to_string(), serde_json::Value::from(*priority))).collect()
+    }
+}
+
+/// Operator choice of image for repos without a `foundry.toml`. Repos keep their own
+/// `default_image` when none is configured.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DefaultImages {
+    /// Used on any architecture without its own image
+    any: Option<String>,
+    by_arch: BTreeMap<String, String>,
+}
+
+/// Image picked for a claiming agent. `platform` is set when the image was chosen for
+/// a specific architecture, which may not be the agent's own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefaultImage {
+    pub image: String,
+    pub platform: Option<String>,
+}
+
+impl DefaultImages {
+    /// Parse `arch=image` pairs such as `amd64=ubuntu:24.04,arm64=arm64v8/ubuntu:24.04`.
+    /// An entry without an arch applies to every other architecture.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut images = Self::default();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match entry.split_once('=') {
+                Some((arch, image)) => {
+                    let (arch, image) = (arch.trim(), image.trim());
+                    if arch.is_empty() || image.is_empty() {
+                        anyhow::bail!("Expected arch=image, got '{}'", entry);
+                    }
+                    images.by_arch.insert(arch.to_string(), image.to_string());
+                }
+                None if images.any.is_some() => {
+                    anyhow::bail!("More than one image without an arch: '{}'", entry)
+                }
+                None => images.any = Some(entry.to_string()),
+            }
+        }
+        Ok(images)
+    }
+
+    /// Image for an agent on `arch`, or `None` to use the repo's. With no image for
+    /// the agent's architecture (or an agent too old to report one), the amd64 image
+    /// is preferred so the agent can warn that it may not run it natively.
+    pub fn resolve(&self, arch: Option<&str>) -> Option<DefaultImage> {
+        let for_arch = |arch: &str, image: &String| DefaultImage {
+            image: image.clone(),
+            platform: Some(format!("linux/{}", arch)),
+        };
+
+        if let Some((arch, image)) = arch.and_then(|a| self.by_arch.get_key_value(a)) {
+            return Some(for_arch(arch, image));
+        }
+        if let Some(image) = &self.any {
+            return Some(DefaultImage { image: image.clone(), platform: None });
+        }
+        self.by_arch
+            .get_key_value("amd64")
+            .or_else(|| self.by_arch.iter().next())
+            .map(|(arch, image)| for_arch(arch, image))
+    }
 }
 
 impl fmt::Debug for Config {
@@ -20,6 +179,24 @@ impl fmt::Debug for Config {
             .field("github_webhook_secret", &"[REDACTED]")
             .field("tunnel", &self.tunnel)
             .field("auth", &self.auth)
+            .field("public_url", &self.public_url)
+            .field("public_job_ids_only", &self.public_job_ids_only)
+            .field("build_branches", &self.build_branches)
+            .field("allowed_repos", &self.allowed_repos)
+            .field("agent_tokens", &format!("[{} REDACTED]", self.agent_tokens.len()))
+            .field("artifact_dir", &self.artifact_dir)
+            .field("max_artifact_size", &self.max_artifact_size)
+            .field("max_log_size", &self.max_log_size)
+            .field("webhook_retention_days", &self.webhook_retention_days)
+            .field("log_retention_days", &self.log_retention_days)
+            .field("heartbeat_timeout_secs", &self.heartbeat_timeout_secs)
+            .field("pin_timeout_secs", &self.pin_timeout_secs)
+            .field("shutdown_timeout_secs", &self.shutdown_timeout_secs)
+            .field("trigger_priorities", &self.trigger_priorities)
+            .field("default_images", &self.default_images)
+            .field("secret_key", &self.secret_key)
+            .field("block_incompatible_agents", &self.block_incompatible_agents)
+            .field("log_store", &self.log_store)
             .finish()
     }
 }
@@ -45,6 +222,36 @@ impl fmt::Debug for TunnelConfig {
     }
 }
 
+#[derive(Clone)]
+pub struct LogStoreConfig {
+    /// Base URL of the S3 API, e.g. `https://s3.eu-west-1.amazonaws.com`; objects are
+    /// addressed path-style as `<endpoint>/<bucket>/<key>`
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prepended to every object key
+    pub prefix: String,
+    /// Days after a job finishes before its offloaded log rows are deleted from
+    /// Postgres; `None` keeps them
+    pub db_retention_days: Option<u32>,
+}
+
+impl fmt::Debug for LogStoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogStoreConfig")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"[REDACTED]")
+            .field("prefix", &self.prefix)
+            .field("db_retention_days", &self.db_retention_days)
+            .finish()
+    }
+}
+
 #[derive(Clone)]
 pub struct AuthConfig {
     pub issuer_url: String,
@@ -53,6 +260,8 @@ pub struct AuthConfig {
     pub cookie_secret: String,
     pub redirect_url: String,
     pub allowed_emails: Vec<String>,
+    /// Who may change anything from the dashboard; empty leaves every signed-in user a viewer
+    pub admin_emails: Vec<String>,
 }
 
 impl fmt::Debug for AuthConfig {
@@ -64,6 +273,7 @@ impl fmt::Debug for AuthConfig {
             .field("cookie_secret", &"[REDACTED]")
             .field("redirect_url", &self.redirect_url)
             .field("allowed_emails", &self.allowed_emails)
+            .field("admin_emails", &self.admin_emails)
             .finish()
     }
 }
@@ -75,7 +285,7 @@ impl Config {
 
         let bind_port = bind_addr
             .split(':')
-            .last()
+            .next_back()
             .and_then(|p| p.parse().ok())
             .unwrap_or(8080);
 
@@ -99,6 +309,32 @@ impl Config {
             None
         };
 
+        let log_store = match std::env::var("FOUNDRY_LOG_STORE_BUCKET") {
+            Ok(bucket) => Some(LogStoreConfig {
+                endpoint: std::env::var("FOUNDRY_LOG_STORE_ENDPOINT")
+                    .context("FOUNDRY_LOG_STORE_ENDPOINT required when FOUNDRY_LOG_STORE_BUCKET is set")?
+                    .trim_end_matches('/')
+                    .to_string(),
+                bucket,
+                region: std::env::var("FOUNDRY_LOG_STORE_REGION")
+                    .unwrap_or_else(|_| "us-east-1".to_string()),
+                access_key_id: std::env::var("FOUNDRY_LOG_STORE_ACCESS_KEY_ID")
+                    .or_else(|_| std::env::var("AWS_ACCESS_KEY_ID"))
+                    .context("FOUNDRY_LOG_STORE_ACCESS_KEY_ID required when FOUNDRY_LOG_STORE_BUCKET is set")?,
+                secret_access_key: std::env::var("FOUNDRY_LOG_STORE_SECRET_ACCESS_KEY")
+                    .or_else(|_| std::env::var("AWS_SECRET_ACCESS_KEY"))
+                    .context("FOUNDRY_LOG_STORE_SECRET_ACCESS_KEY required when FOUNDRY_LOG_STORE_BUCKET is set")?,
+                prefix: std::env::var("FOUNDRY_LOG_STORE_PREFIX")
+                    .unwrap_or_else(|_| "foundry/logs/".to_string()),
+                db_retention_days: std::env::var("FOUNDRY_LOG_DB_RETENTION_DAYS")
+                    .ok()
+                    .map(|v| v.parse())
+                    .transpose()
+                    .context("FOUNDRY_LOG_DB_RETENTION_DAYS must be a number of days")?,
+            }),
+            Err(_) => None,
+        };
+
         let auth = if std::env::var("FOUNDRY_AUTH_ENABLED")
             .map(|v| v == "1" || v.to_lowercase() == "true")
             .unwrap_or(false)
@@ -120,11 +356,72 @@ impl Config {
                     .map(|s| s.trim().to_string())
                     .filter(|s| !s.is_empty())
                     .collect(),
+                admin_emails: std::env::var("FOUNDRY_ADMIN_EMAILS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect(),
             })
         } else {
             None
         };
 
+        let public_url = std::env::var("FOUNDRY_PUBLIC_URL")
+            .ok()
+            .or_else(|| tunnel.as_ref().map(|t| format!("https://{}", t.domain)))
+            .map(|url| url.trim_end_matches('/').to_string());
+
+        let public_job_ids_only = std::env::var("FOUNDRY_PUBLIC_JOB_IDS_ONLY")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
+        let build_branches = std::env::var("FOUNDRY_BUILD_BRANCHES")
+            .ok()
+            .map(|v| {
+                v.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let allowed_repos = std::env::var("FOUNDRY_ALLOWED_REPOS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let agent_tokens = std::env::var("FOUNDRY_AGENT_TOKENS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        let max_artifact_size = std::env::var("FOUNDRY_MAX_ARTIFACT_SIZE").unwrap_or_else(|_| "1g".to_string());
+        let max_artifact_size = parse_memory_size(&max_artifact_size).context("FOUNDRY_MAX_ARTIFACT_SIZE")?;
+        let max_log_size = std::env::var("FOUNDRY_MAX_LOG_SIZE").unwrap_or_else(|_| "50m".to_string());
+        let max_log_size = parse_memory_size(&max_log_size).context("FOUNDRY_MAX_LOG_SIZE")?;
+
+        let trigger_priorities = match std::env::var("FOUNDRY_TRIGGER_PRIORITY") {
+            Ok(v) => TriggerPriorities::parse(&v).context("FOUNDRY_TRIGGER_PRIORITY")?,
+            Err(_) => TriggerPriorities::default(),
+        };
+
+        let default_images = match std::env::var("FOUNDRY_DEFAULT_IMAGE") {
+            Ok(v) => DefaultImages::parse(&v).context("FOUNDRY_DEFAULT_IMAGE")?,
+            Err(_) => DefaultImages::default(),
+        };
+
+        let secret_key = std::env::var("FOUNDRY_SECRET_KEY")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| SecretKey::from_base64(&v))
+            .transpose()
+            .context("FOUNDRY_SECRET_KEY")?;
+
         Ok(Self {
             bind_addr,
             bind_port,
@@ -134,6 +431,166 @@ impl Config {
                 .context("GITHUB_WEBHOOK_SECRET must be set")?,
             tunnel,
             auth,
+            public_url,
+            public_job_ids_only,
+            build_branches,
+            allowed_repos,
+            agent_tokens,
+            artifact_dir: std::env::var("FOUNDRY_ARTIFACT_DIR")
+                .unwrap_or_else(|_| "/var/lib/foundry/artifacts".to_string()),
+            max_artifact_size,
+            max_log_size,
+            webhook_retention_days: std::env::var("FOUNDRY_WEBHOOK_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            log_retention_days: std::env::var("FOUNDRY_LOG_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&days| days > 0),
+            heartbeat_timeout_secs: std::env::var("FOUNDRY_HEARTBEAT_TIMEOUT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs| secs > 0)
+                .unwrap_or(300),
+            pin_timeout_secs: std::env::var("FOUNDRY_PIN_TIMEOUT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&secs| secs > 0)
+                .unwrap_or(900),
+            shutdown_timeout_secs: std::env::var("FOUNDRY_SHUTDOWN_TIMEOUT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            trigger_priorities,
+            default_images,
+            secret_key,
+            block_incompatible_agents: std::env::var("FOUNDRY_BLOCK_INCOMPATIBLE_AGENTS")
+                .map(|v| v == "1" || v.to_lowercase() == "true")
+                .unwrap_or(false),
+            log_store,
         })
     }
+
+    #[cfg(test)]
+    pub fn for_tests() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:8080".to_string(),
+            bind_port: 8080,
+            database_url: "postgres://foundry@127.0.0.1:1/foundry".to_string(),
+            github_webhook_secret: "secret".to_string(),
+            tunnel: None,
+            auth: None,
+            public_url: Some("https://ci.example.com".to_string()),
+            public_job_ids_only: false,
+            build_branches: vec![],
+            allowed_repos: Vec::new(),
+            agent_tokens: Vec::new(),
+            artifact_dir: std::env::temp_dir().join("foundry-artifacts").display().to_string(),
+            max_artifact_size: 1 << 20,
+            max_log_size: 1 << 20,
+            webhook_retention_days: 30,
+            log_retention_days: None,
+            heartbeat_timeout_secs: 300,
+            pin_timeout_secs: 900,
+            shutdown_timeout_secs: 30,
+            trigger_priorities: TriggerPriorities::default(),
+            default_images: DefaultImages::default(),
+            secret_key: None,
+            block_incompatible_agents: false,
+            log_store: None,
+        }
+    }
+
+    /// Whether a repo is in `FOUNDRY_ALLOWED_REPOS`. GitHub names are case-insensitive.
+    pub fn repo_allowed(&self, owner: &str, name: &str) -> bool {
+        self.allowed_repos.is_empty()
+            || self.allowed_repos.iter().any(|entry| match entry.split_once('/') {
+                Some((o, "*")) => o.eq_ignore_ascii_case(owner),
+                Some((o, n)) => o.eq_ignore_ascii_case(owner) && n.eq_ignore_ascii_case(name),
+                None => false,
+            })
+    }
+
+    /// Shareable dashboard permalink for a job
+    pub fn job_url(&self, public_id: &str) -> Option<String> {
+        self.public_url
+            .as_ref()
+            .map(|url| format!("{}/j/{}", url, public_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_url_uses_public_id() {
+        let config = Config::for_tests();
+        assert_eq!(
+            config.job_url("k2m7qxa4zt").as_deref(),
+            Some("https://ci.example.com/j/k2m7qxa4zt")
+        );
+
+        let config = Config { public_url: None, ..Config::for_tests() };
+        assert_eq!(config.job_url("k2m7qxa4zt"), None);
+    }
+
+    #[test]
+    fn test_repo_allowed() {
+        let mut config = Config::for_tests();
+        assert!(config.repo_allowed("acme", "app"));
+
+        config.allowed_repos = vec!["acme/app".to_string(), "tools/*".to_string()];
+        assert!(config.repo_allowed("acme", "app"));
+        assert!(config.repo_allowed("Acme", "App"));
+        assert!(config.repo_allowed("tools", "anything"));
+        assert!(!config.repo_allowed("acme", "other"));
+        assert!(!config.repo_allowed("someone", "app"));
+    }
+
+    #[test]
+    fn test_trigger_priorities_override_defaults() {
+        let priorities = TriggerPriorities::parse("pull_request=40, scheduled = -5").unwrap();
+        assert_eq!(priorities.get(TriggerType::PullRequest), 40);
+        assert_eq!(priorities.get(TriggerType::Scheduled), -5);
+        assert_eq!(priorities.get(TriggerType::Manual), 30);
+        assert_eq!(priorities.get(TriggerType::Push), 20);
+        assert_eq!(priorities.to_json()["pull_request"], 40);
+        assert_eq!(TriggerPriorities::parse("").unwrap(), TriggerPriorities::default());
+    }
+
+    #[test]
+    fn test_trigger_priorities_reject_typos() {
+        assert!(TriggerPriorities::parse("deploy=50").is_err());
+        assert!(TriggerPriorities::parse("push").is_err());
+        assert!(TriggerPriorities::parse("push=high").is_err());
+    }
+
+    #[test]
+    fn test_default_image_for_agent_arch() {
+        let images = DefaultImages::parse("amd64=ubuntu:24.04, arm64=arm64v8/ubuntu:24.04").unwrap();
+        let arm = images.resolve(Some("arm64")).unwrap();
+        assert_eq!(arm.image, "arm64v8/ubuntu:24.04");
+        assert_eq!(arm.platform.as_deref(), Some("linux/arm64"));
+
+        // No riscv64 image, and agents that don't report an arch, fall back to amd64
+        for arch in [Some("riscv64"), None] {
+            let fallback = images.resolve(arch).unwrap();
+            assert_eq!(fallback.image, "ubuntu:24.04");
+            assert_eq!(fallback.platform.as_deref(), Some("linux/amd64"));
+        }
+
+        // An image without an arch covers the rest, for whatever platform the agent is
+        let images = DefaultImages::parse("arm64=arm64v8/ubuntu:24.04,debian:12").unwrap();
+        assert_eq!(
+            images.resolve(Some("amd64")),
+            Some(DefaultImage { image: "debian:12".to_string(), platform: None })
+        );
+        assert_eq!(images.resolve(Some("arm64")).unwrap().image, "arm64v8/ubuntu:24.04");
+
+        assert_eq!(DefaultImages::parse("").unwrap().resolve(Some("amd64")), None);
+        assert!(DefaultImages::parse("amd64=").is_err());
+        assert!(DefaultImages::parse("ubuntu:24.04,debian:12").is_err());
+    }
 }
@@ -2,7 +2,9 @@ use anyhow::Result;
 use sqlx::{PgPool, Row};
 use uuid::Uuid;
 
-use foundry_core::{ClaimedJob, github::{PushEvent, PullRequestEvent, TriggerType}};
+use crate::config::TriggerPriorities;
+
+use foundry_core::{config::{MatrixEntry, NotifyConfig, TriggersConfig}, ClaimedJob, DeploymentRequest, JobLimits, JobStatus, LogLevel, PreviewRequest, github::{PushEvent, PullRequestEvent, ReleaseEvent, Repository, TriggerType}};
 
 /// Comprehensive push event data for storage
 #[derive(Debug)]
@@ -59,9 +61,19 @@ impl PushEventData {
     pub fn from_push_event(event: &PushEvent) -> Self {
         let head = event.head_commit.as_ref();
         let distinct_count = event.commits.iter().filter(|c| c.distinct).count() as i32;
+        let is_tag = event.tag().is_some();
+        // `after` is the tag object for annotated tags; build the commit it points at
+        let git_sha = match head {
+            Some(c) if is_tag => c.id.clone(),
+            _ => event.after.clone(),
+        };
+        // A tag's head commit files say nothing about the tag, so path filters don't apply
+        let files = |f: fn(&foundry_core::github::HeadCommit) -> &Vec<String>| {
+            head.filter(|_| !is_tag).map(|c| f(c).clone()).unwrap_or_default()
+        };
         
         Self {
-            git_sha: event.after.clone(),
+            git_sha,
             git_ref: event.git_ref.clone(),
             before_sha: Some(event.before.clone()),
             compare_url: Some(event.compare.clone()),
@@ -77,9 +89,9 @@ impl PushEventData {
             committer_email: head.map(|c| c.committer.email.clone()),
             committer_username: head.and_then(|c| c.committer.username.clone()),
             
-            files_added: head.map(|c| c.added.clone()).unwrap_or_default(),
-            files_modified: head.map(|c| c.modified.clone()).unwrap_or_default(),
-            files_removed: head.map(|c| c.removed.clone()).unwrap_or_default(),
+            files_added: files(|c| &c.added),
+            files_modified: files(|c| &c.modified),
+            files_removed: files(|c| &c.removed),
             
             forced: event.forced,
             deleted: event.deleted,
@@ -97,7 +109,50 @@ impl PushEventData {
             
             installation_id: event.installation.as_ref().map(|i| i.id),
             
-            trigger_type: TriggerType::Push,
+            trigger_type: if is_tag { TriggerType::Tag } else { TriggerType::Push },
+        }
+    }
+
+    pub fn from_release_event(event: &ReleaseEvent) -> Self {
+        let release = &event.release;
+        Self {
+            git_sha: event.build_sha(),
+            git_ref: format!("refs/tags/{}", release.tag_name),
+            before_sha: None,
+            compare_url: None,
+
+            commit_message: Some(release.name.clone().unwrap_or_else(|| release.tag_name.clone())),
+            commit_author: event.sender.as_ref().map(|s| s.login.clone()),
+            commit_author_email: None,
+            commit_url: Some(release.html_url.clone()),
+            commit_timestamp: None,
+            commit_tree_id: None,
+
+            committer_name: None,
+            committer_email: None,
+            committer_username: None,
+
+            files_added: Vec::new(),
+            files_modified: Vec::new(),
+            files_removed: Vec::new(),
+
+            forced: false,
+            deleted: false,
+            created: false,
+            commits_count: 0,
+            distinct_commits_count: 0,
+
+            pusher_name: None,
+            pusher_email: None,
+
+            sender_id: event.sender.as_ref().map(|s| s.id),
+            sender_login: event.sender.as_ref().map(|s| s.login.clone()),
+            sender_avatar_url: event.sender.as_ref().and_then(|s| s.avatar_url.clone()),
+            sender_type: event.sender.as_ref().and_then(|s| s.sender_type.clone()),
+
+            installation_id: event.installation.as_ref().map(|i| i.id),
+
+            trigger_type: TriggerType::Release,
         }
     }
 }
@@ -109,12 +164,13 @@ pub struct PullRequestEventData {
     pub git_ref: String,
     pub pr_number: i64,
     pub pr_title: String,
-    pub pr_body: Option<String>,
     pub pr_url: String,
     pub pr_author: String,
     pub pr_author_avatar: Option<String>,
     pub base_ref: String,
     pub base_sha: String,
+    /// Branch the PR comes from, `owner:branch` for forks
+    pub head_ref: String,
     pub sender_id: Option<i64>,
     pub sender_login: Option<String>,
     pub sender_avatar_url: Option<String>,
@@ -129,12 +185,12 @@ impl PullRequestEventData {
             git_ref: format!("refs/pull/{}/head", pr.number),
             pr_number: pr.number,
             pr_title: pr.title.clone(),
-            pr_body: pr.body.clone(),
             pr_url: pr.html_url.clone(),
             pr_author: pr.user.login.clone(),
             pr_author_avatar: pr.user.avatar_url.clone(),
             base_ref: pr.base.git_ref.clone(),
             base_sha: pr.base.sha.clone(),
+            head_ref: head_ref(pr),
             sender_id: event.sender.as_ref().map(|s| s.id),
             sender_login: event.sender.as_ref().map(|s| s.login.clone()),
             sender_avatar_url: event.sender.as_ref().and_then(|s| s.avatar_url.clone()),
@@ -143,6 +199,19 @@ impl PullRequestEventData {
     }
 }
 
+/// The PR's source branch, with the owner when it comes from a fork
+fn head_ref(pr: &foundry_core::github::PullRequest) -> String {
+    let same_repo = match (&pr.head.repo, &pr.base.repo) {
+        (Some(head), Some(base)) => head.id == base.id,
+        _ => true,
+    };
+    if same_repo {
+        pr.head.git_ref.clone()
+    } else {
+        pr.head.label.clone()
+    }
+}
+
 /// Repository data for upsert
 #[derive(Debug)]
 pub struct RepoData {
@@ -161,7 +230,10 @@ pub struct RepoData {
 
 impl RepoData {
     pub fn from_push_event(event: &PushEvent) -> Self {
-        let repo = &event.repository;
+        Self::from_repository(&event.repository)
+    }
+
+    pub fn from_repository(repo: &Repository) -> Self {
         Self {
             owner: repo.owner.login.clone(),
             name: repo.name.clone(),
@@ -182,6 +254,7 @@ pub async fn enqueue_job(
     pool: &PgPool,
     repo_id: i64,
     data: &PushEventData,
+    priority: i32,
 ) -> Result<i64> {
     let trigger_type_str = data.trigger_type.to_string();
     let row: (i64,) = sqlx::query_as(
@@ -195,7 +268,7 @@ pub async fn enqueue_job(
             files_added, files_modified, files_removed,
             pusher_name, pusher_email,
             sender_id, sender_login, sender_avatar_url, sender_type,
-            installation_id
+            installation_id, priority
         )
         VALUES (
             $1, $2, $3, 'queued', $4::trigger_type,
@@ -206,7 +279,7 @@ pub async fn enqueue_job(
             $21, $22, $23,
             $24, $25,
             $26, $27, $28, $29,
-            $30
+            $30, $31
         )
         RETURNING id
         "#,
@@ -241,6 +314,7 @@ pub async fn enqueue_job(
     .bind(&data.sender_avatar_url)
     .bind(&data.sender_type)
     .bind(data.installation_id)
+    .bind(priority)
     .fetch_one(pool)
     .await?;
 
@@ -252,22 +326,23 @@ pub async fn enqueue_pr_job(
     pool: &PgPool,
     repo_id: i64,
     data: &PullRequestEventData,
+    priority: i32,
 ) -> Result<i64> {
     let row: (i64,) = sqlx::query_as(
         r#"
         INSERT INTO job (
             repo_id, git_sha, git_ref, status, trigger_type,
             pr_number, pr_title, pr_url, pr_author, pr_author_avatar,
-            base_ref, base_sha,
+            base_ref, base_sha, head_ref,
             sender_id, sender_login, sender_avatar_url,
-            installation_id, commit_message
+            installation_id, commit_message, priority
         )
         VALUES (
             $1, $2, $3, 'queued', 'pull_request',
             $4, $5, $6, $7, $8,
-            $9, $10,
+            $9, $10, $17,
             $11, $12, $13,
-            $14, $15
+            $14, $15, $16
         )
         RETURNING id
         "#,
@@ -287,77 +362,155 @@ pub async fn enqueue_pr_job(
     .bind(&data.sender_avatar_url)
     .bind(data.installation_id)
     .bind(&data.pr_title) // Use PR title as commit message for display
+    .bind(priority)
+    .bind(&data.head_ref)
     .fetch_one(pool)
     .await?;
 
     Ok(row.0)
 }
 
-/// Re-run a job by creating a new job with the same parameters
-pub async fn rerun_job(pool: &PgPool, job_id: i64) -> Result<Option<i64>> {
-    // First, get the original job
-    let original = sqlx::query(
+/// Retry a finished job by queueing a copy of it with `retry_of` pointing at the original.
+/// Returns the new job's (id, public_id), or `None` if the job doesn't exist or is still
+/// queued/running. The retry is prioritized by the original's trigger type under the
+/// current `priorities`. With `target_agent` only that agent may claim it.
+pub async fn retry_job(
+    pool: &PgPool,
+    job_id: i64,
+    priorities: &TriggerPriorities,
+    target_agent: Option<&str>,
+) -> Result<Option<(i64, String)>> {
+    let row: Option<(i64, String)> = sqlx::query_as(
         r#"
-        SELECT 
-            repo_id, git_sha, git_ref, trigger_type::text,
+        INSERT INTO job (
+            repo_id, git_sha, git_ref, status, trigger_type,
+            before_sha, compare_url,
+            commit_message, commit_author, commit_author_email, commit_url, commit_timestamp,
             pr_number, pr_title, pr_url, pr_author, pr_author_avatar,
-            base_ref, base_sha, commit_message, commit_author
-        FROM job
-        WHERE id = $1
+            base_ref, base_sha, head_ref,
+            files_added, files_modified, files_removed,
+            sender_id, sender_login, sender_avatar_url,
+            installation_id, parent_job_id, service_path, matrix_key, matrix, retry_of, check_run_id, priority,
+            target_agent, runs_on
+        )
+        SELECT
+            j.repo_id, j.git_sha, j.git_ref, 'queued', j.trigger_type,
+            j.before_sha, j.compare_url,
+            j.commit_message, j.commit_author, j.commit_author_email, j.commit_url, j.commit_timestamp,
+            j.pr_number, j.pr_title, j.pr_url, j.pr_author, j.pr_author_avatar,
+            j.base_ref, j.base_sha, j.head_ref,
+            j.files_added, j.files_modified, j.files_removed,
+            j.sender_id, j.sender_login, j.sender_avatar_url,
+            j.installation_id, j.parent_job_id, j.service_path, j.matrix_key, j.matrix, j.id, j.check_run_id,
+            COALESCE(($2::jsonb ->> j.trigger_type::text)::int, 0),
+            $3, j.runs_on
+        FROM job j
+        WHERE j.id = $1 AND j.status NOT IN ('queued', 'running')
+        RETURNING id, public_id
         "#,
     )
     .bind(job_id)
+    .bind(priorities.to_json())
+    .bind(target_agent)
     .fetch_optional(pool)
     .await?;
 
-    let Some(original) = original else {
-        return Ok(None);
-    };
+    Ok(row)
+}
 
-    let trigger_type: String = original.get("trigger_type");
-    
-    let row: (i64,) = sqlx::query_as(
+/// Queue a manual build of the head of `git_ref`, e.g. `refs/heads/main`. The agent
+/// resolves `name` to a commit when it clones, like scheduled builds. Returns the new
+/// job's (id, public_id), or `None` if the repo doesn't exist.
+pub async fn queue_manual_build(
+    pool: &PgPool,
+    repo_id: i64,
+    git_ref: &str,
+    name: &str,
+    triggered_by: &str,
+    priority: i32,
+    target_agent: Option<&str>,
+) -> Result<Option<(i64, String)>> {
+    let row: Option<(i64, String)> = sqlx::query_as(
         r#"
         INSERT INTO job (
             repo_id, git_sha, git_ref, status, trigger_type,
-            pr_number, pr_title, pr_url, pr_author, pr_author_avatar,
-            base_ref, base_sha, commit_message, commit_author,
-            parent_job_id
+            commit_message, triggered_by, priority, target_agent
         )
-        VALUES (
-            $1, $2, $3, 'queued', $4::trigger_type,
-            $5, $6, $7, $8, $9,
-            $10, $11, $12, $13,
-            $14
+        SELECT id, 'RESOLVE:' || $3, $2, 'queued', 'manual',
+            'Manual build: ' || $3, $4, $5, $6
+        FROM repo
+        WHERE id = $1
+        RETURNING id, public_id
+        "#,
+    )
+    .bind(repo_id)
+    .bind(git_ref)
+    .bind(name)
+    .bind(triggered_by)
+    .bind(priority)
+    .bind(target_agent)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row)
+}
+
+/// Queue a manual build of the newest commit built on the repo's default branch,
+/// copying its commit metadata. The changed files are left empty so path filters
+/// and monorepo fan-out treat it as a full build. Returns the new job's
+/// (id, public_id), or `None` if the repo has no previous default-branch build.
+/// With `target_agent` only that agent may claim it.
+pub async fn rebuild_latest(
+    pool: &PgPool,
+    repo_id: i64,
+    triggered_by: &str,
+    priority: i32,
+    target_agent: Option<&str>,
+) -> Result<Option<(i64, String)>> {
+    let row: Option<(i64, String)> = sqlx::query_as(
+        r#"
+        INSERT INTO job (
+            repo_id, git_sha, git_ref, status, trigger_type,
+            before_sha, compare_url,
+            commit_message, commit_author, commit_author_email, commit_url, commit_timestamp,
+            sender_id, sender_login, sender_avatar_url,
+            installation_id, triggered_by, priority, target_agent
         )
-        RETURNING id
+        SELECT
+            j.repo_id, j.git_sha, j.git_ref, 'queued', 'manual',
+            j.before_sha, j.compare_url,
+            j.commit_message, j.commit_author, j.commit_author_email, j.commit_url, j.commit_timestamp,
+            j.sender_id, j.sender_login, j.sender_avatar_url,
+            j.installation_id, $2, $3, $4
+        FROM job j
+        JOIN repo r ON r.id = j.repo_id
+        WHERE j.repo_id = $1
+          AND j.git_ref = 'refs/heads/' || COALESCE(r.default_branch, 'main')
+          AND j.parent_job_id IS NULL
+          AND j.git_sha NOT LIKE 'RESOLVE:%'
+        ORDER BY j.id DESC
+        LIMIT 1
+        RETURNING id, public_id
         "#,
     )
-    .bind(original.get::<i64, _>("repo_id"))
-    .bind(original.get::<String, _>("git_sha"))
-    .bind(original.get::<String, _>("git_ref"))
-    .bind(&trigger_type)
-    .bind(original.get::<Option<i64>, _>("pr_number"))
-    .bind(original.get::<Option<String>, _>("pr_title"))
-    .bind(original.get::<Option<String>, _>("pr_url"))
-    .bind(original.get::<Option<String>, _>("pr_author"))
-    .bind(original.get::<Option<String>, _>("pr_author_avatar"))
-    .bind(original.get::<Option<String>, _>("base_ref"))
-    .bind(original.get::<Option<String>, _>("base_sha"))
-    .bind(original.get::<Option<String>, _>("commit_message"))
-    .bind(original.get::<Option<String>, _>("commit_author"))
-    .bind(job_id)
-    .fetch_one(pool)
+    .bind(repo_id)
+    .bind(triggered_by)
+    .bind(priority)
+    .bind(target_agent)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(Some(row.0))
+    Ok(row)
 }
 
-pub async fn upsert_repo(pool: &PgPool, data: &RepoData) -> Result<i64> {
-    let row: (i64,) = sqlx::query_as(
+/// Record a repo seen in a webhook. A new repo starts with builds enabled or paused
+/// per `enabled_if_new`; a known one keeps its setting. Returns its id and whether
+/// builds are enabled for it.
+pub async fn upsert_repo(pool: &PgPool, data: &RepoData, enabled_if_new: bool) -> Result<(i64, bool)> {
+    let row: (i64, bool) = sqlx::query_as(
         r#"
-        INSERT INTO repo (owner, name, clone_url, github_id, full_name, html_url, ssh_url, private, default_branch, language, description)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        INSERT INTO repo (owner, name, clone_url, github_id, full_name, html_url, ssh_url, private, default_branch, language, description, enabled)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
         ON CONFLICT (owner, name) DO UPDATE SET 
             clone_url = EXCLUDED.clone_url,
             github_id = COALESCE(EXCLUDED.github_id, repo.github_id),
@@ -369,7 +522,7 @@ pub async fn upsert_repo(pool: &PgPool, data: &RepoData) -> Result<i64> {
             language = COALESCE(EXCLUDED.language, repo.language),
             description = COALESCE(EXCLUDED.description, repo.description),
             updated_at = NOW()
-        RETURNING id
+        RETURNING id, enabled
         "#,
     )
     .bind(&data.owner)
@@ -383,10 +536,11 @@ pub async fn upsert_repo(pool: &PgPool, data: &RepoData) -> Result<i64> {
     .bind(&data.default_branch)
     .bind(&data.language)
     .bind(&data.description)
+    .bind(enabled_if_new)
     .fetch_one(pool)
     .await?;
 
-    Ok(row.0)
+    Ok(row)
 }
 
 /// Store individual commits from a push event
@@ -426,6 +580,127 @@ pub async fn store_commits(pool: &PgPool, job_id: i64, event: &PushEvent) -> Res
     Ok(())
 }
 
+/// Delete up to `limit` webhook events older than `retention_days` that no job links to
+pub async fn prune_webhook_events(pool: &PgPool, retention_days: u32, limit: i64) -> Result<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM webhook_event
+        WHERE id IN (
+            SELECT id FROM webhook_event
+            WHERE job_id IS NULL AND created_at < now() - make_interval(days => $1)
+            ORDER BY id
+            LIMIT $2
+        )
+        "#,
+    )
+    .bind(retention_days as i32)
+    .bind(limit)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WebhookEventSummary {
+    pub id: i64,
+    pub event_type: String,
+    pub delivery_id: Option<String>,
+    pub processed: bool,
+    /// Left out when only public ids are exposed, as well as when there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<i64>,
+    pub created_at: String,
+    pub replayed_at: Option<String>,
+}
+
+/// Stored webhook events without their payloads, newest first
+pub async fn list_webhook_events(
+    pool: &PgPool,
+    event_type: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<WebhookEventSummary>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, event_type, delivery_id, COALESCE(processed, FALSE) as processed, job_id,
+            to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at,
+            to_char(replayed_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as replayed_at
+        FROM webhook_event
+        WHERE ($1::text IS NULL OR event_type = $1)
+        ORDER BY id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(event_type)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .iter()
+        .map(|r| WebhookEventSummary {
+            id: r.get("id"),
+            event_type: r.get("event_type"),
+            delivery_id: r.get("delivery_id"),
+            processed: r.get("processed"),
+            job_id: r.get("job_id"),
+            created_at: r.get("created_at"),
+            replayed_at: r.get("replayed_at"),
+        })
+        .collect())
+}
+
+pub async fn count_webhook_events(pool: &PgPool, event_type: Option<&str>) -> Result<i64> {
+    let row: (i64,) = sqlx::query_as(r#"SELECT COUNT(*) FROM webhook_event WHERE ($1::text IS NULL OR event_type = $1)"#)
+        .bind(event_type)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.0)
+}
+
+/// Event type and payload of a stored webhook event
+pub async fn get_webhook_event(pool: &PgPool, id: i64) -> Result<Option<(String, serde_json::Value)>> {
+    let row = sqlx::query_as(r#"SELECT event_type, payload FROM webhook_event WHERE id = $1"#)
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row)
+}
+
+/// Link a replayed webhook event to the job it queued, and mark the job as a replay
+pub async fn mark_replayed(pool: &PgPool, event_id: i64, job_id: i64) -> Result<()> {
+    sqlx::query(
+        r#"
+        WITH replayed_job AS (
+            UPDATE job SET replayed = TRUE WHERE id = $2
+        )
+        UPDATE webhook_event
+        SET job_id = $2, processed = TRUE, replayed_at = now()
+        WHERE id = $1
+        "#,
+    )
+    .bind(event_id)
+    .bind(job_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Which of `ids` still have an artifact row
+pub async fn existing_artifact_ids(pool: &PgPool, ids: &[i64]) -> Result<Vec<i64>> {
+    let rows: Vec<(i64,)> = sqlx::query_as(r#"SELECT id FROM artifact WHERE id = ANY($1)"#)
+        .bind(ids)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
 /// Store raw webhook event for debugging/replay
 pub async fn store_webhook_event(
     pool: &PgPool,
@@ -454,7 +729,14 @@ pub async fn store_webhook_event(
     Ok(row.0)
 }
 
-pub async fn claim_job(pool: &PgPool, agent_id: &str) -> Result<Option<ClaimedJob>> {
+/// Claim the next queued job for an agent, skipping jobs whose `runs_on` asks for
+/// labels the agent lacks. With `job_id` only that job is claimed.
+pub async fn claim_job(
+    pool: &PgPool,
+    agent_id: &str,
+    labels: &[String],
+    job_id: Option<i64>,
+) -> Result<Option<ClaimedJob>> {
     let claim_token = Uuid::new_v4();
 
     let row = sqlx::query(
@@ -463,33 +745,59 @@ pub async fn claim_job(pool: &PgPool, agent_id: &str) -> Result<Option<ClaimedJo
             UPDATE job
             SET status = 'running', 
                 started_at = now(), 
+                last_heartbeat_at = now(),
                 claimed_by = $1, 
                 claim_token = $2
             WHERE id = (
-                SELECT id FROM job
+                SELECT id FROM job q
                 WHERE status = 'queued'
-                ORDER BY created_at ASC
+                  AND (target_agent IS NULL OR target_agent = $1)
+                  AND runs_on <@ $3::text[]
+                  AND ($4::bigint IS NULL OR id = $4)
+                  AND NOT EXISTS (SELECT 1 FROM setting WHERE key = 'queue_paused' AND value = 'true')
+                  -- One job per concurrency group at a time, in queue order. Checking for
+                  -- an earlier queued job too keeps two agents claiming at once from both
+                  -- getting one: the second skips the first's locked row, then sees it here
+                  AND (concurrency_group IS NULL OR NOT EXISTS (
+                      SELECT 1 FROM job g
+                      WHERE g.concurrency_group = q.concurrency_group
+                        AND g.id <> q.id
+                        AND (g.status = 'running'
+                             OR (g.status = 'queued'
+                                 AND (-g.priority, g.created_at, g.id) < (-q.priority, q.created_at, q.id)))
+                  ))
+                ORDER BY priority DESC, created_at ASC
                 FOR UPDATE SKIP LOCKED
                 LIMIT 1
             )
-            RETURNING id, repo_id, git_sha, git_ref, claim_token
+            RETURNING id, public_id, repo_id, git_sha, git_ref, claim_token, service_path, check_run_id, matrix,
+                COALESCE(files_added, '{}') || COALESCE(files_modified, '{}') || COALESCE(files_removed, '{}') as changed_files
         )
         SELECT 
             c.id,
+            c.public_id,
             c.repo_id,
             c.git_sha,
             c.git_ref,
             c.claim_token,
+            c.service_path,
+            c.check_run_id,
+            c.matrix,
+            c.changed_files,
             r.owner as repo_owner,
             r.name as repo_name,
             r.clone_url,
-            r.default_image as image
+            r.ssh_url,
+            r.default_image as image,
+            r.check_context
         FROM claimed c
         JOIN repo r ON r.id = c.repo_id
         "#,
     )
     .bind(agent_id)
     .bind(claim_token)
+    .bind(labels)
+    .bind(job_id)
     .fetch_optional(pool)
     .await?;
 
@@ -499,150 +807,634 @@ pub async fn claim_job(pool: &PgPool, agent_id: &str) -> Result<Option<ClaimedJo
         repo_owner: r.get("repo_owner"),
         repo_name: r.get("repo_name"),
         clone_url: r.get("clone_url"),
+        ssh_url: r.get("ssh_url"),
         git_sha: r.get("git_sha"),
         git_ref: r.get("git_ref"),
         image: r.get("image"),
         claim_token: r.get("claim_token"),
+        changed_files: r.get("changed_files"),
+        service_path: r.get("service_path"),
+        public_id: r.get("public_id"),
+        details_url: None,
+        check_run_id: r.get("check_run_id"),
+        secrets: Default::default(),
+        matrix: r
+            .get::<Option<serde_json::Value>, _>("matrix")
+            .and_then(|m| serde_json::from_value(m).ok())
+            .unwrap_or_default(),
+        platform: None,
+        check_context: r.get("check_context"),
     }))
 }
 
-pub async fn append_log(
+/// Enqueue one child job per monorepo service, copying the parent's commit metadata.
+/// Returns `None` if the parent job/token is not running.
+pub async fn fan_out_job(
     pool: &PgPool,
     job_id: i64,
     claim_token: Uuid,
-    line: &str,
-) -> Result<bool> {
-    let result = sqlx::query(
+    services: &[String],
+) -> Result<Option<Vec<i64>>> {
+    if !verify_running_job(pool, job_id, claim_token).await? {
+        return Ok(None);
+    }
+
+    let rows: Vec<(i64,)> = sqlx::query_as(
         r#"
-        INSERT INTO job_log (job_id, line)
-        SELECT $1, $3
-        WHERE EXISTS (
-            SELECT 1 FROM job 
-            WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        INSERT INTO job (
+            repo_id, git_sha, git_ref, status, trigger_type,
+            commit_message, commit_author, commit_url,
+            pr_number, pr_title, pr_url, pr_author, pr_author_avatar,
+            base_ref, base_sha, head_ref,
+            files_added, files_modified, files_removed,
+            sender_id, sender_login, sender_avatar_url,
+            installation_id, parent_job_id, service_path, priority
         )
+        SELECT
+            j.repo_id, j.git_sha, j.git_ref, 'queued', j.trigger_type,
+            j.commit_message, j.commit_author, j.commit_url,
+            j.pr_number, j.pr_title, j.pr_url, j.pr_author, j.pr_author_avatar,
+            j.base_ref, j.base_sha, j.head_ref,
+            j.files_added, j.files_modified, j.files_removed,
+            j.sender_id, j.sender_login, j.sender_avatar_url,
+            j.installation_id, j.id, s.service_path, j.priority
+        FROM job j, unnest($2::text[]) AS s(service_path)
+        WHERE j.id = $1
+        RETURNING id
         "#,
     )
     .bind(job_id)
-    .bind(claim_token)
-    .bind(line)
-    .execute(pool)
+    .bind(services)
+    .fetch_all(pool)
     .await?;
 
-    Ok(result.rows_affected() > 0)
+    Ok(Some(rows.into_iter().map(|(id,)| id).collect()))
 }
 
-pub async fn finish_job(
+/// Enqueue one child job per matrix combination, copying the parent's commit metadata
+/// and service. The parent finishes once all of them have. Returns `None` if the
+/// parent job/token is not running.
+pub async fn fan_out_matrix(
     pool: &PgPool,
     job_id: i64,
     claim_token: Uuid,
-    success: bool,
-) -> Result<bool> {
-    let status = if success { "success" } else { "failed" };
+    entries: &[MatrixEntry],
+) -> Result<Option<Vec<i64>>> {
+    if !verify_running_job(pool, job_id, claim_token).await? {
+        return Ok(None);
+    }
 
-    let result = sqlx::query(
+    let rows: Vec<(i64,)> = sqlx::query_as(
         r#"
-        UPDATE job
-        SET status = $3::job_status, finished_at = now()
-        WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        INSERT INTO job (
+            repo_id, git_sha, git_ref, status, trigger_type,
+            commit_message, commit_author, commit_url,
+            pr_number, pr_title, pr_url, pr_author, pr_author_avatar,
+            base_ref, base_sha, head_ref,
+            files_added, files_modified, files_removed,
+            sender_id, sender_login, sender_avatar_url,
+            installation_id, parent_job_id, service_path, matrix_key, matrix, priority
+        )
+        SELECT
+            j.repo_id, j.git_sha, j.git_ref, 'queued', j.trigger_type,
+            j.commit_message, j.commit_author, j.commit_url,
+            j.pr_number, j.pr_title, j.pr_url, j.pr_author, j.pr_author_avatar,
+            j.base_ref, j.base_sha, j.head_ref,
+            j.files_added, j.files_modified, j.files_removed,
+            j.sender_id, j.sender_login, j.sender_avatar_url,
+            j.installation_id, j.id, j.service_path, e->>'key', e->'vars', j.priority
+        FROM job j, jsonb_array_elements($2::jsonb) WITH ORDINALITY AS m(e, n)
+        WHERE j.id = $1
+        ORDER BY m.n
+        RETURNING id
         "#,
     )
     .bind(job_id)
-    .bind(claim_token)
-    .bind(status)
-    .execute(pool)
+    .bind(serde_json::to_value(entries)?)
+    .fetch_all(pool)
     .await?;
 
-    Ok(result.rows_affected() > 0)
+    Ok(Some(rows.into_iter().map(|(id,)| id).collect()))
 }
 
-/// Verify that a claim token belongs to a running job for a given repo
-pub async fn verify_job_token(
+/// Append a log line unless the job has written `max_bytes` of logs already. The line
+/// that would cross the limit is replaced by a truncation notice and later lines are
+/// dropped. Returns false if the token doesn't match; dropped lines still return true.
+pub async fn append_log(
     pool: &PgPool,
-    repo_id: i64,
+    job_id: i64,
     claim_token: Uuid,
+    line: &str,
+    level: LogLevel,
+    max_bytes: u64,
 ) -> Result<bool> {
-    let exists: bool = sqlx::query_scalar(
+    let row: Option<(bool,)> = sqlx::query_as(
         r#"
-        SELECT EXISTS(
-            SELECT 1 FROM job 
-            WHERE repo_id = $1 AND claim_token = $2 AND status = 'running'
+        WITH current AS (
+            SELECT log_truncated FROM job
+            WHERE id = $1 AND claim_token = $2 AND status = 'running'
+            FOR UPDATE
+        ),
+        counted AS (
+            UPDATE job
+            SET log_bytes = job.log_bytes + octet_length($3),
+                log_truncated = job.log_bytes + octet_length($3) > $5
+            FROM current
+            WHERE job.id = $1 AND NOT current.log_truncated
+            RETURNING job.log_truncated
+        ),
+        logged AS (
+            INSERT INTO job_log (job_id, line, level)
+            SELECT $1,
+                CASE WHEN log_truncated
+                    THEN format('✂️  Log truncated: the job reached its %s byte limit, further output is dropped', $5)
+                    ELSE $3
+                END,
+                CASE WHEN log_truncated THEN 'warn' ELSE $4 END::log_level
+            FROM counted
         )
+        SELECT log_truncated FROM current
         "#,
     )
-    .bind(repo_id)
+    .bind(job_id)
     .bind(claim_token)
-    .fetch_one(pool)
+    .bind(line)
+    .bind(level.as_str())
+    .bind(max_bytes as i64)
+    .fetch_optional(pool)
     .await?;
 
-    Ok(exists)
+    Ok(row.is_some())
 }
 
-pub async fn store_metrics(
+pub async fn finish_job(
     pool: &PgPool,
     job_id: i64,
     claim_token: Uuid,
-    metrics: &serde_json::Value,
+    success: bool,
+    exit_code: Option<i32>,
 ) -> Result<bool> {
+    let status = if success { "success" } else { "failed" };
+
+    // A matrix parent that fanned out stays running, unclaimed, until its children finish
     let result = sqlx::query(
         r#"
+        WITH waiting AS (
+            SELECT $3 = 'success' AND EXISTS (
+                SELECT 1 FROM job WHERE parent_job_id = $1 AND matrix_key IS NOT NULL
+            ) AS waits
+        )
         UPDATE job
-        SET metrics_json = $3
-        WHERE id = $1 AND claim_token = $2
+        SET status = CASE WHEN w.waits THEN 'running' ELSE $3 END::job_status,
+            finished_at = CASE WHEN w.waits THEN NULL ELSE now() END,
+            claim_token = CASE WHEN w.waits THEN NULL ELSE claim_token END,
+            exit_code = $4
+        FROM waiting w
+        WHERE id = $1 AND claim_token = $2 AND status = 'running'
         "#,
     )
     .bind(job_id)
     .bind(claim_token)
-    .bind(metrics)
+    .bind(status)
+    .bind(exit_code)
     .execute(pool)
     .await?;
 
-    Ok(result.rows_affected() > 0)
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    // Children that finished before the parent did couldn't settle it
+    sqlx::query("SELECT settle_matrix_parent($1)")
+        .bind(job_id)
+        .execute(pool)
+        .await?;
+
+    Ok(true)
 }
 
-pub async fn get_logs(
-    pool: &PgPool,
-    job_id: i64,
-    claim_token: Uuid,
-) -> Result<Option<String>> {
-    let job_exists: bool = sqlx::query_scalar(
+/// Note that the agent holding a running job is still alive. Returns false if the
+/// token doesn't match, e.g. because the job was reclaimed or cancelled.
+pub async fn heartbeat_job(pool: &PgPool, job_id: i64, claim_token: Uuid, agent_version: Option<&str>) -> Result<bool> {
+    let result = sqlx::query(
         r#"
-        SELECT EXISTS(
-            SELECT 1 FROM job WHERE id = $1 AND claim_token = $2
-        )
+        UPDATE job
+        SET last_heartbeat_at = now(),
+            agent_version = COALESCE($3, agent_version)
+        WHERE id = $1 AND claim_token = $2 AND status = 'running'
         "#,
     )
     .bind(job_id)
     .bind(claim_token)
-    .fetch_one(pool)
+    .bind(agent_version)
+    .execute(pool)
     .await?;
 
-    if !job_exists {
-        return Ok(None);
-    }
+    Ok(result.rows_affected() > 0)
+}
 
-    let rows: Vec<(String,)> = sqlx::query_as(
+/// A running job taken back from an agent that stopped heartbeating
+#[derive(Debug)]
+pub struct ReclaimedJob {
+    pub id: i64,
+    pub claimed_by: Option<String>,
+    /// `queued` if it will run again, `failed` once it has used up its reclaims
+    pub status: String,
+}
+
+/// Take back running jobs with no heartbeat for `timeout_secs`. Each job is requeued
+/// up to `max_reclaims` times and then failed, so a build that kills its agent
+/// can't loop forever. The old claim token is cleared so a late agent can't finish it.
+pub async fn reclaim_stale_jobs(pool: &PgPool, timeout_secs: u64, max_reclaims: i32) -> Result<Vec<ReclaimedJob>> {
+    let rows = sqlx::query(
         r#"
-        SELECT line FROM job_log
-        WHERE job_id = $1
-        ORDER BY ts ASC
+        WITH stale AS (
+            SELECT id, claimed_by, reclaim_count < $2 as requeue
+            FROM job
+            WHERE status = 'running'
+              -- Matrix parents waiting on their children hold no claim
+              AND claim_token IS NOT NULL
+              AND COALESCE(last_heartbeat_at, started_at) < now() - make_interval(secs => $1)
+            FOR UPDATE SKIP LOCKED
+        ),
+        reclaimed AS (
+            UPDATE job j
+            SET status = CASE WHEN s.requeue THEN 'queued' ELSE 'failed' END::job_status,
+                reclaim_count = j.reclaim_count + 1,
+                claim_token = NULL,
+                claimed_by = NULL,
+                last_heartbeat_at = NULL,
+                started_at = CASE WHEN s.requeue THEN NULL ELSE j.started_at END,
+                finished_at = CASE WHEN s.requeue THEN NULL ELSE now() END
+            FROM stale s
+            WHERE j.id = s.id
+            RETURNING j.id, s.claimed_by, j.status::text as status
+        ),
+        logged AS (
+            INSERT INTO job_log (job_id, line, level)
+            SELECT id, CASE WHEN status = 'queued'
+                THEN format('⚠️  Agent %s stopped responding, job requeued', COALESCE(claimed_by, 'unknown'))
+                ELSE format('❌ Agent %s stopped responding and the job was already requeued %s times, giving up', COALESCE(claimed_by, 'unknown'), $2)
+            END,
+            CASE WHEN status = 'queued' THEN 'warn' ELSE 'error' END::log_level
+            FROM reclaimed
+        )
+        SELECT id, claimed_by, status FROM reclaimed
         "#,
     )
-    .bind(job_id)
+    .bind(timeout_secs as f64)
+    .bind(max_reclaims)
     .fetch_all(pool)
     .await?;
 
-    let logs = rows
+    Ok(rows
         .into_iter()
-        .map(|(line,)| line)
-        .collect::<Vec<_>>()
-        .join("\n");
+        .map(|r| ReclaimedJob {
+            id: r.get("id"),
+            claimed_by: r.get("claimed_by"),
+            status: r.get("status"),
+        })
+        .collect())
+}
 
-    Ok(Some(logs))
+/// A queued job failed because its pinned agent never claimed it
+#[derive(Debug)]
+pub struct ExpiredPin {
+    pub id: i64,
+    pub target_agent: String,
+}
+
+/// Fail queued jobs pinned to an agent that hasn't claimed them within `timeout_secs`
+/// of being queued, so a job pinned to an offline agent doesn't wait forever
+pub async fn fail_expired_pins(pool: &PgPool, timeout_secs: u64) -> Result<Vec<ExpiredPin>> {
+    let rows = sqlx::query(
+        r#"
+        WITH expired AS (
+            UPDATE job
+            SET status = 'failed', finished_at = now()
+            WHERE id IN (
+                SELECT id FROM job
+                WHERE status = 'queued'
+                  AND target_agent IS NOT NULL
+                  AND created_at < now() - make_interval(secs => $1)
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, target_agent
+        ),
+        logged AS (
+            INSERT INTO job_log (job_id, line, level)
+            SELECT id, format('❌ Job was pinned to agent %s, which didn''t claim it within %ss', target_agent, $1), 'error'::log_level
+            FROM expired
+        )
+        SELECT id, target_agent FROM expired
+        "#,
+    )
+    .bind(timeout_secs as f64)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| ExpiredPin {
+            id: r.get("id"),
+            target_agent: r.get("target_agent"),
+        })
+        .collect())
+}
+
+/// Put a running job back in the queue for another agent, logging why
+pub async fn release_job(pool: &PgPool, job_id: i64, claim_token: Uuid, reason: &str) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        WITH released AS (
+            UPDATE job
+            SET status = 'queued',
+                claim_token = NULL,
+                claimed_by = NULL,
+                last_heartbeat_at = NULL,
+                started_at = NULL
+            WHERE id = $1 AND claim_token = $2 AND status = 'running'
+            RETURNING id
+        )
+        INSERT INTO job_log (job_id, line, level)
+        SELECT id, format('⚠️  %s, job requeued', $3::text), 'warn'::log_level FROM released
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .bind(reason)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record the GitHub check run for a running job. Returns false if the token doesn't match.
+pub async fn set_job_check_run(
+    pool: &PgPool,
+    job_id: i64,
+    claim_token: Uuid,
+    check_run_id: i64,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE job
+        SET check_run_id = $3
+        WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .bind(check_run_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Find the job a GitHub check run belongs to. Retries share their original's check run,
+/// so the newest job wins.
+pub async fn get_job_id_by_check_run(pool: &PgPool, check_run_id: i64) -> Result<Option<i64>> {
+    let row: Option<(i64,)> = sqlx::query_as(r#"SELECT id FROM job WHERE check_run_id = $1 ORDER BY id DESC LIMIT 1"#)
+        .bind(check_run_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|(id,)| id))
+}
+
+/// Cancel a queued or running job along with its matrix children. Returns false if it
+/// had already finished.
+pub async fn cancel_job(pool: &PgPool, job_id: i64) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE job
+        SET status = 'cancelled', finished_at = now()
+        WHERE (id = $1 OR (parent_job_id = $1 AND matrix_key IS NOT NULL))
+          AND status IN ('queued', 'running')
+        "#,
+    )
+    .bind(job_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Cancel every queued job in one statement, returning how many there were. Running
+/// jobs are left to finish.
+pub async fn clear_queue(pool: &PgPool) -> Result<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE job
+        SET status = 'cancelled', finished_at = now()
+        WHERE status = 'queued'
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Stop or restart handing out jobs; while paused `claim_job` finds nothing
+pub async fn set_queue_paused(pool: &PgPool, paused: bool) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO setting (key, value)
+        VALUES ('queue_paused', $1)
+        ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = now()
+        "#,
+    )
+    .bind(paused.to_string())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_job_status(pool: &PgPool, job_id: i64) -> Result<Option<JobStatus>> {
+    let row: Option<(String,)> = sqlx::query_as(r#"SELECT status::text FROM job WHERE id = $1"#)
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.and_then(|(status,)| JobStatus::parse(&status)))
+}
+
+/// Final outcome of a job, for scripts waiting on a build
+#[derive(Debug, serde::Serialize)]
+pub struct JobResult {
+    /// Left out when only public ids are exposed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub public_id: String,
+    pub status: JobStatus,
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<i64>,
+    pub finished_at: Option<String>,
+}
+
+/// The job's result once it has reached a terminal status; `Ok(None)` while it is still
+/// queued or running, or if it doesn't exist
+pub async fn get_job_result(pool: &PgPool, job_id: i64) -> Result<Option<JobResult>> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, public_id, status::text as status, exit_code,
+            (EXTRACT(EPOCH FROM (finished_at - started_at)) * 1000)::BIGINT as duration_ms,
+            to_char(finished_at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as finished_at
+        FROM job
+        WHERE id = $1 AND status NOT IN ('queued', 'running')
+        "#,
+    )
+    .bind(job_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.and_then(|r| {
+        Some(JobResult {
+            id: Some(r.get("id")),
+            public_id: r.get("public_id"),
+            status: JobStatus::parse(r.get("status"))?,
+            exit_code: r.get("exit_code"),
+            duration_ms: r.get("duration_ms"),
+            finished_at: r.get("finished_at"),
+        })
+    }))
+}
+
+/// Verify that a claim token belongs to a given running job
+pub async fn verify_running_job(
+    pool: &PgPool,
+    job_id: i64,
+    claim_token: Uuid,
+) -> Result<bool> {
+    let exists: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM job 
+            WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        )
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Verify that a claim token belongs to a running job for a given repo
+pub async fn verify_job_token(
+    pool: &PgPool,
+    repo_id: i64,
+    claim_token: Uuid,
+) -> Result<bool> {
+    let exists: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM job 
+            WHERE repo_id = $1 AND claim_token = $2 AND status = 'running'
+        )
+        "#,
+    )
+    .bind(repo_id)
+    .bind(claim_token)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+pub async fn store_metrics(
+    pool: &PgPool,
+    job_id: i64,
+    claim_token: Uuid,
+    metrics: &serde_json::Value,
+) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE job
+        SET metrics_json = $3
+        WHERE id = $1 AND claim_token = $2
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .bind(metrics)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record the limits a running job's containers run with. Returns false if the token doesn't match.
+pub async fn store_limits(pool: &PgPool, job_id: i64, claim_token: Uuid, limits: &JobLimits) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE job
+        SET limits = $3
+        WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .bind(serde_json::to_value(limits)?)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_logs(
+    pool: &PgPool,
+    job_id: i64,
+    claim_token: Uuid,
+) -> Result<Option<String>> {
+    let job_exists: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM job WHERE id = $1 AND claim_token = $2
+        )
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .fetch_one(pool)
+    .await?;
+
+    if !job_exists {
+        return Ok(None);
+    }
+
+    let rows: Vec<(String,)> = sqlx::query_as(
+        r#"
+        SELECT line FROM job_log
+        WHERE job_id = $1
+        ORDER BY ts ASC
+        "#,
+    )
+    .bind(job_id)
+    .fetch_all(pool)
+    .await?;
+
+    let logs = rows
+        .into_iter()
+        .map(|(line,)| line)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(Some(logs))
 }
 
 #[derive(Debug, serde::Serialize)]
 pub struct JobSummary {
-    pub id: i64,
+    /// Left out when only public ids are exposed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub public_id: String,
+    pub acknowledged: bool,
     pub repo_owner: String,
     pub repo_name: String,
     pub git_sha: String,
@@ -652,11 +1444,19 @@ pub struct JobSummary {
     pub commit_author: Option<String>,
     pub duration_secs: Option<i64>,
     pub trigger_type: Option<String>,
+    /// 1 for the next queued job agents will claim; `None` unless queued
+    pub queue_position: Option<i64>,
 }
 
 #[derive(Debug, serde::Serialize)]
 pub struct JobDetail {
+    /// Serialized by the API only while numeric job ids are exposed
+    #[serde(skip)]
     pub id: i64,
+    pub public_id: String,
+    pub acknowledged: bool,
+    pub ack_reason: Option<String>,
+    pub acknowledged_by: Option<String>,
     pub repo_owner: String,
     pub repo_name: String,
     pub git_sha: String,
@@ -670,10 +1470,33 @@ pub struct JobDetail {
     pub commit_url: Option<String>,
     pub duration_secs: Option<i64>,
     pub trigger_type: Option<String>,
+    /// 1 for the next queued job agents will claim; `None` unless queued
+    pub queue_position: Option<i64>,
     pub pr_number: Option<i64>,
     pub pr_title: Option<String>,
     pub pr_url: Option<String>,
+    pub pr_author: Option<String>,
+    /// Branch the PR comes from, `owner:branch` for forks
+    pub pr_head_ref: Option<String>,
+    /// Branch the PR targets
+    pub pr_base_ref: Option<String>,
+    pub service_path: Option<String>,
+    /// Matrix combination this job builds, e.g. `rust=1.76`
+    pub matrix_key: Option<String>,
+    /// Public id of the job that fanned this one out
+    pub parent_public_id: Option<String>,
+    /// Dashboard user who queued a manual build
+    pub triggered_by: Option<String>,
+    pub claimed_by: Option<String>,
+    /// Only agent allowed to claim the job, when pinned from the dashboard
+    pub target_agent: Option<String>,
+    /// Group from the repo's `[concurrency]` when the job was queued
+    pub concurrency_group: Option<String>,
+    /// Build of the agent that ran the job, from its heartbeats
+    pub agent_version: Option<String>,
     pub metrics: Option<serde_json::Value>,
+    /// `JobLimits` the agent ran the build with
+    pub limits: Option<serde_json::Value>,
 }
 
 #[derive(Debug, serde::Serialize)]
@@ -685,6 +1508,24 @@ pub struct RepoSummary {
     pub success_count: i32,
     pub failure_count: i32,
     pub last_build_at: Option<String>,
+    /// Statuses of the last 10 builds, newest first
+    pub recent_statuses: Vec<JobStatus>,
+    /// Public ids of the same builds, for linking each status to its job
+    pub recent_job_ids: Vec<String>,
+    /// False while builds are paused, e.g. for a repo outside `FOUNDRY_ALLOWED_REPOS`
+    pub enabled: bool,
+}
+
+/// Number of builds shown in a repo's recent-status strip
+const RECENT_BUILDS: i64 = 10;
+
+/// Pair up the recent status/id arrays, dropping any status we don't recognise
+fn recent_builds(statuses: Vec<String>, public_ids: Vec<String>) -> (Vec<JobStatus>, Vec<String>) {
+    statuses
+        .iter()
+        .zip(public_ids)
+        .filter_map(|(status, id)| Some((JobStatus::parse(status)?, id)))
+        .unzip()
 }
 
 #[derive(Debug, Default, serde::Serialize)]
@@ -694,21 +1535,31 @@ pub struct DashboardStats {
     pub success_rate: f64,
     pub queued_count: i64,
     pub running_count: i64,
+    /// Set by `POST /api/admin/pause`; agents get no jobs until resumed
+    pub queue_paused: bool,
+}
+
+/// Percentage of finished builds that succeeded. Callers pass the failure
+/// count with acknowledged failures already excluded.
+pub fn success_rate(success_count: i64, failed_count: i64) -> f64 {
+    let finished = success_count + failed_count;
+    if finished == 0 {
+        return 0.0;
+    }
+    success_count as f64 / finished as f64 * 100.0
 }
 
 pub async fn get_dashboard_stats(pool: &PgPool) -> Result<DashboardStats> {
     let row = sqlx::query(
         r#"
         SELECT 
-            COUNT(*) as total_jobs,
-            COUNT(*) FILTER (WHERE created_at > now() - interval '24 hours') as jobs_today,
-            COALESCE(
-                COUNT(*) FILTER (WHERE status = 'success')::float / 
-                NULLIF(COUNT(*) FILTER (WHERE status IN ('success', 'failed')), 0) * 100,
-                0
-            ) as success_rate,
+            COUNT(*) FILTER (WHERE NOT replayed) as total_jobs,
+            COUNT(*) FILTER (WHERE created_at > now() - interval '24 hours' AND NOT replayed) as jobs_today,
+            COUNT(*) FILTER (WHERE status = 'success') as success_count,
+            COUNT(*) FILTER (WHERE status = 'failed' AND NOT acknowledged) as failed_count,
             COUNT(*) FILTER (WHERE status = 'queued') as queued_count,
-            COUNT(*) FILTER (WHERE status = 'running') as running_count
+            COUNT(*) FILTER (WHERE status = 'running') as running_count,
+            EXISTS (SELECT 1 FROM setting WHERE key = 'queue_paused' AND value = 'true') as queue_paused
         FROM job
         "#,
     )
@@ -718,20 +1569,29 @@ pub async fn get_dashboard_stats(pool: &PgPool) -> Result<DashboardStats> {
     Ok(DashboardStats {
         total_jobs: row.get("total_jobs"),
         jobs_today: row.get("jobs_today"),
-        success_rate: row.get("success_rate"),
+        success_rate: success_rate(row.get("success_count"), row.get("failed_count")),
         queued_count: row.get("queued_count"),
         running_count: row.get("running_count"),
+        queue_paused: row.get("queue_paused"),
     })
 }
 
-pub async fn list_repos(pool: &PgPool) -> Result<Vec<RepoSummary>> {
+/// Number of jobs a repo has in one status
+#[derive(Debug)]
+pub struct RepoStatusCount {
+    pub repo: String,
+    pub status: String,
+    pub count: i64,
+}
+
+pub async fn get_job_counts_by_repo(pool: &PgPool) -> Result<Vec<RepoStatusCount>> {
     let rows = sqlx::query(
         r#"
-        SELECT 
-            id, owner, name, build_count, success_count, failure_count,
-            to_char(last_build_at, 'YYYY-MM-DD HH24:MI:SS') as last_build_at
-        FROM repo
-        ORDER BY last_build_at DESC NULLS LAST
+        SELECT r.owner || '/' || r.name as repo, j.status::text as status, COUNT(*) as count
+        FROM job j
+        JOIN repo r ON r.id = j.repo_id
+        GROUP BY r.owner, r.name, j.status
+        ORDER BY r.owner, r.name, j.status
         "#,
     )
     .fetch_all(pool)
@@ -739,29 +1599,137 @@ pub async fn list_repos(pool: &PgPool) -> Result<Vec<RepoSummary>> {
 
     Ok(rows
         .into_iter()
-        .map(|r| RepoSummary {
-            id: r.get("id"),
-            owner: r.get("owner"),
-            name: r.get("name"),
-            build_count: r.get("build_count"),
-            success_count: r.get("success_count"),
-            failure_count: r.get("failure_count"),
-            last_build_at: r.get("last_build_at"),
+        .map(|r| RepoStatusCount {
+            repo: r.get("repo"),
+            status: r.get("status"),
+            count: r.get("count"),
         })
         .collect())
 }
 
-#[derive(Debug, serde::Serialize)]
-pub struct RepoDetail {
-    pub id: i64,
-    pub owner: String,
-    pub name: String,
-    pub full_name: Option<String>,
-    pub html_url: Option<String>,
+/// Queued and running jobs of one repo, for autoscaling agents
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RepoQueue {
+    pub repo: String,
+    pub queued: i64,
+    pub running: i64,
+    /// How long the oldest queued job has waited; `None` if nothing is queued
+    pub oldest_queued_secs: Option<i64>,
+}
+
+/// Repos with queued or running jobs. Only reads unfinished jobs, so it stays cheap
+/// however long the job history is.
+pub async fn get_queue_by_repo(pool: &PgPool) -> Result<Vec<RepoQueue>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            r.owner || '/' || r.name as repo,
+            COUNT(*) FILTER (WHERE j.status = 'queued') as queued,
+            COUNT(*) FILTER (WHERE j.status = 'running') as running,
+            EXTRACT(EPOCH FROM NOW() - MIN(j.created_at) FILTER (WHERE j.status = 'queued'))::BIGINT
+                as oldest_queued_secs
+        FROM job j
+        JOIN repo r ON r.id = j.repo_id
+        WHERE j.status IN ('queued', 'running')
+        GROUP BY r.owner, r.name
+        ORDER BY r.owner, r.name
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| RepoQueue {
+            repo: r.get("repo"),
+            queued: r.get("queued"),
+            running: r.get("running"),
+            oldest_queued_secs: r.get("oldest_queued_secs"),
+        })
+        .collect())
+}
+
+/// Builds finished in the last hour and their average duration in seconds
+pub async fn get_recent_build_duration(pool: &PgPool) -> Result<(i64, Option<f64>)> {
+    let row = sqlx::query(
+        r#"
+        SELECT
+            COUNT(*) as builds,
+            AVG(EXTRACT(EPOCH FROM (finished_at - started_at)))::float8 as avg_secs
+        FROM job
+        WHERE finished_at > now() - interval '1 hour'
+          AND started_at IS NOT NULL
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((row.get("builds"), row.get("avg_secs")))
+}
+
+pub async fn list_repos(pool: &PgPool) -> Result<Vec<RepoSummary>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT 
+            r.id, r.owner, r.name, r.build_count, r.success_count, r.failure_count, r.enabled,
+            to_char(r.last_build_at, 'YYYY-MM-DD HH24:MI:SS') as last_build_at,
+            COALESCE(recent.statuses, '{}') as recent_statuses,
+            COALESCE(recent.public_ids, '{}') as recent_job_ids
+        FROM repo r
+        LEFT JOIN LATERAL (
+            SELECT
+                array_agg(j.status::text ORDER BY j.created_at DESC, j.id DESC) as statuses,
+                array_agg(j.public_id ORDER BY j.created_at DESC, j.id DESC) as public_ids
+            FROM (
+                SELECT id, status, public_id, created_at
+                FROM job
+                WHERE repo_id = r.id
+                ORDER BY created_at DESC, id DESC
+                LIMIT $1
+            ) j
+        ) recent ON TRUE
+        ORDER BY r.last_build_at DESC NULLS LAST
+        "#,
+    )
+    .bind(RECENT_BUILDS)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let (recent_statuses, recent_job_ids) =
+                recent_builds(r.get("recent_statuses"), r.get("recent_job_ids"));
+            RepoSummary {
+                id: r.get("id"),
+                owner: r.get("owner"),
+                name: r.get("name"),
+                build_count: r.get("build_count"),
+                success_count: r.get("success_count"),
+                failure_count: r.get("failure_count"),
+                last_build_at: r.get("last_build_at"),
+                recent_statuses,
+                recent_job_ids,
+                enabled: r.get("enabled"),
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RepoDetail {
+    pub id: i64,
+    pub owner: String,
+    pub name: String,
+    pub full_name: Option<String>,
+    pub html_url: Option<String>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub default_branch: Option<String>,
     pub private: bool,
+    /// Webhooks and schedules queue builds only while set
+    pub enabled: bool,
+    pub cancel_outdated: CancelOutdated,
     pub build_count: i32,
     pub success_count: i32,
     pub failure_count: i32,
@@ -774,7 +1742,7 @@ pub async fn get_repo(pool: &PgPool, id: i64) -> Result<Option<RepoDetail>> {
         r#"
         SELECT 
             id, owner, name, full_name, html_url, description, language,
-            default_branch, private, build_count, success_count, failure_count,
+            default_branch, private, enabled, cancel_outdated, build_count, success_count, failure_count,
             to_char(last_build_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as last_build_at,
             to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at
         FROM repo
@@ -795,6 +1763,8 @@ pub async fn get_repo(pool: &PgPool, id: i64) -> Result<Option<RepoDetail>> {
         language: r.get("language"),
         default_branch: r.get("default_branch"),
         private: r.get("private"),
+        enabled: r.get("enabled"),
+        cancel_outdated: CancelOutdated::parse(r.get("cancel_outdated")).unwrap_or_default(),
         build_count: r.get("build_count"),
         success_count: r.get("success_count"),
         failure_count: r.get("failure_count"),
@@ -803,18 +1773,103 @@ pub async fn get_repo(pool: &PgPool, id: i64) -> Result<Option<RepoDetail>> {
     }))
 }
 
+/// Durations of successful builds finished in one window, in seconds
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct DurationPercentiles {
+    pub builds: i64,
+    pub p50_secs: Option<i64>,
+    pub p95_secs: Option<i64>,
+}
+
+/// Build durations over the last `days` and the `days` before that
+#[derive(Debug, serde::Serialize)]
+pub struct RepoBuildStats {
+    pub days: u32,
+    pub current: DurationPercentiles,
+    pub previous: DurationPercentiles,
+    /// How much slower (positive) or faster the median build got between the windows
+    pub p50_change_percent: Option<f64>,
+}
+
+/// Relative change from `previous` to `current`, or `None` without both
+pub fn change_percent(previous: Option<i64>, current: Option<i64>) -> Option<f64> {
+    match (previous, current) {
+        (Some(previous), Some(current)) if previous > 0 => {
+            Some(((current - previous) as f64 / previous as f64 * 1000.0).round() / 10.0)
+        }
+        _ => None,
+    }
+}
+
+/// Median and p95 duration of a repo's successful builds over the last `days`,
+/// compared with the window before. Failed builds are left out since they often
+/// stop early. Returns `None` if the repo doesn't exist.
+pub async fn get_repo_build_stats(pool: &PgPool, repo_id: i64, days: u32) -> Result<Option<RepoBuildStats>> {
+    let row = sqlx::query(
+        r#"
+        WITH durations AS (
+            SELECT
+                EXTRACT(EPOCH FROM (finished_at - started_at))::float8 as secs,
+                finished_at >= now() - make_interval(days => $2) as current
+            FROM job
+            WHERE repo_id = $1
+              AND status = 'success'
+              AND started_at IS NOT NULL
+              AND finished_at >= now() - make_interval(days => $2 * 2)
+        )
+        SELECT
+            COUNT(*) FILTER (WHERE current) as builds,
+            round(percentile_cont(0.5) WITHIN GROUP (ORDER BY secs) FILTER (WHERE current))::bigint as p50,
+            round(percentile_cont(0.95) WITHIN GROUP (ORDER BY secs) FILTER (WHERE current))::bigint as p95,
+            COUNT(*) FILTER (WHERE NOT current) as previous_builds,
+            round(percentile_cont(0.5) WITHIN GROUP (ORDER BY secs) FILTER (WHERE NOT current))::bigint as previous_p50,
+            round(percentile_cont(0.95) WITHIN GROUP (ORDER BY secs) FILTER (WHERE NOT current))::bigint as previous_p95
+        FROM durations
+        HAVING EXISTS (SELECT 1 FROM repo WHERE id = $1)
+        "#,
+    )
+    .bind(repo_id)
+    .bind(days as i32)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| {
+        let current = DurationPercentiles {
+            builds: r.get("builds"),
+            p50_secs: r.get("p50"),
+            p95_secs: r.get("p95"),
+        };
+        let previous = DurationPercentiles {
+            builds: r.get("previous_builds"),
+            p50_secs: r.get("previous_p50"),
+            p95_secs: r.get("previous_p95"),
+        };
+        RepoBuildStats {
+            days,
+            p50_change_percent: change_percent(previous.p50_secs, current.p50_secs),
+            current,
+            previous,
+        }
+    }))
+}
+
 pub async fn get_repo_jobs(pool: &PgPool, repo_id: i64, limit: i64) -> Result<Vec<JobSummary>> {
     let rows = sqlx::query(
         r#"
         SELECT 
-            j.id, r.owner as repo_owner, r.name as repo_name,
-            j.git_sha, j.status,
+            j.id, j.public_id, j.acknowledged, r.owner as repo_owner, r.name as repo_name,
+            j.git_sha, j.status::text as status,
             to_char(j.created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at,
             j.commit_message, j.commit_author,
-            EXTRACT(EPOCH FROM (COALESCE(j.finished_at, now()) - j.started_at))::int as duration_secs,
-            j.trigger_type
+            EXTRACT(EPOCH FROM (COALESCE(j.finished_at, now()) - j.started_at))::bigint as duration_secs,
+            j.trigger_type::text as trigger_type,
+            q.queue_position
         FROM job j
         JOIN repo r ON r.id = j.repo_id
+        LEFT JOIN (
+            SELECT id, row_number() OVER (ORDER BY priority DESC, created_at ASC) as queue_position
+            FROM job WHERE status = 'queued'
+        ) q ON q.id = j.id
         WHERE j.repo_id = $1
         ORDER BY j.created_at DESC
         LIMIT $2
@@ -828,7 +1883,9 @@ pub async fn get_repo_jobs(pool: &PgPool, repo_id: i64, limit: i64) -> Result<Ve
     Ok(rows
         .into_iter()
         .map(|r| JobSummary {
-            id: r.get("id"),
+            id: Some(r.get("id")),
+            public_id: r.get("public_id"),
+            acknowledged: r.get("acknowledged"),
             repo_owner: r.get("repo_owner"),
             repo_name: r.get("repo_name"),
             git_sha: r.get("git_sha"),
@@ -838,6 +1895,7 @@ pub async fn get_repo_jobs(pool: &PgPool, repo_id: i64, limit: i64) -> Result<Ve
             commit_author: r.get("commit_author"),
             duration_secs: r.get("duration_secs"),
             trigger_type: r.get("trigger_type"),
+            queue_position: r.get("queue_position"),
         })
         .collect())
 }
@@ -895,6 +1953,140 @@ pub async fn list_schedules(pool: &PgPool) -> Result<Vec<ScheduleSummary>> {
         .collect())
 }
 
+/// Pause or resume webhook and scheduled builds of a repo. Returns false if it doesn't exist.
+pub async fn set_repo_enabled(pool: &PgPool, repo_id: i64, enabled: bool) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE repo
+        SET enabled = $2, updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(repo_id)
+    .bind(enabled)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Which older builds of a ref a new push to it cancels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelOutdated {
+    #[default]
+    Off,
+    Queued,
+    /// Queued and running builds
+    Running,
+}
+
+impl CancelOutdated {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Self::Off),
+            "queued" => Some(Self::Queued),
+            "running" => Some(Self::Running),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Queued => "queued",
+            Self::Running => "running",
+        }
+    }
+}
+
+pub async fn set_repo_cancel_outdated(pool: &PgPool, repo_id: i64, mode: CancelOutdated) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE repo
+        SET cancel_outdated = $2, updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(repo_id)
+    .bind(mode.as_str())
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Cancel the push builds of `git_ref` queued before `job_id`, and running ones too if
+/// the repo's `cancel_outdated` says so. Returns the ids cancelled.
+pub async fn cancel_outdated_jobs(pool: &PgPool, repo_id: i64, git_ref: &str, job_id: i64) -> Result<Vec<i64>> {
+    let rows: Vec<(i64,)> = sqlx::query_as(
+        r#"
+        WITH cancelled AS (
+            UPDATE job j
+            SET status = 'cancelled', finished_at = now()
+            FROM repo r
+            WHERE r.id = $1
+              AND j.repo_id = r.id
+              AND j.git_ref = $2
+              AND j.id < $3
+              AND j.trigger_type = 'push'
+              AND (j.status = 'queued' OR (j.status = 'running' AND r.cancel_outdated = 'running'))
+              AND r.cancel_outdated <> 'off'
+              -- A replayed push is older than the builds queued since it first arrived
+              AND NOT EXISTS (SELECT 1 FROM job n WHERE n.id = $3 AND n.replayed)
+            RETURNING j.id
+        ),
+        logged AS (
+            INSERT INTO job_log (job_id, line, level)
+            SELECT c.id, format('🛑 Cancelled: superseded by a newer push (job %s)', n.public_id), 'warn'::log_level
+            FROM cancelled c, job n
+            WHERE n.id = $3
+        )
+        SELECT id FROM cancelled
+        "#,
+    )
+    .bind(repo_id)
+    .bind(git_ref)
+    .bind(job_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
+/// Cancel a closed PR's queued and running builds, so none deploys a preview after
+/// it was torn down
+pub async fn cancel_pr_jobs(pool: &PgPool, owner: &str, name: &str, pr_number: i64) -> Result<Vec<i64>> {
+    let rows: Vec<(i64,)> = sqlx::query_as(
+        r#"
+        WITH cancelled AS (
+            UPDATE job j
+            SET status = 'cancelled', finished_at = now()
+            FROM repo r
+            WHERE j.repo_id = r.id
+              AND r.owner = $1
+              AND r.name = $2
+              AND j.pr_number = $3
+              AND j.status IN ('queued', 'running')
+            RETURNING j.id
+        ),
+        logged AS (
+            INSERT INTO job_log (job_id, line, level)
+            SELECT id, '🛑 Cancelled: the pull request was closed', 'warn'::log_level
+            FROM cancelled
+        )
+        SELECT id FROM cancelled
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .bind(pr_number)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
 pub async fn toggle_schedule(pool: &PgPool, schedule_id: i64, enabled: bool) -> Result<bool> {
     let result = sqlx::query(
         r#"
@@ -922,11 +2114,22 @@ pub async fn delete_schedule_by_id(pool: &PgPool, schedule_id: i64) -> Result<bo
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn list_jobs(pool: &PgPool, limit: i64) -> Result<Vec<JobSummary>> {
+/// Narrows the job list; `None` fields match every job
+#[derive(Debug, Default)]
+pub struct JobFilter {
+    pub status: Option<JobStatus>,
+    /// `owner/name`
+    pub repo: Option<String>,
+}
+
+/// One page of jobs, newest first
+pub async fn list_jobs(pool: &PgPool, filter: &JobFilter, limit: i64, offset: i64) -> Result<Vec<JobSummary>> {
     let rows = sqlx::query(
         r#"
         SELECT 
             j.id, 
+            j.public_id,
+            j.acknowledged,
             r.owner as repo_owner, 
             r.name as repo_name, 
             j.git_sha, 
@@ -935,10 +2138,88 @@ pub async fn list_jobs(pool: &PgPool, limit: i64) -> Result<Vec<JobSummary>> {
             j.commit_message,
             j.commit_author,
             EXTRACT(EPOCH FROM (j.finished_at - j.started_at))::bigint as duration_secs,
-            j.trigger_type::text as trigger_type
+            j.trigger_type::text as trigger_type,
+            q.queue_position
         FROM job j
         JOIN repo r ON r.id = j.repo_id
+        LEFT JOIN (
+            SELECT id, row_number() OVER (ORDER BY priority DESC, created_at ASC) as queue_position
+            FROM job WHERE status = 'queued'
+        ) q ON q.id = j.id
+        WHERE ($3::text IS NULL OR j.status::text = $3)
+            AND ($4::text IS NULL OR r.owner || '/' || r.name = $4)
         ORDER BY j.created_at DESC
+        LIMIT $1 OFFSET $2
+        "#,
+    )
+    .bind(limit)
+    .bind(offset)
+    .bind(filter.status.map(|s| s.as_str()))
+    .bind(filter.repo.as_deref())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| JobSummary {
+            id: Some(r.get("id")),
+            public_id: r.get("public_id"),
+            acknowledged: r.get("acknowledged"),
+            repo_owner: r.get("repo_owner"),
+            repo_name: r.get("repo_name"),
+            git_sha: r.get("git_sha"),
+            status: r.get("status"),
+            created_at: r.get("created_at"),
+            commit_message: r.get("commit_message"),
+            commit_author: r.get("commit_author"),
+            duration_secs: r.get("duration_secs"),
+            trigger_type: r.get("trigger_type"),
+            queue_position: r.get("queue_position"),
+        })
+        .collect())
+}
+
+/// Number of jobs matching `filter`, for paging through `list_jobs`
+pub async fn count_jobs(pool: &PgPool, filter: &JobFilter) -> Result<i64> {
+    let row: (i64,) = sqlx::query_as(
+        r#"
+        SELECT COUNT(*)
+        FROM job j
+        JOIN repo r ON r.id = j.repo_id
+        WHERE ($1::text IS NULL OR j.status::text = $1)
+            AND ($2::text IS NULL OR r.owner || '/' || r.name = $2)
+        "#,
+    )
+    .bind(filter.status.map(|s| s.as_str()))
+    .bind(filter.repo.as_deref())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.0)
+}
+
+/// Queued jobs in the order agents will claim them, up to `limit`
+pub async fn list_queued_jobs(pool: &PgPool, limit: i64) -> Result<Vec<JobSummary>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT
+            j.id,
+            j.public_id,
+            j.acknowledged,
+            r.owner as repo_owner,
+            r.name as repo_name,
+            j.git_sha,
+            j.status::text,
+            to_char(j.created_at, 'YYYY-MM-DD HH24:MI:SS') as created_at,
+            j.commit_message,
+            j.commit_author,
+            NULL::bigint as duration_secs,
+            j.trigger_type::text as trigger_type,
+            row_number() OVER (ORDER BY j.priority DESC, j.created_at ASC) as queue_position
+        FROM job j
+        JOIN repo r ON r.id = j.repo_id
+        WHERE j.status = 'queued'
+        ORDER BY queue_position
         LIMIT $1
         "#,
     )
@@ -949,7 +2230,9 @@ pub async fn list_jobs(pool: &PgPool, limit: i64) -> Result<Vec<JobSummary>> {
     Ok(rows
         .into_iter()
         .map(|r| JobSummary {
-            id: r.get("id"),
+            id: Some(r.get("id")),
+            public_id: r.get("public_id"),
+            acknowledged: r.get("acknowledged"),
             repo_owner: r.get("repo_owner"),
             repo_name: r.get("repo_name"),
             git_sha: r.get("git_sha"),
@@ -959,6 +2242,7 @@ pub async fn list_jobs(pool: &PgPool, limit: i64) -> Result<Vec<JobSummary>> {
             commit_author: r.get("commit_author"),
             duration_secs: r.get("duration_secs"),
             trigger_type: r.get("trigger_type"),
+            queue_position: r.get("queue_position"),
         })
         .collect())
 }
@@ -968,6 +2252,8 @@ pub async fn get_job(pool: &PgPool, job_id: i64) -> Result<Option<JobDetail>> {
         r#"
         SELECT 
             j.id, 
+            j.public_id,
+            j.acknowledged,
             r.owner as repo_owner, 
             r.name as repo_name, 
             j.git_sha,
@@ -981,12 +2267,32 @@ pub async fn get_job(pool: &PgPool, job_id: i64) -> Result<Option<JobDetail>> {
             j.commit_url,
             EXTRACT(EPOCH FROM (j.finished_at - j.started_at))::bigint as duration_secs,
             j.trigger_type::text as trigger_type,
+            q.queue_position,
             j.pr_number,
             j.pr_title,
             j.pr_url,
-            j.metrics_json as metrics
+            j.pr_author,
+            j.head_ref as pr_head_ref,
+            j.base_ref as pr_base_ref,
+            j.service_path,
+            j.matrix_key,
+            p.public_id as parent_public_id,
+            j.ack_reason,
+            j.acknowledged_by,
+            j.triggered_by,
+            j.claimed_by,
+            j.target_agent,
+            j.concurrency_group,
+            j.agent_version,
+            j.metrics_json as metrics,
+            j.limits
         FROM job j
         JOIN repo r ON r.id = j.repo_id
+        LEFT JOIN job p ON p.id = j.parent_job_id
+        LEFT JOIN (
+            SELECT id, row_number() OVER (ORDER BY priority DESC, created_at ASC) as queue_position
+            FROM job WHERE status = 'queued'
+        ) q ON q.id = j.id
         WHERE j.id = $1
         "#,
     )
@@ -996,6 +2302,10 @@ pub async fn get_job(pool: &PgPool, job_id: i64) -> Result<Option<JobDetail>> {
 
     Ok(row.map(|r| JobDetail {
         id: r.get("id"),
+        public_id: r.get("public_id"),
+        acknowledged: r.get("acknowledged"),
+        ack_reason: r.get("ack_reason"),
+        acknowledged_by: r.get("acknowledged_by"),
         repo_owner: r.get("repo_owner"),
         repo_name: r.get("repo_name"),
         git_sha: r.get("git_sha"),
@@ -1009,91 +2319,406 @@ pub async fn get_job(pool: &PgPool, job_id: i64) -> Result<Option<JobDetail>> {
         commit_url: r.get("commit_url"),
         duration_secs: r.get("duration_secs"),
         trigger_type: r.get("trigger_type"),
+        queue_position: r.get("queue_position"),
         pr_number: r.get("pr_number"),
         pr_title: r.get("pr_title"),
         pr_url: r.get("pr_url"),
+        pr_author: r.get("pr_author"),
+        pr_head_ref: r.get("pr_head_ref"),
+        pr_base_ref: r.get("pr_base_ref"),
+        service_path: r.get("service_path"),
+        matrix_key: r.get("matrix_key"),
+        parent_public_id: r.get("parent_public_id"),
+        triggered_by: r.get("triggered_by"),
+        claimed_by: r.get("claimed_by"),
+        target_agent: r.get("target_agent"),
+        concurrency_group: r.get("concurrency_group"),
+        agent_version: r.get("agent_version"),
         metrics: r.get("metrics"),
+        limits: r.get("limits"),
     }))
 }
 
-pub async fn get_job_logs(pool: &PgPool, job_id: i64) -> Result<Option<String>> {
-    let rows: Vec<(String,)> = sqlx::query_as(
+/// A monorepo service or matrix job fanned out from another job
+#[derive(Debug, serde::Serialize)]
+pub struct ChildJob {
+    /// Left out when only public ids are exposed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub public_id: String,
+    pub status: String,
+    pub service_path: Option<String>,
+    pub matrix_key: Option<String>,
+    pub duration_secs: Option<i64>,
+}
+
+pub async fn list_child_jobs(pool: &PgPool, parent_id: i64) -> Result<Vec<ChildJob>> {
+    let rows = sqlx::query(
         r#"
-        SELECT line FROM job_log
-        WHERE job_id = $1
-        ORDER BY ts ASC
+        SELECT
+            id,
+            public_id,
+            status::text as status,
+            service_path,
+            matrix_key,
+            EXTRACT(EPOCH FROM (finished_at - started_at))::bigint as duration_secs
+        FROM job
+        WHERE parent_job_id = $1
+        ORDER BY id
         "#,
     )
-    .bind(job_id)
+    .bind(parent_id)
     .fetch_all(pool)
     .await?;
 
-    if rows.is_empty() {
-        return Ok(None);
-    }
+    Ok(rows
+        .into_iter()
+        .map(|r| ChildJob {
+            id: Some(r.get("id")),
+            public_id: r.get("public_id"),
+            status: r.get("status"),
+            service_path: r.get("service_path"),
+            matrix_key: r.get("matrix_key"),
+            duration_secs: r.get("duration_secs"),
+        })
+        .collect())
+}
 
-    Ok(Some(rows.into_iter().map(|(line,)| line).collect::<Vec<_>>().join("\n")))
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct JobNote {
+    pub id: i64,
+    /// Left out when only public ids are exposed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<i64>,
+    pub author: String,
+    pub body: String,
+    pub created_at: String,
 }
 
-/// Check if a push to a branch should trigger a build based on repo config
-pub async fn should_build_branch(pool: &PgPool, owner: &str, name: &str, branch: &str) -> Result<bool> {
-    let row: Option<(Vec<String>,)> = sqlx::query_as(
+/// Add an operator note to a job. Returns `None` if the job doesn't exist.
+pub async fn add_job_note(
+    pool: &PgPool,
+    job_id: i64,
+    author: &str,
+    body: &str,
+) -> Result<Option<JobNote>> {
+    let row = sqlx::query(
         r#"
-        SELECT COALESCE(triggers_branches, ARRAY['main', 'master']) as branches
-        FROM repo
-        WHERE owner = $1 AND name = $2
+        INSERT INTO job_note (job_id, author, body)
+        SELECT id, $2, $3 FROM job WHERE id = $1
+        RETURNING id, job_id, author, body,
+            to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at
         "#,
     )
-    .bind(owner)
-    .bind(name)
+    .bind(job_id)
+    .bind(author)
+    .bind(body)
     .fetch_optional(pool)
     .await?;
 
-    // If repo doesn't exist yet, use defaults
-    let branches = row.map(|(b,)| b).unwrap_or_else(|| vec!["main".to_string(), "master".to_string()]);
-    
-    Ok(branches.iter().any(|b| b == branch))
+    Ok(row.map(|r| JobNote {
+        id: r.get("id"),
+        job_id: Some(r.get("job_id")),
+        author: r.get("author"),
+        body: r.get("body"),
+        created_at: r.get("created_at"),
+    }))
 }
 
-/// Check if a PR should trigger a build based on repo config
-pub async fn should_build_pr(pool: &PgPool, owner: &str, name: &str, target_branch: &str) -> Result<bool> {
-    let row: Option<(bool, Option<Vec<String>>)> = sqlx::query_as(
+pub async fn get_job_notes(pool: &PgPool, job_id: i64) -> Result<Vec<JobNote>> {
+    let rows = sqlx::query(
         r#"
-        SELECT 
-            COALESCE(triggers_pull_requests, TRUE) as pr_enabled,
-            triggers_pr_target_branches
-        FROM repo
-        WHERE owner = $1 AND name = $2
+        SELECT id, job_id, author, body,
+            to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at
+        FROM job_note
+        WHERE job_id = $1
+        ORDER BY created_at ASC, id ASC
         "#,
     )
-    .bind(owner)
-    .bind(name)
-    .fetch_optional(pool)
+    .bind(job_id)
+    .fetch_all(pool)
     .await?;
 
-    match row {
-        Some((pr_enabled, target_branches)) => {
-            if !pr_enabled {
-                return Ok(false);
-            }
-            // If specific target branches are configured, check against them
-            if let Some(targets) = target_branches {
-                Ok(targets.iter().any(|b| b == target_branch))
-            } else {
-                Ok(true) // No filter, build all PRs
-            }
-        }
-        None => Ok(true), // Repo not in DB yet, default to building
-    }
+    Ok(rows
+        .into_iter()
+        .map(|r| JobNote {
+            id: r.get("id"),
+            job_id: Some(r.get("job_id")),
+            author: r.get("author"),
+            body: r.get("body"),
+            created_at: r.get("created_at"),
+        })
+        .collect())
+}
+
+/// Acknowledge a failed job so it stops counting against success rates.
+/// Returns false if the job isn't an unacknowledged failure.
+pub async fn acknowledge_job(pool: &PgPool, job_id: i64, author: &str, reason: &str) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query(
+        r#"
+        UPDATE job
+        SET acknowledged = TRUE, ack_reason = $2, acknowledged_by = $3, acknowledged_at = now()
+        WHERE id = $1 AND status = 'failed' AND NOT acknowledged
+        "#,
+    )
+    .bind(job_id)
+    .bind(reason)
+    .bind(author)
+    .execute(&mut *tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    // Take the failure back out of the repo's running totals
+    sqlx::query(
+        r#"
+        UPDATE repo SET
+            build_count = GREATEST(build_count - 1, 0),
+            failure_count = GREATEST(failure_count - 1, 0)
+        WHERE id = (SELECT repo_id FROM job WHERE id = $1)
+        "#,
+    )
+    .bind(job_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Resolve a job's public permalink id to its numeric id
+pub async fn get_job_id_by_public_id(pool: &PgPool, public_id: &str) -> Result<Option<i64>> {
+    let row: Option<(i64,)> = sqlx::query_as(r#"SELECT id FROM job WHERE public_id = $1"#)
+        .bind(public_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.map(|(id,)| id))
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JobLogRow {
+    pub id: i64,
+    pub ts: String,
+    pub line: String,
+    pub level: String,
+}
+
+/// Log lines for a job with ids greater than `after_id`, oldest first
+pub async fn get_job_logs_after(
+    pool: &PgPool,
+    job_id: i64,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<JobLogRow>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, to_char(ts AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"') as ts, line, level::text as level
+        FROM job_log
+        WHERE job_id = $1 AND id > $2
+        ORDER BY id ASC
+        LIMIT $3
+        "#,
+    )
+    .bind(job_id)
+    .bind(after_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| JobLogRow {
+            id: r.get("id"),
+            ts: r.get("ts"),
+            line: r.get("line"),
+            level: r.get("level"),
+        })
+        .collect())
+}
+
+/// Finished jobs whose logs haven't been offloaded yet, oldest first. Jobs are left
+/// alone for a minute after finishing so late log lines still make it in.
+pub async fn jobs_pending_log_offload(pool: &PgPool, limit: i64) -> Result<Vec<i64>> {
+    let rows: Vec<(i64,)> = sqlx::query_as(
+        r#"
+        SELECT id FROM job
+        WHERE log_url IS NULL
+          AND status IN ('success', 'failed', 'cancelled')
+          AND finished_at < now() - interval '1 minute'
+        ORDER BY id
+        LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
+pub async fn set_job_log_url(pool: &PgPool, job_id: i64, url: &str) -> Result<()> {
+    sqlx::query("UPDATE job SET log_url = $2 WHERE id = $1")
+        .bind(job_id)
+        .bind(url)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Where a job's logs were offloaded to, if they were
+pub async fn get_job_log_url(pool: &PgPool, job_id: i64) -> Result<Option<String>> {
+    let row: Option<(Option<String>,)> = sqlx::query_as("SELECT log_url FROM job WHERE id = $1")
+        .bind(job_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.and_then(|(url,)| url))
+}
+
+/// Delete up to `limit` log rows of offloaded jobs that finished more than
+/// `retention_days` ago. Returns the number of rows deleted.
+pub async fn delete_offloaded_logs(pool: &PgPool, retention_days: u32, limit: i64) -> Result<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM job_log
+        WHERE id IN (
+            SELECT l.id FROM job_log l
+            JOIN job j ON j.id = l.job_id
+            WHERE j.log_url IS NOT NULL
+              AND j.finished_at < now() - make_interval(days => $1)
+            LIMIT $2
+        )
+        "#,
+    )
+    .bind(retention_days as i32)
+    .bind(limit)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Delete up to `limit` log rows of jobs that finished more than `retention_days` ago,
+/// whether or not they were offloaded. The job rows themselves are kept.
+pub async fn delete_expired_logs(pool: &PgPool, retention_days: u32, limit: i64) -> Result<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM job_log
+        WHERE id IN (
+            SELECT l.id FROM job_log l
+            JOIN job j ON j.id = l.job_id
+            WHERE j.status IN ('success', 'failed', 'cancelled')
+              AND j.finished_at < now() - make_interval(days => $1)
+            LIMIT $2
+        )
+        "#,
+    )
+    .bind(retention_days as i32)
+    .bind(limit)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Branch globs synced from the repo's foundry.toml; `None` if the repo is new or has
+/// never synced triggers, in which case the server's default branches apply
+pub async fn get_branch_triggers(pool: &PgPool, owner: &str, name: &str) -> Result<Option<Vec<String>>> {
+    let row: Option<(Option<Vec<String>>,)> = sqlx::query_as(
+        r#"
+        SELECT triggers_branches
+        FROM repo
+        WHERE owner = $1 AND name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.and_then(|(branches,)| branches))
+}
+
+/// Tag globs and whether published releases build, as last synced from foundry.toml.
+/// Both are off until a build syncs them.
+pub async fn get_tag_triggers(pool: &PgPool, owner: &str, name: &str) -> Result<(Vec<String>, bool)> {
+    let row: Option<(Vec<String>, bool)> = sqlx::query_as(
+        r#"
+        SELECT COALESCE(triggers_tags, '{}') as tags, triggers_releases
+        FROM repo
+        WHERE owner = $1 AND name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.unwrap_or_default())
+}
+
+/// Path filters last synced from the repo's foundry.toml, as (paths, paths_ignore)
+pub async fn get_path_filters(pool: &PgPool, owner: &str, name: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let row: Option<(Vec<String>, Vec<String>)> = sqlx::query_as(
+        r#"
+        SELECT
+            COALESCE(triggers_paths, '{}') as paths,
+            COALESCE(triggers_paths_ignore, '{}') as paths_ignore
+        FROM repo
+        WHERE owner = $1 AND name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.unwrap_or_default())
+}
+
+/// Check if a PR should trigger a build based on repo config
+pub async fn should_build_pr(pool: &PgPool, owner: &str, name: &str, target_branch: &str) -> Result<bool> {
+    let row: Option<(bool, Option<Vec<String>>)> = sqlx::query_as(
+        r#"
+        SELECT 
+            COALESCE(triggers_pull_requests, TRUE) as pr_enabled,
+            triggers_pr_target_branches
+        FROM repo
+        WHERE owner = $1 AND name = $2
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .fetch_optional(pool)
+    .await?;
+
+    match row {
+        Some((pr_enabled, target_branches)) => {
+            if !pr_enabled {
+                return Ok(false);
+            }
+            // If specific target branches are configured, check against them
+            if let Some(targets) = target_branches {
+                Ok(targets.iter().any(|b| b == target_branch))
+            } else {
+                Ok(true) // No filter, build all PRs
+            }
+        }
+        None => Ok(true), // Repo not in DB yet, default to building
+    }
 }
 
 /// Sync the foundry config triggers to the repo table
 pub async fn sync_repo_triggers(
     pool: &PgPool,
     repo_id: i64,
-    branches: &[String],
-    pull_requests: bool,
-    pr_target_branches: Option<&[String]>,
+    triggers: &TriggersConfig,
     config_json: Option<&serde_json::Value>,
 ) -> Result<()> {
     sqlx::query(
@@ -1102,18 +2727,995 @@ pub async fn sync_repo_triggers(
             triggers_branches = $2,
             triggers_pull_requests = $3,
             triggers_pr_target_branches = $4,
-            config_json = COALESCE($5, config_json),
+            triggers_paths = $5,
+            triggers_paths_ignore = $6,
+            triggers_tags = $8,
+            triggers_releases = $9,
+            config_json = COALESCE($7, config_json),
             updated_at = NOW()
         WHERE id = $1
         "#,
     )
     .bind(repo_id)
-    .bind(branches)
-    .bind(pull_requests)
-    .bind(pr_target_branches)
+    .bind(&triggers.branches)
+    .bind(triggers.pull_requests)
+    .bind(&triggers.pr_target_branches)
+    .bind(&triggers.paths)
+    .bind(&triggers.paths_ignore)
     .bind(config_json)
+    .bind(&triggers.tags)
+    .bind(triggers.releases)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn sync_repo_notify(pool: &PgPool, repo_id: i64, notify: &NotifyConfig) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE repo SET
+            notify_slack_webhook = $2,
+            notify_discord_webhook = $3,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(repo_id)
+    .bind(&notify.slack_webhook)
+    .bind(&notify.discord_webhook)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Store the repo's `[github] context`; agents report jobs claimed from now on under it
+pub async fn sync_repo_check_context(pool: &PgPool, repo_id: i64, context: Option<&str>) -> Result<()> {
+    sqlx::query(r#"UPDATE repo SET check_context = $2, updated_at = NOW() WHERE id = $1"#)
+        .bind(repo_id)
+        .bind(context)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Store the repo's `[concurrency]` settings; jobs queued from now on take its group
+pub async fn sync_repo_concurrency(
+    pool: &PgPool,
+    repo_id: i64,
+    group: Option<&str>,
+    cancel_in_progress: bool,
+) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE repo SET
+            concurrency_group = $2,
+            concurrency_cancel_in_progress = $3,
+            updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(repo_id)
+    .bind(group)
+    .bind(cancel_in_progress)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Record the `[build] runs_on` of the job holding `claim_token`. A root job's becomes
+/// the repo's, applied to its queued root jobs too. Returns false if the token matches
+/// no running job.
+pub async fn sync_job_runs_on(pool: &PgPool, claim_token: Uuid, runs_on: &[String]) -> Result<bool> {
+    let row: (bool,) = sqlx::query_as(
+        r#"
+        WITH synced AS (
+            UPDATE job SET runs_on = $2
+            WHERE claim_token = $1 AND status = 'running'
+            RETURNING repo_id, parent_job_id
+        ), repo_synced AS (
+            UPDATE repo SET runs_on = $2, updated_at = now()
+            WHERE id IN (SELECT repo_id FROM synced WHERE parent_job_id IS NULL)
+            RETURNING id
+        ), queued AS (
+            UPDATE job SET runs_on = $2
+            WHERE repo_id IN (SELECT id FROM repo_synced) AND status = 'queued' AND parent_job_id IS NULL
+        )
+        SELECT EXISTS (SELECT 1 FROM synced)
+        "#,
+    )
+    .bind(claim_token)
+    .bind(runs_on)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.0)
+}
+
+/// Webhooks of the repo `job_id` belongs to, as last synced from its foundry.toml
+pub async fn get_job_notify(pool: &PgPool, job_id: i64) -> Result<Option<NotifyConfig>> {
+    let row = sqlx::query(
+        r#"
+        SELECT r.notify_slack_webhook, r.notify_discord_webhook
+        FROM job j
+        JOIN repo r ON r.id = j.repo_id
+        WHERE j.id = $1
+        "#,
+    )
+    .bind(job_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| NotifyConfig {
+        slack_webhook: r.get("notify_slack_webhook"),
+        discord_webhook: r.get("notify_discord_webhook"),
+    }))
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Artifact {
+    pub id: i64,
+    /// Left out when only public ids are exposed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<i64>,
+    pub name: String,
+    pub size: i64,
+    pub content_type: String,
+    pub created_at: String,
+}
+
+fn artifact_from_row(r: &sqlx::postgres::PgRow) -> Artifact {
+    Artifact {
+        id: r.get("id"),
+        job_id: Some(r.get("job_id")),
+        name: r.get("name"),
+        size: r.get("size"),
+        content_type: r.get("content_type"),
+        created_at: r.get("created_at"),
+    }
+}
+
+/// Record an uploaded artifact. Returns `None` unless the job is running under `claim_token`.
+pub async fn create_artifact(
+    pool: &PgPool,
+    job_id: i64,
+    claim_token: Uuid,
+    name: &str,
+    size: i64,
+    content_type: &str,
+) -> Result<Option<i64>> {
+    let id: Option<i64> = sqlx::query_scalar(
+        r#"
+        INSERT INTO artifact (job_id, name, size, content_type)
+        SELECT $1, $3, $4, $5
+        WHERE EXISTS (
+            SELECT 1 FROM job
+            WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        )
+        RETURNING id
+        "#,
+    )
+    .bind(job_id)
+    .bind(claim_token)
+    .bind(name)
+    .bind(size)
+    .bind(content_type)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(id)
+}
+
+pub async fn list_artifacts(pool: &PgPool, job_id: i64) -> Result<Vec<Artifact>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, job_id, name, size, content_type,
+            to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at
+        FROM artifact
+        WHERE job_id = $1
+        ORDER BY name ASC, id ASC
+        "#,
+    )
+    .bind(job_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(artifact_from_row).collect())
+}
+
+pub async fn get_artifact(pool: &PgPool, id: i64) -> Result<Option<Artifact>> {
+    let row = sqlx::query(
+        r#"
+        SELECT id, job_id, name, size, content_type,
+            to_char(created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at
+        FROM artifact
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(artifact_from_row))
+}
+
+/// Store an encrypted secret, replacing any existing value with the same name.
+/// Returns false if the repo doesn't exist.
+pub async fn upsert_repo_secret(pool: &PgPool, repo_id: i64, name: &str, sealed: &[u8]) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO repo_secret (repo_id, name, value)
+        SELECT id, $2, $3 FROM repo WHERE id = $1
+        ON CONFLICT (repo_id, name) DO UPDATE
+        SET value = EXCLUDED.value, updated_at = NOW()
+        "#,
+    )
+    .bind(repo_id)
+    .bind(name)
+    .bind(sealed)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Names of a repo's secrets, for the dashboard. Values are never returned.
+pub async fn list_repo_secret_names(pool: &PgPool, repo_id: i64) -> Result<Vec<String>> {
+    let rows: Vec<(String,)> = sqlx::query_as(
+        r#"SELECT name FROM repo_secret WHERE repo_id = $1 ORDER BY name"#,
+    )
+    .bind(repo_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(|(name,)| name).collect())
+}
+
+/// A repo's encrypted secrets as (name, nonce + ciphertext)
+pub async fn get_repo_secrets(pool: &PgPool, repo_id: i64) -> Result<Vec<(String, Vec<u8>)>> {
+    let rows = sqlx::query_as(
+        r#"SELECT name, value FROM repo_secret WHERE repo_id = $1"#,
+    )
+    .bind(repo_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Note that an agent polled for work. Returns true if it is new or now reports
+/// a different build than last time.
+pub async fn record_agent(
+    pool: &PgPool,
+    agent_id: &str,
+    version: Option<&str>,
+    protocol_version: Option<i32>,
+    docker_error: Option<&str>,
+    arch: Option<&str>,
+    labels: &[String],
+) -> Result<bool> {
+    let row: (bool,) = sqlx::query_as(
+        r#"
+        WITH previous AS (
+            SELECT version, protocol_version FROM agent WHERE id = $1
+        ), upserted AS (
+            INSERT INTO agent (id, version, protocol_version, docker_error, arch, labels)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (id) DO UPDATE
+            SET version = EXCLUDED.version,
+                protocol_version = EXCLUDED.protocol_version,
+                docker_error = EXCLUDED.docker_error,
+                arch = EXCLUDED.arch,
+                labels = EXCLUDED.labels,
+                last_seen_at = now()
+        )
+        SELECT NOT EXISTS (
+            SELECT 1 FROM previous
+            WHERE version IS NOT DISTINCT FROM $2 AND protocol_version IS NOT DISTINCT FROM $3
+        )
+        "#,
+    )
+    .bind(agent_id)
+    .bind(version)
+    .bind(protocol_version)
+    .bind(docker_error)
+    .bind(arch)
+    .bind(labels)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(row.0)
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct AgentSummary {
+    pub id: String,
+    pub version: Option<String>,
+    pub protocol_version: Option<i32>,
+    pub first_seen_at: String,
+    pub last_seen_at: String,
+    /// Set while the agent can't reach Docker
+    pub docker_error: Option<String>,
+    pub arch: Option<String>,
+    pub labels: Vec<String>,
+}
+
+pub async fn list_agents(pool: &PgPool) -> Result<Vec<AgentSummary>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, version, protocol_version, docker_error, arch, labels,
+            to_char(first_seen_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as first_seen_at,
+            to_char(last_seen_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as last_seen_at
+        FROM agent
+        ORDER BY last_seen_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| AgentSummary {
+            id: r.get("id"),
+            version: r.get("version"),
+            protocol_version: r.get("protocol_version"),
+            first_seen_at: r.get("first_seen_at"),
+            last_seen_at: r.get("last_seen_at"),
+            docker_error: r.get("docker_error"),
+            arch: r.get("arch"),
+            labels: r.get("labels"),
+        })
+        .collect())
+}
+
+/// Whether an agent with this id has ever polled the server
+pub async fn agent_exists(pool: &PgPool, agent_id: &str) -> Result<bool> {
+    let row: (bool,) = sqlx::query_as("SELECT EXISTS (SELECT 1 FROM agent WHERE id = $1)")
+        .bind(agent_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.0)
+}
+
+/// Record a `docker run` deploy. Returns `false` unless the job is running under `claim_token`.
+pub async fn record_deployment(pool: &PgPool, req: &DeploymentRequest) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO deployment (app_name, repo_id, job_id, image, run_args, command)
+        SELECT $3, repo_id, id, $4, $5, $6
+        FROM job
+        WHERE id = $1 AND claim_token = $2 AND status = 'running'
+        "#,
+    )
+    .bind(req.job_id)
+    .bind(req.claim_token)
+    .bind(&req.app_name)
+    .bind(&req.image)
+    .bind(serde_json::to_value(&req.args)?)
+    .bind(serde_json::to_value(&req.command)?)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record a PR preview deploy. Returns `false` unless the job is a running PR build
+/// under `claim_token`.
+pub async fn record_preview(pool: &PgPool, req: &PreviewRequest) -> Result<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO preview (repo_id, pr_number, app_name, hostname)
+        SELECT repo_id, pr_number, $3, $4
+        FROM job
+        WHERE id = $1 AND claim_token = $2 AND status = 'running' AND pr_number IS NOT NULL
+        ON CONFLICT (repo_id, pr_number, app_name) DO UPDATE SET hostname = EXCLUDED.hostname, created_at = now()
+        "#,
+    )
+    .bind(req.job_id)
+    .bind(req.claim_token)
+    .bind(&req.app_name)
+    .bind(&req.hostname)
     .execute(pool)
     .await?;
 
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    pub app_name: String,
+    pub hostname: String,
+}
+
+/// Forget a closed PR's previews and their deployment history, so they can't be
+/// rolled back to, returning them so they can be torn down
+pub async fn take_previews(pool: &PgPool, owner: &str, name: &str, pr_number: i64) -> Result<Vec<Preview>> {
+    let rows = sqlx::query(
+        r#"
+        WITH taken AS (
+            DELETE FROM preview p
+            USING repo r
+            WHERE p.repo_id = r.id AND r.owner = $1 AND r.name = $2 AND p.pr_number = $3
+            RETURNING p.repo_id, p.app_name, p.hostname
+        ),
+        forgotten AS (
+            DELETE FROM deployment d
+            USING taken t
+            WHERE d.repo_id = t.repo_id AND d.app_name = t.app_name
+        )
+        SELECT app_name, hostname FROM taken
+        "#,
+    )
+    .bind(owner)
+    .bind(name)
+    .bind(pr_number)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| Preview {
+            app_name: r.get("app_name"),
+            hostname: r.get("hostname"),
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Deployment {
+    pub id: i64,
+    pub app_name: String,
+    /// Left out when only public ids are exposed, as well as when there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<i64>,
+    pub job_public_id: Option<String>,
+    pub image: String,
+    pub args: Vec<String>,
+    pub command: Vec<String>,
+    pub created_at: String,
+    pub rolled_back_at: Option<String>,
+}
+
+fn deployment_from_row(r: &sqlx::postgres::PgRow) -> Deployment {
+    Deployment {
+        id: r.get("id"),
+        app_name: r.get("app_name"),
+        job_id: r.get("job_id"),
+        job_public_id: r.get("job_public_id"),
+        image: r.get("image"),
+        args: serde_json::from_value(r.get("run_args")).unwrap_or_default(),
+        command: serde_json::from_value(r.get("command")).unwrap_or_default(),
+        created_at: r.get("created_at"),
+        rolled_back_at: r.get("rolled_back_at"),
+    }
+}
+
+/// A repo's deploys, newest first
+pub async fn list_deployments(pool: &PgPool, repo_id: i64, limit: i64) -> Result<Vec<Deployment>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT d.id, d.app_name, d.job_id, j.public_id as job_public_id, d.image, d.run_args, d.command,
+            to_char(d.created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at,
+            to_char(d.rolled_back_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as rolled_back_at
+        FROM deployment d
+        LEFT JOIN job j ON j.id = d.job_id
+        WHERE d.repo_id = $1
+        ORDER BY d.id DESC
+        LIMIT $2
+        "#,
+    )
+    .bind(repo_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(deployment_from_row).collect())
+}
+
+/// The app's live deployment and the one before it, skipping deployments that were
+/// already rolled back so repeated rollbacks keep walking back through history
+pub async fn get_rollback_deployments(
+    pool: &PgPool,
+    app_name: &str,
+) -> Result<(Option<Deployment>, Option<Deployment>)> {
+    let rows = sqlx::query(
+        r#"
+        SELECT d.id, d.app_name, d.job_id, j.public_id as job_public_id, d.image, d.run_args, d.command,
+            to_char(d.created_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as created_at,
+            to_char(d.rolled_back_at, 'YYYY-MM-DD"T"HH24:MI:SS"Z"') as rolled_back_at
+        FROM deployment d
+        LEFT JOIN job j ON j.id = d.job_id
+        WHERE d.app_name = $1 AND d.rolled_back_at IS NULL
+        ORDER BY d.id DESC
+        LIMIT 2
+        "#,
+    )
+    .bind(app_name)
+    .fetch_all(pool)
+    .await?;
+
+    let mut deployments = rows.iter().map(deployment_from_row);
+    Ok((deployments.next(), deployments.next()))
+}
+
+pub async fn mark_deployment_rolled_back(pool: &PgPool, id: i64) -> Result<()> {
+    sqlx::query("UPDATE deployment SET rolled_back_at = now() WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?;
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acknowledged_failure_raises_success_rate() {
+        // 3 successes, 1 failure
+        assert_eq!(success_rate(3, 1), 75.0);
+        // Same jobs once the failure is acknowledged
+        assert_eq!(success_rate(3, 0), 100.0);
+    }
+
+    #[test]
+    fn test_recent_builds_newest_first() {
+        let statuses = vec!["running".to_string(), "failed".to_string(), "success".to_string()];
+        let ids = vec!["newest".to_string(), "middle".to_string(), "oldest".to_string()];
+
+        let (statuses, ids) = recent_builds(statuses, ids);
+        assert_eq!(statuses, vec![JobStatus::Running, JobStatus::Failed, JobStatus::Success]);
+        assert_eq!(ids, vec!["newest", "middle", "oldest"]);
+    }
+
+    #[test]
+    fn test_recent_builds_keeps_ids_aligned() {
+        let statuses = vec!["success".to_string(), "bogus".to_string(), "failed".to_string()];
+        let ids = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        let (statuses, ids) = recent_builds(statuses, ids);
+        assert_eq!(statuses, vec![JobStatus::Success, JobStatus::Failed]);
+        assert_eq!(ids, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_change_percent() {
+        assert_eq!(change_percent(Some(100), Some(150)), Some(50.0));
+        assert_eq!(change_percent(Some(300), Some(200)), Some(-33.3));
+        assert_eq!(change_percent(None, Some(200)), None);
+        assert_eq!(change_percent(Some(0), Some(200)), None);
+    }
+
+    #[test]
+    fn test_success_rate_without_finished_jobs() {
+        assert_eq!(success_rate(0, 0), 0.0);
+    }
+
+    /// Pool for tests that need Postgres, from `FOUNDRY_TEST_DATABASE_URL`. These
+    /// tests pass without running when it isn't set.
+    pub(crate) async fn test_pool() -> Option<PgPool> {
+        let url = std::env::var("FOUNDRY_TEST_DATABASE_URL").ok()?;
+        let pool = PgPool::connect(&url).await.expect("connect to FOUNDRY_TEST_DATABASE_URL");
+        sqlx::migrate!("../../migrations").run(&pool).await.expect("run migrations");
+        Some(pool)
+    }
+
+    /// A repo under `owner` with a unique name
+    pub(crate) async fn insert_test_repo(pool: &PgPool, owner: &str) -> i64 {
+        let (repo_id,): (i64,) = sqlx::query_as(
+            "INSERT INTO repo (owner, name, clone_url) VALUES ($1, $2, 'https://example.com/repo.git') RETURNING id",
+        )
+        .bind(owner)
+        .bind(Uuid::new_v4().to_string())
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        repo_id
+    }
+
+    /// Columns a test job sets; the rest keep the table defaults, on `refs/heads/main`
+    #[derive(Default)]
+    pub(crate) struct TestJob<'a> {
+        pub git_ref: Option<&'a str>,
+        pub status: Option<&'a str>,
+        pub trigger_type: Option<&'a str>,
+        pub target_agent: Option<&'a str>,
+        pub priority: Option<i32>,
+        pub pr_number: Option<i64>,
+        pub claim_token: Option<Uuid>,
+        pub finished_days_ago: Option<i32>,
+    }
+
+    pub(crate) async fn insert_test_job(pool: &PgPool, repo_id: i64, job: TestJob<'_>) -> i64 {
+        let (job_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO job (repo_id, git_sha, git_ref, status, trigger_type, target_agent, priority, pr_number, claim_token, finished_at)
+            VALUES (
+                $1, 'abc123', COALESCE($2, 'refs/heads/main'), COALESCE($3::job_status, 'queued'),
+                COALESCE($4::trigger_type, 'push'), $5, COALESCE($6, 0), $7, $8, now() - make_interval(days => $9)
+            )
+            RETURNING id
+            "#,
+        )
+        .bind(repo_id)
+        .bind(job.git_ref)
+        .bind(job.status)
+        .bind(job.trigger_type)
+        .bind(job.target_agent)
+        .bind(job.priority)
+        .bind(job.pr_number)
+        .bind(job.claim_token)
+        .bind(job.finished_days_ago)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+        job_id
+    }
+
+    #[tokio::test]
+    async fn test_pinned_job_claimed_only_by_target_agent() {
+        let Some(pool) = test_pool().await else { return };
+        let target = format!("target-{}", Uuid::new_v4());
+        let other = format!("other-{}", Uuid::new_v4());
+
+        let repo_id = insert_test_repo(&pool, "pin-test").await;
+        let job_id = insert_test_job(
+            &pool,
+            repo_id,
+            TestJob { target_agent: Some(&target), priority: Some(1000), ..Default::default() },
+        )
+        .await;
+
+        // Other agents drain the rest of the queue without ever getting the pinned job
+        while let Some(job) = claim_job(&pool, &other, &[], None).await.unwrap() {
+            assert_ne!(job.id, job_id);
+        }
+
+        let claimed = claim_job(&pool, &target, &[], None).await.unwrap().expect("pinned job claimed");
+        assert_eq!(claimed.id, job_id);
+    }
+
+    #[tokio::test]
+    async fn test_claim_job_by_id() {
+        let Some(pool) = test_pool().await else { return };
+        let agent = format!("once-{}", Uuid::new_v4());
+        let repo_id = insert_test_repo(&pool, "once-test").await;
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            ids.push(insert_test_job(&pool, repo_id, TestJob { target_agent: Some(&agent), ..Default::default() }).await);
+        }
+
+        // The later job is claimed first when asked for by id
+        let claimed = claim_job(&pool, &agent, &[], Some(ids[1])).await.unwrap().expect("job claimed");
+        assert_eq!(claimed.id, ids[1]);
+        assert!(claim_job(&pool, &agent, &[], Some(ids[1])).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_group_runs_one_job_at_a_time() {
+        let Some(pool) = test_pool().await else { return };
+        // Pinned so other tests draining the queue leave these jobs alone
+        let agent = format!("group-{}", Uuid::new_v4());
+        let group = format!("deploy-{}", Uuid::new_v4());
+        let repo_id = insert_test_repo(&pool, "group-test").await;
+        sync_repo_concurrency(&pool, repo_id, Some(&group), false).await.unwrap();
+        let job = || insert_test_job(&pool, repo_id, TestJob { target_agent: Some(&agent), ..Default::default() });
+        let drain = || async {
+            let mut claimed = Vec::new();
+            while let Some(job) = claim_job(&pool, &agent, &[], None).await.unwrap() {
+                claimed.push(job.id);
+            }
+            claimed
+        };
+
+        let first = job().await;
+        let second = job().await;
+        let (first_group,): (Option<String>,) = sqlx::query_as("SELECT concurrency_group FROM job WHERE id = $1")
+            .bind(first)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(first_group.as_deref(), Some(group.as_str()));
+
+        let claimed = drain().await;
+        assert!(claimed.contains(&first));
+        assert!(!claimed.contains(&second));
+
+        sqlx::query("UPDATE job SET status = 'success', finished_at = now() WHERE id = $1")
+            .bind(first)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(drain().await.contains(&second));
+
+        // A new job takes over the group instead of waiting
+        sync_repo_concurrency(&pool, repo_id, Some(&group), true).await.unwrap();
+        let third = job().await;
+        assert_eq!(get_job_status(&pool, second).await.unwrap(), Some(JobStatus::Cancelled));
+        assert_eq!(get_job_status(&pool, first).await.unwrap(), Some(JobStatus::Success));
+        assert!(drain().await.contains(&third));
+    }
+
+    #[tokio::test]
+    async fn test_claimed_job_has_check_context() {
+        let Some(pool) = test_pool().await else { return };
+        let agent = format!("context-{}", Uuid::new_v4());
+        let repo_id = insert_test_repo(&pool, "context-test").await;
+        sync_repo_check_context(&pool, repo_id, Some("foundry/test")).await.unwrap();
+        let job_id = insert_test_job(&pool, repo_id, TestJob { target_agent: Some(&agent), ..Default::default() }).await;
+
+        let claimed = loop {
+            let job = claim_job(&pool, &agent, &[], None).await.unwrap().expect("job claimed");
+            if job.id == job_id {
+                break job;
+            }
+        };
+        assert_eq!(claimed.check_context.as_deref(), Some("foundry/test"));
+    }
+
+    #[tokio::test]
+    async fn test_runs_on_needs_agent_labels() {
+        let Some(pool) = test_pool().await else { return };
+        // Pinned so other tests draining the queue leave these jobs alone
+        let agent = format!("labels-{}", Uuid::new_v4());
+        let gpu = vec![format!("gpu-{}", Uuid::new_v4())];
+        let repo_id = insert_test_repo(&pool, "labels-test").await;
+        let job = || insert_test_job(&pool, repo_id, TestJob { target_agent: Some(&agent), ..Default::default() });
+        let drain = |labels: Vec<String>| {
+            let (pool, agent) = (pool.clone(), agent.clone());
+            async move {
+                let mut claimed = Vec::new();
+                while let Some(job) = claim_job(&pool, &agent, &labels, None).await.unwrap() {
+                    claimed.push(job);
+                }
+                claimed
+            }
+        };
+
+        // The first build runs anywhere and brings back the repo's requirement
+        let first = job().await;
+        let queued = job().await;
+        let claimed = drain(Vec::new()).await;
+        let running = claimed.iter().find(|j| j.id == first).expect("first job claimed");
+        assert!(claimed.iter().any(|j| j.id == queued));
+        release_job(&pool, queued, claimed.iter().find(|j| j.id == queued).unwrap().claim_token, "test")
+            .await
+            .unwrap();
+        assert!(sync_job_runs_on(&pool, running.claim_token, &gpu).await.unwrap());
+        assert!(!sync_job_runs_on(&pool, Uuid::new_v4(), &gpu).await.unwrap());
+
+        let later = job().await;
+        let (runs_on,): (Vec<String>,) = sqlx::query_as("SELECT runs_on FROM job WHERE id = $1")
+            .bind(later)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(runs_on, gpu);
+
+        let unlabelled: Vec<i64> = drain(Vec::new()).await.iter().map(|j| j.id).collect();
+        assert!(!unlabelled.contains(&later));
+        let mut labels = gpu.clone();
+        labels.push("big-disk".to_string());
+        let labelled: Vec<i64> = drain(labels).await.iter().map(|j| j.id).collect();
+        assert!(labelled.contains(&later));
+        assert!(labelled.contains(&queued), "queued jobs take the synced requirement");
+    }
+
+    #[tokio::test]
+    async fn test_log_truncated_at_limit() {
+        let Some(pool) = test_pool().await else { return };
+        let token = Uuid::new_v4();
+        let repo_id = insert_test_repo(&pool, "log-test").await;
+        let job_id = insert_test_job(
+            &pool,
+            repo_id,
+            TestJob { status: Some("running"), claim_token: Some(token), ..Default::default() },
+        )
+        .await;
+
+        for line in ["0123456789", "abcdefghij", "over the limit", "dropped"] {
+            assert!(append_log(&pool, job_id, token, line, LogLevel::Info, 20).await.unwrap());
+        }
+        assert!(!append_log(&pool, job_id, Uuid::new_v4(), "wrong token", LogLevel::Info, 20).await.unwrap());
+
+        let lines: Vec<(String, String)> =
+            sqlx::query_as("SELECT line, level::text FROM job_log WHERE job_id = $1 ORDER BY id")
+                .bind(job_id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].0, "abcdefghij");
+        assert!(lines[2].0.contains("Log truncated"), "{}", lines[2].0);
+        assert_eq!(lines[2].1, "warn");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_outdated_jobs() {
+        let Some(pool) = test_pool().await else { return };
+        let repo_id = insert_test_repo(&pool, "cancel-test").await;
+        let job = |git_ref: &'static str, status: &'static str, trigger: &'static str| {
+            let job = TestJob { git_ref: Some(git_ref), status: Some(status), trigger_type: Some(trigger), ..Default::default() };
+            insert_test_job(&pool, repo_id, job)
+        };
+        let running = job("refs/heads/main", "running", "push").await;
+        let queued = job("refs/heads/main", "queued", "push").await;
+        let scheduled = job("refs/heads/main", "queued", "scheduled").await;
+        let other_branch = job("refs/heads/dev", "queued", "push").await;
+        let newest = job("refs/heads/main", "queued", "push").await;
+
+        // Off by default
+        assert!(cancel_outdated_jobs(&pool, repo_id, "refs/heads/main", newest).await.unwrap().is_empty());
+
+        set_repo_cancel_outdated(&pool, repo_id, CancelOutdated::Queued).await.unwrap();
+        assert_eq!(cancel_outdated_jobs(&pool, repo_id, "refs/heads/main", newest).await.unwrap(), [queued]);
+
+        set_repo_cancel_outdated(&pool, repo_id, CancelOutdated::Running).await.unwrap();
+        assert_eq!(cancel_outdated_jobs(&pool, repo_id, "refs/heads/main", newest).await.unwrap(), [running]);
+
+        for (id, status) in [(scheduled, JobStatus::Queued), (other_branch, JobStatus::Queued), (newest, JobStatus::Queued)] {
+            assert_eq!(get_job_status(&pool, id).await.unwrap(), Some(status));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replayed_push_keeps_newer_jobs() {
+        let Some(pool) = test_pool().await else { return };
+        let repo_id = insert_test_repo(&pool, "replay-cancel-test").await;
+        set_repo_cancel_outdated(&pool, repo_id, CancelOutdated::Queued).await.unwrap();
+        let event_id = store_webhook_event(&pool, "push", Some("delivery-old"), br#"{"ref": "refs/heads/main"}"#, None)
+            .await
+            .unwrap();
+        let newer = insert_test_job(&pool, repo_id, TestJob::default()).await;
+
+        // Replaying the old delivery queues its commit after the newer build
+        let replayed = insert_test_job(&pool, repo_id, TestJob::default()).await;
+        mark_replayed(&pool, event_id, replayed).await.unwrap();
+        assert!(cancel_outdated_jobs(&pool, repo_id, "refs/heads/main", replayed).await.unwrap().is_empty());
+        assert_eq!(get_job_status(&pool, newer).await.unwrap(), Some(JobStatus::Queued));
+    }
+
+    #[tokio::test]
+    async fn test_closed_pr_previews() {
+        let Some(pool) = test_pool().await else { return };
+        let repo_id = insert_test_repo(&pool, "preview-test").await;
+        let (name,): (String,) = sqlx::query_as("SELECT name FROM repo WHERE id = $1")
+            .bind(repo_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let claim_token = Uuid::new_v4();
+        let job = |git_ref: &'static str, pr_number: Option<i64>| {
+            let job = TestJob {
+                git_ref: Some(git_ref),
+                status: Some("running"),
+                pr_number,
+                claim_token: Some(claim_token),
+                ..Default::default()
+            };
+            insert_test_job(&pool, repo_id, job)
+        };
+        let pr = job("refs/pull/12/head", Some(12)).await;
+        let push = job("refs/heads/main", None).await;
+        let preview = |job_id: i64| PreviewRequest {
+            job_id,
+            claim_token,
+            app_name: "app-pr-12".to_string(),
+            hostname: "pr-12.example.com".to_string(),
+        };
+
+        assert!(!record_preview(&pool, &preview(push)).await.unwrap());
+        assert!(record_preview(&pool, &preview(pr)).await.unwrap());
+        // A second deploy of the same PR replaces the record
+        assert!(record_preview(&pool, &preview(pr)).await.unwrap());
+        let deployment = DeploymentRequest {
+            job_id: pr,
+            claim_token,
+            app_name: "app-pr-12".to_string(),
+            image: "app:pr-12".to_string(),
+            args: Vec::new(),
+            command: Vec::new(),
+        };
+        assert!(record_deployment(&pool, &deployment).await.unwrap());
+
+        // Closing the PR cancels its unfinished builds
+        assert_eq!(cancel_pr_jobs(&pool, "preview-test", &name, 12).await.unwrap(), [pr]);
+        assert_eq!(get_job_status(&pool, pr).await.unwrap(), Some(JobStatus::Cancelled));
+        assert_eq!(get_job_status(&pool, push).await.unwrap(), Some(JobStatus::Running));
+
+        assert!(take_previews(&pool, "preview-test", &name, 13).await.unwrap().is_empty());
+        let taken = take_previews(&pool, "preview-test", &name, 12).await.unwrap();
+        assert_eq!(
+            taken,
+            [Preview { app_name: "app-pr-12".to_string(), hostname: "pr-12.example.com".to_string() }]
+        );
+        assert!(take_previews(&pool, "preview-test", &name, 12).await.unwrap().is_empty());
+        let (deployments,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM deployment WHERE repo_id = $1")
+            .bind(repo_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(deployments, 0);
+    }
+
+    #[tokio::test]
+    async fn test_delete_expired_logs() {
+        let Some(pool) = test_pool().await else { return };
+        let repo_id = insert_test_repo(&pool, "log-retention-test").await;
+        let job = |status: &'static str, finished_days_ago: Option<i32>| {
+            let pool = pool.clone();
+            async move {
+                let id = insert_test_job(
+                    &pool,
+                    repo_id,
+                    TestJob { status: Some(status), finished_days_ago, ..Default::default() },
+                )
+                .await;
+                sqlx::query("INSERT INTO job_log (job_id, line, level) SELECT $1, 'line', 'info'::log_level FROM generate_series(1, 3)")
+                    .bind(id)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+                id
+            }
+        };
+        let expired = job("failed", Some(40)).await;
+        let recent = job("success", Some(5)).await;
+        let running = job("running", None).await;
+
+        while delete_expired_logs(&pool, 30, 2).await.unwrap() == 2 {}
+
+        for (id, lines) in [(expired, 0), (recent, 3), (running, 3)] {
+            let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM job_log WHERE job_id = $1")
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(count, lines);
+        }
+        assert_eq!(get_job_status(&pool, expired).await.unwrap(), Some(JobStatus::Failed));
+    }
+
+    #[tokio::test]
+    async fn test_mark_replayed() {
+        let Some(pool) = test_pool().await else { return };
+        let repo_id = insert_test_repo(&pool, "replay-test").await;
+        let job_id = insert_test_job(&pool, repo_id, TestJob::default()).await;
+        let event_id = store_webhook_event(&pool, "push", Some("delivery-1"), br#"{"ref": "refs/heads/main"}"#, None)
+            .await
+            .unwrap();
+
+        let (event_type, payload) = get_webhook_event(&pool, event_id).await.unwrap().unwrap();
+        assert_eq!(event_type, "push");
+        assert_eq!(payload["ref"], "refs/heads/main");
+        assert!(get_webhook_event(&pool, -1).await.unwrap().is_none());
+
+        let events = list_webhook_events(&pool, Some("push"), 1000, 0).await.unwrap();
+        let listed = events.iter().find(|e| e.id == event_id).unwrap();
+        assert_eq!(listed.delivery_id.as_deref(), Some("delivery-1"));
+        assert!(!listed.processed);
+        assert!(events.iter().all(|e| e.event_type == "push"));
+        assert!(count_webhook_events(&pool, Some("push")).await.unwrap() >= 1);
+
+        mark_replayed(&pool, event_id, job_id).await.unwrap();
+        let (replayed,): (bool,) = sqlx::query_as("SELECT replayed FROM job WHERE id = $1")
+            .bind(job_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(replayed);
+        let (linked_job, processed, replayed_at): (Option<i64>, Option<bool>, Option<chrono::DateTime<chrono::Utc>>) =
+            sqlx::query_as("SELECT job_id, processed, replayed_at FROM webhook_event WHERE id = $1")
+                .bind(event_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(linked_job, Some(job_id));
+        assert_eq!(processed, Some(true));
+        assert!(replayed_at.is_some());
+    }
+}
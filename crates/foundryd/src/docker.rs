@@ -9,6 +9,7 @@ use std::process::Stdio;
 use tokio::process::Command;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc;
+use tracing::warn;
 
 /// Information about a Docker container
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +22,25 @@ pub struct ContainerInfo {
     pub created: String,
     pub ports: String,
     pub project: Option<String>,
+    /// `owner/name` from the `foundry.repo` label on containers the agent started
+    pub repo: Option<String>,
+    /// `build` or `deploy`, from the `foundry.kind` label
+    pub kind: Option<String>,
+    /// Job that started the container, from the `foundry.job` label. Left out when there
+    /// is none or only public ids are exposed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<i64>,
+}
+
+/// Narrows `list_containers`; every field that is set must match
+#[derive(Debug, Default)]
+pub struct ContainerFilter<'a> {
+    /// Compose project
+    pub project: Option<&'a str>,
+    /// `owner/name`; matches containers deployed with `docker run`, which have no project
+    pub repo: Option<&'a str>,
+    /// Only containers Foundry started, labelled `foundry.managed=true`
+    pub managed: bool,
 }
 
 /// Container logs response
@@ -30,9 +50,9 @@ pub struct ContainerLogs {
     pub logs: Vec<String>,
 }
 
-/// List all running containers, optionally filtered by project name
-pub async fn list_containers(project_filter: Option<&str>) -> Result<Vec<ContainerInfo>> {
-    let format = r#"{{.ID}}\t{{.Names}}\t{{.Image}}\t{{.Status}}\t{{.State}}\t{{.CreatedAt}}\t{{.Ports}}\t{{index .Labels "com.docker.compose.project"}}"#;
+/// List all containers, optionally filtered by compose project and/or Foundry labels
+pub async fn list_containers(filter: &ContainerFilter<'_>) -> Result<Vec<ContainerInfo>> {
+    let format = r#"{{.ID}}\t{{.Names}}\t{{.Image}}\t{{.Status}}\t{{.State}}\t{{.CreatedAt}}\t{{.Ports}}\t{{index .Labels "com.docker.compose.project"}}\t{{index .Labels "foundry.repo"}}\t{{index .Labels "foundry.managed"}}\t{{index .Labels "foundry.kind"}}\t{{index .Labels "foundry.job"}}"#;
     
     let output = Command::new("docker")
         .args(["ps", "-a", "--format", format])
@@ -46,22 +66,29 @@ pub async fn list_containers(project_filter: Option<&str>) -> Result<Vec<Contain
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(parse_containers(&stdout, filter))
+}
+
+/// Parse `docker ps` lines in the format used by `list_containers`
+fn parse_containers(stdout: &str, filter: &ContainerFilter<'_>) -> Vec<ContainerInfo> {
     let mut containers = Vec::new();
 
     for line in stdout.lines() {
         let parts: Vec<&str> = line.split('\t').collect();
         if parts.len() >= 7 {
-            let project = if parts.len() > 7 && !parts[7].is_empty() {
-                Some(parts[7].to_string())
-            } else {
-                None
-            };
+            let label = |i: usize| parts.get(i).filter(|v| !v.is_empty()).map(|v| v.to_string());
+            let project = label(7);
+            let repo = label(8);
 
-            // Apply project filter if specified
-            if let Some(filter) = project_filter {
-                if project.as_deref() != Some(filter) {
-                    continue;
-                }
+            // Apply filters if specified
+            if filter.project.is_some_and(|filter| project.as_deref() != Some(filter)) {
+                continue;
+            }
+            if filter.repo.is_some_and(|filter| repo.as_deref() != Some(filter)) {
+                continue;
+            }
+            if filter.managed && label(9).as_deref() != Some("true") {
+                continue;
             }
 
             containers.push(ContainerInfo {
@@ -73,11 +100,14 @@ pub async fn list_containers(project_filter: Option<&str>) -> Result<Vec<Contain
                 created: parts[5].to_string(),
                 ports: parts[6].to_string(),
                 project,
+                repo,
+                kind: label(10),
+                job_id: label(11).and_then(|id| id.parse().ok()),
             });
         }
     }
 
-    Ok(containers)
+    containers
 }
 
 /// Get logs from a specific container
@@ -114,13 +144,12 @@ pub async fn get_container_logs(container_id: &str, lines: Option<u32>) -> Resul
     })
 }
 
-/// Stream logs from a container (returns a channel for live updates)
+/// Stream logs from a container (returns a channel for live updates). Dropping the
+/// receiver kills the underlying `docker logs -f`.
 pub async fn stream_container_logs(
     container_id: &str,
     lines: Option<u32>,
 ) -> Result<mpsc::Receiver<String>> {
-    let (tx, rx) = mpsc::channel(100);
-    
     let mut args = vec!["logs", "-f", "--timestamps"];
     
     let tail_str;
@@ -132,16 +161,36 @@ pub async fn stream_container_logs(
     
     args.push(container_id);
 
-    let mut child = Command::new("docker")
-        .args(&args)
+    let mut cmd = Command::new("docker");
+    cmd.args(&args);
+    stream_lines(cmd).context("Failed to spawn docker logs")
+}
+
+/// Spawn `cmd` and forward its stdout and stderr lines. The child is killed as soon as
+/// the receiver is dropped rather than when it next writes a line.
+fn stream_lines(mut cmd: Command) -> Result<mpsc::Receiver<String>> {
+    let (tx, rx) = mpsc::channel(100);
+
+    let mut child = cmd
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to spawn docker logs")?;
+        .kill_on_drop(true)
+        .spawn()?;
 
     let stdout = child.stdout.take().expect("stdout not captured");
     let stderr = child.stderr.take().expect("stderr not captured");
 
+    // Reap the child, killing it once nobody is listening
+    let watch_tx = tx.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = child.wait() => {}
+            _ = watch_tx.closed() => {
+                let _ = child.kill().await;
+            }
+        }
+    });
+
     // Spawn task to read stdout
     let tx_clone = tx.clone();
     tokio::spawn(async move {
@@ -216,6 +265,49 @@ pub async fn start_container(container_id: &str) -> Result<()> {
     Ok(())
 }
 
+/// Replace an app's `foundry-<app>` container with one started from a recorded deploy
+pub async fn redeploy(app_name: &str, image: &str, args: &[String], command: &[String]) -> Result<()> {
+    let container_name = format!("foundry-{}", app_name);
+    // Missing containers are fine; the app may have been stopped by hand
+    let _ = Command::new("docker").args(["stop", &container_name]).output().await;
+    let _ = Command::new("docker").args(["rm", &container_name]).output().await;
+
+    let output = Command::new("docker")
+        .arg("run")
+        .args(args)
+        .arg(image)
+        .args(command)
+        .output()
+        .await
+        .context("Failed to start container")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Failed to start container: {}", stderr);
+    }
+
+    Ok(())
+}
+
+/// Stop and remove an app's `foundry-<app>` container
+pub async fn remove_app(app_name: &str) -> Result<()> {
+    let container_name = format!("foundry-{}", app_name);
+    // Let the app shut down cleanly; `rm -f` below covers one that won't
+    let _ = Command::new("docker").args(["stop", &container_name]).output().await;
+    let output = Command::new("docker")
+        .args(["rm", "-f", &container_name])
+        .output()
+        .await
+        .context("Failed to remove container")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Failed to remove container: {}", stderr);
+    }
+
+    Ok(())
+}
+
 /// Restart all containers in a docker-compose project
 pub async fn restart_project(project_name: &str) -> Result<()> {
     let output = Command::new("docker")
@@ -278,13 +370,133 @@ pub async fn list_projects() -> Result<Vec<String>> {
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    
-    #[derive(Deserialize)]
-    struct ProjectInfo {
-        #[serde(rename = "Name")]
-        name: String,
+    Ok(parse_projects(&stdout))
+}
+
+#[derive(Deserialize)]
+struct ProjectInfo {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+/// Project names from `docker compose ls --format json`, which is a JSON array on
+/// most Docker versions and one object per line on others
+fn parse_projects(stdout: &str) -> Vec<String> {
+    let trimmed = stdout.trim();
+    if trimmed.is_empty() {
+        return Vec::new();
+    }
+
+    if trimmed.starts_with('[') {
+        return match serde_json::from_str::<Vec<ProjectInfo>>(trimmed) {
+            Ok(projects) => projects.into_iter().map(|p| p.name).collect(),
+            Err(e) => {
+                warn!("Failed to parse docker compose ls output: {}", e);
+                Vec::new()
+            }
+        };
     }
 
-    let projects: Vec<ProjectInfo> = serde_json::from_str(&stdout).unwrap_or_default();
-    Ok(projects.into_iter().map(|p| p.name).collect())
+    trimmed
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str::<ProjectInfo>(line) {
+            Ok(project) => Some(project.name),
+            Err(e) => {
+                warn!("Skipping unparseable docker compose ls line {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn process_exists(pid: &str) -> bool {
+        std::path::Path::new(&format!("/proc/{}", pid)).exists()
+    }
+
+    #[tokio::test]
+    async fn test_dropping_receiver_kills_child() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo $$; exec sleep 30"]);
+        let mut rx = stream_lines(cmd).unwrap();
+
+        let pid = rx.recv().await.unwrap();
+        assert!(process_exists(&pid));
+
+        drop(rx);
+        for _ in 0..50 {
+            if !process_exists(&pid) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("child {} still running after the receiver was dropped", pid);
+    }
+
+    #[test]
+    fn test_filter_containers_by_repo_label() {
+        let stdout = [
+            "a1\tfoundry-api\tfoundry-api-7:latest\tUp 2 hours\trunning\t2024-01-01\t0.0.0.0:3000->3000/tcp\t\tacme/api\ttrue\tdeploy\t7",
+            "b2\tshop-web-1\tnginx\tUp 1 hour\trunning\t2024-01-01\t80/tcp\tshop\t",
+            "c3\tpostgres\tpostgres:16\tUp 3 days\trunning\t2024-01-01\t5432/tcp",
+        ]
+        .join("\n");
+
+        let all = parse_containers(&stdout, &ContainerFilter::default());
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2].repo, None);
+        assert_eq!(all[2].project, None);
+
+        let api = parse_containers(&stdout, &ContainerFilter { repo: Some("acme/api"), ..Default::default() });
+        assert_eq!(api.len(), 1);
+        assert_eq!(api[0].name, "foundry-api");
+        assert_eq!(api[0].project, None);
+        assert_eq!(api[0].kind.as_deref(), Some("deploy"));
+        assert_eq!(api[0].job_id, Some(7));
+
+        let shop = parse_containers(&stdout, &ContainerFilter { project: Some("shop"), ..Default::default() });
+        assert_eq!(shop.len(), 1);
+        assert_eq!(shop[0].repo, None);
+
+        let both = ContainerFilter { project: Some("shop"), repo: Some("acme/api"), ..Default::default() };
+        assert!(parse_containers(&stdout, &both).is_empty());
+
+        let managed = parse_containers(&stdout, &ContainerFilter { managed: true, ..Default::default() });
+        assert_eq!(managed.len(), 1);
+        assert_eq!(managed[0].id, "a1");
+    }
+
+    #[test]
+    fn test_parse_projects_array() {
+        let stdout = r#"[{"Name":"app","Status":"running(2)","ConfigFiles":"/srv/app/docker-compose.yml"},{"Name":"foundry","Status":"running(3)"}]"#;
+        assert_eq!(parse_projects(stdout), vec!["app", "foundry"]);
+        assert_eq!(parse_projects("[]\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_parse_projects_ndjson() {
+        let stdout = "{\"Name\":\"app\",\"Status\":\"running(2)\"}\n{\"Name\":\"foundry\",\"Status\":\"running(3)\"}\n";
+        assert_eq!(parse_projects(stdout), vec!["app", "foundry"]);
+        assert_eq!(parse_projects("{\"Name\":\"app\"}\nWARNING: something\n"), vec!["app"]);
+        assert_eq!(parse_projects(""), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_when_child_exits() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2"]);
+        let mut rx = stream_lines(cmd).unwrap();
+
+        let mut lines = Vec::new();
+        while let Some(line) = rx.recv().await {
+            lines.push(line);
+        }
+        lines.sort();
+        assert_eq!(lines, vec!["err", "out"]);
+    }
 }
@@ -0,0 +1,130 @@
+//! Job completion events
+//!
+//! A trigger on the job table sends `NOTIFY job_finished, '<id>'` whenever a job
+//! reaches a terminal status. One listener per server fans these out over a
+//! broadcast channel so long-polling requests don't each hold a connection.
+
+use std::future::Future;
+use std::time::Duration;
+
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info};
+
+const JOB_FINISHED_CHANNEL: &str = "job_finished";
+
+/// Forward `job_finished` notifications to `tx`, reconnecting if the connection drops
+pub async fn listen_job_finished(pool: PgPool, tx: broadcast::Sender<i64>) {
+    loop {
+        match PgListener::connect_with(&pool).await {
+            Ok(mut listener) => {
+                if let Err(e) = listener.listen(JOB_FINISHED_CHANNEL).await {
+                    error!("Failed to LISTEN {}: {}", JOB_FINISHED_CHANNEL, e);
+                } else {
+                    info!("Listening for job completion events");
+                    loop {
+                        match listener.recv().await {
+                            Ok(notification) => match notification.payload().parse::<i64>() {
+                                // No receivers just means nobody is waiting
+                                Ok(job_id) => {
+                                    let _ = tx.send(job_id);
+                                }
+                                Err(_) => debug!("Ignoring job_finished payload: {}", notification.payload()),
+                            },
+                            Err(e) => {
+                                error!("Job event listener disconnected: {}", e);
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            Err(e) => error!("Failed to connect job event listener: {}", e),
+        }
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+/// Wait until `check` reports a result for `job_id`, re-checking whenever that job's
+/// completion event arrives. The first check always runs, even with a zero timeout.
+/// Returns `None` on timeout.
+///
+/// `rx` must be subscribed before the first check so a job finishing in between
+/// isn't missed.
+pub async fn wait_for_job<T, F, Fut>(
+    mut rx: broadcast::Receiver<i64>,
+    job_id: i64,
+    timeout: Duration,
+    mut check: F,
+) -> Option<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Option<T>>,
+{
+    if let Some(result) = check().await {
+        return Some(result);
+    }
+
+    let wait = async {
+        loop {
+            match rx.recv().await {
+                Ok(id) if id != job_id => continue,
+                // Missed events may include ours, so check either way
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if let Some(result) = check().await {
+                        return Some(result);
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    };
+
+    tokio::time::timeout(timeout, wait).await.ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_wait_unblocks_when_job_finishes() {
+        let (tx, rx) = broadcast::channel(16);
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let notifier = {
+            let finished = finished.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                // Another job finishing must not end the wait
+                tx.send(7).unwrap();
+                finished.store(true, Ordering::SeqCst);
+                tx.send(42).unwrap();
+                tokio::time::sleep(Duration::from_secs(60)).await;
+            })
+        };
+
+        let start = Instant::now();
+        let result = wait_for_job(rx, 42, Duration::from_secs(30), || {
+            let finished = finished.clone();
+            async move { finished.load(Ordering::SeqCst).then_some("success") }
+        })
+        .await;
+
+        assert_eq!(result, Some("success"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        notifier.abort();
+    }
+
+    #[tokio::test]
+    async fn test_wait_times_out() {
+        let (_tx, rx) = broadcast::channel::<i64>(16);
+        let result = wait_for_job(rx, 42, Duration::from_millis(50), || async { None::<()> }).await;
+        assert_eq!(result, None);
+    }
+}
@@ -3,17 +3,24 @@ mod cloudflare;
 mod config;
 mod db;
 mod docker;
+mod events;
+mod log_store;
+mod notify;
+mod prune;
 mod routes;
 mod scheduler;
+mod secrets;
 mod watchdog;
 
 use anyhow::Result;
 use axum::Router;
 use sqlx::postgres::PgPoolOptions;
+use std::future::IntoFuture;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::auth::AuthState;
@@ -34,20 +41,40 @@ pub struct AppState {
     pub db: sqlx::PgPool,
     pub config: Config,
     pub auth: Option<AuthState>,
+    /// Ids of jobs that just reached a terminal status
+    pub job_finished: tokio::sync::broadcast::Sender<i64>,
+    /// Last `/api/queue` answer, so frequent scaler polls share one query
+    pub queue_cache: routes::metrics::QueueCache,
+    /// Where finished jobs' logs are offloaded, if configured
+    pub log_store: Option<log_store::S3Store>,
+}
+
+/// State for route tests. The lazy pool points at a closed port, so any test that
+/// reaches the database fails instead of hanging.
+#[cfg(test)]
+pub fn test_state(config: Config) -> Arc<AppState> {
+    let db = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(100))
+        .connect_lazy("postgres://foundry@127.0.0.1:1/foundry")
+        .unwrap();
+    let (job_finished, _) = tokio::sync::broadcast::channel(16);
+    Arc::new(AppState { db, config, auth: None, job_finished, queue_cache: Default::default(), log_store: None })
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let log_format = foundry_core::LogFormat::from_env()?;
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "foundryd=debug,tower_http=debug".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with((log_format == foundry_core::LogFormat::Text).then(tracing_subscriber::fmt::layer))
+        .with((log_format == foundry_core::LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json()))
         .init();
 
     let config = Config::from_env()?;
-    info!("Starting foundryd on {}", config.bind_addr);
+    info!("Starting foundryd {} on {}", foundry_core::build_info!(), config.bind_addr);
 
     let db = PgPoolOptions::new()
         .max_connections(10)
@@ -85,8 +112,9 @@ async fn main() -> Result<()> {
     };
 
     let db_pool = Arc::new(db.clone());
+    let scheduler_config = config.clone();
     tokio::spawn(async move {
-        scheduler::run_scheduler(db_pool).await;
+        scheduler::run_scheduler(db_pool, scheduler_config).await;
     });
 
     // Initialize auth if enabled
@@ -107,15 +135,38 @@ async fn main() -> Result<()> {
         None
     };
 
-    let state = Arc::new(AppState { db, config, auth });
+    let (job_finished, _) = tokio::sync::broadcast::channel(256);
+    tokio::spawn(events::listen_job_finished(db.clone(), job_finished.clone()));
+
+    let log_store = config.log_store.clone().map(log_store::S3Store::new);
+    if let (Some(store), Some(log_config)) = (&log_store, &config.log_store) {
+        tokio::spawn(log_store::run_offloader(db.clone(), store.clone(), log_config.db_retention_days));
+    }
+
+    let state = Arc::new(AppState {
+        db,
+        config,
+        auth,
+        job_finished,
+        queue_cache: Default::default(),
+        log_store,
+    });
 
     // Start the agent watchdog
     watchdog::start_agent_watchdog();
 
+    if state.config.agent_tokens.is_empty() {
+        tracing::warn!("FOUNDRY_AGENT_TOKENS not set - agent API, /metrics and /api/queue are unauthenticated");
+    }
+    if state.config.auth.as_ref().is_some_and(|auth| auth.admin_emails.is_empty()) {
+        tracing::warn!("FOUNDRY_ADMIN_EMAILS not set - every signed-in user is a viewer");
+    }
+
     // Build the router with optional auth protection
     let mut app = Router::new()
         .merge(routes::webhook::router())
-        .merge(routes::health::router());
+        .merge(routes::health::router())
+        .merge(routes::metrics::router(&state));
 
     // Add auth routes if auth is enabled
     if state.auth.is_some() {
@@ -123,14 +174,14 @@ async fn main() -> Result<()> {
             .merge(routes::frontend::api_router())
             .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth::require_auth));
         app = app
-            .merge(routes::agent::router())
+            .merge(routes::agent::router(&state))
             .merge(protected)
             .merge(routes::frontend::static_router()) // public: login page must load before session exists
             .merge(auth::router());
     } else {
         app = app
             .merge(routes::frontend::router())
-            .merge(routes::agent::router());
+            .merge(routes::agent::router(&state));
     }
 
     let app = app
@@ -141,7 +192,56 @@ async fn main() -> Result<()> {
     let listener = TcpListener::bind(&state.config.bind_addr).await?;
     info!("Listening on {}", state.config.bind_addr);
 
-    axum::serve(listener, app).await?;
+    // Stop accepting connections on SIGTERM and let in-flight requests finish, up to
+    // FOUNDRY_SHUTDOWN_TIMEOUT; long-polls could otherwise hold the server for minutes
+    let stopping = Arc::new(tokio::sync::Notify::new());
+    let server = axum::serve(listener, app).with_graceful_shutdown({
+        let stopping = stopping.clone();
+        async move {
+            shutdown_signal().await;
+            info!("Shutting down, draining in-flight requests");
+            stopping.notify_one();
+        }
+    });
+    let drain_timeout = Duration::from_secs(state.config.shutdown_timeout_secs);
+    tokio::select! {
+        result = server.into_future() => result?,
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(drain_timeout).await;
+        } => warn!("Requests still running after {}s, shutting down anyway", drain_timeout.as_secs()),
+    }
 
+    info!("Shutdown complete");
     Ok(())
 }
+
+/// Resolve on Ctrl-C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,166 @@
+//! Build notifications
+//!
+//! Repos list Slack and Discord webhooks under `[notify]` in foundry.toml; the
+//! agent syncs them to the repo row and the server posts a one-line summary of
+//! each finished job there. Notifications are best effort: failures are logged
+//! and never affect the job.
+
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use sqlx::PgPool;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::db::{self, JobDetail};
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Post `job_id`'s result to its repo's webhooks, if it has finished and any are set
+pub async fn notify_job_finished(pool: &PgPool, config: &Config, job_id: i64) {
+    let notify = match db::get_job_notify(pool, job_id).await {
+        Ok(Some(notify)) => notify,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to load notification settings for job {}: {}", job_id, e);
+            return;
+        }
+    };
+    if notify.slack_webhook.is_none() && notify.discord_webhook.is_none() {
+        return;
+    }
+
+    let job = match db::get_job(pool, job_id).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to load job {} for notification: {}", job_id, e);
+            return;
+        }
+    };
+    // A matrix parent keeps running until its combinations finish
+    if job.status == "running" {
+        return;
+    }
+
+    let text = message(&job, config.job_url(&job.public_id).as_deref());
+    let client = reqwest::Client::new();
+    if let Some(url) = &notify.slack_webhook {
+        let body = serde_json::json!({ "text": text });
+        match post(&client, url, &body).await {
+            Ok(()) => debug!("Sent Slack notification for job {}", job_id),
+            Err(e) => warn!("Failed to send Slack notification for job {}: {}", job_id, e),
+        }
+    }
+    if let Some(url) = &notify.discord_webhook {
+        let body = serde_json::json!({ "content": text });
+        match post(&client, url, &body).await {
+            Ok(()) => debug!("Sent Discord notification for job {}", job_id),
+            Err(e) => warn!("Failed to send Discord notification for job {}: {}", job_id, e),
+        }
+    }
+}
+
+async fn post(client: &reqwest::Client, url: &str, body: &serde_json::Value) -> Result<()> {
+    let response = client.post(url).timeout(NOTIFY_TIMEOUT).json(body).send().await?;
+    if !response.status().is_success() {
+        bail!("webhook returned {}", response.status());
+    }
+    Ok(())
+}
+
+/// Summary of a finished job; Slack and Discord both render `*bold*` and bare links
+fn message(job: &JobDetail, job_url: Option<&str>) -> String {
+    let (icon, result) = match job.status.as_str() {
+        "success" => ("✅", "succeeded"),
+        "cancelled" => ("🛑", "was cancelled"),
+        _ => ("❌", "failed"),
+    };
+    let branch = job
+        .git_ref
+        .strip_prefix("refs/heads/")
+        .or_else(|| job.git_ref.strip_prefix("refs/tags/"))
+        .unwrap_or(&job.git_ref);
+
+    let mut text = format!("{} *{}/{}*", icon, job.repo_owner, job.repo_name);
+    if let Some(service) = &job.service_path {
+        text.push_str(&format!(" ({})", service));
+    }
+    if let Some(key) = &job.matrix_key {
+        text.push_str(&format!(" [{}]", key));
+    }
+    text.push_str(&format!(" build {} on {} at {}", result, branch, &job.git_sha[..7.min(job.git_sha.len())]));
+    if let Some(secs) = job.duration_secs {
+        let took = if secs >= 60 { format!("{}m {}s", secs / 60, secs % 60) } else { format!("{}s", secs) };
+        text.push_str(&format!(" in {}", took));
+    }
+    if let Some(subject) = job.commit_message.as_deref().and_then(|m| m.lines().next()) {
+        text.push_str(&format!("\n{}", subject));
+    }
+    if let Some(url) = job_url {
+        text.push_str(&format!("\n{}", url));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job() -> JobDetail {
+        JobDetail {
+            id: 1,
+            public_id: "k2m7qxa4zt".into(),
+            acknowledged: false,
+            ack_reason: None,
+            acknowledged_by: None,
+            repo_owner: "acme".into(),
+            repo_name: "app".into(),
+            git_sha: "1a2b3c4d5e6f".into(),
+            git_ref: "refs/heads/main".into(),
+            status: "failed".into(),
+            created_at: "2026-01-01 00:00:00".into(),
+            started_at: None,
+            finished_at: None,
+            commit_message: Some("Fix login\n\nLonger description".into()),
+            commit_author: None,
+            commit_url: None,
+            duration_secs: Some(135),
+            trigger_type: None,
+            queue_position: None,
+            pr_number: None,
+            pr_title: None,
+            pr_url: None,
+            pr_author: None,
+            pr_head_ref: None,
+            pr_base_ref: None,
+            service_path: None,
+            matrix_key: None,
+            parent_public_id: None,
+            triggered_by: None,
+            claimed_by: None,
+            target_agent: None,
+            concurrency_group: None,
+            agent_version: None,
+            metrics: None,
+            limits: None,
+        }
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(
+            message(&job(), Some("https://ci.example.com/j/k2m7qxa4zt")),
+            "❌ *acme/app* build failed on main at 1a2b3c4 in 2m 15s\nFix login\nhttps://ci.example.com/j/k2m7qxa4zt"
+        );
+
+        let job = JobDetail {
+            status: "success".into(),
+            service_path: Some("services/api".into()),
+            commit_message: None,
+            duration_secs: Some(9),
+            ..job()
+        };
+        assert_eq!(message(&job, None), "✅ *acme/app* (services/api) build succeeded on main at 1a2b3c4 in 9s");
+    }
+}
@@ -0,0 +1,129 @@
+//! Cleanup of data nothing refers to any more
+//!
+//! Webhook events are kept for debugging, but those no job links to are only useful
+//! for `webhook_retention_days`. Artifact files are named by their row id, so a file
+//! whose row is gone (its job was deleted) can never be downloaded again; uploads
+//! that were never renamed into place are left behind by a crash mid-upload.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::PgPool;
+
+use crate::db;
+
+/// Rows deleted per statement, so pruning a large backlog doesn't hold long locks
+const BATCH_SIZE: i64 = 1000;
+
+/// Uploads this old are no longer being written
+const STALE_UPLOAD_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct PruneReport {
+    pub webhook_events: u64,
+    pub artifact_files: u64,
+    pub stale_uploads: u64,
+}
+
+pub async fn prune(pool: &PgPool, artifact_dir: &Path, webhook_retention_days: u32) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+
+    loop {
+        let deleted = db::prune_webhook_events(pool, webhook_retention_days, BATCH_SIZE).await?;
+        report.webhook_events += deleted;
+        if deleted < BATCH_SIZE as u64 {
+            break;
+        }
+    }
+
+    // List before querying: a file only appears once its row exists, so one that is
+    // listed but has no row was orphaned, not uploaded in between
+    let files = scan_artifact_dir(artifact_dir, STALE_UPLOAD_AGE).await?;
+    let mut referenced = HashSet::new();
+    for batch in files.artifacts.chunks(BATCH_SIZE as usize) {
+        let ids: Vec<i64> = batch.iter().map(|(id, _)| *id).collect();
+        referenced.extend(db::existing_artifact_ids(pool, &ids).await?);
+    }
+
+    for path in orphaned_artifacts(&files, &referenced) {
+        tokio::fs::remove_file(path).await?;
+        report.artifact_files += 1;
+    }
+    for path in &files.stale_uploads {
+        tokio::fs::remove_file(path).await?;
+        report.stale_uploads += 1;
+    }
+
+    Ok(report)
+}
+
+#[derive(Debug, Default)]
+struct ArtifactFiles {
+    /// Stored artifacts by id
+    artifacts: Vec<(i64, PathBuf)>,
+    /// `.upload-*` files older than the stale age
+    stale_uploads: Vec<PathBuf>,
+}
+
+/// Files in the artifact directory Foundry created. Anything else is left alone.
+async fn scan_artifact_dir(dir: &Path, stale_upload_age: Duration) -> Result<ArtifactFiles> {
+    let mut files = ArtifactFiles::default();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        // Nothing has been uploaded yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Ok(id) = name.parse::<i64>() {
+            files.artifacts.push((id, entry.path()));
+        } else if name.starts_with(".upload-") {
+            let age = entry.metadata().await?.modified()?.elapsed().unwrap_or_default();
+            if age >= stale_upload_age {
+                files.stale_uploads.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn orphaned_artifacts<'a>(files: &'a ArtifactFiles, referenced: &HashSet<i64>) -> Vec<&'a Path> {
+    files
+        .artifacts
+        .iter()
+        .filter(|(id, _)| !referenced.contains(id))
+        .map(|(_, path)| path.as_path())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_only_unreferenced_artifacts_are_orphaned() {
+        let dir = std::env::temp_dir().join(format!("foundry-prune-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        for name in ["1", "2", "3", ".upload-abc", "README"] {
+            tokio::fs::write(dir.join(name), b"x").await.unwrap();
+        }
+
+        // Artifact 2's job was deleted
+        let referenced = HashSet::from([1, 3]);
+        let files = scan_artifact_dir(&dir, STALE_UPLOAD_AGE).await.unwrap();
+        assert_eq!(orphaned_artifacts(&files, &referenced), vec![dir.join("2").as_path()]);
+        // An upload that just started is still being written
+        assert!(files.stale_uploads.is_empty());
+
+        let files = scan_artifact_dir(&dir, Duration::ZERO).await.unwrap();
+        assert_eq!(files.stale_uploads, vec![dir.join(".upload-abc")]);
+        assert_eq!(files.artifacts.len(), 3);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        assert!(scan_artifact_dir(&dir, STALE_UPLOAD_AGE).await.unwrap().artifacts.is_empty());
+    }
+}
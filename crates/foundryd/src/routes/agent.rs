@@ -1,37 +1,188 @@
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{multipart::Field, DefaultBodyLimit, Multipart, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use foundry_core::{ApiResponse, ClaimRequest, ClaimResponse, FinishRequest, LogRequest, SyncScheduleRequest, SyncTriggersRequest};
+use foundry_core::{config::{validate_agent_label, validate_check_context, validate_concurrency_group, NotifyConfig, TriggersConfig, MAX_MATRIX_JOBS}, ApiResponse, CheckRunRequest, ClaimRequest, ClaimResponse, ClaimedJob, DeploymentRequest, FanOutRequest, FinishRequest, HeartbeatRequest, JobStatusResponse, LimitsRequest, LogRequest, MatrixRequest, PreviewRequest, ReleaseRequest, SyncCheckContextRequest, SyncConcurrencyRequest, SyncNotifyRequest, SyncRunsOnRequest, SyncScheduleRequest, SyncTriggersRequest, protocol_mismatch, PROTOCOL_HEADER, PROTOCOL_VERSION};
 
-use crate::{db, scheduler, AppState};
+use crate::{db, notify, scheduler, AppState};
 
-pub fn router() -> Router<Arc<AppState>> {
+pub fn router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/agent/claim", post(claim_job))
         .route("/agent/log", post(append_log))
         .route("/agent/finish", post(finish_job))
+        .route("/agent/fanout", post(fan_out_job))
+        .route("/agent/matrix", post(fan_out_matrix))
+        .route("/agent/deployment", post(record_deployment))
+        .route("/agent/preview", post(record_preview))
+        .route("/agent/check_run", post(set_check_run))
+        .route("/agent/heartbeat", post(heartbeat))
+        .route("/agent/release", post(release_job))
+        .route("/agent/cancel/{job_id}", post(cancel_job))
+        .route("/agent/job/{job_id}/status", get(job_status))
         .route("/agent/logs/{job_id}", get(get_logs))
         .route("/agent/metrics", post(report_metrics))
+        .route("/agent/limits", post(report_limits))
         .route("/agent/schedule", post(sync_schedule))
         .route("/agent/triggers", post(sync_triggers))
+        .route("/agent/notify", post(sync_notify))
+        .route("/agent/concurrency", post(sync_concurrency))
+        .route("/agent/check-context", post(sync_check_context))
+        .route("/agent/runs-on", post(sync_runs_on))
+        // Size is enforced while streaming to disk, against FOUNDRY_MAX_ARTIFACT_SIZE
+        .route("/agent/artifact", post(upload_artifact).layer(DefaultBodyLimit::disable()))
+        .route_layer(middleware::from_fn(require_supported_protocol))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_agent_token))
+}
+
+/// Turn away agents whose `PROTOCOL_HEADER` is out of range before their request
+/// body is parsed, so they get a clear error instead of a deserialization failure.
+/// Agents older than the header are let through; claims handle them.
+async fn require_supported_protocol(request: Request, next: Next) -> Response {
+    let Some(value) = request.headers().get(PROTOCOL_HEADER) else {
+        return next.run(request).await;
+    };
+
+    let error = match value.to_str().ok().and_then(|v| v.trim().parse::<u32>().ok()) {
+        Some(version) => protocol_mismatch(version),
+        None => Some(format!("Invalid {} header", PROTOCOL_HEADER)),
+    };
+    match error {
+        Some(error) => (StatusCode::CONFLICT, Json(ApiResponse::error(error))).into_response(),
+        None => next.run(request).await,
+    }
+}
+
+/// Reject agent requests without a valid `Authorization: Bearer` token.
+/// Open when no tokens are configured, so existing single-host setups keep working.
+/// Also guards `/metrics` and `/api/queue`, which name repos.
+pub(crate) async fn require_agent_token(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let tokens = &state.config.agent_tokens;
+    if tokens.is_empty() {
+        return next.run(request).await;
+    }
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match provided {
+        Some(provided) if tokens.iter().any(|t| token_eq(t, provided)) => next.run(request).await,
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::error("Invalid or missing agent token")),
+        )
+            .into_response(),
+    }
+}
+
+/// Compare tokens without short-circuiting on the first differing byte
+fn token_eq(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// How an agent's build relates to the server's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentCompatibility {
+    /// Same build as the server
+    Current,
+    /// A different build speaking the same protocol
+    Skewed,
+    /// Speaks a protocol the server doesn't support, or is too old to report one
+    Incompatible,
+}
+
+pub fn agent_compatibility(
+    server_version: &str,
+    agent_version: Option<&str>,
+    protocol_version: Option<u32>,
+) -> AgentCompatibility {
+    match protocol_version {
+        Some(version) if protocol_mismatch(version).is_none() => {
+            if agent_version == Some(server_version) {
+                AgentCompatibility::Current
+            } else {
+                AgentCompatibility::Skewed
+            }
+        }
+        _ => AgentCompatibility::Incompatible,
+    }
 }
 
 async fn claim_job(
     State(state): State<Arc<AppState>>,
     Json(req): Json<ClaimRequest>,
 ) -> impl IntoResponse {
-    match db::claim_job(&state.db, &req.agent_id).await {
-        Ok(Some(job)) => {
+    let server_version = foundry_core::build_info!().to_string();
+    let compatibility = agent_compatibility(&server_version, req.agent_version.as_deref(), req.protocol_version);
+
+    let protocol_version = req.protocol_version.map(|v| v as i32);
+    let docker_error = req.docker_error.as_deref();
+    let arch = req.arch.as_deref();
+    match db::record_agent(&state.db, &req.agent_id, req.agent_version.as_deref(), protocol_version, docker_error, arch, &req.labels).await {
+        // Only on first contact or upgrade, not on every poll
+        Ok(true) if compatibility != AgentCompatibility::Current => warn!(
+            "Agent {} runs {} (protocol {}), server runs {} (protocol {})",
+            req.agent_id,
+            req.agent_version.as_deref().unwrap_or("an unknown version"),
+            req.protocol_version.map(|v| v.to_string()).unwrap_or_else(|| "unknown".to_string()),
+            server_version,
+            PROTOCOL_VERSION,
+        ),
+        Ok(_) => {}
+        Err(e) => error!("Failed to record agent {}: {}", req.agent_id, e),
+    }
+
+    // Agents on an unsupported protocol can't run jobs correctly. Those too old to
+    // report a protocol are given work unless the operator opted to block them.
+    let rejection = match req.protocol_version {
+        Some(version) => protocol_mismatch(version),
+        None if state.config.block_incompatible_agents => Some(format!(
+            "Agent doesn't report a protocol version; upgrade the agent to {}",
+            server_version
+        )),
+        None => None,
+    };
+    if let Some(error) = rejection {
+        return (StatusCode::CONFLICT, Json(ClaimResponse::Rejected { error }));
+    }
+
+    // The agent is only checking in; a job would fail as soon as it started a container
+    if docker_error.is_some() {
+        return (StatusCode::OK, Json(ClaimResponse::Empty));
+    }
+
+    match db::claim_job(&state.db, &req.agent_id, &req.labels, req.job_id).await {
+        Ok(Some(mut job)) => {
             info!("Agent {} claimed job {}", req.agent_id, job.id);
-            (StatusCode::OK, Json(ClaimResponse::Claimed { job }))
+            job.details_url = state.config.job_url(&job.public_id);
+            // Only used if the repo has no foundry.toml, which the agent finds out
+            if let Some(default) = state.config.default_images.resolve(arch) {
+                job.image = default.image;
+                job.platform = default.platform;
+            }
+            resolve_secrets(&state, &mut job).await;
+            (StatusCode::OK, Json(ClaimResponse::Claimed { job: Box::new(job) }))
         }
         Ok(None) => (StatusCode::OK, Json(ClaimResponse::Empty)),
         Err(e) => {
@@ -41,11 +192,38 @@ async fn claim_job(
     }
 }
 
+/// Decrypt the repo's secrets into the claimed job. Pull request builds run code
+/// from the PR branch, so they never receive secrets.
+async fn resolve_secrets(state: &AppState, job: &mut ClaimedJob) {
+    if job.git_ref.starts_with("refs/pull/") {
+        return;
+    }
+    let Some(key) = &state.config.secret_key else {
+        return;
+    };
+
+    let sealed = match db::get_repo_secrets(&state.db, job.repo_id).await {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            error!("Failed to load secrets for job {}: {}", job.id, e);
+            return;
+        }
+    };
+    for (name, value) in sealed {
+        match key.decrypt(job.repo_id, &name, &value) {
+            Ok(value) => {
+                job.secrets.0.insert(name, value);
+            }
+            Err(e) => warn!("Skipping secret for job {}: {}", job.id, e),
+        }
+    }
+}
+
 async fn append_log(
     State(state): State<Arc<AppState>>,
     Json(req): Json<LogRequest>,
 ) -> impl IntoResponse {
-    match db::append_log(&state.db, req.job_id, req.claim_token, &req.line).await {
+    match db::append_log(&state.db, req.job_id, req.claim_token, &req.line, req.level, state.config.max_log_size).await {
         Ok(true) => (StatusCode::OK, Json(ApiResponse::ok())),
         Ok(false) => (
             StatusCode::FORBIDDEN,
@@ -67,9 +245,14 @@ async fn finish_job(
 ) -> impl IntoResponse {
     let status_str = if req.success { "success" } else { "failed" };
 
-    match db::finish_job(&state.db, req.job_id, req.claim_token, req.success).await {
+    match db::finish_job(&state.db, req.job_id, req.claim_token, req.success, req.exit_code).await {
         Ok(true) => {
             info!("Job {} finished with status: {}", req.job_id, status_str);
+            // Slow or broken webhooks mustn't hold up the agent
+            let state = state.clone();
+            tokio::spawn(async move {
+                notify::notify_job_finished(&state.db, &state.config, req.job_id).await;
+            });
             (StatusCode::OK, Json(ApiResponse::ok()))
         }
         Ok(false) => (
@@ -86,11 +269,349 @@ async fn finish_job(
     }
 }
 
+async fn heartbeat(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<HeartbeatRequest>,
+) -> impl IntoResponse {
+    if let Some(error) = req.protocol_version.and_then(protocol_mismatch) {
+        return (StatusCode::CONFLICT, Json(ApiResponse::error(error)));
+    }
+    match db::heartbeat_job(&state.db, req.job_id, req.claim_token, req.agent_version.as_deref()).await {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to record heartbeat: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn release_job(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ReleaseRequest>,
+) -> impl IntoResponse {
+    match db::release_job(&state.db, req.job_id, req.claim_token, &req.reason).await {
+        Ok(true) => {
+            warn!("Job {} released back to the queue: {}", req.job_id, req.reason);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to release job: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn fan_out_job(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<FanOutRequest>,
+) -> impl IntoResponse {
+    match db::fan_out_job(&state.db, req.job_id, req.claim_token, &req.services).await {
+        Ok(Some(job_ids)) => {
+            info!("Job {} fanned out to service jobs {:?}", req.job_id, job_ids);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(None) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to fan out job: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn fan_out_matrix(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<MatrixRequest>,
+) -> impl IntoResponse {
+    if req.entries.is_empty() || req.entries.len() > MAX_MATRIX_JOBS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::error(format!(
+                "Matrix must have between 1 and {} combinations",
+                MAX_MATRIX_JOBS
+            ))),
+        );
+    }
+
+    match db::fan_out_matrix(&state.db, req.job_id, req.claim_token, &req.entries).await {
+        Ok(Some(job_ids)) => {
+            info!("Job {} fanned out to matrix jobs {:?}", req.job_id, job_ids);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(None) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to fan out matrix job: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn set_check_run(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CheckRunRequest>,
+) -> impl IntoResponse {
+    match db::set_job_check_run(&state.db, req.job_id, req.claim_token, req.check_run_id).await {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to store check run: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn record_deployment(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<DeploymentRequest>,
+) -> impl IntoResponse {
+    match db::record_deployment(&state.db, &req).await {
+        Ok(true) => {
+            info!("Job {} deployed {} as {}", req.job_id, req.app_name, req.image);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to record deployment: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn record_preview(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PreviewRequest>,
+) -> impl IntoResponse {
+    match db::record_preview(&state.db, &req).await {
+        Ok(true) => {
+            info!("Job {} deployed preview {} at {}", req.job_id, req.app_name, req.hostname);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to record preview: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+/// Store a build artifact. The multipart form sends `job_id` and `claim_token`
+/// before the `file` part, so the claim is checked before anything is written.
+async fn upload_artifact(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let mut job_id = None;
+    let mut claim_token = None;
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return (e.status(), Json(ApiResponse::error(e.body_text()))),
+        };
+
+        match field.name() {
+            Some("job_id") => job_id = field.text().await.ok().and_then(|v| v.parse::<i64>().ok()),
+            Some("claim_token") => claim_token = field.text().await.ok().and_then(|v| v.parse::<uuid::Uuid>().ok()),
+            Some("file") => {
+                let (Some(job_id), Some(claim_token)) = (job_id, claim_token) else {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        Json(ApiResponse::error("job_id and claim_token must come before the file")),
+                    );
+                };
+                return store_artifact(&state, job_id, claim_token, field).await;
+            }
+            _ => {}
+        }
+    }
+
+    (StatusCode::BAD_REQUEST, Json(ApiResponse::error("Missing file")))
+}
+
+async fn store_artifact(
+    state: &AppState,
+    job_id: i64,
+    claim_token: uuid::Uuid,
+    mut field: Field<'_>,
+) -> (StatusCode, Json<ApiResponse>) {
+    let name = match field.file_name() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => return (StatusCode::BAD_REQUEST, Json(ApiResponse::error("Missing file name"))),
+    };
+    let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+
+    match db::verify_running_job(&state.db, job_id, claim_token).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::FORBIDDEN, Json(ApiResponse::error("Invalid job or token"))),
+        Err(e) => {
+            error!("Failed to verify artifact upload: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error("Database error")));
+        }
+    }
+
+    let dir = std::path::Path::new(&state.config.artifact_dir);
+    let tmp = dir.join(format!(".upload-{}", uuid::Uuid::new_v4()));
+    let size = match write_upload(&mut field, dir, &tmp, state.config.max_artifact_size).await {
+        Ok(Some(size)) => size,
+        Ok(None) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(ApiResponse::error(format!(
+                    "Artifact exceeds {} bytes",
+                    state.config.max_artifact_size
+                ))),
+            );
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            error!("Failed to store artifact {} for job {}: {}", name, job_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error("Failed to store artifact")));
+        }
+    };
+
+    let stored = match db::create_artifact(&state.db, job_id, claim_token, &name, size as i64, &content_type).await {
+        Ok(Some(id)) => tokio::fs::rename(&tmp, dir.join(id.to_string()))
+            .await
+            .map(|_| Some(id))
+            .map_err(anyhow::Error::from),
+        other => other,
+    };
+
+    match stored {
+        Ok(Some(id)) => {
+            info!("Stored artifact {} ({} bytes) for job {} as {}", name, size, job_id, id);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(None) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            (StatusCode::FORBIDDEN, Json(ApiResponse::error("Invalid job or token")))
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            error!("Failed to store artifact {} for job {}: {}", name, job_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(ApiResponse::error("Failed to store artifact")))
+        }
+    }
+}
+
+/// Stream an upload to `tmp`. Returns the size, or `None` once it passes `max_size`.
+async fn write_upload(
+    field: &mut Field<'_>,
+    dir: &std::path::Path,
+    tmp: &std::path::Path,
+    max_size: u64,
+) -> anyhow::Result<Option<u64>> {
+    use tokio::io::AsyncWriteExt;
+
+    tokio::fs::create_dir_all(dir).await?;
+    let mut file = tokio::fs::File::create(tmp).await?;
+    let mut size = 0u64;
+    while let Some(chunk) = field.chunk().await? {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Ok(None);
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(Some(size))
+}
+
 #[derive(Deserialize)]
 struct GetLogsQuery {
     claim_token: uuid::Uuid,
 }
 
+async fn cancel_job(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<i64>,
+) -> impl IntoResponse {
+    match db::cancel_job(&state.db, job_id).await {
+        Ok(true) => {
+            info!("Job {} cancelled", job_id);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok(false) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::error("Job is not queued or running")),
+        ),
+        Err(e) => {
+            error!("Failed to cancel job: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
+async fn job_status(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<i64>,
+) -> impl IntoResponse {
+    match db::get_job_status(&state.db, job_id).await {
+        Ok(Some(status)) => (StatusCode::OK, Json(JobStatusResponse { status })).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::error("Job not found")),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to get job status: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+                .into_response()
+        }
+    }
+}
+
 async fn get_logs(
     State(state): State<Arc<AppState>>,
     Path(job_id): Path<i64>,
@@ -133,6 +654,26 @@ async fn report_metrics(
     }
 }
 
+async fn report_limits(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<LimitsRequest>,
+) -> impl IntoResponse {
+    match db::store_limits(&state.db, req.job_id, req.claim_token, &req.limits).await {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Ok(false) => (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid job or token")),
+        ),
+        Err(e) => {
+            error!("Failed to store limits: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Database error")),
+            )
+        }
+    }
+}
+
 async fn sync_schedule(
     State(state): State<Arc<AppState>>,
     Json(req): Json<SyncScheduleRequest>,
@@ -147,41 +688,43 @@ async fn sync_schedule(
         );
     }
     
-    if !req.enabled || req.cron.is_none() {
-        // Delete existing schedule
-        match scheduler::delete_schedule(&state.db, req.repo_id, req.branch.as_deref()).await {
-            Ok(_) => {
-                info!("Deleted schedule for repo {}", req.repo_id);
-                (StatusCode::OK, Json(ApiResponse::ok()))
-            }
-            Err(e) => {
-                error!("Failed to delete schedule: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::error("Failed to delete schedule")),
-                )
+    match req.cron.as_deref() {
+        Some(cron) if req.enabled => {
+            // Upsert schedule
+            match scheduler::upsert_schedule(
+                &state.db,
+                req.repo_id,
+                cron,
+                req.branch.as_deref(),
+                req.timezone.as_deref(),
+            ).await {
+                Ok(id) => {
+                    info!("Upserted schedule {} for repo {} with cron: {}", id, req.repo_id, cron);
+                    (StatusCode::OK, Json(ApiResponse::ok()))
+                }
+                Err(e) => {
+                    error!("Failed to upsert schedule: {}", e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ApiResponse::error(format!("Failed to create schedule: {}", e))),
+                    )
+                }
             }
         }
-    } else {
-        // Upsert schedule
-        let cron = req.cron.as_ref().unwrap();
-        match scheduler::upsert_schedule(
-            &state.db,
-            req.repo_id,
-            cron,
-            req.branch.as_deref(),
-            req.timezone.as_deref(),
-        ).await {
-            Ok(id) => {
-                info!("Upserted schedule {} for repo {} with cron: {}", id, req.repo_id, cron);
-                (StatusCode::OK, Json(ApiResponse::ok()))
-            }
-            Err(e) => {
-                error!("Failed to upsert schedule: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::error(&format!("Failed to create schedule: {}", e))),
-                )
+        _ => {
+            // Delete existing schedule
+            match scheduler::delete_schedule(&state.db, req.repo_id, req.branch.as_deref()).await {
+                Ok(_) => {
+                    info!("Deleted schedule for repo {}", req.repo_id);
+                    (StatusCode::OK, Json(ApiResponse::ok()))
+                }
+                Err(e) => {
+                    error!("Failed to delete schedule: {}", e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ApiResponse::error("Failed to delete schedule")),
+                    )
+                }
             }
         }
     }
@@ -201,18 +744,21 @@ async fn sync_triggers(
         );
     }
     
-    match db::sync_repo_triggers(
-        &state.db,
-        req.repo_id,
-        &req.branches,
-        req.pull_requests,
-        req.pr_target_branches.as_deref(),
-        None,
-    ).await {
+    let triggers = TriggersConfig {
+        branches: req.branches,
+        pull_requests: req.pull_requests,
+        pr_target_branches: req.pr_target_branches,
+        paths: req.paths,
+        paths_ignore: req.paths_ignore,
+        tags: req.tags,
+        releases: req.releases,
+    };
+
+    match db::sync_repo_triggers(&state.db, req.repo_id, &triggers, None).await {
         Ok(()) => {
             info!(
                 "Synced triggers for repo {}: branches={:?}, PRs={}",
-                req.repo_id, req.branches, req.pull_requests
+                req.repo_id, triggers.branches, triggers.pull_requests
             );
             (StatusCode::OK, Json(ApiResponse::ok()))
         }
@@ -220,8 +766,338 @@ async fn sync_triggers(
             error!("Failed to sync triggers: {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::error(&format!("Failed to sync triggers: {}", e))),
+                Json(ApiResponse::error(format!("Failed to sync triggers: {}", e))),
             )
         }
     }
 }
+
+async fn sync_notify(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SyncNotifyRequest>,
+) -> impl IntoResponse {
+    let valid = db::verify_job_token(&state.db, req.repo_id, req.claim_token).await;
+
+    if !matches!(valid, Ok(true)) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid repo or token")),
+        );
+    }
+
+    let notify = NotifyConfig {
+        slack_webhook: req.slack_webhook,
+        discord_webhook: req.discord_webhook,
+    };
+    for url in [&notify.slack_webhook, &notify.discord_webhook].into_iter().flatten() {
+        if !url.starts_with("https://") {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Notification webhooks must be https:// URLs")),
+            );
+        }
+    }
+
+    match db::sync_repo_notify(&state.db, req.repo_id, &notify).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Err(e) => {
+            error!("Failed to sync notifications: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!("Failed to sync notifications: {}", e))),
+            )
+        }
+    }
+}
+
+async fn sync_concurrency(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SyncConcurrencyRequest>,
+) -> impl IntoResponse {
+    let valid = db::verify_job_token(&state.db, req.repo_id, req.claim_token).await;
+
+    if !matches!(valid, Ok(true)) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid repo or token")),
+        );
+    }
+
+    if let Some(Err(e)) = req.group.as_deref().map(validate_concurrency_group) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+    }
+
+    match db::sync_repo_concurrency(&state.db, req.repo_id, req.group.as_deref(), req.cancel_in_progress).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Err(e) => {
+            error!("Failed to sync concurrency group: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!("Failed to sync concurrency group: {}", e))),
+            )
+        }
+    }
+}
+
+async fn sync_check_context(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SyncCheckContextRequest>,
+) -> impl IntoResponse {
+    let valid = db::verify_job_token(&state.db, req.repo_id, req.claim_token).await;
+
+    if !matches!(valid, Ok(true)) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid repo or token")),
+        );
+    }
+
+    if let Some(Err(e)) = req.context.as_deref().map(validate_check_context) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+    }
+
+    match db::sync_repo_check_context(&state.db, req.repo_id, req.context.as_deref()).await {
+        Ok(()) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Err(e) => {
+            error!("Failed to sync check context: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!("Failed to sync check context: {}", e))),
+            )
+        }
+    }
+}
+
+async fn sync_runs_on(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SyncRunsOnRequest>,
+) -> impl IntoResponse {
+    let valid = db::verify_job_token(&state.db, req.repo_id, req.claim_token).await;
+
+    if !matches!(valid, Ok(true)) {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(ApiResponse::error("Invalid repo or token")),
+        );
+    }
+
+    if let Err(e) = req.runs_on.iter().try_for_each(|label| validate_agent_label(label)) {
+        return (StatusCode::BAD_REQUEST, Json(ApiResponse::error(e.to_string())));
+    }
+
+    match db::sync_job_runs_on(&state.db, req.claim_token, &req.runs_on).await {
+        Ok(true) => (StatusCode::OK, Json(ApiResponse::ok())),
+        Ok(false) => (StatusCode::CONFLICT, Json(ApiResponse::error("Job is no longer running"))),
+        Err(e) => {
+            error!("Failed to sync runs_on: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error(format!("Failed to sync runs_on: {}", e))),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_state(agent_tokens: &[&str]) -> Arc<AppState> {
+        let mut config = crate::config::Config::for_tests();
+        config.agent_tokens = agent_tokens.iter().map(|t| t.to_string()).collect();
+        crate::test_state(config)
+    }
+
+    async fn claim(state: Arc<AppState>, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let response = router(&state)
+            .with_state(state)
+            .oneshot(
+                Request::post("/agent/claim")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn status(state: Arc<AppState>, token: Option<&str>) -> StatusCode {
+        let mut request = Request::get("/agent/job/1/status");
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        router(&state)
+            .with_state(state)
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_agent_routes_require_token() {
+        let state = test_state(&["tok-a", "tok-b"]);
+        assert_eq!(status(state.clone(), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(state.clone(), Some("tok-c")).await, StatusCode::UNAUTHORIZED);
+        // A valid token gets through to the handler (which then fails without a database)
+        assert_ne!(status(state, Some("tok-b")).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_agent_routes_open_without_tokens() {
+        assert_ne!(status(test_state(&[]), None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_artifact_upload_needs_claim_before_file() {
+        let state = test_state(&[]);
+        let body = concat!(
+            "--XBOUNDARY\r\n",
+            "Content-Disposition: form-data; name=\"file\"; filename=\"app\"\r\n",
+            "\r\n",
+            "binary\r\n",
+            "--XBOUNDARY\r\n",
+            "Content-Disposition: form-data; name=\"job_id\"\r\n",
+            "\r\n",
+            "1\r\n",
+            "--XBOUNDARY--\r\n",
+        );
+        let request = Request::post("/agent/artifact")
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XBOUNDARY")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = router(&state).with_state(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_token_eq() {
+        assert!(token_eq("secret", "secret"));
+        assert!(!token_eq("secret", "secreT"));
+        assert!(!token_eq("secret", "secret2"));
+    }
+
+    #[test]
+    fn test_agent_compatibility() {
+        let server = "0.2.0 (1a2b3c4)";
+        assert_eq!(
+            agent_compatibility(server, Some("0.2.0 (1a2b3c4)"), Some(PROTOCOL_VERSION)),
+            AgentCompatibility::Current
+        );
+        assert_eq!(
+            agent_compatibility(server, Some("0.1.0 (9f8e7d6)"), Some(PROTOCOL_VERSION)),
+            AgentCompatibility::Skewed
+        );
+        assert_eq!(
+            agent_compatibility(server, Some("0.3.0 (5a6b7c8)"), Some(PROTOCOL_VERSION + 1)),
+            AgentCompatibility::Incompatible
+        );
+        assert_eq!(agent_compatibility(server, Some(server), Some(0)), AgentCompatibility::Incompatible);
+        // Agents from before version reporting
+        assert_eq!(agent_compatibility(server, None, None), AgentCompatibility::Incompatible);
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_agent_refused_when_blocking() {
+        let mut state = Arc::into_inner(test_state(&[])).unwrap();
+        state.config.block_incompatible_agents = true;
+        let state = Arc::new(state);
+
+        let (status, body) = claim(state.clone(), serde_json::json!({"agent_id": "old-agent"})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["status"], "rejected");
+        assert!(body["error"].as_str().unwrap().contains("doesn't report a protocol version"));
+
+        // A compatible agent gets past the check (and finds no work without a database)
+        let (status, body) = claim(
+            state,
+            serde_json::json!({"agent_id": "new-agent", "agent_version": "0.1.0 (1a2b3c4)", "protocol_version": PROTOCOL_VERSION}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "empty");
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_agent_allowed_by_default() {
+        let (status, body) = claim(test_state(&[]), serde_json::json!({"agent_id": "old-agent"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "empty");
+    }
+
+    #[tokio::test]
+    async fn test_claim_rejects_unsupported_protocol() {
+        let (status, body) = claim(
+            test_state(&[]),
+            serde_json::json!({"agent_id": "future-agent", "protocol_version": PROTOCOL_VERSION + 1}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["status"], "rejected");
+        assert!(body["error"].as_str().unwrap().contains("upgrade foundryd"));
+    }
+
+    async fn post_with_protocol(uri: &str, protocol: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let state = test_state(&[]);
+        let response = router(&state)
+            .with_state(state)
+            .oneshot(
+                Request::post(uri)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(PROTOCOL_HEADER, protocol)
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_protocol_header_checked_before_body() {
+        // A body this server can't parse still gets the protocol error
+        let (status, body) = post_with_protocol("/agent/log", "99", r#"{"batch": []}"#).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["ok"], false);
+        assert!(body["error"].as_str().unwrap().contains("too new"));
+
+        let (status, _) = post_with_protocol("/agent/heartbeat", "0", "{}").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = post_with_protocol("/agent/heartbeat", "one", "{}").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // Supported protocols reach the handler, which rejects the body itself
+        let (status, _) = post_with_protocol("/agent/log", &PROTOCOL_VERSION.to_string(), r#"{"batch": []}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_rejects_unsupported_protocol() {
+        let body = serde_json::json!({
+            "job_id": 1,
+            "claim_token": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "protocol_version": PROTOCOL_VERSION + 1,
+        });
+        let state = test_state(&[]);
+        let response = router(&state)
+            .with_state(state)
+            .oneshot(
+                Request::post("/agent/heartbeat")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+}
@@ -1,6 +1,6 @@
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Json, sse::{Event, Sse}},
     routing::{delete, get, post},
     Router,
@@ -10,9 +10,18 @@ use std::sync::Arc;
 use std::convert::Infallible;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt as _;
+use tower::ServiceExt as _;
 use tower_http::services::{ServeDir, ServeFile};
-use crate::db::{self, DashboardStats, JobDetail, JobSummary, RepoSummary, ScheduleSummary};
+use axum_extra::extract::cookie::CookieJar;
+use crate::auth::{self, RequireAdmin};
+use crate::db::{self, Artifact, ChildJob, DashboardStats, Deployment, JobDetail, JobNote, JobResult, JobSummary, RepoSummary, ScheduleSummary, WebhookEventSummary};
+use foundry_core::{config, JobStatus, TriggerType};
 use crate::docker;
+use crate::routes::agent::{agent_compatibility, AgentCompatibility};
+use crate::routes::webhook;
+use crate::events;
+use crate::log_store;
+use crate::prune;
 use crate::AppState;
 
 fn static_dir() -> std::path::PathBuf {
@@ -32,10 +41,34 @@ pub fn api_router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/api/stats", get(api_stats))
         .route("/api/jobs", get(api_jobs))
+        .route("/api/queue/jobs", get(api_queue_jobs))
         .route("/api/job/{id}", get(api_job))
+        .route("/api/j/{public_id}", get(api_job_by_public_id))
+        .route("/api/job/{id}/cancel", post(api_cancel_job))
+        .route("/api/job/{id}/note", post(api_add_job_note))
+        .route("/api/job/{id}/ack", post(api_ack_job))
+        .route("/api/job/{id}/retry", post(api_retry_job))
+        .route("/api/job/{id}/logs", get(api_job_logs))
+        .route("/api/job/{id}/logs/stream", get(api_job_logs_stream))
+        .route("/api/jobs/{id}/result", get(api_job_result))
+        .route("/api/job/{id}/artifacts", get(api_job_artifacts))
+        .route("/api/artifact/{id}/download", get(api_download_artifact))
         .route("/api/repos", get(api_repos))
         .route("/api/repo/{id}", get(api_repo))
         .route("/api/repo/{id}/jobs", get(api_repo_jobs))
+        .route("/api/repos/{id}/rebuild-latest", post(api_rebuild_latest))
+        .route("/api/repos/{id}/build", post(api_build_ref))
+        .route("/api/repos/{id}/enabled", post(api_set_repo_enabled))
+        .route("/api/repos/{id}/cancel-outdated", post(api_set_repo_cancel_outdated))
+        .route("/api/repos/{id}/stats", get(api_repo_stats))
+        .route("/api/repos/{id}/secrets", get(api_repo_secrets).post(api_set_repo_secret))
+        .route("/api/repos/{id}/deployments", get(api_repo_deployments))
+        .route("/api/deploy/{app}/rollback", post(api_rollback_deployment))
+        .route("/api/webhook-events", get(api_webhook_events))
+        .route("/api/webhook-events/{id}", get(api_webhook_event))
+        .route("/api/webhook-events/{id}/replay", post(api_replay_webhook_event))
+        .route("/api/agents", get(api_agents))
+        .route("/api/validate-config", post(api_validate_config))
         .route("/api/schedules", get(api_schedules))
         .route("/api/schedule/{id}/toggle", post(api_toggle_schedule))
         .route("/api/schedule/{id}", delete(api_delete_schedule))
@@ -49,6 +82,10 @@ pub fn api_router() -> Router<Arc<AppState>> {
         .route("/api/projects/{name}/restart", post(api_restart_project))
         .route("/api/projects/{name}/stop", post(api_stop_project))
         .route("/api/projects/{name}/start", post(api_start_project))
+        .route("/api/admin/prune", post(api_admin_prune))
+        .route("/api/admin/clear-queue", post(api_admin_clear_queue))
+        .route("/api/admin/pause", post(api_admin_pause))
+        .route("/api/admin/resume", post(api_admin_resume))
 }
 
 /// Static file serving — always public so the login page (index.html) and its
@@ -70,112 +107,988 @@ pub fn router() -> Router<Arc<AppState>> {
 #[derive(Deserialize)]
 struct JobsQuery {
     limit: Option<i32>,
+    offset: Option<i64>,
+    status: Option<JobStatus>,
+    /// `owner/name`
+    repo: Option<String>,
 }
 
-async fn api_stats(State(state): State<Arc<AppState>>) -> Json<DashboardStats> {
-    let stats = db::get_dashboard_stats(&state.db).await.unwrap_or_default();
-    Json(stats)
+/// Job counts for the dashboard and external ones. A database error is a 500 rather
+/// than zeroed stats, so a dashboard can't mistake an outage for an empty queue.
+async fn api_stats(State(state): State<Arc<AppState>>) -> Result<Json<DashboardStats>, (StatusCode, Json<serde_json::Value>)> {
+    db::get_dashboard_stats(&state.db).await.map(Json).map_err(|e| {
+        tracing::error!("{}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+    })
 }
 
+/// A page of jobs; the `X-Total-Count` header has the number of matching jobs
 async fn api_jobs(
     State(state): State<Arc<AppState>>,
     Query(query): Query<JobsQuery>,
-) -> Json<Vec<JobSummary>> {
+) -> impl IntoResponse {
     let limit = query.limit.unwrap_or(50) as i64;
-    let jobs = db::list_jobs(&state.db, limit).await.unwrap_or_default();
+    let offset = query.offset.unwrap_or(0).max(0);
+    let filter = db::JobFilter {
+        status: query.status,
+        repo: query.repo.filter(|r| !r.is_empty()),
+    };
+    let (jobs, total) = tokio::join!(
+        db::list_jobs(&state.db, &filter, limit, offset),
+        db::count_jobs(&state.db, &filter),
+    );
+    let mut jobs = jobs.unwrap_or_default();
+    hide_job_ids(&state, &mut jobs);
+    ([("x-total-count", total.unwrap_or_default().to_string())], Json::<Vec<JobSummary>>(jobs))
+}
+
+#[derive(Deserialize)]
+struct QueueJobsQuery {
+    limit: Option<i32>,
+}
+
+/// Queued jobs in claim order; the scaler's `/api/queue` only has per-repo counts
+async fn api_queue_jobs(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<QueueJobsQuery>,
+) -> Json<Vec<JobSummary>> {
+    let limit = query.limit.unwrap_or(100) as i64;
+    let mut jobs = db::list_queued_jobs(&state.db, limit).await.unwrap_or_default();
+    hide_job_ids(&state, &mut jobs);
     Json(jobs)
 }
 
 #[derive(Serialize)]
 struct JobWithLogs {
+    /// Left out when only public ids are exposed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
     #[serde(flatten)]
     job: JobDetail,
     logs: Vec<LogEntry>,
+    notes: Vec<JobNote>,
+    children: Vec<ChildJob>,
+}
+
+#[derive(Serialize)]
+struct LogEntry {
+    timestamp: String,
+    message: String,
+    level: String,
+}
+
+async fn api_job(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    // Sequential ids leak build counts and are trivially enumerable
+    if state.config.public_job_ids_only {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    job_with_logs(&state, id).await.into_response()
+}
+
+async fn api_job_by_public_id(
+    State(state): State<Arc<AppState>>,
+    Path(public_id): Path<String>,
+) -> Json<Option<JobWithLogs>> {
+    if !is_public_id(&public_id) {
+        return Json(None);
+    }
+
+    match db::get_job_id_by_public_id(&state.db, &public_id).await {
+        Ok(Some(id)) => job_with_logs(&state, id).await,
+        _ => Json(None),
+    }
+}
+
+#[derive(Deserialize)]
+struct QueueJobRequest {
+    /// Only let this agent claim the job, e.g. to debug a build on one machine
+    target_agent: Option<String>,
+}
+
+/// The agent a job should be pinned to, which must have polled the server before
+async fn target_agent(
+    state: &AppState,
+    requested: Option<String>,
+) -> Result<Option<String>, (StatusCode, Json<serde_json::Value>)> {
+    let Some(agent_id) = requested
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    match db::agent_exists(&state.db, &agent_id).await {
+        Ok(true) => Ok(Some(agent_id)),
+        Ok(false) => Err((
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": format!("Unknown agent {}", agent_id)})),
+        )),
+        Err(e) => {
+            tracing::error!("{}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"}))))
+        }
+    }
+}
+
+/// Queue a fresh copy of a finished job; agents claim it like any other job unless
+/// it is pinned to one with `target_agent`. Accepts a numeric id or a public id.
+async fn api_retry_job(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    body: Option<Json<QueueJobRequest>>,
+) -> impl IntoResponse {
+    let Some(id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"})));
+    };
+
+    let target_agent = match target_agent(&state, body.and_then(|Json(body)| body.target_agent)).await {
+        Ok(agent) => agent,
+        Err(response) => return response,
+    };
+
+    match db::retry_job(&state.db, id, &state.config.trigger_priorities, target_agent.as_deref()).await {
+        Ok(Some((job_id, public_id))) => {
+            match &target_agent {
+                Some(agent) => tracing::info!("Job {} queued as a retry of job {} pinned to agent {}", job_id, id, agent),
+                None => tracing::info!("Job {} queued as a retry of job {}", job_id, id),
+            }
+            (StatusCode::OK, Json(queued_job_body(state.config.public_job_ids_only, job_id, public_id)))
+        }
+        Ok(None) => (StatusCode::CONFLICT, Json(serde_json::json!({"ok": false, "error": "Job not found or still in progress"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct JobResultQuery {
+    wait: Option<u64>,
+}
+
+/// Longest a result request may block, in seconds
+const MAX_RESULT_WAIT_SECS: u64 = 300;
+
+/// Final status of a job, optionally blocking up to `wait` seconds for it to finish.
+/// Accepts a numeric id or a public id. Returns 202 if the job is still in progress.
+async fn api_job_result(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<JobResultQuery>,
+) -> impl IntoResponse {
+    let Some(job_id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"})));
+    };
+
+    // Subscribe before the first lookup so a job finishing in between still wakes us
+    let rx = state.job_finished.subscribe();
+    let wait = std::time::Duration::from_secs(query.wait.unwrap_or(0).min(MAX_RESULT_WAIT_SECS));
+    let db = state.db.clone();
+    let result = events::wait_for_job(rx, job_id, wait, || {
+        let db = db.clone();
+        async move { db::get_job_result(&db, job_id).await.ok().flatten() }
+    })
+    .await;
+
+    match result {
+        Some(mut result) => {
+            hide_job_ids(&state, std::slice::from_mut(&mut result));
+            (StatusCode::OK, Json(serde_json::json!(result)))
+        }
+        None => match db::get_job_status(&state.db, job_id).await {
+            Ok(Some(status)) if state.config.public_job_ids_only => {
+                (StatusCode::ACCEPTED, Json(serde_json::json!({"status": status})))
+            }
+            Ok(Some(status)) => (StatusCode::ACCEPTED, Json(serde_json::json!({"id": job_id, "status": status}))),
+            Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"}))),
+            Err(e) => {
+                tracing::error!("{}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+            }
+        },
+    }
+}
+
+/// Job id from a path segment holding a numeric id or a public id
+async fn resolve_job_id(state: &AppState, id: &str) -> Option<i64> {
+    match id.parse::<i64>() {
+        Ok(job_id) if !state.config.public_job_ids_only => Some(job_id),
+        _ if is_public_id(id) => db::get_job_id_by_public_id(&state.db, id).await.ok().flatten(),
+        _ => None,
+    }
+}
+
+/// Responses that carry numeric job ids, which are left out when only public ids are exposed
+trait NumericJobIds {
+    fn clear_job_ids(&mut self);
+}
+
+impl NumericJobIds for JobSummary {
+    fn clear_job_ids(&mut self) {
+        self.id = None;
+    }
+}
+
+impl NumericJobIds for JobResult {
+    fn clear_job_ids(&mut self) {
+        self.id = None;
+    }
+}
+
+impl NumericJobIds for ChildJob {
+    fn clear_job_ids(&mut self) {
+        self.id = None;
+    }
+}
+
+impl NumericJobIds for JobNote {
+    fn clear_job_ids(&mut self) {
+        self.job_id = None;
+    }
+}
+
+impl NumericJobIds for Artifact {
+    fn clear_job_ids(&mut self) {
+        self.job_id = None;
+    }
+}
+
+impl NumericJobIds for Deployment {
+    fn clear_job_ids(&mut self) {
+        self.job_id = None;
+    }
+}
+
+impl NumericJobIds for WebhookEventSummary {
+    fn clear_job_ids(&mut self) {
+        self.job_id = None;
+    }
+}
+
+impl NumericJobIds for docker::ContainerInfo {
+    fn clear_job_ids(&mut self) {
+        self.job_id = None;
+    }
+}
+
+/// Drop the numeric job ids from `items` when `public_job_ids_only` is set
+fn hide_job_ids<T: NumericJobIds>(state: &AppState, items: &mut [T]) {
+    if state.config.public_job_ids_only {
+        items.iter_mut().for_each(T::clear_job_ids);
+    }
+}
+
+/// Body for a newly queued job; its numeric id is left out when only public ids are exposed
+fn queued_job_body(public_job_ids_only: bool, job_id: i64, public_id: String) -> serde_json::Value {
+    if public_job_ids_only {
+        serde_json::json!({"ok": true, "public_id": public_id})
+    } else {
+        serde_json::json!({"ok": true, "job_id": job_id, "public_id": public_id})
+    }
+}
+
+/// Artifacts uploaded for a job. Accepts a numeric id or a public id.
+async fn api_job_artifacts(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let Some(job_id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"})));
+    };
+
+    match db::list_artifacts(&state.db, job_id).await {
+        Ok(mut artifacts) => {
+            hide_job_ids(&state, &mut artifacts);
+            (StatusCode::OK, Json(serde_json::json!(artifacts)))
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+/// Download an artifact. Always sent as an attachment so uploaded HTML can't run on
+/// the dashboard's origin.
+async fn api_download_artifact(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    request: axum::extract::Request,
+) -> axum::response::Response {
+    let artifact = match db::get_artifact(&state.db, id).await {
+        Ok(Some(artifact)) => artifact,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("{}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let path = std::path::Path::new(&state.config.artifact_dir).join(artifact.id.to_string());
+    let mut response = match ServeFile::new(path).oneshot(request).await {
+        Ok(response) => response.map(axum::body::Body::new),
+        Err(infallible) => match infallible {},
+    };
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    if let Ok(content_type) = header::HeaderValue::from_str(&artifact.content_type) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    if let Ok(disposition) = header::HeaderValue::from_str(&content_disposition(&artifact.name)) {
+        headers.insert(header::CONTENT_DISPOSITION, disposition);
+    }
+    response
+}
+
+/// `attachment` disposition naming the file after the last component of the artifact path
+fn content_disposition(name: &str) -> String {
+    let file_name: String = name
+        .rsplit('/')
+        .next()
+        .unwrap_or(name)
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '"' && c != '\\') || c == ' ' { c } else { '_' })
+        .collect();
+    format!("attachment; filename=\"{}\"", file_name)
+}
+
+/// Accepts a numeric id or a public id.
+async fn api_cancel_job(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let Some(id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"})));
+    };
+
+    match db::cancel_job(&state.db, id).await {
+        Ok(true) => (StatusCode::OK, Json(serde_json::json!({"ok": true}))),
+        Ok(false) => (StatusCode::CONFLICT, Json(serde_json::json!({"ok": false, "error": "Job is not queued or running"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct NoteRequest {
+    body: String,
+}
+
+/// Accepts a numeric id or a public id.
+async fn api_add_job_note(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    jar: CookieJar,
+    Json(req): Json<NoteRequest>,
+) -> impl IntoResponse {
+    let Some(id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Job not found"}))).into_response();
+    };
+
+    let body = req.body.trim();
+    if body.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "Note cannot be empty"}))).into_response();
+    }
+
+    let author = request_user(&state, &jar);
+    match db::add_job_note(&state.db, id, &author, &escape_markdown(body)).await {
+        Ok(Some(mut note)) => {
+            hide_job_ids(&state, std::slice::from_mut(&mut note));
+            Json(note).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Job not found"}))).into_response(),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Internal server error"}))).into_response()
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct AckRequest {
+    reason: String,
+}
+
+/// Accepts a numeric id or a public id.
+async fn api_ack_job(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    jar: CookieJar,
+    Json(req): Json<AckRequest>,
+) -> impl IntoResponse {
+    let Some(id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"})));
+    };
+
+    let reason = req.reason.trim();
+    if reason.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"ok": false, "error": "A reason is required"})));
+    }
+
+    let author = request_user(&state, &jar);
+    match db::acknowledge_job(&state.db, id, &author, &escape_markdown(reason)).await {
+        Ok(true) => (StatusCode::OK, Json(serde_json::json!({"ok": true}))),
+        Ok(false) => (StatusCode::CONFLICT, Json(serde_json::json!({"ok": false, "error": "Only unacknowledged failed jobs can be acknowledged"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        },
+    }
+}
+
+/// The signed-in user for attributing notes and acks; without auth there is no identity to record
+fn request_user(state: &AppState, jar: &CookieJar) -> String {
+    auth::session_email(state.auth.as_ref(), jar).unwrap_or_else(|| "anonymous".to_string())
+}
+
+/// Escape markdown syntax so notes render literally wherever they're shown
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '!') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Public ids are short lowercase base32 strings generated by the database
+fn is_public_id(s: &str) -> bool {
+    !s.is_empty() && s.len() <= 32 && s.chars().all(|c| matches!(c, 'a'..='z' | '2'..='7'))
+}
+
+/// Build a log entry from a stored line and its level. A leading `[timestamp]` is used
+/// when present; other bracketed prefixes (e.g. stage names) are left in the message.
+fn log_entry(line: &str, level: &str, fallback_timestamp: &str) -> LogEntry {
+    let (timestamp, message) = line
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .filter(|(ts, _)| chrono::DateTime::parse_from_rfc3339(ts).is_ok())
+        .map(|(ts, msg)| (ts.to_string(), msg.trim().to_string()))
+        .unwrap_or_else(|| (fallback_timestamp.to_string(), line.to_string()));
+
+    LogEntry { timestamp, message, level: level.to_string() }
+}
+
+#[derive(Deserialize)]
+struct JobLogsQuery {
+    format: Option<String>,
+}
+
+/// Full log of a job, for scripts. Plain text by default; `?format=json` returns one
+/// JSON object per line with its id and timestamp. Accepts a numeric id or a public id.
+async fn api_job_logs(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<JobLogsQuery>,
+) -> axum::response::Response {
+    let json = match query.format.as_deref() {
+        None | Some("text") => false,
+        Some("json") => true,
+        Some(other) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"ok": false, "error": format!("Unknown format '{}': expected text or json", other)})),
+            )
+                .into_response()
+        }
+    };
+
+    let not_found = || (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"}))).into_response();
+    let Some(job_id) = resolve_job_id(&state, &id).await else {
+        return not_found();
+    };
+    match db::get_job_status(&state.db, job_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(),
+        Err(e) => {
+            tracing::error!("{}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let rows = job_log_rows(&state, job_id).await;
+    if !json {
+        return match rows {
+            Ok(rows) => {
+                let text = rows.into_iter().map(|row| row.line).collect::<Vec<_>>().join("\n");
+                ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response()
+            }
+            Err(e) => {
+                tracing::error!("{}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+    }
+
+    match rows {
+        Ok(rows) => {
+            let body: String = rows
+                .iter()
+                .map(|row| {
+                    let entry = log_entry(&row.line, &row.level, &row.ts);
+                    let mut line = serde_json::json!({
+                        "id": row.id,
+                        "timestamp": entry.timestamp,
+                        "message": entry.message,
+                        "level": entry.level,
+                    })
+                    .to_string();
+                    line.push('\n');
+                    line
+                })
+                .collect();
+            ([(header::CONTENT_TYPE, "application/x-ndjson")], body).into_response()
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Tail `job_log` for a job as server-sent events, ending once the job finishes.
+/// Honours `Last-Event-ID` so a reconnecting EventSource resumes where it left off.
+/// Accepts a numeric id or a public id.
+async fn api_job_logs_stream(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let Some(id) = resolve_job_id(&state, &id).await else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Job not found"}))).into_response();
+    };
+
+    let mut after_id: i64 = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<Event>(100);
+    let db = state.db.clone();
+
+    tokio::spawn(async move {
+        loop {
+            // Read the status before the rows so lines written just before the job
+            // finished are still delivered
+            let status = match db::get_job_status(&db, id).await {
+                Ok(Some(status)) => status,
+                _ => break,
+            };
+
+            let rows = match db::get_job_logs_after(&db, id, after_id, 500).await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    tracing::error!("Failed to tail logs for job {}: {}", id, e);
+                    break;
+                }
+            };
+            let caught_up = rows.len() < 500;
+
+            for row in rows {
+                after_id = row.id;
+                let entry = log_entry(&row.line, &row.level, &row.ts);
+                let event = Event::default()
+                    .id(row.id.to_string())
+                    .event("log")
+                    .json_data(&entry)
+                    .unwrap_or_default();
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+
+            if caught_up && !matches!(status, JobStatus::Queued | JobStatus::Running) {
+                let done = Event::default()
+                    .event("done")
+                    .json_data(status)
+                    .unwrap_or_default();
+                let _ = tx.send(done).await;
+                break;
+            }
+
+            if caught_up {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+        }
+    });
+
+    let stream = ReceiverStream::new(rx).map(Ok::<_, Infallible>);
+    Sse::new(stream).keep_alive(axum::response::sse::KeepAlive::default()).into_response()
+}
+
+/// All log rows of a job, read back from the log store once they have been
+/// offloaded and deleted from the database
+async fn job_log_rows(state: &AppState, job_id: i64) -> anyhow::Result<Vec<db::JobLogRow>> {
+    let rows = db::get_job_logs_after(&state.db, job_id, 0, i64::MAX).await?;
+    match &state.log_store {
+        Some(store) if rows.is_empty() => log_store::load_offloaded_logs(&state.db, store, job_id).await,
+        _ => Ok(rows),
+    }
+}
+
+async fn job_with_logs(state: &AppState, id: i64) -> Json<Option<JobWithLogs>> {
+    let job = match db::get_job(&state.db, id).await {
+        Ok(Some(job)) => job,
+        _ => return Json(None),
+    };
+
+    let logs: Vec<LogEntry> = job_log_rows(state, id)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("Failed to load logs of job {}: {:#}", id, e);
+            Vec::new()
+        })
+        .iter()
+        .map(|row| log_entry(&row.line, &row.level, &row.ts))
+        .collect();
+
+    let mut notes = db::get_job_notes(&state.db, id).await.unwrap_or_default();
+    let mut children = db::list_child_jobs(&state.db, id).await.unwrap_or_default();
+    hide_job_ids(state, &mut notes);
+    hide_job_ids(state, &mut children);
+
+    let id = (!state.config.public_job_ids_only).then_some(job.id);
+    Json(Some(JobWithLogs { id, job, logs, notes, children }))
+}
+
+async fn api_repos(State(state): State<Arc<AppState>>) -> Json<Vec<RepoSummary>> {
+    let repos = db::list_repos(&state.db).await.unwrap_or_default();
+    Json(repos)
+}
+
+async fn api_repo(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match db::get_repo(&state.db, id).await {
+        Ok(Some(repo)) => Json(serde_json::json!(repo)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Repo not found"}))).into_response(),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Internal server error"}))).into_response()
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct RepoStatsQuery {
+    days: Option<u32>,
+}
+
+/// p50/p95 build durations over the last `days` (default 30), with the window before
+/// for comparison
+async fn api_repo_stats(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<RepoStatsQuery>,
+) -> impl IntoResponse {
+    let days = query.days.unwrap_or(30).clamp(1, 365);
+    match db::get_repo_build_stats(&state.db, id, days).await {
+        Ok(Some(stats)) => (StatusCode::OK, Json(serde_json::json!(stats))),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Repo not found"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RepoJobsQuery {
+    limit: Option<i32>,
+}
+
+async fn api_repo_jobs(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Query(query): Query<RepoJobsQuery>,
+) -> Json<Vec<JobSummary>> {
+    let limit = query.limit.unwrap_or(50) as i64;
+    let mut jobs = db::get_repo_jobs(&state.db, id, limit).await.unwrap_or_default();
+    hide_job_ids(&state, &mut jobs);
+    Json(jobs)
+}
+
+/// Rebuild the newest default-branch commit of a repo as a manual job, optionally
+/// pinned to one agent with `target_agent`
+async fn api_rebuild_latest(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+    body: Option<Json<QueueJobRequest>>,
+) -> impl IntoResponse {
+    let target_agent = match target_agent(&state, body.and_then(|Json(body)| body.target_agent)).await {
+        Ok(agent) => agent,
+        Err(response) => return response,
+    };
+
+    let triggered_by = request_user(&state, &jar);
+    let priority = state.config.trigger_priorities.get(TriggerType::Manual);
+    let result = db::rebuild_latest(&state.db, id, &triggered_by, priority, target_agent.as_deref()).await;
+    if let Ok(Some((job_id, _))) = &result {
+        tracing::info!("Job {} queued by {} to rebuild the latest build of repo {}", job_id, triggered_by, id);
+    }
+    rebuild_response(result, state.config.public_job_ids_only)
+}
+
+#[derive(Deserialize)]
+struct BuildRefRequest {
+    /// Branch name, or a full ref like `refs/tags/v1.2.0`; defaults to the default branch
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+    target_agent: Option<String>,
+}
+
+/// Full ref and the name the agent clones for a requested branch or ref, or `None`
+/// if it isn't a valid ref name. Names can't start with `-` so git never reads them
+/// as an option.
+fn build_ref(requested: &str) -> Option<(String, String)> {
+    let (git_ref, name) = if let Some(name) = requested.strip_prefix("refs/heads/") {
+        (requested.to_string(), name)
+    } else if let Some(name) = requested.strip_prefix("refs/tags/") {
+        (requested.to_string(), name)
+    } else if requested.starts_with("refs/") {
+        return None;
+    } else {
+        (format!("refs/heads/{}", requested), requested)
+    };
+
+    let valid = !name.is_empty()
+        && !name.starts_with(['-', '/', '.'])
+        && !name.ends_with(['/', '.'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control() || "~^:?*[\\".contains(c));
+    valid.then(|| (git_ref, name.to_string()))
+}
+
+/// Queue a manual build of the head of a branch or tag, resolved to a commit by the
+/// agent when it clones
+async fn api_build_ref(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+    body: Option<Json<BuildRefRequest>>,
+) -> impl IntoResponse {
+    let (requested, requested_agent) = match body {
+        Some(Json(body)) => (body.git_ref.filter(|r| !r.trim().is_empty()), body.target_agent),
+        None => (None, None),
+    };
+    let target_agent = match target_agent(&state, requested_agent).await {
+        Ok(agent) => agent,
+        Err(response) => return response,
+    };
+
+    let requested = match requested {
+        Some(requested) => requested.trim().to_string(),
+        None => match db::get_repo(&state.db, id).await {
+            Ok(Some(repo)) => repo.default_branch.unwrap_or_else(|| "main".to_string()),
+            Ok(None) => return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Repo not found"}))),
+            Err(e) => {
+                tracing::error!("{}", e);
+                return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})));
+            }
+        },
+    };
+    let Some((git_ref, name)) = build_ref(&requested) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": format!("Invalid branch or ref '{}'", requested)})),
+        );
+    };
+
+    let triggered_by = request_user(&state, &jar);
+    let priority = state.config.trigger_priorities.get(TriggerType::Manual);
+    match db::queue_manual_build(&state.db, id, &git_ref, &name, &triggered_by, priority, target_agent.as_deref()).await {
+        Ok(Some((job_id, public_id))) => {
+            tracing::info!("Job {} queued by {} to build {} of repo {}", job_id, triggered_by, git_ref, id);
+            (StatusCode::OK, Json(queued_job_body(state.config.public_job_ids_only, job_id, public_id)))
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Repo not found"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+fn rebuild_response(
+    result: anyhow::Result<Option<(i64, String)>>,
+    public_job_ids_only: bool,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match result {
+        Ok(Some((job_id, public_id))) => (StatusCode::OK, Json(queued_job_body(public_job_ids_only, job_id, public_id))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"ok": false, "error": "No previous build of the default branch to rebuild"})),
+        ),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        },
+    }
 }
 
-#[derive(Serialize)]
-struct LogEntry {
-    timestamp: String,
-    message: String,
-    level: String,
+#[derive(Deserialize)]
+struct SetRepoEnabledRequest {
+    enabled: bool,
 }
 
-async fn api_job(
+/// Pause or resume builds of a repo; webhooks keep being accepted either way
+async fn api_set_repo_enabled(
+    _: RequireAdmin,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-) -> Json<Option<JobWithLogs>> {
-    let job = match db::get_job(&state.db, id).await {
-        Ok(Some(job)) => job,
-        _ => return Json(None),
-    };
-
-    let logs_text = db::get_job_logs(&state.db, id)
-        .await
-        .unwrap_or_default()
-        .unwrap_or_default();
-    
-    // Parse logs - each line is a log entry
-    let logs: Vec<LogEntry> = logs_text
-        .lines()
-        .map(|line| {
-            // Try to extract timestamp if present (format: [timestamp] message)
-            let (timestamp, message) = if line.starts_with('[') {
-                if let Some(end) = line.find(']') {
-                    (line[1..end].to_string(), line[end+1..].trim().to_string())
-                } else {
-                    (chrono::Utc::now().to_rfc3339(), line.to_string())
-                }
-            } else {
-                (chrono::Utc::now().to_rfc3339(), line.to_string())
-            };
-            
-            let level = if message.to_lowercase().contains("error") {
-                "error"
-            } else if message.to_lowercase().contains("warning") || message.to_lowercase().contains("warn") {
-                "warning"
-            } else {
-                "info"
-            }.to_string();
-            
-            LogEntry { timestamp, message, level }
-        })
-        .collect();
-
-    Json(Some(JobWithLogs { job, logs }))
+    jar: CookieJar,
+    Json(req): Json<SetRepoEnabledRequest>,
+) -> impl IntoResponse {
+    match db::set_repo_enabled(&state.db, id, req.enabled).await {
+        Ok(true) => {
+            let action = if req.enabled { "resumed" } else { "paused" };
+            tracing::info!("Builds of repo {} {} by {}", id, action, request_user(&state, &jar));
+            (StatusCode::OK, Json(serde_json::json!({"ok": true})))
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Repo not found"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
 }
 
-async fn api_repos(State(state): State<Arc<AppState>>) -> Json<Vec<RepoSummary>> {
-    let repos = db::list_repos(&state.db).await.unwrap_or_default();
-    Json(repos)
+#[derive(Deserialize)]
+struct SetCancelOutdatedRequest {
+    cancel_outdated: db::CancelOutdated,
 }
 
-async fn api_repo(
+/// Choose which older builds of a branch a new push to it cancels
+async fn api_set_repo_cancel_outdated(
+    _: RequireAdmin,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
+    jar: CookieJar,
+    Json(req): Json<SetCancelOutdatedRequest>,
 ) -> impl IntoResponse {
-    match db::get_repo(&state.db, id).await {
-        Ok(Some(repo)) => Json(serde_json::json!(repo)).into_response(),
-        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Repo not found"}))).into_response(),
+    match db::set_repo_cancel_outdated(&state.db, id, req.cancel_outdated).await {
+        Ok(true) => {
+            tracing::info!(
+                "Repo {} set to cancel outdated builds: {} by {}",
+                id,
+                req.cancel_outdated.as_str(),
+                request_user(&state, &jar)
+            );
+            (StatusCode::OK, Json(serde_json::json!({"ok": true})))
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Repo not found"}))),
         Err(e) => {
             tracing::error!("{}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Internal server error"}))).into_response()
-        },
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
     }
 }
 
+async fn api_repo_secrets(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Json<Vec<String>> {
+    Json(db::list_repo_secret_names(&state.db, id).await.unwrap_or_default())
+}
+
 #[derive(Deserialize)]
-struct RepoJobsQuery {
-    limit: Option<i32>,
+struct SetSecretRequest {
+    name: String,
+    value: String,
 }
 
-async fn api_repo_jobs(
+async fn api_set_repo_secret(
+    _: RequireAdmin,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
-    Query(query): Query<RepoJobsQuery>,
-) -> Json<Vec<JobSummary>> {
-    let limit = query.limit.unwrap_or(50) as i64;
-    let jobs = db::get_repo_jobs(&state.db, id, limit).await.unwrap_or_default();
-    Json(jobs)
+    jar: CookieJar,
+    Json(req): Json<SetSecretRequest>,
+) -> impl IntoResponse {
+    let Some(key) = &state.config.secret_key else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"ok": false, "error": "Secrets are disabled: set FOUNDRY_SECRET_KEY on the server"})),
+        );
+    };
+    if !foundry_core::config::is_valid_env_name(&req.name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"ok": false, "error": "Secret names must be letters, digits and underscores, not starting with a digit"})),
+        );
+    }
+    if req.value.is_empty() {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"ok": false, "error": "Secret value is required"})));
+    }
+
+    let stored = match key.encrypt(id, &req.name, &req.value) {
+        Ok(sealed) => db::upsert_repo_secret(&state.db, id, &req.name, &sealed).await,
+        Err(e) => Err(e),
+    };
+    match stored {
+        Ok(true) => {
+            tracing::info!("Secret {} of repo {} set by {}", req.name, id, request_user(&state, &jar));
+            (StatusCode::OK, Json(serde_json::json!({"ok": true})))
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Repo not found"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AgentsResponse {
+    server_version: String,
+    protocol_version: u32,
+    agents: Vec<AgentView>,
+}
+
+#[derive(Serialize)]
+struct AgentView {
+    #[serde(flatten)]
+    agent: db::AgentSummary,
+    compatibility: AgentCompatibility,
+}
+
+/// Agents that have polled for work, flagged where their build differs from the server's
+async fn api_agents(State(state): State<Arc<AppState>>) -> Json<AgentsResponse> {
+    let server_version = foundry_core::build_info!().to_string();
+    let agents = db::list_agents(&state.db)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|agent| AgentView {
+            compatibility: agent_compatibility(
+                &server_version,
+                agent.version.as_deref(),
+                agent.protocol_version.map(|v| v as u32),
+            ),
+            agent,
+        })
+        .collect();
+    Json(AgentsResponse { server_version, protocol_version: foundry_core::PROTOCOL_VERSION, agents })
 }
 
 async fn api_schedules(State(state): State<Arc<AppState>>) -> Json<Vec<ScheduleSummary>> {
@@ -189,6 +1102,7 @@ struct ToggleScheduleRequest {
 }
 
 async fn api_toggle_schedule(
+    _: RequireAdmin,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
     Json(req): Json<ToggleScheduleRequest>,
@@ -204,6 +1118,7 @@ async fn api_toggle_schedule(
 }
 
 async fn api_delete_schedule(
+    _: RequireAdmin,
     State(state): State<Arc<AppState>>,
     Path(id): Path<i64>,
 ) -> impl IntoResponse {
@@ -222,13 +1137,27 @@ async fn api_delete_schedule(
 #[derive(Deserialize)]
 struct ContainersQuery {
     project: Option<String>,
+    /// `owner/name`; matches containers deployed with `docker run`, which have no project
+    repo: Option<String>,
+    /// Only containers Foundry started, skipping others on a shared host
+    #[serde(default)]
+    managed: bool,
 }
 
 async fn api_list_containers(
+    State(state): State<Arc<AppState>>,
     Query(query): Query<ContainersQuery>,
 ) -> impl IntoResponse {
-    match docker::list_containers(query.project.as_deref()).await {
-        Ok(containers) => (StatusCode::OK, Json(serde_json::json!(containers))).into_response(),
+    let filter = docker::ContainerFilter {
+        project: query.project.as_deref(),
+        repo: query.repo.as_deref(),
+        managed: query.managed,
+    };
+    match docker::list_containers(&filter).await {
+        Ok(mut containers) => {
+            hide_job_ids(&state, &mut containers);
+            (StatusCode::OK, Json(serde_json::json!(containers))).into_response()
+        }
         Err(e) => {
             tracing::error!("{}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Internal server error"}))).into_response()
@@ -259,12 +1188,7 @@ async fn api_container_logs_stream(
     Query(query): Query<LogsQuery>,
 ) -> impl IntoResponse {
     match docker::stream_container_logs(&id, query.lines).await {
-        Ok(rx) => {
-            let stream = ReceiverStream::new(rx).map(|line| {
-                Ok::<_, Infallible>(Event::default().data(line))
-            });
-            Sse::new(stream).into_response()
-        }
+        Ok(rx) => container_logs_sse(rx).into_response(),
         Err(e) => {
             tracing::error!("{}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Internal server error"}))).into_response()
@@ -272,7 +1196,19 @@ async fn api_container_logs_stream(
     }
 }
 
+/// One unnamed event per log line, then `done` once `docker logs` exits so the browser
+/// doesn't reconnect and replay the tail. A client going away drops the stream and `rx`.
+fn container_logs_sse(
+    rx: tokio::sync::mpsc::Receiver<String>,
+) -> Sse<impl tokio_stream::Stream<Item = Result<Event, Infallible>>> {
+    let lines = ReceiverStream::new(rx).map(|line| Event::default().data(line));
+    // Browsers skip events without data
+    let done = tokio_stream::once(Event::default().event("done").data("exited"));
+    Sse::new(lines.chain(done).map(Ok)).keep_alive(axum::response::sse::KeepAlive::default())
+}
+
 async fn api_restart_container(
+    _: RequireAdmin,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     match docker::restart_container(&id).await {
@@ -285,6 +1221,7 @@ async fn api_restart_container(
 }
 
 async fn api_stop_container(
+    _: RequireAdmin,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     match docker::stop_container(&id).await {
@@ -297,6 +1234,7 @@ async fn api_stop_container(
 }
 
 async fn api_start_container(
+    _: RequireAdmin,
     Path(id): Path<String>,
 ) -> impl IntoResponse {
     match docker::start_container(&id).await {
@@ -320,7 +1258,199 @@ async fn api_list_projects() -> impl IntoResponse {
     }
 }
 
+async fn api_repo_deployments(State(state): State<Arc<AppState>>, Path(id): Path<i64>) -> Json<Vec<Deployment>> {
+    let mut deployments = db::list_deployments(&state.db, id, 20).await.unwrap_or_default();
+    hide_job_ids(&state, &mut deployments);
+    Json(deployments)
+}
+
+/// Check a foundry.toml sent as the request body without pushing it. Schedules are
+/// checked here since cron parsing lives in the server.
+async fn api_validate_config(body: String) -> impl IntoResponse {
+    let errors = match config::parse_config(&body) {
+        Ok(fc) => {
+            let mut errors = fc.validate();
+            if let Some(schedule) = &fc.schedule {
+                let next = crate::scheduler::next_run(&schedule.cron, schedule.timezone.as_deref(), chrono::Utc::now());
+                if let Err(e) = next {
+                    errors.push(config::ConfigError { field: "schedule".to_string(), message: e.to_string(), line: None });
+                }
+            }
+            errors
+        }
+        Err(error) => vec![error],
+    };
+
+    if errors.is_empty() {
+        (StatusCode::OK, Json(serde_json::json!({"ok": true, "errors": errors})))
+    } else {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({"ok": false, "error": "foundry.toml is invalid", "errors": errors})),
+        )
+    }
+}
+
+/// Delete webhook events past retention and artifact files nothing refers to
+async fn api_admin_prune(_: RequireAdmin, State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let artifact_dir = std::path::Path::new(&state.config.artifact_dir);
+    match prune::prune(&state.db, artifact_dir, state.config.webhook_retention_days).await {
+        Ok(removed) => {
+            tracing::info!("Pruned {:?}", removed);
+            (StatusCode::OK, Json(serde_json::json!({"ok": true, "removed": removed})))
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WebhookEventsQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    event_type: Option<String>,
+}
+
+/// A page of stored webhook deliveries, for finding out why a push didn't build; the
+/// `X-Total-Count` header has the number of matching events
+async fn api_webhook_events(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<WebhookEventsQuery>,
+) -> axum::response::Response {
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+    let offset = query.offset.unwrap_or(0).max(0);
+    let event_type = query.event_type.as_deref().filter(|t| !t.is_empty());
+    let (events, total) = tokio::join!(
+        db::list_webhook_events(&state.db, event_type, limit, offset),
+        db::count_webhook_events(&state.db, event_type),
+    );
+    match (events, total) {
+        (Ok(mut events), Ok(total)) => {
+            hide_job_ids(&state, &mut events);
+            ([("x-total-count", total.to_string())], Json(events)).into_response()
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+                .into_response()
+        }
+    }
+}
+
+/// The payload of a stored webhook delivery, exactly as GitHub sent it
+async fn api_webhook_event(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match db::get_webhook_event(&state.db, id).await {
+        Ok(Some((_, payload))) => (StatusCode::OK, Json(payload)),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Webhook event not found"}))),
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+/// Run a stored webhook delivery through its handler again, e.g. one whose build was
+/// lost to a transient database error. Its signature was checked when it arrived.
+async fn api_replay_webhook_event(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let (event_type, payload) = match db::get_webhook_event(&state.db, id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "Webhook event not found"})));
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})));
+        }
+    };
+
+    tracing::info!("{} replaying {} webhook event {}", request_user(&state, &jar), event_type, id);
+    let body = axum::body::Bytes::from(payload.to_string());
+    let (status, Json(response)) = webhook::dispatch(&state, &event_type, &body, Some(id)).await;
+    (status, Json(serde_json::json!(response)))
+}
+
+/// Cancel every queued job, for flushing the queue during an incident
+async fn api_admin_clear_queue(_: RequireAdmin, State(state): State<Arc<AppState>>, jar: CookieJar) -> impl IntoResponse {
+    match db::clear_queue(&state.db).await {
+        Ok(cancelled) => {
+            tracing::warn!("{} cleared the queue, cancelling {} jobs", request_user(&state, &jar), cancelled);
+            (StatusCode::OK, Json(serde_json::json!({"ok": true, "cancelled": cancelled})))
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+async fn api_admin_pause(_: RequireAdmin, State(state): State<Arc<AppState>>, jar: CookieJar) -> impl IntoResponse {
+    set_queue_paused(&state, &jar, true).await
+}
+
+async fn api_admin_resume(_: RequireAdmin, State(state): State<Arc<AppState>>, jar: CookieJar) -> impl IntoResponse {
+    set_queue_paused(&state, &jar, false).await
+}
+
+/// Stop or restart agents claiming jobs. Running jobs carry on, and webhooks still queue.
+async fn set_queue_paused(state: &AppState, jar: &CookieJar, paused: bool) -> (StatusCode, Json<serde_json::Value>) {
+    match db::set_queue_paused(&state.db, paused).await {
+        Ok(()) => {
+            tracing::warn!("{} {} the queue", request_user(state, jar), if paused { "paused" } else { "resumed" });
+            (StatusCode::OK, Json(serde_json::json!({"ok": true, "paused": paused})))
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})))
+        }
+    }
+}
+
+/// Put the app's previous `docker run` deploy back, with the image and arguments it ran with
+async fn api_rollback_deployment(
+    _: RequireAdmin,
+    State(state): State<Arc<AppState>>,
+    Path(app): Path<String>,
+) -> impl IntoResponse {
+    let (current, previous) = match db::get_rollback_deployments(&state.db, &app).await {
+        Ok(deployments) => deployments,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": "Internal server error"})));
+        }
+    };
+    let Some(current) = current else {
+        return (StatusCode::NOT_FOUND, Json(serde_json::json!({"ok": false, "error": "No deployments recorded for this app"})));
+    };
+    let Some(previous) = previous else {
+        return (StatusCode::CONFLICT, Json(serde_json::json!({"ok": false, "error": "No earlier deployment to roll back to"})));
+    };
+
+    if let Err(e) = docker::redeploy(&app, &previous.image, &previous.args, &previous.command).await {
+        tracing::error!("Rollback of {} failed: {}", app, e);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"ok": false, "error": e.to_string()})));
+    }
+
+    if let Err(e) = db::mark_deployment_rolled_back(&state.db, current.id).await {
+        tracing::error!("{}", e);
+    }
+    tracing::info!("Rolled {} back from {} to {}", app, current.image, previous.image);
+    (StatusCode::OK, Json(serde_json::json!({"ok": true, "image": previous.image, "job_public_id": previous.job_public_id})))
+}
+
 async fn api_restart_project(
+    _: RequireAdmin,
     Path(name): Path<String>,
 ) -> impl IntoResponse {
     match docker::restart_project(&name).await {
@@ -333,6 +1463,7 @@ async fn api_restart_project(
 }
 
 async fn api_stop_project(
+    _: RequireAdmin,
     Path(name): Path<String>,
 ) -> impl IntoResponse {
     match docker::stop_project(&name).await {
@@ -345,6 +1476,7 @@ async fn api_stop_project(
 }
 
 async fn api_start_project(
+    _: RequireAdmin,
     Path(name): Path<String>,
 ) -> impl IntoResponse {
     match docker::start_project(&name).await {
@@ -355,3 +1487,453 @@ async fn api_start_project(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_state(public_job_ids_only: bool) -> Arc<AppState> {
+        let mut config = crate::config::Config::for_tests();
+        config.public_job_ids_only = public_job_ids_only;
+        crate::test_state(config)
+    }
+
+    async fn get(state: Arc<AppState>, uri: &str) -> (StatusCode, String) {
+        let response = api_router()
+            .with_state(state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_is_public_id() {
+        assert!(is_public_id("k2m7qxa4zt"));
+        assert!(!is_public_id(""));
+        assert!(!is_public_id("K2M7QXA4ZT"));
+        assert!(!is_public_id("k2m7qxa4z1"));
+        assert!(!is_public_id("../etc"));
+    }
+
+    #[test]
+    fn test_log_entry_timestamp_prefix() {
+        let entry = log_entry("[2024-05-01T10:00:00+00:00] Cloning repo", "info", "fallback");
+        assert_eq!(entry.timestamp, "2024-05-01T10:00:00+00:00");
+        assert_eq!(entry.message, "Cloning repo");
+        assert_eq!(entry.level, "info");
+    }
+
+    #[test]
+    fn test_log_entry_keeps_stage_prefix() {
+        let entry = log_entry("[test] STDERR: error: build failed", "error", "fallback");
+        assert_eq!(entry.timestamp, "fallback");
+        assert_eq!(entry.message, "[test] STDERR: error: build failed");
+        assert_eq!(entry.level, "error");
+    }
+
+    #[test]
+    fn test_escape_markdown() {
+        assert_eq!(escape_markdown("known flake, infra issue"), "known flake, infra issue");
+        assert_eq!(escape_markdown("**bold** [link](x)"), "\\*\\*bold\\*\\* \\[link\\](x)");
+        assert_eq!(escape_markdown("<img src=x>"), "\\<img src=x\\>");
+    }
+
+    #[tokio::test]
+    async fn test_note_author_from_session() {
+        let auth = auth::AuthState::new(crate::config::AuthConfig {
+            issuer_url: "https://auth.example.com".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            cookie_secret: "cookie-secret".to_string(),
+            redirect_url: "https://ci.example.com/auth/callback".to_string(),
+            allowed_emails: vec![],
+            admin_emails: vec![],
+        })
+        .await
+        .unwrap();
+        let token = auth.create_session("ops@example.com").unwrap();
+
+        let mut state = Arc::into_inner(test_state(false)).unwrap();
+        state.auth = Some(auth);
+
+        let jar = CookieJar::new().add(axum_extra::extract::cookie::Cookie::new("foundry_session", token));
+        assert_eq!(request_user(&state, &jar), "ops@example.com");
+        assert_eq!(request_user(&state, &CookieJar::new()), "anonymous");
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoints_require_admin() {
+        let auth = auth::AuthState::new(crate::config::AuthConfig {
+            issuer_url: "https://auth.example.com".to_string(),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            cookie_secret: "cookie-secret".to_string(),
+            redirect_url: "https://ci.example.com/auth/callback".to_string(),
+            allowed_emails: vec![],
+            admin_emails: vec!["ops@example.com".to_string()],
+        })
+        .await
+        .unwrap();
+        let token = auth.create_session("dev@example.com").unwrap();
+        let admin = CookieJar::new().add(axum_extra::extract::cookie::Cookie::new(
+            "foundry_session",
+            auth.create_session("ops@example.com").unwrap(),
+        ));
+        assert!(auth::is_admin(Some(&auth), &admin));
+        assert!(auth::is_admin(None, &CookieJar::new()));
+
+        assert_eq!(auth.role("dev@example.com"), auth::Role::Viewer);
+        let mut state = Arc::into_inner(test_state(false)).unwrap();
+        state.auth = Some(auth);
+        let state = Arc::new(state);
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("cookie", format!("foundry_session={}", token))
+                .header("content-type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+        for (method, uri) in [
+            ("POST", "/api/admin/clear-queue"),
+            ("POST", "/api/admin/pause"),
+            ("POST", "/api/admin/resume"),
+            ("POST", "/api/admin/prune"),
+            ("GET", "/api/webhook-events"),
+            ("GET", "/api/webhook-events/1"),
+            ("POST", "/api/job/1/cancel"),
+            ("POST", "/api/job/1/retry"),
+            ("POST", "/api/job/1/note"),
+            ("POST", "/api/repos/1/build"),
+            ("POST", "/api/repos/1/enabled"),
+            ("POST", "/api/repos/1/secrets"),
+            ("POST", "/api/schedule/1/toggle"),
+            ("DELETE", "/api/schedule/1"),
+            ("POST", "/api/containers/app/restart"),
+            ("POST", "/api/deploy/app/rollback"),
+        ] {
+            let response = api_router().with_state(state.clone()).oneshot(request(method, uri)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+
+        // Viewers can still look
+        let response = api_router().with_state(state.clone()).oneshot(request("GET", "/api/jobs")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ack_requires_reason() {
+        let response = api_router()
+            .with_state(test_state(false))
+            .oneshot(
+                Request::post("/api/job/1/ack")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"reason": "  "}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_job_list_rejects_unknown_status() {
+        let (status, _) = get(test_state(false), "/api/jobs?status=exploded").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = get(test_state(false), "/api/jobs?status=failed&offset=50&repo=acme%2Fapp").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn test_stats_fail_without_database() {
+        let (status, body) = get(test_state(false), "/api/stats").await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.contains("Internal server error"));
+    }
+
+    #[tokio::test]
+    async fn test_queue_jobs_listed() {
+        let (status, body) = get(test_state(false), "/api/queue/jobs?limit=10").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn test_numeric_job_lookup_disabled() {
+        let (status, _) = get(test_state(true), "/api/job/42").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get(test_state(false), "/api/job/42").await;
+        assert_eq!(status, StatusCode::OK);
+
+        for (uri, body) in [
+            ("/api/job/42/cancel", "{}"),
+            ("/api/job/42/retry", "{}"),
+            ("/api/job/42/note", r#"{"body": "flaky"}"#),
+            ("/api/job/42/ack", r#"{"reason": "flaky"}"#),
+        ] {
+            let response = api_router()
+                .with_state(test_state(true))
+                .oneshot(
+                    Request::post(uri)
+                        .header("content-type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+    }
+
+    /// Job-shaped values must have no `id`, and nothing may carry a `job_id`
+    fn assert_no_numeric_job_ids(value: &serde_json::Value, job_shaped: bool) {
+        match value {
+            serde_json::Value::Array(items) => items.iter().for_each(|item| assert_no_numeric_job_ids(item, job_shaped)),
+            serde_json::Value::Object(fields) => {
+                assert!(!(job_shaped && fields.contains_key("id")), "numeric id in {}", value);
+                assert!(!fields.contains_key("job_id"), "numeric job_id in {}", value);
+            }
+            _ => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_public_only_responses_omit_numeric_ids() {
+        use db::tests::{insert_test_job, insert_test_repo, test_pool, TestJob};
+
+        let Some(pool) = test_pool().await else { return };
+        let agent = uuid::Uuid::new_v4().to_string();
+        let claim_token = uuid::Uuid::new_v4();
+        let repo_id = insert_test_repo(&pool, "public-only").await;
+        let parent = insert_test_job(
+            &pool,
+            repo_id,
+            TestJob { status: Some("running"), target_agent: Some(&agent), claim_token: Some(claim_token), ..Default::default() },
+        )
+        .await;
+        let child = insert_test_job(
+            &pool,
+            repo_id,
+            TestJob { status: Some("success"), target_agent: Some(&agent), finished_days_ago: Some(0), ..Default::default() },
+        )
+        .await;
+        sqlx::query("UPDATE job SET parent_job_id = $1, matrix_key = 'rust=1.76' WHERE id = $2")
+            .bind(parent)
+            .bind(child)
+            .execute(&pool)
+            .await
+            .unwrap();
+        db::create_artifact(&pool, parent, claim_token, "dist/app", 10, "application/octet-stream").await.unwrap().unwrap();
+        db::add_job_note(&pool, parent, "ops@example.com", "flaky").await.unwrap().unwrap();
+        let (repo_name, parent_id, child_id): (String, String, String) = sqlx::query_as(
+            "SELECT r.name, p.public_id, c.public_id FROM repo r, job p, job c WHERE r.id = $1 AND p.id = $2 AND c.id = $3",
+        )
+        .bind(repo_id)
+        .bind(parent)
+        .bind(child)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        let mut state = Arc::into_inner(test_state(true)).unwrap();
+        state.db = pool;
+        let state = Arc::new(state);
+        for (uri, expected, job_shaped) in [
+            (format!("/api/jobs?repo=public-only%2F{}", repo_name), StatusCode::OK, true),
+            ("/api/queue/jobs?limit=1000".to_string(), StatusCode::OK, true),
+            (format!("/api/repo/{}/jobs", repo_id), StatusCode::OK, true),
+            (format!("/api/jobs/{}/result", child_id), StatusCode::OK, true),
+            (format!("/api/jobs/{}/result", parent_id), StatusCode::ACCEPTED, true),
+            (format!("/api/job/{}/artifacts", parent_id), StatusCode::OK, false),
+        ] {
+            let (status, body) = get(state.clone(), &uri).await;
+            assert_eq!(status, expected, "{}", uri);
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert!(!body.as_array().is_some_and(|items| items.is_empty()), "{} is empty", uri);
+            assert_no_numeric_job_ids(&body, job_shaped);
+        }
+
+        let (_, body) = get(state.clone(), &format!("/api/j/{}", parent_id)).await;
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["public_id"], parent_id);
+        assert_no_numeric_job_ids(&body, true);
+        assert_eq!(body["children"][0]["public_id"], child_id);
+        assert_no_numeric_job_ids(&body["children"], true);
+        assert_eq!(body["notes"][0]["body"], "flaky");
+        assert_no_numeric_job_ids(&body["notes"], false);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_public_id_rejected() {
+        let (status, body) = get(test_state(false), "/api/j/NOT-VALID").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "null");
+    }
+
+    #[test]
+    fn test_artifact_content_disposition() {
+        assert_eq!(content_disposition("target/release/app"), "attachment; filename=\"app\"");
+        assert_eq!(content_disposition("dist/my \"app\".tar.gz"), "attachment; filename=\"my _app_.tar.gz\"");
+        assert_eq!(content_disposition("bin/héllo"), "attachment; filename=\"h_llo\"");
+    }
+
+    #[tokio::test]
+    async fn test_artifacts_by_numeric_id_disabled() {
+        let (status, _) = get(test_state(true), "/api/job/42/artifacts").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_rebuild_latest_queues_job() {
+        let (status, Json(body)) = rebuild_response(Ok(Some((42, "k2m7qxa4zt".to_string()))), false);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({"ok": true, "job_id": 42, "public_id": "k2m7qxa4zt"}));
+
+        let (_, Json(body)) = rebuild_response(Ok(Some((42, "k2m7qxa4zt".to_string()))), true);
+        assert_eq!(body, serde_json::json!({"ok": true, "public_id": "k2m7qxa4zt"}));
+    }
+
+    #[test]
+    fn test_rebuild_latest_without_history() {
+        let (status, Json(body)) = rebuild_response(Ok(None), false);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["ok"], false);
+        assert_eq!(body["error"], "No previous build of the default branch to rebuild");
+    }
+
+    #[test]
+    fn test_build_ref() {
+        let full = |git_ref: &str, name: &str| Some((git_ref.to_string(), name.to_string()));
+        assert_eq!(build_ref("main"), full("refs/heads/main", "main"));
+        assert_eq!(build_ref("feature/login"), full("refs/heads/feature/login", "feature/login"));
+        assert_eq!(build_ref("refs/heads/release-1.x"), full("refs/heads/release-1.x", "release-1.x"));
+        assert_eq!(build_ref("refs/tags/v1.2.0"), full("refs/tags/v1.2.0", "v1.2.0"));
+
+        for invalid in ["--upload-pack=touch /tmp/x", "refs/pull/1/head", "refs/heads/", "a..b", "a b", "main.lock", "HEAD@{1}", "a:b"] {
+            assert_eq!(build_ref(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_rejects_invalid_ref() {
+        let response = api_router()
+            .with_state(test_state(false))
+            .oneshot(
+                Request::post("/api/repos/1/build")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"ref": "-x"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    async fn set_secret(state: Arc<AppState>, body: &'static str) -> (StatusCode, serde_json::Value) {
+        let response = api_router()
+            .with_state(state)
+            .oneshot(
+                Request::post("/api/repos/1/secrets")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_set_secret_requires_server_key() {
+        let (status, body) = set_secret(test_state(false), r#"{"name": "NPM_TOKEN", "value": "npm_abc"}"#).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ok"], false);
+    }
+
+    #[tokio::test]
+    async fn test_set_secret_validates_name() {
+        let mut state = Arc::into_inner(test_state(false)).unwrap();
+        state.config.secret_key =
+            Some(crate::secrets::SecretKey::from_base64("MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=").unwrap());
+        let state = Arc::new(state);
+
+        let (status, _) = set_secret(state.clone(), r#"{"name": "NPM-TOKEN", "value": "npm_abc"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = set_secret(state, r#"{"name": "NPM_TOKEN", "value": ""}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_container_log_lines_reach_sse_stream() {
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        tx.send("2024-01-01T00:00:00Z listening on :3000".to_string()).await.unwrap();
+        tx.send("2024-01-01T00:00:01Z GET /health 200".to_string()).await.unwrap();
+        drop(tx);
+
+        let response = container_logs_sse(rx).into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+
+        assert_eq!(
+            body,
+            "data: 2024-01-01T00:00:00Z listening on :3000\n\n\
+             data: 2024-01-01T00:00:01Z GET /health 200\n\n\
+             event: done\ndata: exited\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_job_logs_rejects_unknown_format() {
+        let (status, body) = get(test_state(false), "/api/job/1/logs?format=xml").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("expected text or json"));
+    }
+
+    #[tokio::test]
+    async fn test_job_logs_hides_numeric_ids_when_public_only() {
+        let (status, _) = get(test_state(true), "/api/job/1/logs").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get(test_state(true), "/api/job/1/logs/stream").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    async fn validate(body: &'static str) -> (StatusCode, serde_json::Value) {
+        let response = api_router()
+            .with_state(test_state(false))
+            .oneshot(Request::post("/api/validate-config").body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_validate_config() {
+        let (status, body) = validate("[build]\ncommand = \"make\"\n\n[schedule]\ncron = \"0 0 * * *\"\n").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["errors"], serde_json::json!([]));
+
+        let (status, body) = validate("[build]\ncommand = make\n").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["line"], 2);
+
+        let (status, body) = validate("[schedule]\ncron = \"daily\"\n\n[deploy]\nport = 0\n").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["errors"][0]["field"], "deploy.port");
+        assert_eq!(body["errors"][1]["field"], "schedule");
+    }
+}
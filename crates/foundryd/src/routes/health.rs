@@ -1,22 +1,135 @@
-use axum::{routing::get, Json, Router};
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use foundry_core::BuildInfo;
 use serde::Serialize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::AppState;
 
 #[derive(Serialize)]
 struct Health {
     status: &'static str,
-    version: &'static str,
+    #[serde(flatten)]
+    build: BuildInfo,
 }
 
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    database: &'static str,
+    connections: PoolConnections,
+}
+
+#[derive(Serialize)]
+struct PoolConnections {
+    idle: usize,
+    used: usize,
+    max: u32,
+}
+
+/// Longest the readiness check waits on Postgres, so probes don't hang on a dead pool
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub fn router() -> Router<Arc<AppState>> {
-    Router::new().route("/health", get(health))
+    Router::new()
+        .route("/health", get(health))
+        .route("/health/ready", get(ready))
+        .route("/version", get(version))
 }
 
 async fn health() -> Json<Health> {
     Json(Health {
         status: "ok",
-        version: env!("CARGO_PKG_VERSION"),
+        build: foundry_core::build_info!(),
     })
 }
+
+/// Readiness: whether the database answers. `/health` stays a liveness probe that
+/// doesn't touch it, so an outage doesn't get the server restarted.
+async fn ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let check = tokio::time::timeout(READY_TIMEOUT, sqlx::query("SELECT 1").execute(&state.db)).await;
+    let database_ok = match check {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            tracing::warn!("Readiness check failed: {}", e);
+            false
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check timed out after {}s", READY_TIMEOUT.as_secs());
+            false
+        }
+    };
+
+    let idle = state.db.num_idle();
+    let connections = PoolConnections {
+        idle,
+        used: (state.db.size() as usize).saturating_sub(idle),
+        max: state.db.options().get_max_connections(),
+    };
+    if database_ok {
+        (StatusCode::OK, Json(Readiness { status: "ok", database: "ok", connections }))
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(Readiness { status: "unavailable", database: "unreachable", connections }))
+    }
+}
+
+/// Which build is running, to confirm a deploy rolled out
+async fn version() -> Json<BuildInfo> {
+    Json(foundry_core::build_info!())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    async fn request(uri: &str) -> (StatusCode, serde_json::Value) {
+        let state = crate::test_state(crate::config::Config::for_tests());
+
+        let response = router()
+            .with_state(state)
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn get_json(uri: &str) -> serde_json::Value {
+        let (status, body) = request(uri).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    #[tokio::test]
+    async fn test_version_reports_build_info() {
+        let body = get_json("/version").await;
+        for field in ["version", "git_sha", "built_at"] {
+            assert!(!body[field].as_str().unwrap().is_empty(), "{} is empty", field);
+        }
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert_ne!(body["built_at"], "unknown");
+    }
+
+    #[tokio::test]
+    async fn test_health_includes_build_info() {
+        let body = get_json("/health").await;
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["git_sha"], get_json("/version").await["git_sha"]);
+    }
+
+    #[tokio::test]
+    async fn test_ready_fails_without_database() {
+        // Liveness doesn't depend on the database
+        get_json("/health").await;
+
+        let (status, body) = request("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["database"], "unreachable");
+        assert_eq!(body["connections"]["used"], 0);
+        assert_eq!(body["connections"]["max"], 10);
+    }
+}
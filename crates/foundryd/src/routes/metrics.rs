@@ -0,0 +1,279 @@
+//! Prometheus metrics in the text exposition format, and a queue summary for
+//! autoscalers
+
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::header, http::StatusCode, middleware, response::IntoResponse, routing::get, Json, Router};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::db::{self, DashboardStats, RepoQueue, RepoStatusCount};
+use crate::routes::agent::require_agent_token;
+use crate::AppState;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// How long a queue snapshot is reused before querying again
+const QUEUE_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// Both routes name repos, so once `FOUNDRY_AGENT_TOKENS` is set scrapers and scalers
+/// must send one of those tokens as a bearer token, like agents do
+pub fn router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .route("/api/queue", get(queue))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_agent_token))
+}
+
+/// Work waiting for and held by agents, overall and per repo
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueSnapshot {
+    pub queued: i64,
+    pub running: i64,
+    /// How long the oldest queued job has waited, 0 if nothing is queued
+    pub oldest_queued_secs: i64,
+    pub repos: Vec<RepoQueue>,
+}
+
+impl QueueSnapshot {
+    fn from_repos(repos: Vec<RepoQueue>) -> Self {
+        Self {
+            queued: repos.iter().map(|r| r.queued).sum(),
+            running: repos.iter().map(|r| r.running).sum(),
+            oldest_queued_secs: repos.iter().filter_map(|r| r.oldest_queued_secs).max().unwrap_or(0),
+            repos,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct QueueCache(Mutex<Option<(Instant, QueueSnapshot)>>);
+
+/// Queue depth for an autoscaler such as KEDA's metrics-api scaler to poll, guarded by
+/// the same agent token as `/metrics`. Answered from a snapshot up to `QUEUE_CACHE_TTL` old.
+async fn queue(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // Holding the lock while querying makes concurrent polls wait for one query
+    let mut cached = state.queue_cache.0.lock().await;
+    if let Some((at, snapshot)) = cached.as_ref() {
+        if at.elapsed() < QUEUE_CACHE_TTL {
+            return (StatusCode::OK, Json(serde_json::json!(snapshot)));
+        }
+    }
+
+    match db::get_queue_by_repo(&state.db).await {
+        Ok(repos) => {
+            let snapshot = QueueSnapshot::from_repos(repos);
+            let body = serde_json::json!(snapshot);
+            *cached = Some((Instant::now(), snapshot));
+            (StatusCode::OK, Json(body))
+        }
+        Err(e) => {
+            tracing::error!("Failed to read job queue: {}", e);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({"ok": false, "error": "Queue unavailable"})),
+            )
+        }
+    }
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let snapshot = async {
+        let stats = db::get_dashboard_stats(&state.db).await?;
+        let by_repo = db::get_job_counts_by_repo(&state.db).await?;
+        let recent = db::get_recent_build_duration(&state.db).await?;
+        anyhow::Ok((stats, by_repo, recent))
+    };
+
+    match snapshot.await {
+        Ok((stats, by_repo, (builds, avg_secs))) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, CONTENT_TYPE)],
+            render(&stats, &by_repo, builds, avg_secs),
+        ),
+        Err(e) => {
+            tracing::error!("Failed to collect metrics: {}", e);
+            // A failed scrape is better than reporting zeros
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::CONTENT_TYPE, CONTENT_TYPE)],
+                String::new(),
+            )
+        }
+    }
+}
+
+fn render(stats: &DashboardStats, by_repo: &[RepoStatusCount], builds_last_hour: i64, avg_secs: Option<f64>) -> String {
+    let mut out = String::new();
+
+    gauge(&mut out, "foundry_jobs", "Jobs by repository and status", |out| {
+        for c in by_repo {
+            let _ = writeln!(
+                out,
+                "foundry_jobs{{repo=\"{}\",status=\"{}\"}} {}",
+                escape_label(&c.repo),
+                escape_label(&c.status),
+                c.count
+            );
+        }
+    });
+    simple(&mut out, "foundry_jobs_last_24h", "Jobs queued in the last 24 hours", stats.jobs_today as f64);
+    simple(&mut out, "foundry_queue_depth", "Jobs waiting for an agent", stats.queued_count as f64);
+    simple(&mut out, "foundry_jobs_running", "Jobs currently running", stats.running_count as f64);
+    simple(&mut out, "foundry_queue_paused", "1 while the queue is paused", stats.queue_paused as u8 as f64);
+    simple(
+        &mut out,
+        "foundry_success_rate_percent",
+        "Share of finished builds that succeeded, excluding acknowledged failures",
+        stats.success_rate,
+    );
+    simple(&mut out, "foundry_builds_last_hour", "Builds finished in the last hour", builds_last_hour as f64);
+    simple(
+        &mut out,
+        "foundry_build_duration_seconds_avg",
+        "Average duration of builds finished in the last hour",
+        avg_secs.unwrap_or(0.0),
+    );
+
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, samples: impl FnOnce(&mut String)) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    samples(out);
+}
+
+fn simple(out: &mut String, name: &str, help: &str, value: f64) {
+    gauge(out, name, help, |out| {
+        let _ = writeln!(out, "{} {}", name, value);
+    });
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let stats = DashboardStats {
+            total_jobs: 12,
+            jobs_today: 3,
+            success_rate: 75.0,
+            queued_count: 2,
+            running_count: 1,
+            queue_paused: true,
+        };
+        let by_repo = vec![
+            RepoStatusCount { repo: "acme/app".into(), status: "success".into(), count: 9 },
+            RepoStatusCount { repo: "acme/app".into(), status: "failed".into(), count: 3 },
+        ];
+
+        let out = render(&stats, &by_repo, 4, Some(92.5));
+
+        assert!(out.contains("# TYPE foundry_jobs gauge\n"));
+        assert!(out.contains("foundry_queue_paused 1\n"));
+        assert!(out.contains("foundry_jobs{repo=\"acme/app\",status=\"success\"} 9\n"));
+        assert!(out.contains("foundry_jobs{repo=\"acme/app\",status=\"failed\"} 3\n"));
+        assert!(out.contains("foundry_queue_depth 2\n"));
+        assert!(out.contains("foundry_success_rate_percent 75\n"));
+        assert!(out.contains("foundry_build_duration_seconds_avg 92.5\n"));
+        // Every sample line belongs to a declared metric
+        for line in out.lines().filter(|l| !l.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            assert!(out.contains(&format!("# TYPE {} gauge", name)), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_queue_snapshot_totals() {
+        let repos = vec![
+            RepoQueue { repo: "acme/api".into(), queued: 3, running: 1, oldest_queued_secs: Some(45) },
+            RepoQueue { repo: "acme/app".into(), queued: 2, running: 2, oldest_queued_secs: Some(300) },
+            // Only running jobs, so it has no queue age
+            RepoQueue { repo: "acme/docs".into(), queued: 0, running: 1, oldest_queued_secs: None },
+        ];
+
+        let snapshot = QueueSnapshot::from_repos(repos);
+        assert_eq!(snapshot.queued, 5);
+        assert_eq!(snapshot.running, 4);
+        assert_eq!(snapshot.oldest_queued_secs, 300);
+        assert_eq!(snapshot.repos.len(), 3);
+
+        let empty = QueueSnapshot::from_repos(Vec::new());
+        assert_eq!((empty.queued, empty.running, empty.oldest_queued_secs), (0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_queue_served_from_cache() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        // Unreachable database: only a cached snapshot can answer
+        let state = crate::test_state(crate::config::Config::for_tests());
+        let snapshot = QueueSnapshot::from_repos(vec![RepoQueue {
+            repo: "acme/app".into(),
+            queued: 4,
+            running: 1,
+            oldest_queued_secs: Some(90),
+        }]);
+        *state.queue_cache.0.lock().await = Some((Instant::now(), snapshot));
+
+        let request = || Request::get("/api/queue").body(Body::empty()).unwrap();
+        let api = || router(&state).with_state(state.clone());
+        let response = api().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["queued"], 4);
+        assert_eq!(json["running"], 1);
+        assert_eq!(json["oldest_queued_secs"], 90);
+        assert_eq!(json["repos"][0]["repo"], "acme/app");
+
+        // Once stale, the database is asked again
+        state.queue_cache.0.lock().await.as_mut().unwrap().0 -= QUEUE_CACHE_TTL;
+        let response = api().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_and_queue_require_agent_token() {
+        use axum::body::Body;
+        use axum::http::Request;
+        use tower::ServiceExt;
+
+        let mut config = crate::config::Config::for_tests();
+        config.agent_tokens = vec!["scrape-token".to_string()];
+        let state = crate::test_state(config);
+        let request = |uri: &str, token: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        for uri in ["/metrics", "/api/queue"] {
+            for token in [None, Some("wrong")] {
+                let response = router(&state).with_state(state.clone()).oneshot(request(uri, token)).await.unwrap();
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+            }
+            // Past the token check, the unreachable database is what fails
+            let response = router(&state).with_state(state.clone()).oneshot(request(uri, Some("scrape-token"))).await.unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+}
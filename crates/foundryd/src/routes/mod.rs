@@ -1,4 +1,5 @@
 pub mod agent;
 pub mod frontend;
 pub mod health;
+pub mod metrics;
 pub mod webhook;
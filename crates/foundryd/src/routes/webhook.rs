@@ -9,9 +9,9 @@ use axum::{
 use std::sync::Arc;
 use tracing::{error, info, warn};
 
-use foundry_core::{github::{PushEvent, PullRequestEvent}, verify_github_signature, ApiResponse};
+use foundry_core::{cloudflare::CloudflareClient, config::{matching_glob, TriggersConfig}, github::{CheckRunEvent, PushEvent, PullRequestEvent, ReleaseEvent}, verify_github_signature, ApiResponse, TriggerType};
 
-use crate::{db::{self, PushEventData, PullRequestEventData, RepoData}, AppState};
+use crate::{db::{self, PushEventData, PullRequestEventData, RepoData}, docker, AppState};
 
 pub fn router() -> Router<Arc<AppState>> {
     Router::new().route("/webhook/github", post(github_webhook))
@@ -60,9 +60,22 @@ async fn github_webhook(
         warn!("Failed to store webhook event: {}", e);
     }
 
+    dispatch(&state, event_type, &body, None).await
+}
+
+/// Run a payload through the handler for its event type. `replay_of` is the stored
+/// event being replayed, which the jobs it queues are marked with.
+pub async fn dispatch(
+    state: &Arc<AppState>,
+    event_type: &str,
+    body: &Bytes,
+    replay_of: Option<i64>,
+) -> (StatusCode, Json<ApiResponse>) {
     match event_type {
-        "push" => handle_push_event(&state, &body).await,
-        "pull_request" => handle_pull_request_event(&state, &body).await,
+        "push" => handle_push_event(state, body, replay_of).await,
+        "pull_request" => handle_pull_request_event(state, body, replay_of).await,
+        "check_run" => handle_check_run_event(state, body).await,
+        "release" => handle_release_event(state, body, replay_of).await,
         _ => {
             info!("Ignoring event type: {}", event_type);
             (StatusCode::OK, Json(ApiResponse::ok()))
@@ -70,9 +83,20 @@ async fn github_webhook(
     }
 }
 
+/// Link a job queued by a replay to its stored event
+async fn mark_replay(state: &AppState, replay_of: Option<i64>, job_id: i64) {
+    if let Some(event_id) = replay_of {
+        info!("Job {} queued by replaying webhook event {}", job_id, event_id);
+        if let Err(e) = db::mark_replayed(&state.db, event_id, job_id).await {
+            warn!("Failed to mark job {} as a replay of webhook event {}: {}", job_id, event_id, e);
+        }
+    }
+}
+
 async fn handle_push_event(
     state: &Arc<AppState>,
     body: &Bytes,
+    replay_of: Option<i64>,
 ) -> (StatusCode, Json<ApiResponse>) {
     let push: PushEvent = match serde_json::from_slice(body) {
         Ok(p) => p,
@@ -91,35 +115,121 @@ async fn handle_push_event(
         return (StatusCode::OK, Json(ApiResponse::ok()));
     }
 
-    let ref_name = push.git_ref.strip_prefix("refs/heads/").unwrap_or(&push.git_ref);
     let repo = &push.repository;
+
+    if let Some(tag) = push.tag() {
+        let tags = match db::get_tag_triggers(&state.db, &repo.owner.login, &repo.name).await {
+            Ok((tags, _)) => tags,
+            Err(e) => {
+                warn!("Failed to check tag config, not building: {}", e);
+                Vec::new()
+            }
+        };
+        match matching_glob(&tags, tag) {
+            Some(rule) => info!("Tag {} matches '{}' from foundry.toml, proceeding with build", tag, rule),
+            None => {
+                info!("Ignoring push of tag {}: no match in tags {:?}", tag, tags);
+                return (StatusCode::OK, Json(ApiResponse::ok()));
+            }
+        }
+        return enqueue_push(state, &push, replay_of).await;
+    }
+
+    let ref_name = push.git_ref.strip_prefix("refs/heads/").unwrap_or(&push.git_ref);
     
-    // Check if this branch should trigger a build (using stored config or defaults)
-    match db::should_build_branch(&state.db, &repo.owner.login, &repo.name, ref_name).await {
-        Ok(true) => {
-            info!("Branch {} matches trigger config, proceeding with build", ref_name);
+    // Check if this branch should trigger a build (using stored config or server defaults)
+    let (branches, source) = match db::get_branch_triggers(&state.db, &repo.owner.login, &repo.name).await {
+        Ok(Some(branches)) => (branches, "foundry.toml"),
+        Ok(None) => (
+            server_default_branches(&state.config.build_branches, &repo.default_branch),
+            "server defaults",
+        ),
+        Err(e) => {
+            warn!("Failed to check branch config, using server defaults: {}", e);
+            (
+                server_default_branches(&state.config.build_branches, &repo.default_branch),
+                "server defaults",
+            )
         }
-        Ok(false) => {
-            info!("Ignoring push to non-configured branch: {}", ref_name);
+    };
+    match matching_glob(&branches, ref_name) {
+        Some(rule) => {
+            info!("Branch {} matches '{}' from {}, proceeding with build", ref_name, rule, source);
+        }
+        None => {
+            info!("Ignoring push to {}: no match in {} branches {:?}", ref_name, source, branches);
             return (StatusCode::OK, Json(ApiResponse::ok()));
         }
-        Err(e) => {
-            warn!("Failed to check branch config, using fallback: {}", e);
-            // Fallback to default behavior
-            if ref_name != "main" && ref_name != "master" {
-                info!("Ignoring push to non-default branch: {}", ref_name);
-                return (StatusCode::OK, Json(ApiResponse::ok()));
+    }
+
+    let push_data = PushEventData::from_push_event(&push);
+    let changed_files: Vec<String> = push_data
+        .files_added
+        .iter()
+        .chain(&push_data.files_modified)
+        .chain(&push_data.files_removed)
+        .cloned()
+        .collect();
+
+    // The stored filters come from the last build, so a push that edits foundry.toml
+    // always builds; the agent re-checks against the new config after cloning.
+    let config_changed = changed_files.iter().any(|f| f == "foundry.toml");
+    if !config_changed {
+        match db::get_path_filters(&state.db, &repo.owner.login, &repo.name).await {
+            Ok((paths, paths_ignore)) => {
+                let triggers = TriggersConfig { paths, paths_ignore, ..Default::default() };
+                if !triggers.should_build_paths(&changed_files) {
+                    info!(
+                        "Ignoring push to {}/{}: no changed files match trigger paths",
+                        repo.owner.login, repo.name
+                    );
+                    return (StatusCode::OK, Json(ApiResponse::ok()));
+                }
             }
+            Err(e) => warn!("Failed to check path filters, building anyway: {}", e),
         }
     }
 
-    // Extract comprehensive data from push event
-    let repo_data = RepoData::from_push_event(&push);
-    let push_data = PushEventData::from_push_event(&push);
+    enqueue_push(state, &push, replay_of).await
+}
 
-    match db::upsert_repo(&state.db, &repo_data).await {
-        Ok(repo_id) => {
-            match db::enqueue_job(&state.db, repo_id, &push_data).await {
+/// Branches built for a repo that hasn't synced `[triggers] branches`: `FOUNDRY_BUILD_BRANCHES`
+/// when set, otherwise the repo's own default branch (`trunk`, `develop`, ...). Payloads
+/// without a default branch fall back to `main` and `master`.
+fn server_default_branches(build_branches: &[String], default_branch: &str) -> Vec<String> {
+    if !build_branches.is_empty() {
+        build_branches.to_vec()
+    } else if !default_branch.is_empty() {
+        vec![default_branch.to_string()]
+    } else {
+        vec!["main".to_string(), "master".to_string()]
+    }
+}
+
+/// Store the webhook's repo; one that is new and outside `FOUNDRY_ALLOWED_REPOS` is
+/// added with builds paused, to be enabled from the dashboard
+async fn upsert_repo(state: &AppState, repo_data: &RepoData) -> anyhow::Result<(i64, bool)> {
+    let allowed = state.config.repo_allowed(&repo_data.owner, &repo_data.name);
+    let (repo_id, enabled) = db::upsert_repo(&state.db, repo_data, allowed).await?;
+    if !enabled && !allowed {
+        info!("{}/{} is not in FOUNDRY_ALLOWED_REPOS; enable it in the dashboard to build it", repo_data.owner, repo_data.name);
+    }
+    Ok((repo_id, enabled))
+}
+
+async fn enqueue_push(state: &Arc<AppState>, push: &PushEvent, replay_of: Option<i64>) -> (StatusCode, Json<ApiResponse>) {
+    let repo = &push.repository;
+    let repo_data = RepoData::from_push_event(push);
+    let push_data = PushEventData::from_push_event(push);
+
+    match upsert_repo(state, &repo_data).await {
+        Ok((_, false)) => {
+            info!("Ignoring {} for {}/{}: builds are paused", push.git_ref, repo.owner.login, repo.name);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok((repo_id, true)) => {
+            let priority = state.config.trigger_priorities.get(push_data.trigger_type);
+            match db::enqueue_job(&state.db, repo_id, &push_data, priority).await {
                 Ok(job_id) => {
                     info!(
                         "Enqueued job {} for {}/{} @ {} (commits: {}, forced: {})",
@@ -130,12 +240,24 @@ async fn handle_push_event(
                         push.commits.len(),
                         push.forced
                     );
+                    mark_replay(state, replay_of, job_id).await;
                     
                     // Store individual commits
-                    if let Err(e) = db::store_commits(&state.db, job_id, &push).await {
+                    if let Err(e) = db::store_commits(&state.db, job_id, push).await {
                         warn!("Failed to store commits for job {}: {}", job_id, e);
                     }
-                    
+
+                    // A replayed push is the outdated one; it mustn't cancel builds of later pushes
+                    if replay_of.is_none() {
+                        match db::cancel_outdated_jobs(&state.db, repo_id, &push.git_ref, job_id).await {
+                            Ok(cancelled) if !cancelled.is_empty() => {
+                                info!("Cancelled outdated jobs {:?} of {} in favor of job {}", cancelled, push.git_ref, job_id)
+                            }
+                            Ok(_) => {}
+                            Err(e) => warn!("Failed to cancel outdated jobs of {}: {}", push.git_ref, e),
+                        }
+                    }
+
                     (StatusCode::OK, Json(ApiResponse::ok()))
                 }
                 Err(e) => {
@@ -157,9 +279,134 @@ async fn handle_push_event(
     }
 }
 
+/// Published releases build the release tag when `[triggers] releases` is on
+async fn handle_release_event(
+    state: &Arc<AppState>,
+    body: &Bytes,
+    replay_of: Option<i64>,
+) -> (StatusCode, Json<ApiResponse>) {
+    let event: ReleaseEvent = match serde_json::from_slice(body) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Failed to parse release event: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Invalid payload")),
+            );
+        }
+    };
+
+    if !event.should_build() {
+        info!("Ignoring release event: action={}", event.action);
+        return (StatusCode::OK, Json(ApiResponse::ok()));
+    }
+
+    let repo = &event.repository;
+    match db::get_tag_triggers(&state.db, &repo.owner.login, &repo.name).await {
+        Ok((_, true)) => {}
+        Ok((_, false)) => {
+            info!("Ignoring release {} for {}/{}: releases not enabled", event.release.tag_name, repo.owner.login, repo.name);
+            return (StatusCode::OK, Json(ApiResponse::ok()));
+        }
+        Err(e) => {
+            warn!("Failed to check release config, not building: {}", e);
+            return (StatusCode::OK, Json(ApiResponse::ok()));
+        }
+    }
+
+    let repo_data = RepoData::from_repository(repo);
+    let release_data = PushEventData::from_release_event(&event);
+
+    match upsert_repo(state, &repo_data).await {
+        Ok((_, false)) => {
+            info!("Ignoring release {} for {}/{}: builds are paused", event.release.tag_name, repo.owner.login, repo.name);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok((repo_id, true)) => {
+            let priority = state.config.trigger_priorities.get(TriggerType::Release);
+            match db::enqueue_job(&state.db, repo_id, &release_data, priority).await {
+                Ok(job_id) => {
+                    info!(
+                        "Enqueued release job {} for {}/{} {} @ {}",
+                        job_id, repo.owner.login, repo.name, event.release.tag_name, event.release.target_commitish
+                    );
+                    mark_replay(state, replay_of, job_id).await;
+                    (StatusCode::OK, Json(ApiResponse::ok()))
+                }
+                Err(e) => {
+                    error!("Failed to enqueue release job: {}", e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        Json(ApiResponse::error("Failed to enqueue job")),
+                    )
+                }
+            }
+        }
+        Err(e) => {
+            error!("Failed to upsert repo: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to process repo")),
+            )
+        }
+    }
+}
+
+/// The "Cancel" button on a foundry check run cancels the job that created it
+async fn handle_check_run_event(
+    state: &Arc<AppState>,
+    body: &Bytes,
+) -> (StatusCode, Json<ApiResponse>) {
+    let event: CheckRunEvent = match serde_json::from_slice(body) {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Failed to parse check_run event: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::error("Invalid payload")),
+            );
+        }
+    };
+
+    let Some(check_run_id) = event.cancel_requested() else {
+        info!("Ignoring check_run event: action={}", event.action);
+        return (StatusCode::OK, Json(ApiResponse::ok()));
+    };
+
+    let job_id = match db::get_job_id_by_check_run(&state.db, check_run_id).await {
+        Ok(Some(job_id)) => job_id,
+        Ok(None) => {
+            info!("No job found for check run {}", check_run_id);
+            return (StatusCode::OK, Json(ApiResponse::ok()));
+        }
+        Err(e) => {
+            error!("Failed to look up check run {}: {}", check_run_id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to look up job")),
+            );
+        }
+    };
+
+    match db::cancel_job(&state.db, job_id).await {
+        Ok(true) => info!("Cancelled job {} from GitHub check run {}", job_id, check_run_id),
+        Ok(false) => info!("Job {} already finished, ignoring cancel", job_id),
+        Err(e) => {
+            error!("Failed to cancel job {}: {}", job_id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiResponse::error("Failed to cancel job")),
+            );
+        }
+    }
+
+    (StatusCode::OK, Json(ApiResponse::ok()))
+}
+
 async fn handle_pull_request_event(
     state: &Arc<AppState>,
     body: &Bytes,
+    replay_of: Option<i64>,
 ) -> (StatusCode, Json<ApiResponse>) {
     let pr_event: PullRequestEvent = match serde_json::from_slice(body) {
         Ok(p) => p,
@@ -172,6 +419,21 @@ async fn handle_pull_request_event(
         }
     };
 
+    // A closed PR's builds are moot and its previews go away
+    if pr_event.action == "closed" {
+        let repo = &pr_event.repository;
+        let (owner, name, number) = (repo.owner.login.clone(), repo.name.clone(), pr_event.pull_request.number);
+        match db::cancel_pr_jobs(&state.db, &owner, &name, number).await {
+            Ok(cancelled) if !cancelled.is_empty() => {
+                info!("Cancelled jobs {:?} of closed PR #{} of {}/{}", cancelled, number, owner, name)
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to cancel jobs of closed PR #{} of {}/{}: {}", number, owner, name, e),
+        }
+        let state = state.clone();
+        tokio::spawn(async move { tear_down_previews(&state, &owner, &name, number).await });
+    }
+
     // Only build on opened, synchronize, reopened (not closed, merged, etc.)
     if !pr_event.should_build() {
         info!(
@@ -225,9 +487,14 @@ async fn handle_pull_request_event(
 
     let pr_data = PullRequestEventData::from_pr_event(&pr_event);
 
-    match db::upsert_repo(&state.db, &repo_data).await {
-        Ok(repo_id) => {
-            match db::enqueue_pr_job(&state.db, repo_id, &pr_data).await {
+    match upsert_repo(state, &repo_data).await {
+        Ok((_, false)) => {
+            info!("Ignoring PR #{} for {}/{}: builds are paused", pr.number, repo.owner.login, repo.name);
+            (StatusCode::OK, Json(ApiResponse::ok()))
+        }
+        Ok((repo_id, true)) => {
+            let priority = state.config.trigger_priorities.get(TriggerType::PullRequest);
+            match db::enqueue_pr_job(&state.db, repo_id, &pr_data, priority).await {
                 Ok(job_id) => {
                     info!(
                         "Enqueued PR job {} for {}/{} PR #{} @ {}",
@@ -237,6 +504,7 @@ async fn handle_pull_request_event(
                         pr.number,
                         &pr.head.sha[..8.min(pr.head.sha.len())],
                     );
+                    mark_replay(state, replay_of, job_id).await;
                     (StatusCode::OK, Json(ApiResponse::ok()))
                 }
                 Err(e) => {
@@ -257,3 +525,53 @@ async fn handle_pull_request_event(
         }
     }
 }
+
+/// Remove the containers and tunnel routes of a closed PR's previews
+async fn tear_down_previews(state: &AppState, owner: &str, name: &str, pr_number: i64) {
+    let previews = match db::take_previews(&state.db, owner, name, pr_number).await {
+        Ok(previews) => previews,
+        Err(e) => {
+            error!("Failed to look up previews of {}/{} PR #{}: {}", owner, name, pr_number, e);
+            return;
+        }
+    };
+    if previews.is_empty() {
+        return;
+    }
+
+    let cloudflare = CloudflareClient::from_env().unwrap_or_else(|e| {
+        warn!("Cloudflare is misconfigured, leaving preview routes in place: {}", e);
+        None
+    });
+    for preview in previews {
+        info!("Tearing down preview {} of {}/{} PR #{}", preview.app_name, owner, name, pr_number);
+        if let Err(e) = docker::remove_app(&preview.app_name).await {
+            warn!("Failed to remove preview {}: {}", preview.app_name, e);
+        }
+        if let Some(cloudflare) = &cloudflare {
+            if let Err(e) = cloudflare.remove_domain(&preview.hostname).await {
+                warn!("Failed to remove preview route {}: {}", preview.hostname, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_default_branches_use_repo_default() {
+        let trunk = server_default_branches(&[], "trunk");
+        assert_eq!(trunk, vec!["trunk"]);
+        assert!(matching_glob(&trunk, "trunk").is_some());
+        assert!(matching_glob(&trunk, "main").is_none());
+        assert!(matching_glob(&trunk, "feature/x").is_none());
+
+        assert_eq!(server_default_branches(&[], ""), vec!["main", "master"]);
+
+        // FOUNDRY_BUILD_BRANCHES replaces the repo's default branch
+        let configured = vec!["release/*".to_string()];
+        assert_eq!(server_default_branches(&configured, "trunk"), configured);
+    }
+}
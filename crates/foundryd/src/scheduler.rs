@@ -1,23 +1,95 @@
 use std::sync::Arc;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use cron::Schedule;
+use foundry_core::TriggerType;
 use sqlx::PgPool;
-use tracing::{info, error, debug};
+use tracing::{info, error, debug, warn};
 
-pub async fn run_scheduler(pool: Arc<PgPool>) {
+use crate::config::Config;
+use crate::db;
+
+/// Times a job is requeued after losing its agent before it is failed instead
+const MAX_RECLAIMS: i32 = 2;
+
+/// Expired logs are pruned this often rather than on every tick
+const LOG_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Log rows deleted per statement, so pruning a large backlog doesn't hold long locks
+const LOG_PRUNE_BATCH: i64 = 1000;
+
+pub async fn run_scheduler(pool: Arc<PgPool>, config: Config) {
     info!("Starting scheduler");
+    let priority = config.trigger_priorities.get(TriggerType::Scheduled);
+    let mut last_log_prune: Option<Instant> = None;
     
     loop {
-        if let Err(e) = check_and_run_scheduled_jobs(&pool).await {
+        if let Err(e) = check_and_run_scheduled_jobs(&pool, priority).await {
             error!("Scheduler error: {}", e);
         }
+
+        if let Err(e) = reclaim_stale_jobs(&pool, config.heartbeat_timeout_secs).await {
+            error!("Failed to reclaim stale jobs: {}", e);
+        }
+
+        if let Err(e) = fail_expired_pins(&pool, config.pin_timeout_secs).await {
+            error!("Failed to expire pinned jobs: {}", e);
+        }
+
+        if let Some(days) = config.log_retention_days {
+            if last_log_prune.is_none_or(|at| at.elapsed() >= LOG_PRUNE_INTERVAL) {
+                last_log_prune = Some(Instant::now());
+                match prune_expired_logs(&pool, days).await {
+                    Ok(deleted) => info!("Pruned {} log rows of jobs finished over {} days ago", deleted, days),
+                    Err(e) => error!("Failed to prune expired logs: {}", e),
+                }
+            }
+        }
         
         tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
     }
 }
 
-async fn check_and_run_scheduled_jobs(pool: &PgPool) -> anyhow::Result<()> {
+/// Requeue (or fail) running jobs whose agent has stopped heartbeating
+async fn reclaim_stale_jobs(pool: &PgPool, heartbeat_timeout_secs: u64) -> anyhow::Result<()> {
+    for job in db::reclaim_stale_jobs(pool, heartbeat_timeout_secs, MAX_RECLAIMS).await? {
+        warn!(
+            "Job {} had no heartbeat from {} for {}s, now {}",
+            job.id,
+            job.claimed_by.as_deref().unwrap_or("unknown agent"),
+            heartbeat_timeout_secs,
+            job.status
+        );
+    }
+    Ok(())
+}
+
+/// Delete the log rows of jobs past `retention_days` in batches, returning how many went
+async fn prune_expired_logs(pool: &PgPool, retention_days: u32) -> anyhow::Result<u64> {
+    let mut deleted = 0;
+    loop {
+        let count = db::delete_expired_logs(pool, retention_days, LOG_PRUNE_BATCH).await?;
+        deleted += count;
+        if count < LOG_PRUNE_BATCH as u64 {
+            return Ok(deleted);
+        }
+    }
+}
+
+/// Fail queued jobs whose pinned agent never picked them up
+async fn fail_expired_pins(pool: &PgPool, pin_timeout_secs: u64) -> anyhow::Result<()> {
+    for job in db::fail_expired_pins(pool, pin_timeout_secs).await? {
+        warn!(
+            "Job {} was not claimed by its pinned agent {} within {}s, failed",
+            job.id, job.target_agent, pin_timeout_secs
+        );
+    }
+    Ok(())
+}
+
+async fn check_and_run_scheduled_jobs(pool: &PgPool, priority: i32) -> anyhow::Result<()> {
     let now = Utc::now();
     
     let due_jobs = sqlx::query_as::<_, ScheduledJobRow>(
@@ -34,12 +106,12 @@ async fn check_and_run_scheduled_jobs(pool: &PgPool) -> anyhow::Result<()> {
     for scheduled in due_jobs {
         debug!("Processing scheduled job {} for repo {}", scheduled.id, scheduled.repo_id);
         
-        if let Err(e) = enqueue_scheduled_job(pool, &scheduled).await {
+        if let Err(e) = enqueue_scheduled_job(pool, &scheduled, priority).await {
             error!("Failed to enqueue scheduled job {}: {}", scheduled.id, e);
         }
         
-        if let Ok(schedule) = Schedule::from_str(&scheduled.cron_expression) {
-            if let Some(next) = schedule.upcoming(Utc).next() {
+        match next_run(&scheduled.cron_expression, scheduled.timezone.as_deref(), now) {
+            Ok(Some(next)) => {
                 sqlx::query(
                     r#"
                     UPDATE scheduled_job
@@ -53,15 +125,38 @@ async fn check_and_run_scheduled_jobs(pool: &PgPool) -> anyhow::Result<()> {
                 .execute(pool)
                 .await?;
             }
+            Ok(None) => {}
+            Err(e) => error!("Scheduled job {} has an invalid schedule: {}", scheduled.id, e),
         }
     }
     
     Ok(())
 }
 
-async fn enqueue_scheduled_job(pool: &PgPool, scheduled: &ScheduledJobRow) -> anyhow::Result<()> {
+/// Next time `cron_expression` fires after `after`, evaluated as wall-clock time in
+/// `timezone` (an IANA name, UTC if unset). Standard five-field expressions get a
+/// leading seconds field. Across DST changes a time skipped by spring-forward doesn't
+/// fire that day, and a time repeated by fall-back fires once, at its first occurrence.
+pub fn next_run(cron_expression: &str, timezone: Option<&str>, after: DateTime<Utc>) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let expression = if cron_expression.split_whitespace().count() == 5 {
+        format!("0 {}", cron_expression)
+    } else {
+        cron_expression.to_string()
+    };
+    let schedule = Schedule::from_str(&expression)
+        .map_err(|e| anyhow::anyhow!("Invalid cron expression: {}", e))?;
+
+    let timezone = timezone.unwrap_or("UTC");
+    let tz: Tz = timezone.parse().map_err(|_| {
+        anyhow::anyhow!("Unknown timezone '{}': use an IANA name such as America/New_York", timezone)
+    })?;
+
+    Ok(schedule.after(&after.with_timezone(&tz)).next().map(|next| next.with_timezone(&Utc)))
+}
+
+async fn enqueue_scheduled_job(pool: &PgPool, scheduled: &ScheduledJobRow, priority: i32) -> anyhow::Result<()> {
     let repo = sqlx::query_as::<_, RepoInfo>(
-        r#"SELECT owner, name, clone_url, default_branch FROM repo WHERE id = $1"#,
+        r#"SELECT owner, name, clone_url, default_branch, enabled FROM repo WHERE id = $1"#,
     )
     .bind(scheduled.repo_id)
     .fetch_optional(pool)
@@ -70,6 +165,12 @@ async fn enqueue_scheduled_job(pool: &PgPool, scheduled: &ScheduledJobRow) -> an
     let Some(repo) = repo else {
         return Err(anyhow::anyhow!("Repo not found"));
     };
+
+    // The schedule still advances, so resuming doesn't fire a backlog of missed runs
+    if !repo.enabled {
+        info!("Skipping scheduled build of {}: builds are paused", repo.name);
+        return Ok(());
+    }
     
     let branch = scheduled.branch.as_deref().unwrap_or(
         repo.default_branch.as_deref().unwrap_or("main")
@@ -86,9 +187,9 @@ async fn enqueue_scheduled_job(pool: &PgPool, scheduled: &ScheduledJobRow) -> an
         r#"
         INSERT INTO job (
             repo_id, git_sha, git_ref, status, trigger_type,
-            scheduled_job_id, commit_message
+            scheduled_job_id, commit_message, priority
         )
-        VALUES ($1, $2, $3, 'queued', 'scheduled', $4, $5)
+        VALUES ($1, $2, $3, 'queued', 'scheduled', $4, $5, $6)
         "#,
     )
     .bind(scheduled.repo_id)
@@ -96,6 +197,7 @@ async fn enqueue_scheduled_job(pool: &PgPool, scheduled: &ScheduledJobRow) -> an
     .bind(&git_ref)
     .bind(scheduled.id)
     .bind(format!("Scheduled build: {}", scheduled.cron_expression))
+    .bind(priority)
     .execute(pool)
     .await?;
     
@@ -111,10 +213,7 @@ pub async fn upsert_schedule(
     branch: Option<&str>,
     timezone: Option<&str>,
 ) -> anyhow::Result<i64> {
-    let schedule = Schedule::from_str(cron_expression)
-        .map_err(|e| anyhow::anyhow!("Invalid cron expression: {}", e))?;
-    
-    let next_run: Option<DateTime<Utc>> = schedule.upcoming(Utc).next();
+    let next_run: Option<DateTime<Utc>> = next_run(cron_expression, timezone, Utc::now())?;
     
     let row: (i64,) = sqlx::query_as(
         r#"
@@ -159,7 +258,6 @@ struct ScheduledJobRow {
     repo_id: i64,
     cron_expression: String,
     branch: Option<String>,
-    #[allow(dead_code)]
     timezone: Option<String>,
 }
 
@@ -171,4 +269,43 @@ struct RepoInfo {
     #[allow(dead_code)]
     clone_url: String,
     default_branch: Option<String>,
+    enabled: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn next(cron: &str, timezone: Option<&str>, after: &str) -> DateTime<Utc> {
+        next_run(cron, timezone, at(after)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_next_run_in_timezone() {
+        // 8am New York is 13:00 UTC in winter and 12:00 UTC in summer
+        assert_eq!(next("0 8 * * *", Some("America/New_York"), "2026-01-10T00:00:00Z"), at("2026-01-10T13:00:00Z"));
+        assert_eq!(next("0 8 * * *", Some("America/New_York"), "2026-07-10T00:00:00Z"), at("2026-07-10T12:00:00Z"));
+        assert_eq!(next("0 0 8 * * *", None, "2026-01-10T00:00:00Z"), at("2026-01-10T08:00:00Z"));
+    }
+
+    #[test]
+    fn test_next_run_across_dst() {
+        // 2:30am doesn't exist in New York on 2026-03-08
+        assert_eq!(next("30 2 * * *", Some("America/New_York"), "2026-03-08T05:00:00Z"), at("2026-03-09T06:30:00Z"));
+        // 1:30am happens twice on 2026-11-01; only the first one fires
+        let first = next("30 1 * * *", Some("America/New_York"), "2026-11-01T04:00:00Z");
+        assert_eq!(first, at("2026-11-01T05:30:00Z"));
+        assert_eq!(next("30 1 * * *", Some("America/New_York"), "2026-11-01T05:30:00Z"), at("2026-11-02T06:30:00Z"));
+    }
+
+    #[test]
+    fn test_next_run_rejects_bad_input() {
+        let err = next_run("0 8 * * *", Some("Mars/Olympus_Mons"), Utc::now()).unwrap_err();
+        assert!(err.to_string().contains("Unknown timezone 'Mars/Olympus_Mons'"));
+        assert!(next_run("not a cron", None, Utc::now()).is_err());
+    }
 }
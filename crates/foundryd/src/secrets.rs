@@ -0,0 +1,102 @@
+//! Repo secret encryption
+//!
+//! Secret values are stored AES-256-GCM encrypted with the key from
+//! `FOUNDRY_SECRET_KEY`. Each value is bound to its repo and name through the
+//! associated data, so a ciphertext copied to another row won't decrypt.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+
+const NONCE_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct SecretKey(Aes256Gcm);
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl SecretKey {
+    /// Parse a base64-encoded 32-byte key, e.g. from `openssl rand -base64 32`
+    pub fn from_base64(value: &str) -> Result<Self> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(value.trim())
+            .context("Secret key is not valid base64")?;
+        if bytes.len() != 32 {
+            bail!("Secret key must be 32 bytes, got {}", bytes.len());
+        }
+        Ok(Self(Aes256Gcm::new_from_slice(&bytes)?))
+    }
+
+    /// Encrypt `value`, returning the random nonce followed by the ciphertext
+    pub fn encrypt(&self, repo_id: i64, name: &str, value: &str) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let aad = associated_data(repo_id, name);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, Payload { msg: value.as_bytes(), aad: aad.as_bytes() })
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret {}", name))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    pub fn decrypt(&self, repo_id: i64, name: &str, sealed: &[u8]) -> Result<String> {
+        if sealed.len() < NONCE_LEN {
+            bail!("Secret {} is truncated", name);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = associated_data(repo_id, name);
+        let plaintext = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secret {}: wrong key or corrupted value", name))?;
+        String::from_utf8(plaintext).with_context(|| format!("Secret {} is not UTF-8", name))
+    }
+}
+
+fn associated_data(repo_id: i64, name: &str) -> String {
+    format!("{}/{}", repo_id, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn test_roundtrip() {
+        let key = SecretKey::from_base64(KEY).unwrap();
+        let sealed = key.encrypt(1, "NPM_TOKEN", "npm_abc123").unwrap();
+        assert!(!sealed.windows(10).any(|w| w == b"npm_abc123"));
+        assert_eq!(key.decrypt(1, "NPM_TOKEN", &sealed).unwrap(), "npm_abc123");
+
+        // Fresh nonce per value
+        assert_ne!(sealed, key.encrypt(1, "NPM_TOKEN", "npm_abc123").unwrap());
+    }
+
+    #[test]
+    fn test_decrypt_rejects_other_key_or_row() {
+        let key = SecretKey::from_base64(KEY).unwrap();
+        let sealed = key.encrypt(1, "NPM_TOKEN", "npm_abc123").unwrap();
+
+        assert!(key.decrypt(2, "NPM_TOKEN", &sealed).is_err());
+        assert!(key.decrypt(1, "AWS_SECRET_ACCESS_KEY", &sealed).is_err());
+        assert!(key.decrypt(1, "NPM_TOKEN", &sealed[..4]).is_err());
+
+        let other = SecretKey::from_base64("ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=").unwrap();
+        assert!(other.decrypt(1, "NPM_TOKEN", &sealed).is_err());
+    }
+
+    #[test]
+    fn test_key_must_be_32_bytes() {
+        assert!(SecretKey::from_base64("c2hvcnQ=").is_err());
+        assert!(SecretKey::from_base64("not base64!").is_err());
+    }
+}
@@ -5,6 +5,7 @@ import { JobDetailPage } from "@/pages/JobDetail";
 import { Repositories } from "@/pages/Repositories";
 import { RepoDetailPage } from "@/pages/RepoDetail";
 import { Schedules } from "@/pages/Schedules";
+import { Agents } from "@/pages/Agents";
 import { AuthProvider, RequireAuth } from "@/lib/auth";
 
 function App() {
@@ -16,9 +17,11 @@ function App() {
             <Route path="/" element={<Layout />}>
               <Route index element={<Dashboard />} />
               <Route path="job/:id" element={<JobDetailPage />} />
+              <Route path="j/:publicId" element={<JobDetailPage />} />
               <Route path="repos" element={<Repositories />} />
               <Route path="repo/:id" element={<RepoDetailPage />} />
               <Route path="schedules" element={<Schedules />} />
+              <Route path="agents" element={<Agents />} />
             </Route>
           </Routes>
         </RequireAuth>
@@ -6,6 +6,7 @@ import {
   LayoutDashboard,
   GitBranch,
   Calendar,
+  Server,
   LogOut,
 } from "lucide-react";
 
@@ -13,6 +14,7 @@ const navigation = [
   { name: "Dashboard", href: "/", icon: LayoutDashboard },
   { name: "Repositories", href: "/repos", icon: GitBranch },
   { name: "Schedules", href: "/schedules", icon: Calendar },
+  { name: "Agents", href: "/agents", icon: Server },
 ];
 
 export function Layout() {
@@ -4,10 +4,14 @@ export interface DashboardStats {
   success_rate: number;
   queued_count: number;
   running_count: number;
+  queue_paused: boolean;
 }
 
 export interface Job {
-  id: number;
+  /** Left out when the server only exposes public ids */
+  id?: number;
+  public_id: string;
+  acknowledged: boolean;
   repo_owner: string;
   repo_name: string;
   git_sha: string;
@@ -20,7 +24,9 @@ export interface Job {
   commit_author?: string;
   commit_url?: string;
   duration_secs?: number;
-  trigger_type?: "push" | "pull_request" | "manual";
+  trigger_type?: "push" | "pull_request" | "manual" | "scheduled" | "tag" | "release";
+  /** 1 for the next job agents will claim; only set while queued */
+  queue_position?: number;
 
   // Extended fields
   before_sha?: string;
@@ -46,18 +52,82 @@ export interface JobMetrics {
   total_duration_ms: number;
 }
 
+/** Where a limit came from: foundry.toml, the agent's defaults, or the server */
+export type LimitSource = "config" | "agent" | "server";
+
+export interface AppliedLimit<T> {
+  value: T;
+  source: LimitSource;
+}
+
+export interface JobLimits {
+  cpus?: AppliedLimit<number>;
+  memory?: AppliedLimit<string>;
+  platform?: AppliedLimit<string>;
+  user?: AppliedLimit<string>;
+}
+
 export interface JobDetail extends Job {
   logs: LogEntry[];
   pr_number?: number;
   pr_title?: string;
   pr_url?: string;
+  pr_author?: string;
+  /** Branch the PR comes from, `owner:branch` for forks */
+  pr_head_ref?: string;
+  /** Branch the PR targets */
+  pr_base_ref?: string;
+  service_path?: string;
+  matrix_key?: string;
+  parent_public_id?: string;
+  ack_reason?: string;
+  acknowledged_by?: string;
+  triggered_by?: string;
+  claimed_by?: string;
+  /** Only agent allowed to claim the job */
+  target_agent?: string;
+  /** `[concurrency] group` of the repo when the job was queued */
+  concurrency_group?: string;
+  agent_version?: string;
   metrics?: JobMetrics;
+  /** What the build containers actually ran with */
+  limits?: JobLimits;
+  notes: JobNote[];
+  children: ChildJob[];
+}
+
+export interface ChildJob {
+  id?: number;
+  public_id: string;
+  status: Job["status"];
+  service_path?: string;
+  matrix_key?: string;
+  duration_secs?: number;
+}
+
+export interface JobNote {
+  id: number;
+  job_id?: number;
+  author: string;
+  body: string;
+  created_at: string;
+}
+
+export interface Artifact {
+  id: number;
+  job_id?: number;
+  name: string;
+  size: number;
+  content_type: string;
+  created_at: string;
 }
 
+export type LogLevel = "info" | "warn" | "error";
+
 export interface LogEntry {
   timestamp: string;
   message: string;
-  level: string;
+  level: LogLevel;
 }
 
 export interface Repo {
@@ -69,6 +139,10 @@ export interface Repo {
   failure_count: number;
   last_build_at?: string;
   last_status?: string;
+  recent_statuses: Job["status"][];
+  recent_job_ids: string[];
+  /** False while builds are paused, e.g. for repos outside FOUNDRY_ALLOWED_REPOS */
+  enabled: boolean;
   html_url?: string;
   description?: string;
   language?: string;
@@ -84,6 +158,10 @@ export interface RepoDetail {
   language?: string;
   default_branch?: string;
   private: boolean;
+  /** False while builds are paused; webhooks are still accepted */
+  enabled: boolean;
+  /** Older builds of a ref that a new push to it cancels */
+  cancel_outdated: CancelOutdated;
   build_count: number;
   success_count: number;
   failure_count: number;
@@ -99,10 +177,28 @@ export async function fetchStats(): Promise<DashboardStats> {
   return res.json();
 }
 
-export async function fetchJobs(limit = 50): Promise<Job[]> {
-  const res = await fetch(`${API_BASE}/jobs?limit=${limit}`);
+export interface JobPage {
+  jobs: Job[];
+  /** Jobs matching the filters across all pages */
+  total: number;
+}
+
+export async function fetchJobPage(params: {
+  limit: number;
+  offset: number;
+  status?: Job["status"];
+  repo?: string;
+}): Promise<JobPage> {
+  const query = new URLSearchParams({
+    limit: String(params.limit),
+    offset: String(params.offset),
+  });
+  if (params.status) query.set("status", params.status);
+  if (params.repo) query.set("repo", params.repo);
+  const res = await fetch(`${API_BASE}/jobs?${query}`);
   if (!res.ok) throw new Error("Failed to fetch jobs");
-  return res.json();
+  const jobs: Job[] = await res.json();
+  return { jobs, total: Number(res.headers.get("X-Total-Count") ?? jobs.length) };
 }
 
 export async function fetchJob(id: number): Promise<JobDetail | null> {
@@ -111,6 +207,169 @@ export async function fetchJob(id: number): Promise<JobDetail | null> {
   return res.json();
 }
 
+export async function fetchJobByPublicId(publicId: string): Promise<JobDetail | null> {
+  const res = await fetch(`${API_BASE}/j/${publicId}`);
+  if (!res.ok) throw new Error("Failed to fetch job");
+  return res.json();
+}
+
+export async function cancelJob(publicId: string): Promise<void> {
+  const res = await fetch(`${API_BASE}/job/${publicId}/cancel`, {
+    method: "POST",
+  });
+  if (!res.ok) throw new Error("Failed to cancel job");
+}
+
+/** Queue a copy of a finished job, optionally pinned to one agent */
+export async function retryJob(
+  publicId: string,
+  targetAgent?: string
+): Promise<{ job_id?: number; public_id: string }> {
+  const res = await fetch(`${API_BASE}/job/${publicId}/retry`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ target_agent: targetAgent || null }),
+  });
+  const body = await res.json();
+  if (!res.ok) throw new Error(body.error ?? "Failed to retry job");
+  return body;
+}
+
+/** Build the head of a branch or ref (the default branch if omitted) as a manual job */
+export async function triggerBuild(
+  repoId: number,
+  ref?: string
+): Promise<{ job_id?: number; public_id: string }> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/build`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ ref: ref || null }),
+  });
+  const body = await res.json();
+  if (!res.ok) throw new Error(body.error ?? "Failed to start build");
+  return body;
+}
+
+/** Rebuild the newest default-branch commit; rejects with the server's message if there is none */
+export async function rebuildLatest(
+  repoId: number
+): Promise<{ job_id?: number; public_id: string }> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/rebuild-latest`, {
+    method: "POST",
+  });
+  const body = await res.json();
+  if (!res.ok) throw new Error(body.error ?? "Failed to rebuild");
+  return body;
+}
+
+/** Pause or resume webhook and scheduled builds of a repo */
+export async function setRepoEnabled(
+  repoId: number,
+  enabled: boolean
+): Promise<void> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/enabled`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ enabled }),
+  });
+  if (!res.ok) throw new Error("Failed to update repo");
+}
+
+export type CancelOutdated = "off" | "queued" | "running";
+
+/** Choose which older builds of a branch a new push to it cancels */
+export async function setRepoCancelOutdated(
+  repoId: number,
+  cancelOutdated: CancelOutdated
+): Promise<void> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/cancel-outdated`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ cancel_outdated: cancelOutdated }),
+  });
+  if (!res.ok) throw new Error("Failed to update repo");
+}
+
+/** Names of the repo's build secrets; values are never sent back */
+export async function fetchRepoSecrets(repoId: number): Promise<string[]> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/secrets`);
+  if (!res.ok) throw new Error("Failed to fetch secrets");
+  return res.json();
+}
+
+/** Create or replace a build secret; rejects with the server's message */
+export async function setRepoSecret(
+  repoId: number,
+  name: string,
+  value: string
+): Promise<void> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/secrets`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ name, value }),
+  });
+  const body = await res.json();
+  if (!res.ok) throw new Error(body.error ?? "Failed to save secret");
+}
+
+export interface Deployment {
+  id: number;
+  app_name: string;
+  job_id?: number;
+  job_public_id?: string;
+  image: string;
+  args: string[];
+  command: string[];
+  created_at: string;
+  rolled_back_at?: string;
+}
+
+/** The repo's `docker run` deploys, newest first */
+export async function fetchRepoDeployments(repoId: number): Promise<Deployment[]> {
+  const res = await fetch(`${API_BASE}/repos/${repoId}/deployments`);
+  if (!res.ok) throw new Error("Failed to fetch deployments");
+  return res.json();
+}
+
+/** Redeploy the app's previous image; rejects with the server's message */
+export async function rollbackDeployment(app: string): Promise<{ image: string }> {
+  const res = await fetch(`${API_BASE}/deploy/${encodeURIComponent(app)}/rollback`, {
+    method: "POST",
+  });
+  const body = await res.json();
+  if (!res.ok) throw new Error(body.error ?? "Failed to roll back");
+  return body;
+}
+
+export async function fetchJobArtifacts(publicId: string): Promise<Artifact[]> {
+  const res = await fetch(`${API_BASE}/job/${publicId}/artifacts`);
+  if (!res.ok) throw new Error("Failed to fetch artifacts");
+  return res.json();
+}
+
+export function artifactDownloadUrl(id: number): string {
+  return `${API_BASE}/artifact/${id}/download`;
+}
+
+export async function addJobNote(publicId: string, body: string): Promise<JobNote> {
+  const res = await fetch(`${API_BASE}/job/${publicId}/note`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ body }),
+  });
+  if (!res.ok) throw new Error("Failed to add note");
+  return res.json();
+}
+
+export async function acknowledgeJob(publicId: string, reason: string): Promise<void> {
+  const res = await fetch(`${API_BASE}/job/${publicId}/ack`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ reason }),
+  });
+  if (!res.ok) throw new Error("Failed to acknowledge job");
+}
+
 export async function fetchRepos(): Promise<Repo[]> {
   const res = await fetch(`${API_BASE}/repos`);
   if (!res.ok) throw new Error("Failed to fetch repos");
@@ -123,6 +382,27 @@ export async function fetchRepo(id: number): Promise<RepoDetail> {
   return res.json();
 }
 
+export interface DurationPercentiles {
+  builds: number;
+  p50_secs?: number;
+  p95_secs?: number;
+}
+
+/** Successful build durations over the last `days` and the window before */
+export interface RepoBuildStats {
+  days: number;
+  current: DurationPercentiles;
+  previous: DurationPercentiles;
+  /** Positive when the median build got slower */
+  p50_change_percent?: number;
+}
+
+export async function fetchRepoStats(id: number, days = 30): Promise<RepoBuildStats> {
+  const res = await fetch(`${API_BASE}/repos/${id}/stats?days=${days}`);
+  if (!res.ok) throw new Error("Failed to fetch repo stats");
+  return res.json();
+}
+
 export async function fetchRepoJobs(id: number, limit = 50): Promise<Job[]> {
   const res = await fetch(`${API_BASE}/repo/${id}/jobs?limit=${limit}`);
   if (!res.ok) throw new Error("Failed to fetch repo jobs");
@@ -142,6 +422,34 @@ export interface Schedule {
   next_run_at?: string;
 }
 
+export interface Agent {
+  id: string;
+  version?: string;
+  protocol_version?: number;
+  first_seen_at: string;
+  last_seen_at: string;
+  /** Why the agent can't reach Docker; it claims no jobs while set */
+  docker_error?: string;
+  /** Docker architecture of the agent's host, e.g. amd64 or arm64 */
+  arch?: string;
+  /** FOUNDRY_AGENT_LABELS; jobs with build.runs_on only go to agents with all of theirs */
+  labels: string[];
+  /** current: same build as the server; skewed: other build, same protocol */
+  compatibility: "current" | "skewed" | "incompatible";
+}
+
+export interface AgentsResponse {
+  server_version: string;
+  protocol_version: number;
+  agents: Agent[];
+}
+
+export async function fetchAgents(): Promise<AgentsResponse> {
+  const res = await fetch(`${API_BASE}/agents`);
+  if (!res.ok) throw new Error("Failed to fetch agents");
+  return res.json();
+}
+
 export async function fetchSchedules(): Promise<Schedule[]> {
   const res = await fetch(`${API_BASE}/schedules`);
   if (!res.ok) throw new Error("Failed to fetch schedules");
@@ -178,6 +486,9 @@ export interface Container {
   created: string;
   ports: string;
   project?: string;
+  repo?: string;
+  kind?: "build" | "deploy";
+  job_id?: number;
 }
 
 export interface ContainerLogs {
@@ -185,11 +496,15 @@ export interface ContainerLogs {
   logs: string[];
 }
 
-export async function fetchContainers(project?: string): Promise<Container[]> {
-  const url = project
-    ? `${API_BASE}/containers?project=${encodeURIComponent(project)}`
-    : `${API_BASE}/containers`;
-  const res = await fetch(url);
+export async function fetchContainers(
+  filter: { project?: string; repo?: string; managed?: boolean } = {}
+): Promise<Container[]> {
+  const params = new URLSearchParams();
+  if (filter.project) params.set("project", filter.project);
+  if (filter.repo) params.set("repo", filter.repo);
+  if (filter.managed) params.set("managed", "true");
+  const query = params.toString();
+  const res = await fetch(`${API_BASE}/containers${query ? `?${query}` : ""}`);
   if (!res.ok) throw new Error("Failed to fetch containers");
   return res.json();
 }
@@ -219,6 +534,9 @@ export function streamContainerLogs(
     onLog(event.data);
   };
 
+  // docker logs exited (container stopped); don't reconnect and replay the tail
+  eventSource.addEventListener("done", () => eventSource.close());
+
   eventSource.onerror = () => {
     if (onError) {
       onError(new Error("Log stream connection failed"));
@@ -230,6 +548,27 @@ export function streamContainerLogs(
   return () => eventSource.close();
 }
 
+export function streamJobLogs(
+  publicId: string,
+  onLog: (entry: LogEntry) => void,
+  onDone: (status: JobDetail["status"]) => void
+): () => void {
+  const eventSource = new EventSource(`${API_BASE}/job/${publicId}/logs/stream`);
+
+  eventSource.addEventListener("log", (event) => {
+    onLog(JSON.parse((event as MessageEvent).data));
+  });
+
+  eventSource.addEventListener("done", (event) => {
+    eventSource.close();
+    onDone(JSON.parse((event as MessageEvent).data));
+  });
+
+  // EventSource reconnects on its own and resumes from Last-Event-ID
+
+  return () => eventSource.close();
+}
+
 export async function restartContainer(containerId: string): Promise<void> {
   const res = await fetch(`${API_BASE}/containers/${containerId}/restart`, {
     method: "POST",
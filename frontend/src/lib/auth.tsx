@@ -12,6 +12,8 @@ interface AuthState {
   authenticated: boolean;
   email: string | null;
   name: string | null;
+  /** Viewers see jobs and logs; only admins can cancel, retry or change settings */
+  isAdmin: boolean;
   loading: boolean;
 }
 
@@ -35,6 +37,7 @@ async function fetchAuthStatus(): Promise<AuthState> {
         authenticated: data.authenticated,
         email: data.email,
         name: data.name,
+        isAdmin: data.role === "admin",
         loading: false,
       };
     }
@@ -46,6 +49,7 @@ async function fetchAuthStatus(): Promise<AuthState> {
     authenticated: false,
     email: null,
     name: null,
+    isAdmin: false,
     loading: false,
   };
 }
@@ -55,6 +59,7 @@ export function AuthProvider({ children }: { children: ReactNode }) {
     authenticated: false,
     email: null,
     name: null,
+    isAdmin: false,
     loading: true,
   });
 
@@ -0,0 +1,100 @@
+import { useEffect, useState } from "react";
+import { fetchAgents, type Agent, type AgentsResponse } from "@/lib/api";
+import { Badge } from "@/components/ui/badge";
+import { formatRelativeTime } from "@/lib/utils";
+import { Loader2, Server } from "lucide-react";
+
+function CompatibilityBadge({ agent }: Readonly<{ agent: Agent }>) {
+  switch (agent.compatibility) {
+    case "current":
+      return <Badge variant="success">up to date</Badge>;
+    case "skewed":
+      return <Badge variant="warning">version skew</Badge>;
+    default:
+      return <Badge variant="destructive">incompatible</Badge>;
+  }
+}
+
+export function Agents() {
+  const [data, setData] = useState<AgentsResponse | null>(null);
+  const [loading, setLoading] = useState(true);
+
+  const load = async () => {
+    try {
+      setData(await fetchAgents());
+    } catch (e) {
+      console.error("Failed to load agents:", e);
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  useEffect(() => {
+    load();
+    const interval = setInterval(load, 30000);
+    return () => clearInterval(interval);
+  }, []);
+
+  if (loading) {
+    return (
+      <div className="flex items-center justify-center h-64">
+        <Loader2 className="h-8 w-8 animate-spin text-muted-foreground" />
+      </div>
+    );
+  }
+
+  const agents = data?.agents ?? [];
+
+  return (
+    <div className="space-y-6">
+      <div className="flex items-baseline justify-between">
+        <h1 className="text-2xl font-bold">Agents</h1>
+        {data && (
+          <span className="text-sm text-muted-foreground">
+            Server {data.server_version}, protocol {data.protocol_version}
+          </span>
+        )}
+      </div>
+
+      {agents.length === 0 ? (
+        <div className="text-center py-12 text-muted-foreground">
+          <Server className="h-12 w-12 mx-auto mb-4 opacity-50" />
+          <p>No agents have polled for work yet</p>
+        </div>
+      ) : (
+        <div className="space-y-2">
+          {agents.map((agent) => (
+            <div
+              key={agent.id}
+              className="flex items-center justify-between py-3 px-4 rounded-lg bg-card border"
+            >
+              <div className="flex items-center gap-4">
+                <div className="font-medium">{agent.id}</div>
+                <div className="font-mono text-sm text-muted-foreground">
+                  {agent.version ?? "unknown version"}
+                  {agent.protocol_version != null &&
+                    `, protocol ${agent.protocol_version}`}
+                  {agent.arch && `, ${agent.arch}`}
+                </div>
+                <CompatibilityBadge agent={agent} />
+                {agent.labels.map((label) => (
+                  <Badge key={label} variant="outline">
+                    {label}
+                  </Badge>
+                ))}
+                {agent.docker_error && (
+                  <Badge variant="destructive" title={agent.docker_error}>
+                    docker unavailable
+                  </Badge>
+                )}
+              </div>
+              <div className="text-sm text-muted-foreground">
+                Last seen {formatRelativeTime(agent.last_seen_at)}
+              </div>
+            </div>
+          ))}
+        </div>
+      )}
+    </div>
+  );
+}
@@ -1,11 +1,12 @@
 import { useEffect, useState } from "react";
-import { Link } from "react-router-dom";
+import { Link, useSearchParams } from "react-router-dom";
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { Badge } from "@/components/ui/badge";
+import { Button } from "@/components/ui/button";
 import { ScrollArea } from "@/components/ui/scroll-area";
 import {
   fetchStats,
-  fetchJobs,
+  fetchJobPage,
   type DashboardStats,
   type Job,
 } from "@/lib/api";
@@ -17,8 +18,15 @@ import {
   Clock,
   GitCommit,
   Loader2,
+  ChevronLeft,
+  ChevronRight,
+  X,
+  PauseCircle,
 } from "lucide-react";
 
+const PAGE_SIZE = 20;
+const STATUSES: Job["status"][] = ["queued", "running", "success", "failed", "cancelled"];
+
 function StatusBadge({ status }: { status: Job["status"] }) {
   const variants: Record<
     Job["status"],
@@ -54,17 +62,29 @@ function StatusBadge({ status }: { status: Job["status"] }) {
 export function Dashboard() {
   const [stats, setStats] = useState<DashboardStats | null>(null);
   const [jobs, setJobs] = useState<Job[]>([]);
+  const [total, setTotal] = useState(0);
+  const [page, setPage] = useState(0);
+  const [status, setStatus] = useState<Job["status"] | "">("");
   const [loading, setLoading] = useState(true);
+  // `owner/name`, set by the "View builds" link on the repositories page
+  const [searchParams, setSearchParams] = useSearchParams();
+  const repo = searchParams.get("repo") ?? "";
 
   useEffect(() => {
     const load = async () => {
       try {
-        const [statsData, jobsData] = await Promise.all([
+        const [statsData, jobPage] = await Promise.all([
           fetchStats(),
-          fetchJobs(20),
+          fetchJobPage({
+            limit: PAGE_SIZE,
+            offset: page * PAGE_SIZE,
+            status: status || undefined,
+            repo: repo || undefined,
+          }),
         ]);
         setStats(statsData);
-        setJobs(jobsData);
+        setJobs(jobPage.jobs);
+        setTotal(jobPage.total);
       } catch (e) {
         console.error("Failed to load dashboard:", e);
       } finally {
@@ -74,7 +94,9 @@ export function Dashboard() {
     load();
     const interval = setInterval(load, 5000);
     return () => clearInterval(interval);
-  }, []);
+  }, [page, status, repo]);
+
+  const pageCount = Math.max(1, Math.ceil(total / PAGE_SIZE));
 
   if (loading) {
     return (
@@ -97,6 +119,13 @@ export function Dashboard() {
         </div>
       </div>
 
+      {stats?.queue_paused && (
+        <div className="flex items-center gap-2 rounded-md border border-yellow-500/50 bg-yellow-500/15 px-4 py-3 text-sm text-yellow-600 dark:text-yellow-500">
+          <PauseCircle className="h-4 w-4" />
+          The queue is paused: agents won't start new builds until it's resumed
+        </div>
+      )}
+
       {/* Stats Grid */}
       <div className="grid gap-4 md:grid-cols-2 lg:grid-cols-4">
         <Card>
@@ -143,21 +172,54 @@ export function Dashboard() {
 
       {/* Recent Builds */}
       <Card>
-        <CardHeader>
+        <CardHeader className="flex flex-row items-center justify-between space-y-0">
           <CardTitle>Recent Builds</CardTitle>
+          <div className="flex items-center gap-2">
+            {repo && (
+              <Badge variant="secondary" className="gap-1">
+                {repo}
+                <button
+                  onClick={() => {
+                    setSearchParams({});
+                    setPage(0);
+                  }}
+                  title="Show all repositories"
+                >
+                  <X className="h-3 w-3" />
+                </button>
+              </Badge>
+            )}
+            <select
+              value={status}
+              onChange={(e) => {
+                setStatus(e.target.value as Job["status"] | "");
+                setPage(0);
+              }}
+              className="h-9 rounded-md border bg-background px-3 text-sm"
+            >
+              <option value="">All statuses</option>
+              {STATUSES.map((s) => (
+                <option key={s} value={s}>
+                  {s}
+                </option>
+              ))}
+            </select>
+          </div>
         </CardHeader>
         <CardContent>
           <ScrollArea className="h-[500px]">
             {jobs.length === 0 ? (
               <div className="text-center py-12 text-muted-foreground">
-                No builds yet. Push a commit to get started!
+                {status || repo
+                  ? `No ${status ? `${status} ` : ""}builds${repo ? ` of ${repo}` : ""}.`
+                  : "No builds yet. Push a commit to get started!"}
               </div>
             ) : (
               <div className="space-y-2">
                 {jobs.map((job) => (
                   <Link
-                    key={job.id}
-                    to={`/job/${job.id}`}
+                    key={job.public_id}
+                    to={`/j/${job.public_id}`}
                     className="flex items-center justify-between p-4 rounded-lg border hover:bg-accent transition-colors"
                   >
                     <div className="flex items-center gap-4">
@@ -180,9 +242,16 @@ export function Dashboard() {
                     </div>
                     <div className="flex items-center gap-4">
                       <div className="text-right text-sm text-muted-foreground">
-                        <div>{formatDuration(job.duration_secs)}</div>
+                        <div>
+                          {job.queue_position
+                            ? `#${job.queue_position} in queue`
+                            : formatDuration(job.duration_secs)}
+                        </div>
                         <div>{formatRelativeTime(job.created_at)}</div>
                       </div>
+                      {job.acknowledged && (
+                        <Badge variant="outline">acknowledged</Badge>
+                      )}
                       <StatusBadge status={job.status} />
                     </div>
                   </Link>
@@ -190,6 +259,31 @@ export function Dashboard() {
               </div>
             )}
           </ScrollArea>
+          {total > PAGE_SIZE && (
+            <div className="flex items-center justify-between pt-4 text-sm text-muted-foreground">
+              <Button
+                variant="outline"
+                size="sm"
+                onClick={() => setPage((p) => p - 1)}
+                disabled={page === 0}
+              >
+                <ChevronLeft className="h-4 w-4 mr-1" />
+                Newer
+              </Button>
+              <span>
+                Page {page + 1} of {pageCount}
+              </span>
+              <Button
+                variant="outline"
+                size="sm"
+                onClick={() => setPage((p) => p + 1)}
+                disabled={page + 1 >= pageCount}
+              >
+                Older
+                <ChevronRight className="h-4 w-4 ml-1" />
+              </Button>
+            </div>
+          )}
         </CardContent>
       </Card>
     </div>
@@ -1,10 +1,26 @@
-import { useEffect, useState, useRef } from "react";
-import { useParams, Link } from "react-router-dom";
+import { useCallback, useEffect, useState, useRef } from "react";
+import { useParams, Link, useNavigate } from "react-router-dom";
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { Button } from "@/components/ui/button";
 import { ScrollArea } from "@/components/ui/scroll-area";
-import { fetchJob, type JobDetail } from "@/lib/api";
-import { formatDuration, cn } from "@/lib/utils";
+import { Badge } from "@/components/ui/badge";
+import {
+  acknowledgeJob,
+  addJobNote,
+  artifactDownloadUrl,
+  cancelJob,
+  fetchAgents,
+  fetchJobArtifacts,
+  fetchJob,
+  fetchJobByPublicId,
+  retryJob,
+  streamJobLogs,
+  type Artifact,
+  type JobDetail,
+  type LimitSource,
+} from "@/lib/api";
+import { formatBytes, formatDuration, cn } from "@/lib/utils";
+import { useAuth } from "@/lib/auth";
 import {
   ArrowLeft,
   GitCommit,
@@ -19,38 +35,107 @@ import {
   Timer,
   Gauge,
   Play,
+  Square,
+  MessageSquare,
+  RotateCcw,
+  Package,
+  Download,
+  Grid3x3,
+  Cpu,
 } from "lucide-react";
 
+const LIMIT_SOURCES: Record<LimitSource, string> = {
+  config: "foundry.toml",
+  agent: "agent default",
+  server: "server default",
+};
+
+/** Shown for limits the build didn't set */
+const UNSET_LIMITS = {
+  cpus: "unlimited",
+  memory: "unlimited",
+  platform: "native",
+  user: "image default",
+};
+
 export function JobDetailPage() {
-  const { id } = useParams<{ id: string }>();
+  const { id, publicId } = useParams<{ id: string; publicId: string }>();
+  const { isAdmin } = useAuth();
   const [job, setJob] = useState<JobDetail | null>(null);
   const [loading, setLoading] = useState(true);
   const [autoScroll, setAutoScroll] = useState(true);
+  const [cancelling, setCancelling] = useState(false);
+  const [noteBody, setNoteBody] = useState("");
+  const [savingNote, setSavingNote] = useState(false);
+  const [retrying, setRetrying] = useState(false);
+  const [agentIds, setAgentIds] = useState<string[]>([]);
+  const [retryAgent, setRetryAgent] = useState("");
+  const [artifacts, setArtifacts] = useState<Artifact[]>([]);
+  const navigate = useNavigate();
   const logsEndRef = useRef<HTMLDivElement>(null);
 
-  useEffect(() => {
-    if (!id) return;
-
-    const load = async () => {
-      try {
-        const data = await fetchJob(parseInt(id));
-        setJob(data);
-      } catch (e) {
-        console.error("Failed to load job:", e);
-      } finally {
-        setLoading(false);
-      }
-    };
+  const load = useCallback(async () => {
+    if (!id && !publicId) return;
+    try {
+      const data = publicId
+        ? await fetchJobByPublicId(publicId)
+        : await fetchJob(parseInt(id!));
+      setJob(data);
+    } catch (e) {
+      console.error("Failed to load job:", e);
+    } finally {
+      setLoading(false);
+    }
+  }, [id, publicId]);
 
+  useEffect(() => {
     load();
-    const interval = setInterval(() => {
-      if (job?.status === "queued" || job?.status === "running") {
-        load();
-      }
-    }, 2000);
+  }, [load]);
 
+  // Poll until the job is picked up; once running, logs arrive over SSE
+  useEffect(() => {
+    if (job?.status !== "queued") return;
+    const interval = setInterval(load, 2000);
     return () => clearInterval(interval);
-  }, [id, job?.status]);
+  }, [job?.status, load]);
+
+  const streamId = job?.public_id;
+  useEffect(() => {
+    if (!streamId || job?.status !== "running") return;
+
+    // The stream replays from the start, so the first event replaces the snapshot
+    let replaced = false;
+    return streamJobLogs(
+      streamId,
+      (entry) => {
+        setJob((prev) => {
+          if (!prev) return prev;
+          const logs = replaced ? [...prev.logs, entry] : [entry];
+          replaced = true;
+          return { ...prev, logs };
+        });
+      },
+      () => load()
+    );
+  }, [streamId, job?.status, load]);
+
+  // Artifacts are uploaded at the end of a successful build
+  const jobPublicId = job?.public_id;
+  useEffect(() => {
+    if (!jobPublicId || job?.status !== "success") return;
+    fetchJobArtifacts(jobPublicId)
+      .then(setArtifacts)
+      .catch((e) => console.error("Failed to load artifacts:", e));
+  }, [jobPublicId, job?.status]);
+
+  // Finished jobs can be retried on a specific agent
+  const finished = job !== null && job.status !== "queued" && job.status !== "running";
+  useEffect(() => {
+    if (!finished) return;
+    fetchAgents()
+      .then((res) => setAgentIds(res.agents.map((agent) => agent.id)))
+      .catch((e) => console.error("Failed to load agents:", e));
+  }, [finished]);
 
   useEffect(() => {
     if (autoScroll && logsEndRef.current) {
@@ -99,6 +184,55 @@ export function JobDetailPage() {
 
   const { color, bg, icon: StatusIcon } = statusConfig[job.status];
 
+  const handleCancel = async () => {
+    setCancelling(true);
+    try {
+      await cancelJob(job.public_id);
+      setJob({ ...job, status: "cancelled" });
+    } catch (e) {
+      console.error("Failed to cancel job:", e);
+    } finally {
+      setCancelling(false);
+    }
+  };
+
+  const handleRetry = async () => {
+    setRetrying(true);
+    try {
+      const retry = await retryJob(job.public_id, retryAgent);
+      navigate(`/j/${retry.public_id}`);
+    } catch (e) {
+      console.error("Failed to retry job:", e);
+    } finally {
+      setRetrying(false);
+    }
+  };
+
+  const handleAcknowledge = async () => {
+    const reason = window.prompt("Why is this failure being acknowledged?");
+    if (!reason?.trim()) return;
+    try {
+      await acknowledgeJob(job.public_id, reason);
+      setJob({ ...job, acknowledged: true, ack_reason: reason });
+    } catch (e) {
+      console.error("Failed to acknowledge job:", e);
+    }
+  };
+
+  const handleAddNote = async () => {
+    if (!noteBody.trim()) return;
+    setSavingNote(true);
+    try {
+      const note = await addJobNote(job.public_id, noteBody);
+      setJob({ ...job, notes: [...job.notes, note] });
+      setNoteBody("");
+    } catch (e) {
+      console.error("Failed to add note:", e);
+    } finally {
+      setSavingNote(false);
+    }
+  };
+
   return (
     <div className="space-y-6">
       <div className="flex items-center gap-4">
@@ -108,11 +242,95 @@ export function JobDetailPage() {
           </Link>
         </Button>
         <div className="flex-1">
-          <h1 className="text-2xl font-bold">Build #{job.id}</h1>
+          <h1 className="text-2xl font-bold">Build #{job.id ?? job.public_id}</h1>
           <p className="text-muted-foreground">
             {job.repo_owner}/{job.repo_name}
+            {job.service_path && (
+              <span className="ml-2 font-mono text-xs">{job.service_path}</span>
+            )}
+            {job.matrix_key && (
+              <span className="ml-2 font-mono text-xs">[{job.matrix_key}]</span>
+            )}
+            {job.parent_public_id && (
+              <Link
+                to={`/j/${job.parent_public_id}`}
+                className="ml-2 text-xs text-primary hover:underline"
+              >
+                parent build
+              </Link>
+            )}
+            {job.triggered_by && (
+              <span className="ml-2 text-xs">triggered by {job.triggered_by}</span>
+            )}
+            {job.claimed_by && (
+              <span className="ml-2 text-xs">
+                on {job.claimed_by}
+                {job.agent_version && ` (agent ${job.agent_version})`}
+              </span>
+            )}
+            {job.target_agent && (
+              <Badge variant="outline" className="ml-2 text-xs">
+                pinned to {job.target_agent}
+              </Badge>
+            )}
+            {job.concurrency_group && (
+              <Badge variant="outline" className="ml-2 text-xs">
+                group {job.concurrency_group}
+              </Badge>
+            )}
           </p>
         </div>
+        {isAdmin && (job.status === "queued" || job.status === "running") && (
+          <Button
+            variant="destructive"
+            size="sm"
+            className="gap-2"
+            onClick={handleCancel}
+            disabled={cancelling}
+          >
+            <Square className="h-4 w-4" />
+            Cancel
+          </Button>
+        )}
+        {isAdmin && job.status !== "queued" && job.status !== "running" && (
+          <>
+            {agentIds.length > 0 && (
+              <select
+                className="h-8 rounded-md border bg-background px-2 text-sm"
+                value={retryAgent}
+                onChange={(e) => setRetryAgent(e.target.value)}
+                title="Agent to run the retry on"
+              >
+                <option value="">Any agent</option>
+                {agentIds.map((agentId) => (
+                  <option key={agentId} value={agentId}>
+                    {agentId}
+                  </option>
+                ))}
+              </select>
+            )}
+            <Button
+              variant="outline"
+              size="sm"
+              className="gap-2"
+              onClick={handleRetry}
+              disabled={retrying}
+            >
+              <RotateCcw className="h-4 w-4" />
+              Retry
+            </Button>
+          </>
+        )}
+        {isAdmin && job.status === "failed" && !job.acknowledged && (
+          <Button variant="outline" size="sm" onClick={handleAcknowledge}>
+            Acknowledge
+          </Button>
+        )}
+        {job.acknowledged && (
+          <Badge variant="outline" title={job.ack_reason}>
+            acknowledged
+          </Badge>
+        )}
         <Button variant="outline" size="sm" asChild className="gap-2">
           <a
             href={`https://github.com/${job.repo_owner}/${job.repo_name}/commit/${job.git_sha}`}
@@ -134,6 +352,11 @@ export function JobDetailPage() {
           <span className={cn("font-semibold capitalize", color)}>
             {job.status}
           </span>
+          {job.queue_position && (
+            <span className={cn("text-sm", color)} title="Position in the queue">
+              #{job.queue_position}
+            </span>
+          )}
         </div>
       </div>
 
@@ -217,10 +440,19 @@ export function JobDetailPage() {
             <CardTitle className="text-sm flex items-center gap-2">
               <GitPullRequest className="h-4 w-4" />
               Pull Request #{job.pr_number}
+              {job.pr_title && `: ${job.pr_title}`}
             </CardTitle>
           </CardHeader>
           <CardContent>
-            <p className="text-sm">{job.pr_title}</p>
+            {job.pr_base_ref && (
+              <p className="text-sm text-muted-foreground flex items-center gap-1">
+                <GitBranch className="h-3 w-3" />
+                <code>{job.pr_head_ref ?? "unknown"}</code>
+                <span>→</span>
+                <code>{job.pr_base_ref}</code>
+                {job.pr_author && <span className="ml-2">by {job.pr_author}</span>}
+              </p>
+            )}
             {job.pr_url && (
               <a
                 href={job.pr_url}
@@ -235,6 +467,34 @@ export function JobDetailPage() {
         </Card>
       )}
 
+      {artifacts.length > 0 && (
+        <Card>
+          <CardHeader className="pb-2">
+            <CardTitle className="text-sm flex items-center gap-2">
+              <Package className="h-4 w-4" />
+              Artifacts
+            </CardTitle>
+          </CardHeader>
+          <CardContent>
+            <div className="space-y-2">
+              {artifacts.map((artifact) => (
+                <a
+                  key={artifact.id}
+                  href={artifactDownloadUrl(artifact.id)}
+                  className="flex items-center justify-between p-2 rounded bg-muted/50 hover:bg-muted text-sm"
+                >
+                  <span className="font-mono">{artifact.name}</span>
+                  <span className="flex items-center gap-2 text-muted-foreground">
+                    {formatBytes(artifact.size)}
+                    <Download className="h-4 w-4" />
+                  </span>
+                </a>
+              ))}
+            </div>
+          </CardContent>
+        </Card>
+      )}
+
       {job.metrics && (
         <Card>
           <CardHeader className="pb-2">
@@ -264,6 +524,41 @@ export function JobDetailPage() {
         </Card>
       )}
 
+      {job.limits && (
+        <Card>
+          <CardHeader className="pb-2">
+            <CardTitle className="text-sm flex items-center gap-2">
+              <Cpu className="h-4 w-4" />
+              Resource Limits
+            </CardTitle>
+          </CardHeader>
+          <CardContent>
+            <div className="grid gap-2 text-sm">
+              {(["cpus", "memory", "platform", "user"] as const).map((name) => {
+                const limit = job.limits?.[name];
+                return (
+                  <div key={name} className="flex justify-between">
+                    <span className="text-muted-foreground">{name}</span>
+                    {limit ? (
+                      <span className="font-mono">
+                        {limit.value}{" "}
+                        <span className="text-muted-foreground">
+                          ({LIMIT_SOURCES[limit.source]})
+                        </span>
+                      </span>
+                    ) : (
+                      <span className="text-muted-foreground">
+                        {UNSET_LIMITS[name]}
+                      </span>
+                    )}
+                  </div>
+                );
+              })}
+            </div>
+          </CardContent>
+        </Card>
+      )}
+
       {job.metrics?.stages && job.metrics.stages.length > 0 && (
         <Card>
           <CardHeader className="pb-2">
@@ -304,6 +599,87 @@ export function JobDetailPage() {
         </Card>
       )}
 
+      {job.children.length > 0 && (
+        <Card>
+          <CardHeader className="pb-2">
+            <CardTitle className="text-sm flex items-center gap-2">
+              <Grid3x3 className="h-4 w-4" />
+              Child Builds
+            </CardTitle>
+          </CardHeader>
+          <CardContent>
+            <div className="space-y-2">
+              {job.children.map((child) => {
+                const { color, icon: ChildIcon } = statusConfig[child.status];
+                return (
+                  <Link
+                    key={child.public_id}
+                    to={`/j/${child.public_id}`}
+                    className="flex items-center justify-between p-2 rounded bg-muted/50 hover:bg-muted"
+                  >
+                    <div className="flex items-center gap-2">
+                      <ChildIcon
+                        className={cn(
+                          "h-4 w-4",
+                          color,
+                          child.status === "running" && "animate-spin"
+                        )}
+                      />
+                      <span className="font-mono text-sm">
+                        {child.matrix_key ?? child.service_path ?? `#${child.public_id}`}
+                      </span>
+                    </div>
+                    {child.duration_secs != null && (
+                      <span className="text-muted-foreground text-sm">
+                        {formatDuration(child.duration_secs)}
+                      </span>
+                    )}
+                  </Link>
+                );
+              })}
+            </div>
+          </CardContent>
+        </Card>
+      )}
+
+      <Card>
+        <CardHeader className="pb-2">
+          <CardTitle className="text-sm flex items-center gap-2">
+            <MessageSquare className="h-4 w-4" />
+            Notes
+          </CardTitle>
+        </CardHeader>
+        <CardContent className="space-y-3">
+          {job.notes.map((note) => (
+            <div key={note.id} className="p-2 rounded bg-muted/50">
+              <div className="flex justify-between text-xs text-muted-foreground">
+                <span>{note.author}</span>
+                <span>{new Date(note.created_at).toLocaleString()}</span>
+              </div>
+              <p className="text-sm whitespace-pre-wrap mt-1">{note.body}</p>
+            </div>
+          ))}
+          {isAdmin && (
+          <div className="flex gap-2">
+            <textarea
+              value={noteBody}
+              onChange={(e) => setNoteBody(e.target.value)}
+              placeholder="Add a note (e.g. known flake, infra issue)"
+              rows={2}
+              className="flex-1 rounded-md border bg-background px-3 py-2 text-sm"
+            />
+            <Button
+              size="sm"
+              onClick={handleAddNote}
+              disabled={savingNote || !noteBody.trim()}
+            >
+              Add note
+            </Button>
+          </div>
+          )}
+        </CardContent>
+      </Card>
+
       <Card>
         <CardHeader className="flex flex-row items-center justify-between">
           <CardTitle>Build Logs</CardTitle>
@@ -333,9 +709,10 @@ export function JobDetailPage() {
                     <span
                       className={cn(
                         log.level === "error" && "text-red-400",
-                        log.message.toLowerCase().includes("error") &&
-                          "text-red-400",
-                        log.message.includes("✓") && "text-green-400",
+                        log.level === "warn" && "text-yellow-400",
+                        log.level === "info" &&
+                          log.message.includes("✓") &&
+                          "text-green-400",
                       )}
                     >
                       {log.message}
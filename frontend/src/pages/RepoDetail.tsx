@@ -1,20 +1,33 @@
 import { useEffect, useState, useCallback } from "react";
-import { useParams, Link } from "react-router-dom";
+import { useParams, Link, useNavigate } from "react-router-dom";
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { Badge } from "@/components/ui/badge";
 import { Button } from "@/components/ui/button";
 import {
   fetchRepo,
   fetchRepoJobs,
+  fetchRepoStats,
   fetchContainers,
   restartProject,
+  rebuildLatest,
+  triggerBuild,
+  setRepoEnabled,
+  setRepoCancelOutdated,
+  type CancelOutdated,
+  fetchRepoSecrets,
+  setRepoSecret,
+  fetchRepoDeployments,
+  rollbackDeployment,
+  type Deployment,
+  type RepoBuildStats,
   type RepoDetail,
   type Job,
   type Container,
 } from "@/lib/api";
 import { ContainerList } from "@/components/ContainerList";
 import { LogViewer } from "@/components/LogViewer";
-import { formatRelativeTime } from "@/lib/utils";
+import { formatDuration, formatRelativeTime } from "@/lib/utils";
+import { useAuth } from "@/lib/auth";
 import {
   GitBranch,
   ExternalLink,
@@ -28,21 +41,47 @@ import {
   Globe,
   RotateCw,
   Box,
+  KeyRound,
+  Rocket,
+  Undo2,
+  Pause,
+  Play,
 } from "lucide-react";
 
 export function RepoDetailPage() {
   const { id } = useParams<{ id: string }>();
+  const { isAdmin } = useAuth();
   const [repo, setRepo] = useState<RepoDetail | null>(null);
   const [jobs, setJobs] = useState<Job[]>([]);
+  const [buildStats, setBuildStats] = useState<RepoBuildStats | null>(null);
   const [containers, setContainers] = useState<Container[]>([]);
   const [selectedContainer, setSelectedContainer] = useState<Container | null>(null);
   const [loading, setLoading] = useState(true);
   const [restartingProject, setRestartingProject] = useState(false);
+  const [rebuilding, setRebuilding] = useState(false);
+  const [startingBuild, setStartingBuild] = useState(false);
+  const [rebuildError, setRebuildError] = useState<string | null>(null);
+  const [togglingEnabled, setTogglingEnabled] = useState(false);
+  const [secrets, setSecrets] = useState<string[]>([]);
+  const [secretName, setSecretName] = useState("");
+  const [secretValue, setSecretValue] = useState("");
+  const [secretError, setSecretError] = useState<string | null>(null);
+  const [deployments, setDeployments] = useState<Deployment[]>([]);
+  const [rollingBack, setRollingBack] = useState<string | null>(null);
+  const [rollbackError, setRollbackError] = useState<string | null>(null);
+  const navigate = useNavigate();
 
-  const loadContainers = useCallback(async (projectName: string) => {
+  const loadContainers = useCallback(async (repo: RepoDetail) => {
     try {
-      const containerData = await fetchContainers(projectName);
-      setContainers(containerData);
+      // Compose deploys share the repo name as project; `docker run` deploys are labelled
+      const [projectContainers, labelledContainers] = await Promise.all([
+        fetchContainers({ project: repo.name }),
+        fetchContainers({ repo: `${repo.owner}/${repo.name}` }),
+      ]);
+      const byId = new Map(
+        [...projectContainers, ...labelledContainers].map((c) => [c.id, c])
+      );
+      setContainers([...byId.values()]);
     } catch (e) {
       console.error("Failed to load containers:", e);
     }
@@ -58,10 +97,12 @@ export function RepoDetailPage() {
         ]);
         setRepo(repoData);
         setJobs(jobsData);
+        fetchRepoSecrets(Number(id)).then(setSecrets).catch(() => {});
+        fetchRepoDeployments(Number(id)).then(setDeployments).catch(() => {});
+        fetchRepoStats(Number(id)).then(setBuildStats).catch(() => {});
 
-        // Try to load containers for this project (using repo name as project name)
         if (repoData.name) {
-          loadContainers(repoData.name);
+          loadContainers(repoData);
         }
       } catch (e) {
         console.error("Failed to load repo:", e);
@@ -78,7 +119,7 @@ export function RepoDetailPage() {
     try {
       await restartProject(repo.name);
       // Refresh containers after restart
-      loadContainers(repo.name);
+      loadContainers(repo);
     } catch (e) {
       console.error("Failed to restart project:", e);
     } finally {
@@ -86,6 +127,97 @@ export function RepoDetailPage() {
     }
   };
 
+  const handleRebuildLatest = async () => {
+    if (!repo) return;
+    setRebuilding(true);
+    setRebuildError(null);
+    try {
+      const job = await rebuildLatest(repo.id);
+      navigate(`/j/${job.public_id}`);
+    } catch (e) {
+      setRebuildError(e instanceof Error ? e.message : "Failed to rebuild");
+    } finally {
+      setRebuilding(false);
+    }
+  };
+
+  const handleRunBuild = async () => {
+    if (!repo) return;
+    const ref = window.prompt("Branch or ref to build", repo.default_branch ?? "main");
+    if (ref === null) return;
+    setStartingBuild(true);
+    setRebuildError(null);
+    try {
+      const job = await triggerBuild(repo.id, ref.trim());
+      navigate(`/j/${job.public_id}`);
+    } catch (e) {
+      setRebuildError(e instanceof Error ? e.message : "Failed to start build");
+    } finally {
+      setStartingBuild(false);
+    }
+  };
+
+  const handleToggleEnabled = async () => {
+    if (!repo) return;
+    setTogglingEnabled(true);
+    try {
+      await setRepoEnabled(repo.id, !repo.enabled);
+      setRepo({ ...repo, enabled: !repo.enabled });
+    } catch (e) {
+      console.error("Failed to update repo:", e);
+    } finally {
+      setTogglingEnabled(false);
+    }
+  };
+
+  const handleCancelOutdatedChange = async (cancelOutdated: CancelOutdated) => {
+    if (!repo) return;
+    try {
+      await setRepoCancelOutdated(repo.id, cancelOutdated);
+      setRepo({ ...repo, cancel_outdated: cancelOutdated });
+    } catch (e) {
+      console.error("Failed to update repo:", e);
+    }
+  };
+
+  const handleSaveSecret = async (e: React.FormEvent) => {
+    e.preventDefault();
+    if (!repo) return;
+    setSecretError(null);
+    try {
+      await setRepoSecret(repo.id, secretName.trim(), secretValue);
+      setSecretName("");
+      setSecretValue("");
+      setSecrets(await fetchRepoSecrets(repo.id));
+    } catch (e) {
+      setSecretError(e instanceof Error ? e.message : "Failed to save secret");
+    }
+  };
+
+  const handleRollback = async (app: string) => {
+    if (!repo) return;
+    setRollingBack(app);
+    setRollbackError(null);
+    try {
+      await rollbackDeployment(app);
+      setDeployments(await fetchRepoDeployments(repo.id));
+      loadContainers(repo);
+    } catch (e) {
+      setRollbackError(e instanceof Error ? e.message : "Failed to roll back");
+    } finally {
+      setRollingBack(null);
+    }
+  };
+
+  // The newest deployment of each app that hasn't been rolled back is the one running
+  const liveDeployments = new Set<number>();
+  const seenApps = new Set<string>();
+  for (const d of deployments) {
+    if (d.rolled_back_at || seenApps.has(d.app_name)) continue;
+    seenApps.add(d.app_name);
+    liveDeployments.add(d.id);
+  }
+
   if (loading) {
     return (
       <div className="flex items-center justify-center h-64">
@@ -143,6 +275,12 @@ export function RepoDetailPage() {
                 Public
               </Badge>
             )}
+            {!repo.enabled && (
+              <Badge variant="secondary" className="text-yellow-500">
+                <Pause className="h-3 w-3 mr-1" />
+                Builds paused
+              </Badge>
+            )}
           </div>
           {repo.description && (
             <p className="text-muted-foreground mt-2 max-w-2xl">
@@ -150,21 +288,75 @@ export function RepoDetailPage() {
             </p>
           )}
         </div>
-        {repo.html_url && (
-          <a
-            href={repo.html_url}
-            target="_blank"
-            rel="noopener noreferrer"
-            className="flex items-center gap-2 px-4 py-2 bg-secondary hover:bg-secondary/80 rounded-md transition-colors"
-          >
-            View on GitHub
-            <ExternalLink className="h-4 w-4" />
-          </a>
-        )}
+        <div className="flex flex-col items-end gap-2">
+          <div className="flex items-center gap-2">
+            {isAdmin && (
+            <>
+            <Button
+              variant="outline"
+              className="gap-2"
+              onClick={handleToggleEnabled}
+              disabled={togglingEnabled}
+            >
+              {repo.enabled ? (
+                <Pause className="h-4 w-4" />
+              ) : (
+                <Play className="h-4 w-4" />
+              )}
+              {repo.enabled ? "Pause builds" : "Resume builds"}
+            </Button>
+            <select
+              className="h-9 rounded-md border bg-background px-2 text-sm"
+              value={repo.cancel_outdated}
+              onChange={(e) =>
+                handleCancelOutdatedChange(e.target.value as CancelOutdated)
+              }
+              title="Older builds of a branch that a new push to it cancels"
+            >
+              <option value="off">Keep older builds</option>
+              <option value="queued">Cancel queued on push</option>
+              <option value="running">Cancel queued and running on push</option>
+            </select>
+            <Button
+              variant="outline"
+              className="gap-2"
+              onClick={handleRunBuild}
+              disabled={startingBuild}
+            >
+              <Play className="h-4 w-4" />
+              Run build
+            </Button>
+            <Button
+              variant="outline"
+              className="gap-2"
+              onClick={handleRebuildLatest}
+              disabled={rebuilding}
+            >
+              <RotateCw className={`h-4 w-4 ${rebuilding ? "animate-spin" : ""}`} />
+              Rebuild latest
+            </Button>
+            </>
+            )}
+            {repo.html_url && (
+              <a
+                href={repo.html_url}
+                target="_blank"
+                rel="noopener noreferrer"
+                className="flex items-center gap-2 px-4 py-2 bg-secondary hover:bg-secondary/80 rounded-md transition-colors"
+              >
+                View on GitHub
+                <ExternalLink className="h-4 w-4" />
+              </a>
+            )}
+          </div>
+          {rebuildError && (
+            <p className="text-sm text-destructive">{rebuildError}</p>
+          )}
+        </div>
       </div>
 
       {/* Stats cards */}
-      <div className="grid gap-4 md:grid-cols-4">
+      <div className="grid gap-4 md:grid-cols-5">
         <Card>
           <CardHeader className="pb-2">
             <CardTitle className="text-sm font-medium text-muted-foreground">
@@ -211,6 +403,32 @@ export function RepoDetailPage() {
             </div>
           </CardContent>
         </Card>
+        <Card>
+          <CardHeader className="pb-2">
+            <CardTitle className="text-sm font-medium text-muted-foreground">
+              Build Time ({buildStats?.days ?? 30}d)
+            </CardTitle>
+          </CardHeader>
+          <CardContent>
+            <div className="text-3xl font-bold" title="Median successful build">
+              {formatDuration(buildStats?.current.p50_secs)}
+            </div>
+            <p className="text-xs text-muted-foreground">
+              p95 {formatDuration(buildStats?.current.p95_secs)}
+              {buildStats?.p50_change_percent != null && (
+                <span
+                  className={
+                    buildStats.p50_change_percent > 0 ? "ml-2 text-red-500" : "ml-2 text-green-500"
+                  }
+                  title={`Median change from the previous ${buildStats.days} days`}
+                >
+                  {buildStats.p50_change_percent > 0 ? "+" : ""}
+                  {buildStats.p50_change_percent}%
+                </span>
+              )}
+            </p>
+          </CardContent>
+        </Card>
       </div>
 
       {/* Containers section */}
@@ -221,6 +439,7 @@ export function RepoDetailPage() {
               <Box className="h-5 w-5" />
               Containers
             </CardTitle>
+            {isAdmin && (
             <Button
               variant="outline"
               size="sm"
@@ -234,6 +453,7 @@ export function RepoDetailPage() {
               )}
               Restart All
             </Button>
+            )}
           </CardHeader>
           <CardContent>
             {selectedContainer ? (
@@ -245,13 +465,64 @@ export function RepoDetailPage() {
               <ContainerList
                 containers={containers}
                 onViewLogs={setSelectedContainer}
-                onRefresh={() => repo && loadContainers(repo.name)}
+                onRefresh={() => repo && loadContainers(repo)}
               />
             )}
           </CardContent>
         </Card>
       )}
 
+      {/* Deployments */}
+      {deployments.length > 0 && (
+        <Card>
+          <CardHeader>
+            <CardTitle className="flex items-center gap-2">
+              <Rocket className="h-5 w-5" />
+              Deployments
+            </CardTitle>
+          </CardHeader>
+          <CardContent className="space-y-2">
+            {deployments.map((d) => (
+              <div
+                key={d.id}
+                className="flex items-center justify-between p-3 rounded-lg border"
+              >
+                <div className="flex items-center gap-3">
+                  <span className="font-medium">{d.app_name}</span>
+                  <span className="font-mono text-sm text-muted-foreground">{d.image}</span>
+                  {liveDeployments.has(d.id) && <Badge>live</Badge>}
+                  {d.rolled_back_at && <Badge variant="outline">rolled back</Badge>}
+                </div>
+                <div className="flex items-center gap-4 text-sm text-muted-foreground">
+                  {d.job_public_id && (
+                    <Link to={`/j/${d.job_public_id}`} className="hover:underline">
+                      build
+                    </Link>
+                  )}
+                  <span>{formatRelativeTime(d.created_at)}</span>
+                  {isAdmin && liveDeployments.has(d.id) && (
+                    <Button
+                      variant="outline"
+                      size="sm"
+                      onClick={() => handleRollback(d.app_name)}
+                      disabled={rollingBack !== null}
+                    >
+                      {rollingBack === d.app_name ? (
+                        <Loader2 className="h-4 w-4 mr-2 animate-spin" />
+                      ) : (
+                        <Undo2 className="h-4 w-4 mr-2" />
+                      )}
+                      Roll back
+                    </Button>
+                  )}
+                </div>
+              </div>
+            ))}
+            {rollbackError && <p className="text-sm text-red-500">{rollbackError}</p>}
+          </CardContent>
+        </Card>
+      )}
+
       {/* Repo info */}
       <Card>
         <CardHeader>
@@ -289,6 +560,53 @@ export function RepoDetailPage() {
         </CardContent>
       </Card>
 
+      {/* Build secrets */}
+      <Card>
+        <CardHeader>
+          <CardTitle className="flex items-center gap-2">
+            <KeyRound className="h-5 w-5" />
+            Secrets
+          </CardTitle>
+        </CardHeader>
+        <CardContent className="space-y-4">
+          {secrets.length === 0 ? (
+            <p className="text-sm text-muted-foreground">
+              No secrets. Builds receive the ones listed under [secrets] in foundry.toml.
+            </p>
+          ) : (
+            <div className="flex flex-wrap gap-2">
+              {secrets.map((name) => (
+                <Badge key={name} variant="secondary" className="font-mono">
+                  {name}
+                </Badge>
+              ))}
+            </div>
+          )}
+          {isAdmin && (
+          <form onSubmit={handleSaveSecret} className="flex flex-wrap gap-2">
+            <input
+              value={secretName}
+              onChange={(e) => setSecretName(e.target.value)}
+              placeholder="NAME"
+              className="h-9 rounded-md border bg-background px-3 font-mono text-sm"
+            />
+            <input
+              type="password"
+              value={secretValue}
+              onChange={(e) => setSecretValue(e.target.value)}
+              placeholder="Value"
+              autoComplete="off"
+              className="h-9 flex-1 rounded-md border bg-background px-3 text-sm"
+            />
+            <Button type="submit" size="sm" disabled={!secretName.trim() || !secretValue}>
+              Save
+            </Button>
+          </form>
+          )}
+          {secretError && <p className="text-sm text-red-500">{secretError}</p>}
+        </CardContent>
+      </Card>
+
       {/* Recent builds */}
       <Card>
         <CardHeader>
@@ -303,8 +621,8 @@ export function RepoDetailPage() {
             <div className="space-y-2">
               {jobs.map((job) => (
                 <Link
-                  key={job.id}
-                  to={`/job/${job.id}`}
+                  key={job.public_id}
+                  to={`/j/${job.public_id}`}
                   className="flex items-center justify-between p-3 rounded-lg border hover:bg-accent transition-colors"
                 >
                   <div className="flex items-center gap-3">
@@ -2,16 +2,57 @@ import { useEffect, useState } from "react";
 import { Link } from "react-router-dom";
 import { Card, CardContent, CardHeader, CardTitle } from "@/components/ui/card";
 import { Badge } from "@/components/ui/badge";
-import { fetchRepos, type Repo } from "@/lib/api";
-import { formatRelativeTime } from "@/lib/utils";
+import { fetchRepos, type Job, type Repo } from "@/lib/api";
+import { cn, formatRelativeTime } from "@/lib/utils";
 import {
   GitBranch,
   ExternalLink,
   Loader2,
   CheckCircle2,
   XCircle,
+  List,
+  Pause,
 } from "lucide-react";
 
+const statusDotColor: Record<Job["status"], string> = {
+  success: "bg-green-500",
+  failed: "bg-red-500",
+  running: "bg-yellow-500",
+  queued: "bg-muted-foreground",
+  cancelled: "bg-muted",
+};
+
+/** Passed and failed builds as shares of all builds; the rest were cancelled or are in progress */
+function SuccessBar({ repo }: { repo: Repo }) {
+  if (repo.build_count === 0) return null;
+  const share = (count: number) => `${(count / repo.build_count) * 100}%`;
+
+  return (
+    <div className="flex h-1.5 mt-4 overflow-hidden rounded-full bg-muted">
+      <div className="bg-green-500" style={{ width: share(repo.success_count) }} />
+      <div className="bg-red-500" style={{ width: share(repo.failure_count) }} />
+    </div>
+  );
+}
+
+function RecentBuilds({ repo }: { repo: Repo }) {
+  if (repo.recent_statuses.length === 0) return null;
+
+  return (
+    <div className="flex items-center gap-1 mt-4">
+      {repo.recent_statuses.map((status, i) => (
+        <Link
+          key={repo.recent_job_ids[i]}
+          to={`/j/${repo.recent_job_ids[i]}`}
+          onClick={(e) => e.stopPropagation()}
+          title={status}
+          className={cn("h-3 w-3 rounded-full", statusDotColor[status])}
+        />
+      ))}
+    </div>
+  );
+}
+
 export function Repositories() {
   const [repos, setRepos] = useState<Repo[]>([]);
   const [loading, setLoading] = useState(true);
@@ -70,7 +111,13 @@ export function Repositories() {
                         {repo.owner}
                       </p>
                     </div>
-                    {repo.last_status && (
+                    {!repo.enabled && (
+                      <Badge variant="secondary" className="text-yellow-500">
+                        <Pause className="h-3 w-3 mr-1" />
+                        Paused
+                      </Badge>
+                    )}
+                    {repo.enabled && repo.last_status && (
                       <Badge
                         variant={
                           repo.last_status === "success"
@@ -122,6 +169,9 @@ export function Repositories() {
                     </div>
                   </div>
 
+                  <SuccessBar repo={repo} />
+                  <RecentBuilds repo={repo} />
+
                   {(successRate || repo.last_build_at) && (
                     <div className="mt-4 pt-4 border-t text-sm text-muted-foreground">
                       {successRate && <span>{successRate}% success rate</span>}
@@ -133,6 +183,15 @@ export function Repositories() {
                     </div>
                   )}
 
+                  <Link
+                    to={`/?repo=${encodeURIComponent(`${repo.owner}/${repo.name}`)}`}
+                    onClick={(e) => e.stopPropagation()}
+                    className="mt-4 flex items-center justify-center gap-2 text-sm text-primary hover:underline"
+                  >
+                    View builds
+                    <List className="h-3 w-3" />
+                  </Link>
+
                   {repo.html_url && (
                     <a
                       href={repo.html_url}
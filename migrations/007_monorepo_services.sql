@@ -0,0 +1,2 @@
+-- Monorepo service builds: child jobs point at the service directory they build
+ALTER TABLE job ADD COLUMN IF NOT EXISTS service_path TEXT;
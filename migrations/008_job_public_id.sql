@@ -0,0 +1,18 @@
+-- Short unguessable job ids for shareable permalinks (/j/{public_id})
+CREATE OR REPLACE FUNCTION generate_public_id() RETURNS TEXT AS $$
+DECLARE
+    alphabet CONSTANT TEXT := 'abcdefghijklmnopqrstuvwxyz234567';
+    bytes BYTEA := uuid_send(gen_random_uuid());
+    result TEXT := '';
+BEGIN
+    -- Byte 6 carries the UUID version nibble, so skip it to keep every character uniform
+    FOR i IN 0..10 LOOP
+        CONTINUE WHEN i = 6;
+        result := result || substr(alphabet, 1 + (get_byte(bytes, i) % 32), 1);
+    END LOOP;
+    RETURN result;
+END;
+$$ LANGUAGE plpgsql VOLATILE;
+
+ALTER TABLE job ADD COLUMN IF NOT EXISTS public_id TEXT NOT NULL DEFAULT generate_public_id();
+CREATE UNIQUE INDEX IF NOT EXISTS idx_job_public_id ON job(public_id);
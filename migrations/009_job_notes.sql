@@ -0,0 +1,10 @@
+-- Operator notes on jobs (post-mortems, known flakes)
+CREATE TABLE IF NOT EXISTS job_note (
+    id BIGSERIAL PRIMARY KEY,
+    job_id BIGINT NOT NULL REFERENCES job(id) ON DELETE CASCADE,
+    author TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_job_note_job_id ON job_note(job_id, created_at);
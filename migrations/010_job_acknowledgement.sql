@@ -0,0 +1,5 @@
+-- Acknowledged failures (known flakes) don't count against success rates
+ALTER TABLE job ADD COLUMN IF NOT EXISTS acknowledged BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS ack_reason TEXT;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS acknowledged_by TEXT;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS acknowledged_at TIMESTAMPTZ;
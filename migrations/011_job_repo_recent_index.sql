@@ -0,0 +1,2 @@
+-- Serves the per-repo "last N builds" lateral lookup on the repo list
+CREATE INDEX IF NOT EXISTS idx_job_repo_created ON job(repo_id, created_at DESC, id DESC);
@@ -0,0 +1,3 @@
+-- Path filters from foundry.toml [triggers], checked before enqueueing pushes
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS triggers_paths TEXT[];
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS triggers_paths_ignore TEXT[];
@@ -0,0 +1,2 @@
+-- Repos that never synced [triggers] branches fall back to FOUNDRY_BUILD_BRANCHES
+ALTER TABLE repo ALTER COLUMN triggers_branches DROP DEFAULT;
@@ -0,0 +1,18 @@
+-- Exit code reported by the agent, and a notification when a job finishes so
+-- clients can wait on a build without polling
+ALTER TABLE job ADD COLUMN IF NOT EXISTS exit_code INT;
+
+CREATE OR REPLACE FUNCTION notify_job_finished() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.status NOT IN ('queued', 'running') AND OLD.status IS DISTINCT FROM NEW.status THEN
+        PERFORM pg_notify('job_finished', NEW.id::text);
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trigger_notify_job_finished ON job;
+CREATE TRIGGER trigger_notify_job_finished
+    AFTER UPDATE ON job
+    FOR EACH ROW
+    EXECUTE FUNCTION notify_job_finished();
@@ -0,0 +1,3 @@
+-- GitHub check run created for the job, so check_run webhook events can find it
+ALTER TABLE job ADD COLUMN IF NOT EXISTS check_run_id BIGINT;
+CREATE INDEX IF NOT EXISTS idx_job_check_run_id ON job(check_run_id) WHERE check_run_id IS NOT NULL;
@@ -0,0 +1,3 @@
+-- Retries are new jobs pointing at the job they re-run
+ALTER TABLE job ADD COLUMN IF NOT EXISTS retry_of BIGINT REFERENCES job(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS idx_job_retry_of ON job(retry_of) WHERE retry_of IS NOT NULL;
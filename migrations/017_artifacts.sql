@@ -0,0 +1,11 @@
+-- Files uploaded by agents after a successful build; contents live under FOUNDRY_ARTIFACT_DIR/<id>
+CREATE TABLE IF NOT EXISTS artifact (
+    id BIGSERIAL PRIMARY KEY,
+    job_id BIGINT NOT NULL REFERENCES job(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    content_type TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_artifact_job ON artifact(job_id);
@@ -0,0 +1,5 @@
+-- Agents heartbeat running jobs; jobs whose agent goes quiet are requeued a
+-- limited number of times, then failed
+ALTER TABLE job ADD COLUMN IF NOT EXISTS last_heartbeat_at TIMESTAMPTZ;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS reclaim_count INT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_job_running_heartbeat ON job(last_heartbeat_at) WHERE status = 'running';
@@ -0,0 +1,2 @@
+-- Dashboard user who queued a manual build
+ALTER TABLE job ADD COLUMN IF NOT EXISTS triggered_by TEXT;
@@ -0,0 +1,5 @@
+-- Queued jobs are claimed highest priority first; the priority comes from the
+-- server's per-trigger-type policy when the job is enqueued
+ALTER TYPE trigger_type ADD VALUE IF NOT EXISTS 'scheduled';
+ALTER TABLE job ADD COLUMN IF NOT EXISTS priority INT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_job_queued_priority ON job(priority DESC, created_at) WHERE status = 'queued';
@@ -0,0 +1,11 @@
+-- Per-repo build secrets, AES-256-GCM encrypted with the server's FOUNDRY_SECRET_KEY
+-- (value is the nonce followed by the ciphertext)
+CREATE TABLE IF NOT EXISTS repo_secret (
+    id BIGSERIAL PRIMARY KEY,
+    repo_id BIGINT NOT NULL REFERENCES repo(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    value BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (repo_id, name)
+);
@@ -0,0 +1,8 @@
+-- Agents seen polling for work, with the build they run, for spotting version skew
+CREATE TABLE IF NOT EXISTS agent (
+    id TEXT PRIMARY KEY,
+    version TEXT,
+    protocol_version INT,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
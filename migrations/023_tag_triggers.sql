@@ -0,0 +1,5 @@
+-- Builds for tag pushes and published releases, opted into via [triggers] tags/releases
+ALTER TYPE trigger_type ADD VALUE IF NOT EXISTS 'tag';
+ALTER TYPE trigger_type ADD VALUE IF NOT EXISTS 'release';
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS triggers_tags TEXT[];
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS triggers_releases BOOLEAN NOT NULL DEFAULT FALSE;
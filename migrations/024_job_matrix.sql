@@ -0,0 +1,45 @@
+-- Matrix builds: the parent job fans out one child per combination and stays
+-- running, with no claim, until every child has finished
+ALTER TABLE job ADD COLUMN IF NOT EXISTS matrix_key TEXT;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS matrix JSONB;
+
+-- Finish a waiting matrix parent once all its children are done: success only if
+-- every child succeeded
+CREATE OR REPLACE FUNCTION settle_matrix_parent(parent_id BIGINT) RETURNS VOID AS $$
+BEGIN
+    -- Serialize sibling children finishing together; the next statement's snapshot
+    -- then sees whichever finished first
+    PERFORM 1 FROM job WHERE id = parent_id FOR UPDATE;
+
+    UPDATE job p
+    SET status = CASE WHEN s.all_success THEN 'success' ELSE 'failed' END::job_status,
+        finished_at = now()
+    FROM (
+        SELECT bool_and(status = 'success') AS all_success,
+               bool_and(status IN ('success', 'failed', 'cancelled')) AS all_done
+        FROM job
+        WHERE parent_job_id = parent_id AND matrix_key IS NOT NULL
+    ) s
+    WHERE p.id = parent_id
+      AND p.status = 'running'
+      AND p.claim_token IS NULL
+      AND s.all_done;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION settle_matrix_parent_on_child() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM settle_matrix_parent(NEW.parent_job_id);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trigger_settle_matrix_parent ON job;
+CREATE TRIGGER trigger_settle_matrix_parent
+    AFTER UPDATE OF status ON job
+    FOR EACH ROW
+    WHEN (NEW.matrix_key IS NOT NULL
+          AND NEW.parent_job_id IS NOT NULL
+          AND NEW.status NOT IN ('queued', 'running')
+          AND OLD.status IS DISTINCT FROM NEW.status)
+    EXECUTE FUNCTION settle_matrix_parent_on_child();
@@ -0,0 +1,16 @@
+-- Containers started by `docker run` deploys, newest last per app, for rolling back
+CREATE TABLE IF NOT EXISTS deployment (
+    id BIGSERIAL PRIMARY KEY,
+    app_name TEXT NOT NULL,
+    repo_id BIGINT NOT NULL REFERENCES repo(id) ON DELETE CASCADE,
+    job_id BIGINT REFERENCES job(id) ON DELETE SET NULL,
+    image TEXT NOT NULL,
+    run_args JSONB NOT NULL,
+    command JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Set when a rollback replaced this deployment with an earlier one
+    rolled_back_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_deployment_app ON deployment(app_name, id DESC);
+CREATE INDEX IF NOT EXISTS idx_deployment_repo ON deployment(repo_id, id DESC);
@@ -0,0 +1,2 @@
+-- Set while an agent reports it can't reach Docker and isn't claiming jobs
+ALTER TABLE agent ADD COLUMN IF NOT EXISTS docker_error TEXT;
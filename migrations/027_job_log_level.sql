@@ -0,0 +1,8 @@
+DO $$ BEGIN
+    CREATE TYPE log_level AS ENUM ('info', 'warn', 'error');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- Lines written before levels existed, or by agents that don't send one, are info
+ALTER TABLE job_log ADD COLUMN IF NOT EXISTS level log_level NOT NULL DEFAULT 'info';
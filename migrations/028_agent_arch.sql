@@ -0,0 +1,2 @@
+-- Docker architecture reported by the agent, e.g. amd64 or arm64
+ALTER TABLE agent ADD COLUMN IF NOT EXISTS arch TEXT;
@@ -0,0 +1,2 @@
+-- Limits the job's build containers ran with and where each came from, reported by the agent
+ALTER TABLE job ADD COLUMN IF NOT EXISTS limits JSONB;
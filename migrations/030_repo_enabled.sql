@@ -0,0 +1,2 @@
+-- Paused repos still record webhooks but queue no builds
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS enabled BOOLEAN NOT NULL DEFAULT TRUE;
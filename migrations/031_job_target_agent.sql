@@ -0,0 +1,2 @@
+-- Agent a job is pinned to from the dashboard; other agents skip it
+ALTER TABLE job ADD COLUMN IF NOT EXISTS target_agent TEXT;
@@ -0,0 +1,4 @@
+-- Object holding a finished job's logs once they are offloaded to the log store
+ALTER TABLE job ADD COLUMN IF NOT EXISTS log_url TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_job_log_offload ON job(id) WHERE log_url IS NULL AND status IN ('success', 'failed', 'cancelled');
@@ -0,0 +1,3 @@
+-- Log volume written by the job's agent, checked against FOUNDRY_MAX_LOG_SIZE
+ALTER TABLE job ADD COLUMN IF NOT EXISTS log_bytes BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS log_truncated BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Chat webhooks from foundry.toml [notify], posted to when a build finishes
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS notify_slack_webhook TEXT;
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS notify_discord_webhook TEXT;
@@ -0,0 +1,3 @@
+-- Which older builds of the same ref a new push cancels: none, queued ones, or queued and running ones
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS cancel_outdated TEXT NOT NULL DEFAULT 'off'
+    CHECK (cancel_outdated IN ('off', 'queued', 'running'));
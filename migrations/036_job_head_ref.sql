@@ -0,0 +1,2 @@
+-- Branch a pull request build comes from; base_ref is the branch it targets
+ALTER TABLE job ADD COLUMN IF NOT EXISTS head_ref TEXT;
@@ -0,0 +1,70 @@
+-- foundry.toml [concurrency]: jobs sharing a group run one at a time, across repos
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS concurrency_group TEXT;
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS concurrency_cancel_in_progress BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE job ADD COLUMN IF NOT EXISTS concurrency_group TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_job_concurrency_group ON job (concurrency_group)
+    WHERE concurrency_group IS NOT NULL AND status IN ('queued', 'running');
+
+-- Root jobs take their repo's group when queued, whichever path queues them. Matrix
+-- and monorepo children don't: a matrix parent holds the group until its
+-- combinations finish
+CREATE OR REPLACE FUNCTION set_job_concurrency_group() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.parent_job_id IS NULL AND NEW.concurrency_group IS NULL THEN
+        SELECT concurrency_group INTO NEW.concurrency_group FROM repo WHERE id = NEW.repo_id;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trigger_set_job_concurrency_group ON job;
+CREATE TRIGGER trigger_set_job_concurrency_group
+    BEFORE INSERT ON job
+    FOR EACH ROW
+    EXECUTE FUNCTION set_job_concurrency_group();
+
+-- With cancel_in_progress, a new job cancels the rest of its group, along with
+-- their matrix combinations, instead of waiting behind them
+CREATE OR REPLACE FUNCTION cancel_concurrency_group() RETURNS TRIGGER AS $$
+DECLARE
+    cancelled BIGINT[];
+BEGIN
+    IF NOT (SELECT concurrency_cancel_in_progress FROM repo WHERE id = NEW.repo_id) THEN
+        RETURN NEW;
+    END IF;
+
+    WITH c AS (
+        UPDATE job
+        SET status = 'cancelled', finished_at = now()
+        WHERE concurrency_group = NEW.concurrency_group
+          AND id <> NEW.id
+          AND status IN ('queued', 'running')
+        RETURNING id
+    )
+    SELECT array_agg(id) INTO cancelled FROM c;
+
+    IF cancelled IS NULL THEN
+        RETURN NEW;
+    END IF;
+
+    UPDATE job
+    SET status = 'cancelled', finished_at = now()
+    WHERE parent_job_id = ANY(cancelled)
+      AND matrix_key IS NOT NULL
+      AND status IN ('queued', 'running');
+
+    INSERT INTO job_log (job_id, line, level)
+    SELECT id, format('🛑 Cancelled: job %s took over concurrency group %s', NEW.public_id, NEW.concurrency_group), 'warn'::log_level
+    FROM unnest(cancelled) AS id;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trigger_cancel_concurrency_group ON job;
+CREATE TRIGGER trigger_cancel_concurrency_group
+    AFTER INSERT ON job
+    FOR EACH ROW
+    WHEN (NEW.concurrency_group IS NOT NULL)
+    EXECUTE FUNCTION cancel_concurrency_group();
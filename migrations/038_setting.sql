@@ -0,0 +1,6 @@
+-- Server-wide switches operators flip at runtime, e.g. pausing the queue during an incident
+CREATE TABLE IF NOT EXISTS setting (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+-- Jobs queued by replaying a stored webhook event, left out of job counts so a
+-- delivery replayed after it already built isn't counted twice
+ALTER TABLE job ADD COLUMN IF NOT EXISTS replayed BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE webhook_event ADD COLUMN IF NOT EXISTS replayed_at TIMESTAMPTZ;
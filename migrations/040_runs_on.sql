@@ -0,0 +1,26 @@
+-- foundry.toml [build] runs_on: labels an agent needs (FOUNDRY_AGENT_LABELS) to claim the job
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS runs_on TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE job ADD COLUMN IF NOT EXISTS runs_on TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE agent ADD COLUMN IF NOT EXISTS labels TEXT[] NOT NULL DEFAULT '{}';
+
+-- Root jobs take the repo's last synced requirement; matrix combinations and monorepo
+-- services start with their parent's, until their own config is synced
+CREATE OR REPLACE FUNCTION set_job_runs_on() RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.runs_on = '{}' THEN
+        IF NEW.parent_job_id IS NULL THEN
+            SELECT runs_on INTO NEW.runs_on FROM repo WHERE id = NEW.repo_id;
+        ELSE
+            SELECT runs_on INTO NEW.runs_on FROM job WHERE id = NEW.parent_job_id;
+        END IF;
+        NEW.runs_on := COALESCE(NEW.runs_on, '{}');
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS trigger_set_job_runs_on ON job;
+CREATE TRIGGER trigger_set_job_runs_on
+    BEFORE INSERT ON job
+    FOR EACH ROW
+    EXECUTE FUNCTION set_job_runs_on();
@@ -0,0 +1,2 @@
+-- foundry.toml [github] context: name of the repo's commit status and check run
+ALTER TABLE repo ADD COLUMN IF NOT EXISTS check_context TEXT;
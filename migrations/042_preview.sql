@@ -0,0 +1,9 @@
+-- Pull request previews an agent deployed, torn down when the PR closes
+CREATE TABLE IF NOT EXISTS preview (
+    repo_id BIGINT NOT NULL REFERENCES repo(id) ON DELETE CASCADE,
+    pr_number BIGINT NOT NULL,
+    app_name TEXT NOT NULL,
+    hostname TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (repo_id, pr_number, app_name)
+);
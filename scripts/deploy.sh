@@ -19,6 +19,7 @@ else
 fi
 
 cd "$DEPLOY_DIR"
+export FOUNDRY_GIT_SHA="$(git rev-parse HEAD)"
 
 export GITHUB_APP_PRIVATE_KEY_FILE="${HOST_PRIVATE_KEY_PATH:-/root/.config/foundry/github-app.pem}"
 